@@ -1,16 +1,55 @@
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use crate::server::config::{ServerConfig, USAGE};
+use crate::server::dns::resolve_listen_ip;
+use crate::server::estimate::{estimate, EstimateParams};
 
 const IP: &str = "127.0.0.1";
+/// The admin interface stays on the machine unless configured otherwise
+const ADMIN_IP: &str = "127.0.0.1";
+/// Standbys connect from the internal network, which has to be configured
+const REPLICATION_IP: &str = "127.0.0.1";
 const WS_PORT: u16 = 8080;
 const TCP_PORT: u16 = 8081;
+const ADMIN_PORT: u16 = 8082;
+const SHADOW_PORT: u16 = 8083;
 
 mod server;
 
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
-    let mut server = server::Server::new();
-    server.run(IP, WS_PORT, TCP_PORT).await;
+    // Capacity planning only, the server is not started
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--estimate") {
+        let params = EstimateParams::from_args(&args[1..]).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        // Compared with the limits the server would run with, the other arguments are the estimate's
+        let config_args: Vec<String> = args.iter().skip_while(|arg| *arg != "--config").take(2).cloned().collect();
+        let config = ServerConfig::load(&config_args).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        print!("{}", estimate(params, config.channel_size));
+        return Ok(())
+    }
+    if args.first().map(String::as_str) == Some("--help") {
+        println!("{}", USAGE);
+        return Ok(())
+    }
+
+    let config = ServerConfig::load(&args).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    // Printed in full, the error of main is only shown in its debug representation
+    if let Err(e) = config.validate([WS_PORT, TCP_PORT, SHADOW_PORT, ADMIN_PORT]) {
+        eprintln!("{}", e);
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} problems in the configuration", e.0.len())))
+    }
+    let listen_ip = resolve_listen_ip(config.listen_host.as_deref().unwrap_or(IP)).await
+        .map_err(|e| Error::new(ErrorKind::AddrNotAvailable, e))?;
+    let admin_ip = resolve_listen_ip(config.admin_host.as_deref().unwrap_or(ADMIN_IP)).await
+        .map_err(|e| Error::new(ErrorKind::AddrNotAvailable, e))?;
+    let replication_ip = resolve_listen_ip(config.replication_host.as_deref().unwrap_or(REPLICATION_IP)).await
+        .map_err(|e| Error::new(ErrorKind::AddrNotAvailable, e))?;
+    let [ws_port, tcp_port, shadow_port, admin_port] = config.ports;
+    let mut server = server::Server::new(config).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    server.run(listen_ip, ws_port.unwrap_or(WS_PORT), tcp_port.unwrap_or(TCP_PORT), shadow_port.unwrap_or(SHADOW_PORT), SocketAddr::new(admin_ip, admin_port.unwrap_or(ADMIN_PORT)), replication_ip).await
+        .map_err(|e| Error::new(e.kind(), e.to_string()))?;
     Ok(())
 }
 
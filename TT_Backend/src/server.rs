@@ -4,84 +4,1026 @@
 //! another port for incoming tcp connections by host(s) using the HostApp.
 //! An arbitrary number of clients can connect to the server but only one host. If a new one tries
 //! to connect, the old one gets disconnected (to prevent waiting for its timeout)
+//! Additionally one shadow host can connect on a separate port. It receives everything the host
+//! receives (plus the state set by the host), but everything it sends is discarded. This allows
+//! validating a new HostApp version against live traffic.
 //!
 
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use log::{info, warn};
-use tokio::net::TcpStream;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::{Receiver, Sender};
-use crate::server::messages::BackendMessage;
-use crate::server::networking::{ClientConnection, HostConnection};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::UnboundedSender;
+use openssl::ssl::SslAcceptor;
+use crate::server::admin::{AdminRequest, create_admin_listener};
+use crate::server::attendance::Attendance;
+use crate::server::integrity::Integrity;
+use crate::server::challenge::LoginChallenge;
+use crate::server::retention::RetentionPolicy;
+use crate::server::link::{LINK_FEEDBACK_INTERVAL, LinkQuality};
+use crate::server::paging::{Page, paginate};
+use crate::server::codec::EncodedMessage;
+use crate::server::factory::{MessageFactory, StampedMessage};
+use crate::server::timefmt::TimeFormat;
+use crate::server::resync::UpdateBuffer;
+use crate::server::live_view::{create_live_view_listener, LiveView};
+use crate::server::staging::{MAX_STAGED_BYTES, PrefetchAsset, StagedStates};
+use crate::server::templates::{REJECT_REASON_NO_GUESTS, TemplateRegistry};
+use crate::server::connection_limit::{ConnectionLimit, REJECT_REASON_IP_LIMIT};
+use crate::server::digest::{DigestTarget, SessionCounters, SessionDigest};
+use crate::server::bus::{Bus, EventSource, local_bus};
+use crate::server::auth::{AllowAll, AuthDecision, AuthProvider, create_auth_provider, HostSecret};
+use crate::server::compat::HostProtocol;
+use crate::server::upgrade::{confirm_upgrade, inherited_state, spawn_upgrade, UPGRADE_EXIT_DELAY, wait_until_ready};
+use crate::server::handshake::HandshakeStats;
+use crate::server::host_hello::{server_hello, Agreed, PROTOCOL_VERSION};
+use crate::server::reconnect::{RECONNECT_FULL_LOAD_CLIENTS, ReconnectBackoff, ReconnectHint};
+use crate::server::descriptors::{DESCRIPTOR_CHECK_INTERVAL, DescriptorGuard, DescriptorLevel};
+use crate::server::access_code::{AccessCode, REJECT_REASON_ACCESS_CODE};
+use crate::server::rejoin::{REJECT_REASON_REJOIN, rejoin_url, RejoinTokens};
+use crate::server::resume::{DepartedClients, issue_resume_token};
+use crate::server::client_list::ClientListSync;
+use crate::server::subscriptions::{EventClass, Subscriptions, SUMMARY_INTERVAL};
+use crate::server::secrets::SecretKey;
+use crate::server::memory::{MEMORY_CHECK_INTERVAL, MemoryLimit, MemoryUsage, message_bytes};
+use crate::server::no_host::{NoHostBehavior, REJECT_REASON_NO_HOST, WAITING_STATE_ID};
+use crate::server::config::{BindConfig, ServerConfig, SocketConfig, TlsConfig};
+use crate::server::leaderboard::{Leaderboard, ScoringRule};
+use crate::server::lottery::{Lottery, PickedClient, PickFilter};
+use crate::server::messages::{BackendMessage, ClientAction, current_timestamp, encode_backend_msg, HostMessage};
+use crate::server::networking::{bind_listener, BindError, CLIENT_ISSUES_INTERVAL, CLIENT_PING_INTERVAL, LISTENER_CHECK_INTERVAL, ClientConnection, ClientIssues, ClientStats, HostConnection, HostStream, Listener, ListenerRole};
+use crate::server::proxy::TrustedProxies;
+use crate::server::replication::{create_replication_listener, REJECT_REASON_STANDBY, REPLICATION_INTERVAL, ReplicatedClient, ReplicationMessage, Snapshot, start_standby};
+use crate::server::recording::{InputRecorder, RecordedInput};
+use crate::server::rooms::{REJECT_REASON_ROOM_LIMIT, REJECT_REASON_UNKNOWN_ROOM, room_bus, room_config, RoomRequest, Rooms};
+use crate::server::rules::{Action, Rule, RulesEngine};
+use crate::server::session::{Pause, Session, SessionLimits};
+use crate::server::quorum::StateQuorum;
+use crate::server::acme::{AcmeChallenges, AcmeConfig, create_acme_listener, keep_certificate};
+use crate::server::certificates::{CertificateError, load_acceptor, load_host_acceptor};
+use crate::server::stdio_host::{HostStdio, STDIO_HOST_ADDRESS};
+use crate::server::tenants::{BandwidthMeter, REJECT_REASON_CLIENT_QUOTA, REJECT_REASON_OTHER_TENANT, REJECT_REASON_SESSION_QUOTA, Tenant, TenantRegistry};
+use crate::server::teams::{assign_team, TeamSummary};
+use crate::server::timers::{TIMER_TICK, Timers};
+use crate::server::schedule::Schedules;
+use crate::server::usage::{UsageExport, UsageMeter};
+use crate::server::variants::{VariantAssigner, VariantAssignment, Variants};
 use crate::server::networking::tcp_sockets::{create_host_listener, host_socket_reader};
 use crate::server::networking::websockets::{client_socket_reader, create_client_listener, WsReadHalve};
 
 pub mod networking;
 pub mod messages;
+pub mod admin;
+pub mod secrets;
+pub mod auth;
+pub mod config;
+pub mod config_file;
+pub mod codec;
+pub mod stdio_host;
+pub mod quorum;
+pub mod certificates;
+pub mod acme;
+pub mod descriptors;
+pub mod access_code;
+pub mod rejoin;
+pub mod resume;
+pub mod client_list;
+pub mod subscriptions;
+pub mod http_client;
+pub mod estimate;
+pub mod logging;
+pub mod recording;
+pub mod memory;
+pub mod leaderboard;
+pub mod teams;
+pub mod timers;
+pub mod schedule;
+pub mod lottery;
+pub mod variants;
+pub mod session;
+pub mod tenants;
+pub mod usage;
+pub mod dns;
+pub mod proxy;
+pub mod rules;
+pub mod attendance;
+pub mod bus;
+pub mod replication;
+pub mod digest;
+pub mod connection_limit;
+pub mod integrity;
+pub mod challenge;
+pub mod retention;
+pub mod link;
+pub mod paging;
+pub mod factory;
+pub mod timefmt;
+pub mod resync;
+pub mod templates;
+pub mod staging;
+pub mod live_view;
+pub mod compat;
+pub mod no_host;
+#[cfg(feature = "soak")]
+pub mod soak;
+#[cfg(feature = "wasm_plugins")]
+pub mod plugins;
+pub mod upgrade;
+pub mod reconnect;
+pub mod handshake;
+pub mod host_hello;
+pub mod rooms;
+
+/// The server could not start
+#[derive(Debug)]
+pub enum StartupError {
+    Bind(BindError),
+    Certificate(CertificateError),
+}
+
+impl StartupError {
+    pub fn kind(&self) -> std::io::ErrorKind {
+        match self {
+            StartupError::Bind(e) => e.error.kind(),
+            StartupError::Certificate(CertificateError::Unreadable {error, ..}) => error.kind(),
+            StartupError::Certificate(CertificateError::Invalid {..}) => std::io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl Display for StartupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartupError::Bind(e) => write!(f, "{}", e),
+            StartupError::Certificate(e) => write!(f, "The TLS certificate can not be used, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+impl From<BindError> for StartupError {
+    fn from(e: BindError) -> Self {
+        StartupError::Bind(e)
+    }
+}
+
+impl From<CertificateError> for StartupError {
+    fn from(e: CertificateError) -> Self {
+        StartupError::Certificate(e)
+    }
+}
 
 pub struct Server {
     clients: HashMap<SocketAddr, ClientConnection>,
     host: Option<HostConnection>,
+    shadow: Option<HostConnection>,
+    /// Quality of the link to the active host
+    link: LinkQuality,
+    /// Stamps every message sent to hosts and clients
+    factory: MessageFactory,
+    /// Updates of the current state, replayed to resyncing clients
+    updates: UpdateBuffer,
+    /// States uploaded ahead of the presentation
+    staged: StagedStates,
+    /// Break of the session, if the host paused it
+    pause: Option<Pause>,
+    no_host: NoHostBehavior,
+    /// Backoff suggested to disconnected clients
+    reconnect: ReconnectBackoff,
+    /// Counts the times without host, a timer of an earlier one is outdated
+    host_absence: u64,
+    /// Whether the clients were disconnected because the host stayed away too long
+    host_absence_expired: bool,
+    /// Whether a new process is starting to take over
+    upgrading: bool,
     state: Option<BackendMessage>,
-    channel_rcv: Receiver<InternalMessage>,
-    channel_snd: Sender<InternalMessage>,
+    events: Box<dyn EventSource>,
+    bus: Bus,
+    auth: Arc<dyn AuthProvider>,
+    challenge: Option<Arc<LoginChallenge>>,
+    socket_config: SocketConfig,
+    disconnect_grace: Duration,
+    /// Time guests stay connected, unlimited if None
+    guest_ttl: Option<Duration>,
+    connection_limit: ConnectionLimit,
+    /// Disconnected clients whose inputs are still attributed, by identity
+    departed: DepartedClients,
+    /// Identities muted by the host, they stay muted when they resume
+    muted: HashSet<String>,
+    recorder: InputRecorder,
+    attendance: Attendance,
+    /// Vote-stuffing heuristics, disabled if None
+    integrity: Option<Integrity>,
+    /// Acknowledgments of the current state, not reported to the host if None
+    quorum: Option<StateQuorum>,
+    rules: RulesEngine,
+    /// Index of the rule whose action is being executed
+    executing_rule: Option<usize>,
+    leaderboard: Leaderboard,
+    teams: Vec<String>,
+    timers: Timers,
+    /// Messages sent on behalf of the host at given times
+    schedules: Schedules,
+    lottery: Lottery,
+    variants: VariantAssigner,
+    session_limits: SessionLimits,
+    session: Option<Session>,
+    session_generation: u64,
+    tenants: Arc<TenantRegistry>,
+    templates: Arc<TemplateRegistry>,
+    /// Hosts that connected but did not log in with the host secret or the API key of a tenant yet
+    pending_hosts: HashMap<SocketAddr, HostConnection>,
+    /// Secret hosts have to log in with, any host may take over if None
+    host_secret: Option<String>,
+    /// Bearer secret of the admin requests, only '/health' is answered if None
+    admin_secret: Option<Arc<str>>,
+    /// Protects the secrets of snapshots and the rejoin tokens
+    secret_key: SecretKey,
+    #[cfg(feature = "soak")]
+    soak: Option<soak::SoakRates>,
+    /// Limits of the plugins, hosts can not load any if None
+    #[cfg(feature = "wasm_plugins")]
+    plugin_limits: Option<plugins::PluginLimits>,
+    /// Module of the host processing the inputs
+    #[cfg(feature = "wasm_plugins")]
+    plugin: Option<plugins::Plugin>,
+    /// Memory the session may hold before its histories are trimmed
+    memory_limit: MemoryLimit,
+    bandwidth: BandwidthMeter,
+    usage: UsageMeter,
+    usage_export: Option<UsageExport>,
+    usage_interval: Duration,
+    retention: RetentionPolicy,
+    retention_interval: Duration,
+    /// States and inputs of the running session, for the digest
+    counters: SessionCounters,
+    digest: Option<DigestTarget>,
+    digest_from: String,
+    /// Export link of the digest, '{session}' is replaced by the session number
+    digest_link: Option<String>,
+    /// Timezone and locale of human-facing timestamps
+    time_format: TimeFormat,
+    /// Configured public host name and the addresses it resolved to last
+    advertised_host: Option<(String, Vec<IpAddr>)>,
+    dns_refresh: Duration,
+    /// Public url of the server, announced to hosts as join info
+    advertised_url: Option<String>,
+    /// Current operator announcement and the server timestamp it expires at
+    announcement: Option<(String, Option<i64>)>,
+    trusted_proxies: Arc<TrustedProxies>,
+    tls: TlsConfig,
+    /// Capacity of the internal channel
+    channel_size: usize,
+    /// Failed TLS handshakes on the client port by cause
+    handshakes: Arc<HandshakeStats>,
+    /// Open file descriptors, new clients are rejected close to the limit
+    descriptors: Arc<DescriptorGuard>,
+    /// Acceptor of the host ports if hosts authenticate by certificate, set by 'run'
+    host_tls: Option<Arc<SslAcceptor>>,
+    /// Code new clients have to give, replaced by the host on demand
+    access_code: AccessCode,
+    /// Unused one-time rejoin links
+    rejoin: RejoinTokens,
+    /// Client list as last synced by the host
+    client_list: ClientListSync,
+    /// Event classes the host opted out of, summarized periodically instead
+    subscriptions: Subscriptions,
+    rejoin_ttl: Duration,
+    /// Client, host and shadow listener, once running
+    listeners: Option<[Listener; 3]>,
+    admin_listener: Option<Listener>,
+    bind_config: BindConfig,
+    /// Port standbys connect to, no replication if None
+    replication_port: Option<u16>,
+    replication_listener: Option<Listener>,
+    /// Port of the public live view, no live view if None
+    live_view_port: Option<u16>,
+    live_view_listener: Option<Listener>,
+    live_view: LiveView,
+    /// Certificate provisioning of the client port, disabled if None
+    acme: Option<AcmeConfig>,
+    acme_listener: Option<Listener>,
+    acme_challenges: AcmeChallenges,
+    /// Connected standbys, the url clients reach them at and their replication stream
+    standbys: HashMap<SocketAddr, (Option<String>, UnboundedSender<String>)>,
+    /// Replication address of the primary this server is the standby of
+    standby_of: Option<String>,
+    failover_timeout: Duration,
+    /// Whether hosts and clients are rejected because the primary is still active
+    standby: bool,
+    /// Latest snapshot received from the primary
+    replicated: Option<Snapshot>,
+    /// Host connected over standard streams, opened at startup
+    host_stdio: Option<HostStdio>,
+    /// Rooms opened by hosts besides the session of this server
+    rooms: Rooms,
+    /// Configuration the main handlers of the rooms are created with
+    room_config: ServerConfig,
+    /// Session id of the room and the bus of the server that opened it, if this handler runs a room
+    room: Option<(String, Bus)>,
+}
+
+/// Parts read or created once per process, shared by the server and the main handlers of its rooms
+#[derive(Debug, Clone)]
+struct SharedParts {
+    auth: Arc<dyn AuthProvider>,
+    tenants: Arc<TenantRegistry>,
+    templates: Arc<TemplateRegistry>,
+    /// The descriptor reserve is one of the process
+    descriptors: Arc<DescriptorGuard>,
 }
 
 impl Server {
-    /// Creates a new Server
-    pub fn new() -> Self {
-        let _ = env_logger::try_init();
-        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+    /// Creates a new Server handling the events of a local bus
+    /// Fails if the configured AuthProvider can not be created
+    pub fn new(config: ServerConfig) -> Result<Self, String> {
+        let bus = local_bus(config.channel_size);
+        Server::with_bus(config, bus)
+    }
+
+    /// Creates a new Server handling the events of the given bus
+    pub fn with_bus(config: ServerConfig, bus: (Bus, Box<dyn EventSource>)) -> Result<Self, String> {
+        logging::init(config.log_filter.as_deref());
+        let auth = create_auth_provider(&config.auth, config.advertised_url.as_deref())?;
+        let tenants = match config.tenants.as_ref() {
+            None => TenantRegistry::default(),
+            Some(path) => TenantRegistry::load(path)?,
+        };
+        let templates = match config.templates.as_ref() {
+            None => TemplateRegistry::default(),
+            Some(path) => TemplateRegistry::load(path)?,
+        };
+        let descriptors = Arc::new(DescriptorGuard::new(config.fd_reserve, config.reconnect.hint(RECONNECT_FULL_LOAD_CLIENTS)));
+        let shared = SharedParts {auth, tenants: Arc::new(tenants), templates: Arc::new(templates), descriptors};
+        Ok(Server::from_parts(config, shared, bus))
+    }
+
+    /// Creates a Server of the shared parts handling the events of the given bus, nothing is read
+    /// from disk
+    fn from_parts(config: ServerConfig, SharedParts {auth, tenants, templates, descriptors}: SharedParts, (bus, events): (Bus, Box<dyn EventSource>)) -> Self {
+        let retention = RetentionPolicy {max_age: config.retention, tenants: tenants.retention()};
+        let secret_key = config.secret_key.as_deref().map(SecretKey::new).unwrap_or_else(SecretKey::generate);
+        let room_config = room_config(&config, secret_key.material());
 
         Server{
             clients: Default::default(),
             host: None,
+            shadow: None,
+            link: Default::default(),
+            factory: Default::default(),
+            updates: Default::default(),
+            staged: Default::default(),
+            pause: None,
+            no_host: config.no_host,
+            reconnect: config.reconnect,
+            host_absence: 0,
+            host_absence_expired: false,
+            upgrading: false,
             state: None,
-            channel_rcv: rx,
-            channel_snd: tx,
+            events,
+            bus,
+            auth,
+            challenge: config.challenge.map(|challenge| Arc::new(LoginChallenge::new(challenge))),
+            socket_config: config.socket,
+            disconnect_grace: config.disconnect_grace,
+            guest_ttl: config.guest_ttl,
+            connection_limit: config.connection_limit,
+            departed: Default::default(),
+            muted: Default::default(),
+            recorder: Default::default(),
+            attendance: Default::default(),
+            integrity: config.integrity.then(Integrity::default),
+            quorum: config.state_quorum.map(StateQuorum::new),
+            rules: Default::default(),
+            executing_rule: None,
+            leaderboard: Default::default(),
+            teams: config.teams,
+            timers: Default::default(),
+            schedules: Default::default(),
+            lottery: Default::default(),
+            variants: Default::default(),
+            session_limits: config.session,
+            session: None,
+            session_generation: 0,
+            tenants,
+            templates,
+            pending_hosts: Default::default(),
+            host_secret: config.host_secret,
+            admin_secret: config.admin_secret.map(Arc::from),
+            secret_key,
+            memory_limit: MemoryLimit::new(config.session_memory_limit),
+            #[cfg(feature = "soak")]
+            soak: config.soak,
+            #[cfg(feature = "wasm_plugins")]
+            plugin_limits: config.plugins,
+            #[cfg(feature = "wasm_plugins")]
+            plugin: None,
+            bandwidth: Default::default(),
+            usage: Default::default(),
+            usage_export: config.usage_export,
+            usage_interval: config.usage_interval,
+            retention,
+            retention_interval: config.retention_interval,
+            counters: Default::default(),
+            digest: config.digest,
+            digest_from: config.digest_from,
+            digest_link: config.digest_link,
+            time_format: config.time_format,
+            advertised_host: config.advertised_host.map(|host| (host, vec![])),
+            dns_refresh: config.dns_refresh,
+            advertised_url: config.advertised_url,
+            announcement: config.announcement.map(|message| (message, None)),
+            trusted_proxies: Arc::new(config.trusted_proxies),
+            tls: config.tls,
+            channel_size: config.channel_size,
+            handshakes: Default::default(),
+            descriptors,
+            host_tls: None,
+            access_code: AccessCode::new(config.access_code),
+            rejoin: Default::default(),
+            client_list: Default::default(),
+            subscriptions: Default::default(),
+            rejoin_ttl: config.rejoin_ttl,
+            listeners: None,
+            admin_listener: None,
+            bind_config: config.bind,
+            replication_port: config.replication_port,
+            replication_listener: None,
+            live_view_port: config.live_view_port,
+            live_view_listener: None,
+            live_view: Default::default(),
+            acme: config.acme,
+            acme_listener: None,
+            acme_challenges: Default::default(),
+            standbys: Default::default(),
+            standby: config.standby_of.is_some(),
+            standby_of: config.standby_of,
+            failover_timeout: config.failover_timeout,
+            replicated: None,
+            host_stdio: config.host_stdio,
+            rooms: Default::default(),
+            room_config,
+            room: None,
+        }
+    }
+
+    /// Parts the main handlers of the rooms share with this server
+    fn shared_parts(&self) -> SharedParts {
+        SharedParts {
+            auth: self.auth.clone(),
+            tenants: self.tenants.clone(),
+            templates: self.templates.clone(),
+            descriptors: self.descriptors.clone(),
         }
     }
 
     /// Starts listening for incoming connections and handling internal messages
-    pub async fn run(&mut self, listen_ip: &str, web_socket_port: u16, tcp_port: u16) {
-        create_client_listener(self.get_channel_sender(), listen_ip, web_socket_port).await;
-        create_host_listener(self.get_channel_sender(), listen_ip, tcp_port).await;
-        self.run_main_handler().await;
+    /// The main handlers of the rooms run as local tasks of the handler of the server
+    /// Fails if a listener can not be bound on its port, any alternative port or after all retries
+    pub async fn run(&mut self, listen_ip: IpAddr, web_socket_port: u16, tcp_port: u16, shadow_port: u16, admin: SocketAddr, replication_ip: IpAddr) -> Result<(), StartupError> {
+        // Checked up front instead of refusing every client, ACME provisions a missing certificate
+        if !self.tls.insecure_ws && self.acme.is_none() {
+            load_acceptor(&self.tls).await?;
+        }
+        if self.tls.host_tls_enabled() {
+            self.host_tls = Some(load_host_acceptor(&self.tls).await?);
+        }
+        if let Some(snapshot) = inherited_state(&self.secret_key) {
+            self.continue_upgrade(snapshot);
+        }
+        let bind = self.bind_config.clone();
+        let client_listener = bind_listener(ListenerRole::Clients, listen_ip, web_socket_port, &bind,
+            |address| self.create_client_listener(address)).await?;
+        let host_listener = bind_listener(ListenerRole::Hosts, listen_ip, tcp_port, &bind,
+            |address| create_host_listener(self.get_bus(), self.socket_config.clone(), self.host_tls.clone(), address, false)).await?;
+        let shadow_listener = bind_listener(ListenerRole::ShadowHosts, listen_ip, shadow_port, &bind,
+            |address| create_host_listener(self.get_bus(), self.socket_config.clone(), self.host_tls.clone(), address, true)).await?;
+        let admin_listener = bind_listener(ListenerRole::Admin, admin.ip(), admin.port(), &bind,
+            |address| create_admin_listener(self.get_bus(), self.admin_secret.clone(), address)).await?;
+        info!("run(..): Listening for clients on {}, hosts on {}, shadow hosts on {}, admin requests on {}",
+            client_listener.get_address(), host_listener.get_address(), shadow_listener.get_address(), admin_listener.get_address());
+        #[cfg(feature = "soak")]
+        if let Some(rates) = self.soak.clone() {
+            soak::start_soak(rates, client_listener.get_address(), !self.tls.insecure_ws);
+        }
+        self.listeners = Some([client_listener, host_listener, shadow_listener]);
+        self.admin_listener = Some(admin_listener);
+        if let Some(port) = self.replication_port {
+            let listener = bind_listener(ListenerRole::Replication, replication_ip, port, &bind,
+                |address| create_replication_listener(self.get_bus(), self.secret_key.clone(), address)).await?;
+            self.replication_listener = Some(listener);
+            self.start_replication();
+        }
+        if let Some(port) = self.live_view_port {
+            let listener = bind_listener(ListenerRole::LiveView, listen_ip, port, &bind,
+                |address| create_live_view_listener(self.get_bus(), address)).await?;
+            self.live_view_listener = Some(listener);
+        }
+        if let Some(acme) = self.acme.clone() {
+            let listener = bind_listener(ListenerRole::Acme, listen_ip, acme.http_port, &bind,
+                |address| create_acme_listener(self.acme_challenges.clone(), address)).await?;
+            self.acme_listener = Some(listener);
+            self.start_acme(acme);
+        }
+        if let Some(stdio) = self.host_stdio.clone() {
+            self.start_stdio_host(stdio);
+        }
+        if let Some(primary) = self.standby_of.clone() {
+            warn!("run(..): Standby of primary {}, hosts and clients are rejected until the takeover", primary);
+            start_standby(self.get_bus(), primary, self.advertised_url.clone(), self.failover_timeout, self.secret_key.clone());
+        }
+        if self.usage_export.is_some() {
+            self.start_usage_reports();
+        }
+        if self.retention.is_enabled() {
+            self.start_retention();
+        }
+        self.start_link_feedback();
+        self.start_client_pings();
+        self.start_client_issue_reports();
+        self.start_listener_supervision();
+        self.start_descriptor_check();
+        self.start_event_summaries();
+        self.start_memory_check();
+        self.host_left().await;
+        confirm_upgrade();
+        if let Some((host, _)) = self.advertised_host.as_ref() {
+            self.start_dns_refresh(host.clone());
+        }
+        // A Server is not Sync, so the rooms run next to it on this thread
+        tokio::task::LocalSet::new().run_until(self.run_main_handler()).await;
+        Ok(())
+    }
+
+    /// Opens the stdio host and spawns a task triggering the 'HostConnected' event for it
+    /// A launched host is awaited by the task, it is killed when the server stops
+    fn start_stdio_host(&self, stdio: HostStdio) {
+        let (stream, child) = match stdio.open() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("start_stdio_host(..): Launching the host {:?} failed, hosts can only connect over tcp\nError: {}", stdio, e);
+                return
+            }
+        };
+        info!("start_stdio_host(..): Host {:?} connected over standard streams", stdio);
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            channel.send(InternalMessage::HostConnected {stream, address: STDIO_HOST_ADDRESS, shadow: false}).await.expect("start_stdio_host(..): Sending internal message failed");
+            if let Some(mut child) = child {
+                match child.wait().await {
+                    Ok(status) => warn!("start_stdio_host(..): Launched host exited with {}", status),
+                    Err(e) => error!("start_stdio_host(..): Waiting for the launched host failed\nError: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Spawns the task obtaining and renewing the certificate of the client port
+    fn start_acme(&self, acme: AcmeConfig) {
+        tokio::spawn(keep_certificate(acme, self.tls.clone(), self.acme_challenges.clone()));
+    }
+
+    /// Spawns a task triggering the 'UsageReportDue' event every usage interval
+    fn start_usage_reports(&self) {
+        let channel = self.get_bus();
+        let interval = self.usage_interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                channel.send(InternalMessage::UsageReportDue).await.expect("start_usage_reports(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Spawns a task triggering the 'RetentionDue' event right away and every retention interval
+    fn start_retention(&self) {
+        let channel = self.get_bus();
+        let interval = self.retention_interval;
+        tokio::spawn(async move {
+            loop {
+                channel.send(InternalMessage::RetentionDue).await.expect("start_retention(..): Sending internal message failed");
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    /// Spawns a task triggering the 'LinkFeedbackDue' event every feedback interval
+    fn start_link_feedback(&self) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(LINK_FEEDBACK_INTERVAL).await;
+                channel.send(InternalMessage::LinkFeedbackDue).await.expect("start_link_feedback(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Spawns a task triggering the 'ClientPingDue' event every ping interval
+    fn start_client_pings(&self) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CLIENT_PING_INTERVAL).await;
+                channel.send(InternalMessage::ClientPingDue).await.expect("start_client_pings(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Spawns a task triggering the 'ClientIssuesDue' event every issue report interval
+    fn start_client_issue_reports(&self) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CLIENT_ISSUES_INTERVAL).await;
+                channel.send(InternalMessage::ClientIssuesDue).await.expect("start_client_issue_reports(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Spawns a task triggering the 'ListenerCheckDue' event every listener check interval
+    fn start_listener_supervision(&self) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(LISTENER_CHECK_INTERVAL).await;
+                channel.send(InternalMessage::ListenerCheckDue).await.expect("start_listener_supervision(..): Sending internal message failed");
+            }
+        });
     }
 
-    /// Returns a (cloned) sending channel for internal messages
+    /// Spawns a task triggering the 'EventSummaryDue' event every summary interval
+    fn start_event_summaries(&self) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SUMMARY_INTERVAL).await;
+                channel.send(InternalMessage::EventSummaryDue).await.expect("start_event_summaries(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Sends the summaries of the events the host opted out of, if there were any
+    async fn handle_event_summary_due(&mut self) {
+        for msg in self.subscriptions.take_summary(self.clients.len()) {
+            self.send_to_hosts(msg).await;
+        }
+    }
+
+    /// Spawns a task triggering the 'MemoryCheckDue' event every memory check interval
+    fn start_memory_check(&self) {
+        if self.memory_limit.get_limit().is_none() {
+            return
+        }
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(MEMORY_CHECK_INTERVAL).await;
+                channel.send(InternalMessage::MemoryCheckDue).await.expect("start_memory_check(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Approximate bytes held by the session
+    fn session_memory(&self) -> MemoryUsage {
+        MemoryUsage {
+            queued: self.clients.values().map(|client| client.get_queue_stats().bytes()).sum(),
+            state: self.state.as_ref().map(message_bytes).unwrap_or(0),
+            updates: self.updates.bytes(),
+            inputs: self.recorder.bytes(),
+            staged: self.staged.bytes(),
+        }
+    }
+
+    /// Trims the histories of a session above the memory limit, oldest first
+    /// The host is told once if trimming does not get the session below the limit
+    async fn handle_memory_check_due(&mut self) {
+        let usage = self.session_memory();
+        let excess = self.memory_limit.excess(&usage);
+        if excess == 0 {
+            self.memory_limit.set_exceeded(false);
+            return
+        }
+        let mut freed = self.recorder.trim(excess);
+        freed += self.updates.trim(excess.saturating_sub(freed));
+        let limit = self.memory_limit.get_limit().unwrap_or(0);
+        warn!("handle_memory_check_due(..): The session holds {} of {} bytes, trimmed {} bytes of recorded inputs and buffered updates", usage.total(), limit, freed);
+        let total = usage.total().saturating_sub(freed);
+        if self.memory_limit.set_exceeded(total > limit) {
+            error!("handle_memory_check_due(..): The session still holds {} of {} bytes without histories", total, limit);
+            let message = format!("The session holds {} of {} bytes in queued messages and states", total, limit);
+            self.write_to_hosts(BackendMessage::QuotaExceeded {quota: String::from("session_memory"), message}).await;
+        }
+    }
+
+    /// Spawns a task triggering the 'DescriptorCheckDue' event every descriptor check interval
+    fn start_descriptor_check(&self) {
+        if self.descriptors.get_limit().is_none() {
+            info!("start_descriptor_check(..): No descriptor limit, clients are not rejected at capacity");
+            return
+        }
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(DESCRIPTOR_CHECK_INTERVAL).await;
+                channel.send(InternalMessage::DescriptorCheckDue).await.expect("start_descriptor_check(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Counts the open descriptors, changes of the level are logged and the capacity reported
+    async fn handle_descriptor_check_due(&mut self) {
+        let (level, open) = match self.descriptors.check() {
+            None => return,
+            Some(v) => v,
+        };
+        let limit = self.descriptors.get_limit().unwrap_or(0);
+        match level {
+            DescriptorLevel::Normal => info!("handle_descriptor_check_due(..): {} of {} file descriptors open, accepting clients again", open, limit),
+            DescriptorLevel::High => warn!("handle_descriptor_check_due(..): {} of {} file descriptors open", open, limit),
+            DescriptorLevel::Capacity => {
+                error!("handle_descriptor_check_due(..): {} of {} file descriptors open, rejecting new clients", open, limit);
+                let message = format!("{} of {} file descriptors are open, new clients are rejected", open, limit);
+                self.write_to_hosts(BackendMessage::QuotaExceeded {quota: String::from("file_descriptors"), message}).await;
+            }
+        }
+    }
+
+    /// Restarts the accept loops that ended, e.g. once the process ran out of descriptors
+    fn handle_listener_check_due(&mut self) {
+        for (role, listener) in self.listeners_mut() {
+            if listener.is_running() {
+                continue
+            }
+            error!("handle_listener_check_due(..): The {} listener on {} stopped accepting, restarting it", role, listener.get_address());
+            if let Err(e) = listener.restart() {
+                error!("handle_listener_check_due(..): Restarting the {} listener failed\nError: {}", role, e);
+            }
+        }
+    }
+
+    /// Every bound listener with its role
+    fn listeners_mut(&mut self) -> Vec<(ListenerRole, &mut Listener)> {
+        let roles = [ListenerRole::Clients, ListenerRole::Hosts, ListenerRole::ShadowHosts];
+        self.listeners.iter_mut()
+            .flat_map(|listeners| roles.into_iter().zip(listeners.iter_mut()))
+            .chain(self.admin_listener.iter_mut().map(|listener| (ListenerRole::Admin, listener)))
+            .chain(self.replication_listener.iter_mut().map(|listener| (ListenerRole::Replication, listener)))
+            .chain(self.live_view_listener.iter_mut().map(|listener| (ListenerRole::LiveView, listener)))
+            .chain(self.acme_listener.iter_mut().map(|listener| (ListenerRole::Acme, listener)))
+            .collect()
+    }
+
+    /// Spawns a task triggering the 'ReplicationDue' event every replication interval
+    fn start_replication(&self) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REPLICATION_INTERVAL).await;
+                channel.send(InternalMessage::ReplicationDue).await.expect("start_replication(..): Sending internal message failed");
+            }
+        });
+    }
+
+    /// Spawns a task resolving the advertised host every 'dns_refresh'
+    /// Every successful resolution triggers the 'AdvertisedResolved' event
+    fn start_dns_refresh(&self, host: String) {
+        let channel = self.get_bus();
+        let interval = self.dns_refresh;
+        tokio::spawn(async move {
+            loop {
+                match dns::resolve(&host).await {
+                    Ok(addresses) => channel.send(InternalMessage::AdvertisedResolved {addresses}).await.expect("start_dns_refresh(..): Sending internal message failed"),
+                    Err(e) => warn!("start_dns_refresh(..): Keeping the previous addresses of the advertised host\nError: {}", e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    async fn create_client_listener(&self, address: SocketAddr) -> std::io::Result<Listener> {
+        create_client_listener(self.get_bus(), self.auth.clone(), self.challenge.clone(), self.socket_config.clone(), self.trusted_proxies.clone(), self.handshakes.clone(), self.descriptors.clone(), self.tls.clone(), address).await
+    }
+
+    /// Returns a (cloned) handle of the event bus
     /// Is used to enqueue tasks for the main handler
-    pub fn get_channel_sender(&self) -> Sender<InternalMessage> {
-        self.channel_snd.clone()
+    pub fn get_bus(&self) -> Bus {
+        self.bus.clone()
     }
 }
 
 impl Server {
     async fn run_main_handler(&mut self) {
         info!("run_main_handler(..): Started");
-        while let Some(message) = self.channel_rcv.recv().await {
+        while let Some(message) = self.events.recv().await {
+            self.handle_message(message).await;
+        }
+        info!("run_main_handler(..): Event bus closed -> shutting down")
+    }
+
+    /// Handles the events of the room until neither its host nor any client is connected
+    async fn run_room(mut self, code: String) {
+        let (session, parent) = match self.room.clone() {
+            None => return,
+            Some(v) => v,
+        };
+        info!("run_room(..): Room {} opened", code);
+        while let Some(message) = self.events.recv().await {
             self.handle_message(message).await;
+            if self.host.is_none() && self.pending_hosts.is_empty() && self.clients.is_empty() {
+                break
+            }
         }
-        info!("run_main_handler(..): All sending channel ends closed -> shutting down")
+        info!("run_room(..): Room {} closed", code);
+        // Events still published for the room are dropped from now on
+        drop(self);
+        parent.send(InternalMessage::RoomClosed {session}).await.expect("run_room(..): Sending internal message failed");
     }
 
     async fn handle_message(&mut self, message: InternalMessage) {
+        // Events of the connections in a room are handled by the room, a new connection may reuse
+        // the address of a former one
+        let room = match &message {
+            InternalMessage::ClientConnected {client, ..} => {
+                self.rooms.forget(client.get_address());
+                None
+            }
+            InternalMessage::HostConnected {address, ..} => {
+                self.rooms.forget(*address);
+                None
+            }
+            message => message.connection().and_then(|address| self.rooms.bus_of(address)).cloned(),
+        };
+        if let Some(bus) = room {
+            return bus.send(message).await.expect("handle_message(..): Sending internal message failed")
+        }
+        // The periodic checks of the rooms are triggered with the ones of this server
+        if matches!(message, InternalMessage::ClientPingDue | InternalMessage::LinkFeedbackDue | InternalMessage::ClientIssuesDue
+            | InternalMessage::EventSummaryDue | InternalMessage::MemoryCheckDue) {
+            self.tick_rooms(&message).await;
+        }
         match message {
+            InternalMessage::ClientConnected {client, ..} if self.standby =>
+                self.refuse_client_on_standby(client).await,
+            InternalMessage::HostConnected {stream, address, ..} if self.standby =>
+                self.refuse_host_on_standby(stream, address).await,
+            InternalMessage::ClientConnected {client, read} if client.get_room().is_some() =>
+                self.join_room(read, client).await,
             InternalMessage::ClientConnected {client, read} =>
                 self.handle_client_connected(read, client).await,
             InternalMessage::ClientCloseConnection {address, reason} =>
                 self.handle_client_close_connection(address, reason).await,
-            InternalMessage::HostConnected {stream, address} =>
+            InternalMessage::ClientGraceExpired {identity, departed_at} =>
+                self.handle_client_grace_expired(identity, departed_at).await,
+            InternalMessage::GuestExpired {address, guest_until} =>
+                self.handle_guest_expired(address, guest_until).await,
+            InternalMessage::HostConnected {stream, address, shadow: false} =>
                 self.handle_host_connected(stream, address).await,
+            InternalMessage::HostConnected {stream, address, shadow: true} =>
+                self.handle_shadow_connected(stream, address).await,
+            InternalMessage::HostLogin {address, api_key, decision, version, capabilities, room} =>
+                self.handle_host_login(address, api_key, decision, version, capabilities, room).await,
+            InternalMessage::RoomHostJoined {host, api_key, version, capabilities} =>
+                self.handle_room_host_joined(host, api_key, version, capabilities).await,
+            InternalMessage::RoomClosed {session} =>
+                self.handle_room_closed(session),
+            InternalMessage::RoomShutdown {reason} =>
+                self.handle_room_shutdown(reason).await,
             InternalMessage::HostCloseConnection {address, reason} =>
                 self.handle_host_close_connection(address, reason).await,
-            InternalMessage::ClientInput {state_id, address, content} =>
-                self.handle_client_input(state_id, address, content).await,
+            InternalMessage::ClientInput {state_id, address, content, client_ts, input_id, server_ts} =>
+                self.handle_client_input(state_id, address, content, client_ts, input_id, server_ts).await,
             InternalMessage::HostUpdate {state_id, address, content} =>
                 self.handle_host_update(state_id, address, content).await,
-            InternalMessage::HostChangeState {state_id, address, content} =>
-                self.handle_host_change_state(state_id, address, content).await,
+            InternalMessage::HostChangeState {state_id, address, content, variants} =>
+                self.handle_host_change_state(state_id, address, content, variants).await,
+            InternalMessage::HostSetScoring {address, state_id, rule} =>
+                self.handle_host_set_scoring(address, state_id, rule),
+            InternalMessage::HostShowLeaderboard {address, count} =>
+                self.handle_host_show_leaderboard(address, count).await,
+            InternalMessage::HostStartTimer {address, id, duration} =>
+                self.handle_host_start_timer(address, id, duration).await,
+            InternalMessage::HostCancelTimer {address, id} =>
+                self.handle_host_cancel_timer(address, id).await,
+            InternalMessage::TimerTick {id, generation} =>
+                self.handle_timer_tick(id, generation).await,
+            InternalMessage::HostScheduleMessage {address, id, at, interval, message} =>
+                self.handle_host_schedule_message(address, id, at, interval, *message).await,
+            InternalMessage::HostCancelScheduledMessage {address, id} =>
+                self.handle_host_cancel_scheduled_message(address, id).await,
+            InternalMessage::HostListScheduledMessages {address} =>
+                self.handle_host_list_scheduled_messages(address).await,
+            InternalMessage::ScheduleDue {id, generation} =>
+                self.handle_schedule_due(id, generation).await,
+            InternalMessage::ClientIssuesDue =>
+                self.handle_client_issues_due().await,
+            InternalMessage::ListenerCheckDue =>
+                self.handle_listener_check_due(),
+            InternalMessage::DescriptorCheckDue =>
+                self.handle_descriptor_check_due().await,
+            InternalMessage::EventSummaryDue =>
+                self.handle_event_summary_due().await,
+            InternalMessage::MemoryCheckDue =>
+                self.handle_memory_check_due().await,
+            InternalMessage::UpgradeReady {result} =>
+                self.handle_upgrade_ready(result).await,
+            InternalMessage::NoHostTimeout {absence} =>
+                self.handle_no_host_timeout(absence).await,
+            InternalMessage::HostProtocolDetected {address, protocol} =>
+                self.handle_host_protocol_detected(address, protocol).await,
+            InternalMessage::HostSetPublic {address, public} =>
+                self.handle_host_set_public(address, public),
+            InternalMessage::LiveViewState {reply} =>
+                self.handle_live_view_state(reply),
+            InternalMessage::LiveViewerConnected {address, reply} =>
+                self.handle_live_viewer_connected(address, reply),
+            InternalMessage::HostPauseSession {address, message} =>
+                self.handle_host_pause_session(address, message).await,
+            InternalMessage::HostResumeSession {address} =>
+                self.handle_host_resume_session(address).await,
+            InternalMessage::HostStageState {address, index, content, assets} =>
+                self.handle_host_stage_state(address, index, content, assets).await,
+            InternalMessage::HostShowStaged {address, index} =>
+                self.handle_host_show_staged(address, index).await,
+            InternalMessage::HostStartFromTemplate {address, name} =>
+                self.handle_host_start_from_template(address, name).await,
+            InternalMessage::HostPickRandomClients {address, count, filter} =>
+                self.handle_host_pick_random_clients(address, count, filter).await,
+            InternalMessage::HostGetTeamSummary {address} =>
+                self.handle_host_get_team_summary(address).await,
+            InternalMessage::HostRequestSnapshotExport {address} =>
+                self.handle_host_request_snapshot_export(address).await,
+            InternalMessage::HostRotateAccessCode {address, code, announce} =>
+                self.handle_host_rotate_access_code(address, code, announce).await,
+            InternalMessage::HostLoadPlugin {address, module} =>
+                self.handle_host_load_plugin(address, module).await,
+            InternalMessage::HostUnloadPlugin {address} =>
+                self.handle_host_unload_plugin(address).await,
+            InternalMessage::HostGetIntegrity {address} =>
+                self.handle_host_get_integrity(address).await,
+            InternalMessage::HostGetAttendance {address, page} =>
+                self.handle_host_get_attendance(address, page).await,
+            InternalMessage::HostSyncClientList {address, version} =>
+                self.handle_host_sync_client_list(address, version).await,
+            InternalMessage::HostSubscribe {address, events} =>
+                self.handle_host_subscribe(address, events).await,
+            InternalMessage::HostGetClientList {address, page} =>
+                self.handle_host_get_client_list(address, page).await,
+            InternalMessage::HostClientCommand {address, action, min_version} =>
+                self.handle_host_client_command(address, action, min_version).await,
+            InternalMessage::HostSetRules {address, rules} =>
+                self.handle_host_set_rules(address, rules),
+            InternalMessage::RuleTimer {generation, index} =>
+                self.handle_rule_timer(generation, index).await,
+            InternalMessage::HostMuteClient {address, client_id, muted} =>
+                self.handle_host_mute_client(address, client_id, muted),
+            InternalMessage::HostResendState {address, client_id} =>
+                self.handle_host_resend_state(address, client_id).await,
+            InternalMessage::ClientStateAck {address, state_id} =>
+                self.handle_client_state_ack(address, state_id).await,
+            InternalMessage::ClientRequestResync {address} =>
+                self.handle_client_request_resync(address).await,
+            InternalMessage::HostGetClientInputs {address, client_id, state_id, page} =>
+                self.handle_host_get_client_inputs(address, client_id, state_id, page).await,
+            InternalMessage::HostResync {address, count} =>
+                self.handle_host_resync(address, count).await,
+            InternalMessage::HostRetransmit {address, frame} =>
+                self.handle_host_retransmit(address, frame).await,
+            InternalMessage::AdvertisedResolved {addresses} =>
+                self.handle_advertised_resolved(addresses).await,
+            InternalMessage::UsageReportDue =>
+                self.handle_usage_report_due(),
+            InternalMessage::RetentionDue =>
+                self.enforce_retention(None),
+            InternalMessage::ClientPingDue =>
+                self.handle_client_ping_due().await,
+            InternalMessage::LinkFeedbackDue =>
+                self.handle_link_feedback_due().await,
+            InternalMessage::HostLinkSample {address, bytes, elapsed} =>
+                self.handle_host_link_sample(address, bytes, elapsed),
+            InternalMessage::HostLinkProbeAck {address, probe} =>
+                self.handle_host_link_probe_ack(address, probe),
+            InternalMessage::SessionWarning {generation} =>
+                self.handle_session_warning(generation).await,
+            InternalMessage::SessionExpired {generation} =>
+                self.handle_session_expired(generation).await,
+            InternalMessage::ReplicationDue =>
+                self.handle_replication_due(),
+            InternalMessage::StandbyConnected {address, url, sender} =>
+                self.handle_standby_connected(address, url, sender),
+            InternalMessage::StandbyDisconnected {address} =>
+                self.handle_standby_disconnected(address),
+            InternalMessage::Replicated {snapshot} =>
+                self.handle_replicated(snapshot),
+            InternalMessage::Takeover {reason} =>
+                self.handle_takeover(reason),
+            InternalMessage::AdminRequest {request, reply} =>
+                self.handle_admin_request(request, reply).await,
         }
 
     }
@@ -89,141 +1031,2390 @@ impl Server {
     async fn handle_client_connected(&mut self, read: WsReadHalve, mut client: ClientConnection) {
         info!("handle_client_connected(..): Client {} connected, name: {}", client.get_address_as_str(), client.get_name());
 
-        if self.state.is_some() {
-            client.send_message(self.state.as_ref().unwrap().clone()).await;
+        if self.host_absence_expired && self.host.is_none() {
+            info!("handle_client_connected(..): Rejecting client {}, no host connected", client.get_address_as_str());
+            client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_NO_HOST)})).await;
+            client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+            return
+        }
+
+        // A rejoin link stands in for name, credentials and access code
+        let rejoining = client.get_rejoin().map(String::from);
+        if let Some(token) = rejoining.as_deref() {
+            match self.rejoin.redeem(token, current_timestamp(), &self.secret_key) {
+                Some(grant) => {
+                    info!("handle_client_connected(..): Client {} rejoined as {}", client.get_address_as_str(), grant.name);
+                    client.restore(grant);
+                }
+                None => {
+                    info!("handle_client_connected(..): Rejecting client {}, invalid or expired rejoin link", client.get_address_as_str());
+                    client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_REJOIN)})).await;
+                    client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+                    return
+                }
+            }
+        }
+
+        if rejoining.is_none() && !self.access_code.admits(client.get_access_code()) {
+            info!("handle_client_connected(..): Rejecting client {}, invalid access code", client.get_address_as_str());
+            client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_ACCESS_CODE)})).await;
+            client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+            return
+        }
+
+        let access = self.templates.access(self.session.as_ref().and_then(|session| session.template.as_deref()));
+        if client.is_guest() && !access.guests {
+            info!("handle_client_connected(..): Rejecting guest {}, the session admits no guests", client.get_address_as_str());
+            client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_NO_GUESTS)})).await;
+            client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+            return
+        }
+
+        let tenant_limit = self.session_tenant().and_then(|tenant| tenant.max_clients);
+        let limit = match (tenant_limit, access.max_clients) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if let Some(limit) = limit {
+            if self.clients.len() >= limit {
+                info!("handle_client_connected(..): Rejecting client {}, the session has {} of {} clients", client.get_address_as_str(), self.clients.len(), limit);
+                let message = format!("Client {} rejected, the limit of {} clients is reached", client.get_name(), limit);
+                client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_CLIENT_QUOTA)})).await;
+                client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+                self.write_to_hosts(BackendMessage::QuotaExceeded {quota: String::from("clients"), message}).await;
+                return
+            }
+        }
+
+        let ip = client.get_address().ip();
+        let from_ip = self.clients.keys().filter(|address| address.ip() == ip).count();
+        if !self.connection_limit.allows(ip, from_ip) {
+            info!("handle_client_connected(..): Rejecting client {}, {} has {} connection(s) already", client.get_address_as_str(), ip, from_ip);
+            client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_IP_LIMIT)})).await;
+            client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+            return
+        }
+
+        self.resume_client(&mut client).await;
+
+        let members = self.clients.values().filter_map(|client| client.get_team());
+        let team = assign_team(&self.teams, client.get_team().map(String::from), members);
+        client.set_team(team);
+        if let Some(team) = client.get_team() {
+            client.send_message(self.factory.build(BackendMessage::Team {team: String::from(team)})).await;
+        }
+
+        let mut assignment = None;
+        match (&self.no_host, self.host.is_none()) {
+            (NoHostBehavior::Waiting(content), true) =>
+                client.send_message(self.factory.build(BackendMessage::ChangeState {state_id: WAITING_STATE_ID, content: content.clone()})).await,
+            _ => if let Some((state, assigned)) = self.state_for_client(&client) {
+                client.send_message(self.factory.build(state)).await;
+                assignment = assigned;
+            },
+        }
+
+        if let Some(announcement) = self.current_announcement() {
+            client.send_message(self.factory.build(announcement)).await;
+        }
+        for hint in self.prefetch_hints() {
+            client.send_message(self.factory.build(hint)).await;
+        }
+        if let Some(pause) = self.pause.clone() {
+            client.send_message(self.factory.build(BackendMessage::SessionPaused {message: pause.message, since: pause.since})).await;
+        }
+
+        // Late clients see the same remaining time as everybody else
+        let now = current_timestamp();
+        for (id, timer) in self.timers.running() {
+            client.send_message(self.factory.build(BackendMessage::Timer {id: String::from(id), remaining: timer.ends_at - now, ends_at: timer.ends_at})).await;
         }
 
         self.notify_host_client_connected(&client).await;
+        if let Some((state_id, assignment)) = assignment {
+            self.write_to_hosts(BackendMessage::VariantsAssigned {state_id, assignments: vec![assignment]}).await;
+        }
 
-        tokio::spawn(client_socket_reader(self.get_channel_sender(), read, client.get_address()));
+        tokio::spawn(client_socket_reader(self.get_bus(), read, client.get_address(), client.get_codec(), client.get_traffic_stats().clone()));
+        self.start_guest_ttl(&mut client);
 
+        self.usage.client_connected(client.get_address(), current_timestamp());
+        self.attendance.joined(client.get_name(), current_timestamp());
+        if let Some(integrity) = self.integrity.as_mut() {
+            integrity.joined(client.get_name(), client.get_address().ip(), current_timestamp());
+        }
         self.clients.insert(client.get_address(), client);
     }
 
     async fn notify_host_client_connected(&mut self, client: &ClientConnection) {
-        if let Some(host) = self.host.as_mut() {
-            let msg = BackendMessage::ClientConnected {
-                name: String::from(client.get_name()),
-                address: client.get_address_as_str()
-            };
-            host.send_message(msg).await;
+        let msg = BackendMessage::ClientConnected {
+            name: String::from(client.get_name()),
+            address: client.get_address_as_str(),
+            role: client.get_role().map(String::from),
+            team: client.get_team().map(String::from),
+        };
+        if self.pause.is_none() {
+            self.write_to_hosts(msg).await;
         }
     }
 
     async fn handle_client_close_connection(&mut self, address: SocketAddr, reason: &str) {
+        let reconnect = self.reconnect.hint(1);
+        self.close_client(address, reason, Some(reconnect)).await
+    }
+
+    /// Closes the connection of the client, clients leaving together share the reconnect hint
+    async fn close_client(&mut self, address: SocketAddr, reason: &str, reconnect: Option<ReconnectHint>) {
         if let Some(client) = self.clients.remove(&address) {
             info!("handle_client_close_connection(..): Closing connection to client {} ({})\nReason: {}", client.get_name(), address, reason);
+            self.usage.client_disconnected(address, client.get_bytes_sent(), current_timestamp());
+            self.attendance.left(client.get_name(), current_timestamp());
+            if let Some(quorum) = self.quorum.as_mut() {
+                quorum.client_left(address);
+            }
 
             self.notify_host_client_disconnected(&client, reason).await;
+            self.check_quorum().await;
+            self.start_disconnect_grace(&client);
 
-            client.close(reason).await;
+            client.close(reason, reconnect).await;
         }
     }
 
     async fn notify_host_client_disconnected(&mut self, client: &ClientConnection, reason: &str) {
-        if let Some(host) = self.host.as_mut() {
-            let msg = BackendMessage::ClientDisconnected {
-                name: String::from(client.get_name()),
-                address: client.get_address_as_str(),
-                reason: String::from(reason)
-            };
-            host.send_message(msg).await;
+        let answered = self.current_state_id().map(|state_id| client.has_answered(state_id)).unwrap_or(false);
+        let msg = BackendMessage::ClientDisconnected {
+            name: String::from(client.get_name()),
+            address: client.get_address_as_str(),
+            reason: String::from(reason),
+            answered,
+            last_state_id: client.get_last_state(),
+        };
+        if self.pause.is_none() {
+            self.write_to_hosts(msg).await;
         }
     }
 
-    async fn handle_host_connected(&mut self, stream: TcpStream, address: SocketAddr) {
-        info!("handle_host_connected(..): Host {} connected", address);
+    /// Continues the departed participant of the resume token the client presented, a client
+    /// without one (or with an expired one) receives a new token
+    async fn resume_client(&mut self, client: &mut ClientConnection) {
+        let identity = client.get_resume().map(|token| self.secret_key.hash(token));
+        // The old connection may not have noticed yet that it dropped
+        let stale = self.clients.values().find(|other| Some(other.get_identity()) == identity.as_deref()).map(ClientConnection::get_address);
+        if let Some(address) = stale {
+            self.close_client(address, networking::DISCONNECT_REASON_RESUMED, None).await;
+        }
+        let departed = identity.as_deref().and_then(|identity| self.departed.resume(identity));
+        match (identity, departed) {
+            (Some(identity), Some(departed)) => {
+                info!("resume_client(..): Client {} resumed {} ({})", client.get_address_as_str(), departed.name, departed.address);
+                self.recorder.transfer(departed.address, client.get_address());
+                client.resume_from(&departed);
+                client.set_muted(self.muted.contains(&identity));
+                client.set_identity(identity);
+            }
+            _ => {
+                let (token, identity) = issue_resume_token(&self.secret_key);
+                client.set_identity(identity);
+                client.send_message(self.factory.build(BackendMessage::Resume {token})).await;
+            }
+        }
+    }
 
-        let (read_half, write_half) = stream.into_split();
+    /// Keeps the inputs of the client attributed until the grace period is over
+    /// Triggers the 'ClientGraceExpired' event afterwards
+    fn start_disconnect_grace(&mut self, client: &ClientConnection) {
+        let identity = String::from(client.get_identity());
+        let departed_at = current_timestamp();
+        self.departed.depart(&identity, client.departed(departed_at));
 
-        if let Some(host) = self.host.take() {
-            info!("handle_host_connected(..): Old host {} still connected. Disconnecting.", host.get_address());
-            host.close(networking::DISCONNECT_REASON_HOST_OTHER).await;
+        let channel = self.get_bus();
+        let grace = self.disconnect_grace;
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            channel.send(InternalMessage::ClientGraceExpired {identity, departed_at}).await.expect("start_disconnect_grace(..): Sending internal message failed");
+        });
+    }
+
+    /// Drops the inputs of the participant unless it resumed in the meantime
+    async fn handle_client_grace_expired(&mut self, identity: String, departed_at: i64) {
+        if let Some(departed) = self.departed.expire(&identity, departed_at) {
+            info!("handle_client_grace_expired(..): Grace period of client {} ({}) is over", departed.name, departed.address);
+            self.recorder.forget(departed.address);
+            self.muted.remove(&identity);
+            let msg = BackendMessage::ClientExpired {name: departed.name, address: departed.address.to_string()};
+            self.write_to_hosts(msg).await;
         }
-        assert!(self.host.is_none(), "handle_host_connected(..): Host should have been consumed");
+    }
 
-        tokio::spawn(host_socket_reader(self.get_channel_sender(), read_half, address));
+    /// Limits the access of a guest to the guest TTL
+    /// Triggers the 'GuestExpired' event afterwards
+    fn start_guest_ttl(&self, client: &mut ClientConnection) {
+        let ttl = match self.guest_ttl {
+            Some(ttl) if client.is_guest() => ttl,
+            _ => return,
+        };
+        let guest_until = current_timestamp() + ttl.as_millis() as i64;
+        client.set_guest_until(Some(guest_until));
 
-        self.host = Some(HostConnection::new(address, write_half, self.get_channel_sender()));
+        let channel = self.get_bus();
+        let address = client.get_address();
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            channel.send(InternalMessage::GuestExpired {address, guest_until}).await.expect("start_guest_ttl(..): Sending internal message failed");
+        });
     }
 
-    async fn handle_host_close_connection(&mut self, address: SocketAddr, reason: &str) {
-        if self.host.is_some() {
-            if self.host.as_ref().unwrap().get_address() == address {
-                info!("handle_host_closed(..): Disconnecting host {}\nReason: {}", address, reason);
+    /// Disconnects the guest, it has to log in again to continue
+    /// A connection that took over the address in the meantime is not affected
+    async fn handle_guest_expired(&mut self, address: SocketAddr, guest_until: i64) {
+        let expired = self.clients.get(&address).and_then(ClientConnection::get_guest_until) == Some(guest_until);
+        if expired {
+            info!("handle_guest_expired(..): Guest access of client {} expired", address);
+            self.handle_client_close_connection(address, networking::DISCONNECT_REASON_GUEST_EXPIRED).await;
+        }
+    }
+
+    async fn handle_host_connected(&mut self, stream: HostStream, address: SocketAddr) {
+        info!("handle_host_connected(..): Host {} connected", address);
 
-                self.host.take().unwrap().close(reason).await;
+        tokio::spawn(host_socket_reader(self.get_bus(), stream.read, address, self.host_auth(address)));
 
-                assert!(self.host.is_none(), "handle_host_closed(..): Host should have been consumed");
+        let mut host = HostConnection::new(address, stream.write, self.get_bus(), self.socket_config.send_timeout);
+        // The active host is only replaced once the new one answered the hello with its login
+        host.send_message(self.factory.build(server_hello(self.socket_config.send_timeout))).await;
+        self.pending_hosts.insert(address, host);
+    }
+
+    /// Makes the host the active one, starting a session for the tenant if none is running
+    async fn promote_host(&mut self, host: HostConnection, tenant: Option<String>) {
+        let returned = match self.host.take() {
+            None => true,
+            Some(old) => {
+                info!("promote_host(..): Old host {} still connected. Disconnecting.", old.get_address());
+                old.close(networking::DISCONNECT_REASON_HOST_OTHER, None).await;
+                false
             }
+        };
+        if returned {
+            self.host_returned().await;
         }
-    }
+        assert!(self.host.is_none(), "promote_host(..): Host should have been consumed");
 
-    async fn handle_client_input(&mut self, state_id: i32, address: SocketAddr, content: String) {
-        if let Some(client) = self.clients.get(&address) {
+        self.host = Some(host);
+        self.link = Default::default();
+        self.client_list.reset();
+        self.subscriptions.reset();
+        // Lets the host show join links and QR codes pointing at the public url
+        if let Some(url) = self.advertised_url.clone() {
             if let Some(host) = self.host.as_mut() {
-                info!("handle_client_input(..): Client {} ({}) send input\nContent: {}", client.get_name(), address, content);
+                host.send_message(self.factory.build(BackendMessage::JoinInfo {url})).await;
+            }
+        }
+        if let Some((name, addresses)) = self.advertised_host.clone().filter(|(_, addresses)| !addresses.is_empty()) {
+            if let Some(host) = self.host.as_mut() {
+                host.send_message(self.factory.build(BackendMessage::AdvertisedAddress {host: name, addresses})).await;
+            }
+        }
+        if let Some(announcement) = self.current_announcement() {
+            if let Some(host) = self.host.as_mut() {
+                host.send_message(self.factory.build(announcement)).await;
+            }
+        }
+        if self.session.is_none() {
+            self.start_session(tenant);
+        }
+    }
 
-                let msg = BackendMessage::Input {
-                    state_id,
-                    input: content,
-                    name: String::from(client.get_name()),
-                    address: address.to_string()
-                };
-                host.send_message(msg).await;
+    /// Sends the messages held back until the protocol of the host was known
+    async fn handle_host_protocol_detected(&mut self, address: SocketAddr, protocol: HostProtocol) {
+        let host = self.host.iter_mut()
+            .chain(self.shadow.iter_mut())
+            .chain(self.pending_hosts.values_mut())
+            .find(|host| host.get_address() == address);
+        if let Some(host) = host {
+            host.set_protocol(protocol).await;
+        }
+    }
+
+    /// Rejects a pending host denied by the AuthProvider, agrees on the capabilities and checks its
+    /// API key against the tenants and their session quota before it becomes the active host
+    /// A host asking for a room is handed over to it instead
+    async fn handle_host_login(&mut self, address: SocketAddr, api_key: Option<String>, decision: AuthDecision, version: Option<u32>, capabilities: Option<Vec<String>>, room: Option<RoomRequest>) {
+        let mut host = match self.pending_hosts.remove(&address) {
+            None => return,
+            Some(v) => v,
+        };
+        if let AuthDecision::Deny {reason} = decision {
+            return self.reject_host(host, &reason).await
+        }
+        if let Some(room) = room {
+            return self.host_to_room(host, room, api_key, version, capabilities).await
+        }
+        if version.is_some_and(|version| version != PROTOCOL_VERSION) {
+            warn!("handle_host_login(..): Host {} speaks protocol version {:?}, the server {}", address, version, PROTOCOL_VERSION);
+        }
+        let agreed = Agreed::negotiate(capabilities.as_deref());
+        info!("handle_host_login(..): Capabilities agreed with host {}: {:?}", address, agreed.names());
+        host.set_agreed(agreed);
+        if !self.tenants.is_enabled() {
+            return self.promote_host(host, None).await
+        }
+        let tenant = match self.tenants.authenticate(api_key.as_deref()) {
+            Err(reason) => return self.reject_host(host, reason).await,
+            Ok(tenant) => tenant,
+        };
+        let session_tenant = self.session.as_ref().map(|session| session.tenant.as_deref());
+        match session_tenant {
+            Some(session_tenant) if session_tenant != Some(tenant.name.as_str()) =>
+                return self.reject_host(host, REJECT_REASON_OTHER_TENANT).await,
+            // A new session, the rooms of the tenant count against its quota
+            None if tenant.max_sessions.is_some_and(|max| self.rooms.of_tenant(&tenant.name) >= max) =>
+                return self.reject_host(host, REJECT_REASON_SESSION_QUOTA).await,
+            _ => {}
+        }
+        info!("handle_host_login(..): Host {} logged in for tenant {}", address, tenant.name);
+        let tenant = tenant.name.clone();
+        self.promote_host(host, Some(tenant)).await;
+    }
+
+    /// Provider deciding about the login of the host, the host secret replaces the configured
+    /// provider and the host launched by the server itself is always accepted
+    fn host_auth(&self, address: SocketAddr) -> Arc<dyn AuthProvider> {
+        match self.host_secret.as_ref() {
+            _ if address == STDIO_HOST_ADDRESS => Arc::new(AllowAll),
+            None => self.auth.clone(),
+            Some(secret) => Arc::new(HostSecret {secret: secret.clone()}),
+        }
+    }
+
+    /// Hands the authenticated host over to the room it opens or returns to, the room agrees on
+    /// the capabilities and checks the tenant like the server does for its own session
+    async fn host_to_room(&mut self, mut host: HostConnection, room: RoomRequest, api_key: Option<String>, version: Option<u32>, capabilities: Option<Vec<String>>) {
+        let session = match room {
+            RoomRequest::Return(session) if self.rooms.get(&session).is_some() => {
+                info!("host_to_room(..): Host {} returns to its room", host.get_address());
+                session
             }
+            RoomRequest::Return(_) => return self.reject_host(host, REJECT_REASON_UNKNOWN_ROOM).await,
+            RoomRequest::Open => match self.open_room(api_key.as_deref()) {
+                Err(reason) => return self.reject_host(host, &reason).await,
+                Ok((session, code)) => {
+                    info!("host_to_room(..): Host {} opened room {}", host.get_address(), code);
+                    host.send_message(self.factory.build(BackendMessage::RoomOpened {code, session: session.clone()})).await;
+                    session
+                }
+            },
+        };
+        self.rooms.route(host.get_address(), &session);
+        if let Some(bus) = self.rooms.get(&session) {
+            bus.send(InternalMessage::RoomHostJoined {host, api_key, version, capabilities}).await.expect("host_to_room(..): Sending internal message failed");
         }
     }
 
-    async fn handle_host_update(&mut self, state_id: i32, address: SocketAddr, content: String) {
-        if let Some(host) = self.host.as_ref() {
-            if host.get_address() == address {
-                if self.clients.is_empty() {
-                    warn!("handle_host_update(..): No clients connected");
-                } else {
-                    info!("handle_host_update(..): Host {} send update\nContent: {}", host.get_address(), content);
-                    let msg = BackendMessage::Update {state_id, content};
-                    self.write_to_all_clients(msg).await;
+    /// Starts the main handler of a new room for the tenant of the API key, returns its session id
+    /// and join code
+    /// The session of the server and the other rooms of the tenant count against its session quota
+    fn open_room(&mut self, api_key: Option<&str>) -> Result<(String, String), String> {
+        let tenant = match self.tenants.is_enabled() {
+            false => None,
+            true => {
+                let tenant = self.tenants.authenticate(api_key)?;
+                let server_session = self.session.as_ref().is_some_and(|session| session.tenant.as_deref() == Some(tenant.name.as_str()));
+                let sessions = self.rooms.of_tenant(&tenant.name) + usize::from(server_session);
+                if tenant.max_sessions.is_some_and(|max| sessions >= max) {
+                    return Err(String::from(REJECT_REASON_SESSION_QUOTA))
                 }
+                Some(tenant.name.clone())
+            }
+        };
+        let (bus, events) = room_bus(self.room_config.channel_size);
+        let (session, code) = self.rooms.open(bus.clone(), tenant).ok_or_else(|| String::from(REJECT_REASON_ROOM_LIMIT))?;
+        let mut room = Server::from_parts(self.room_config.clone(), self.shared_parts(), (bus, events));
+        room.room = Some((session.clone(), self.get_bus()));
+        tokio::task::spawn_local(room.run_room(code.clone()));
+        Ok((session, code))
+    }
+
+    /// Logs in the host handed over by the server, it was authenticated there
+    async fn handle_room_host_joined(&mut self, host: HostConnection, api_key: Option<String>, version: Option<u32>, capabilities: Option<Vec<String>>) {
+        let address = host.get_address();
+        self.pending_hosts.insert(address, host);
+        self.handle_host_login(address, api_key, AuthDecision::allow(), version, capabilities, None).await;
+    }
+
+    /// Hands the client over to the room of its join code
+    async fn join_room(&mut self, read: WsReadHalve, mut client: ClientConnection) {
+        let code = client.get_room().map(String::from).unwrap_or_default();
+        let session = self.rooms.session_of(&code).map(String::from);
+        let bus = match session.as_deref().and_then(|session| self.rooms.get(session)) {
+            None => {
+                info!("join_room(..): Rejecting client {}, there is no room {}", client.get_address_as_str(), code);
+                client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_UNKNOWN_ROOM)})).await;
+                client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+                return
             }
+            Some(v) => v.clone(),
+        };
+        info!("join_room(..): Client {} joins room {}", client.get_address_as_str(), code);
+        self.rooms.route(client.get_address(), session.as_deref().unwrap_or_default());
+        // Within the room the client is one of its session
+        client.set_room(None);
+        bus.send(InternalMessage::ClientConnected {read, client}).await.expect("join_room(..): Sending internal message failed");
+    }
+
+    fn handle_room_closed(&mut self, session: String) {
+        self.rooms.close(&session);
+    }
+
+    /// Ends every room, e.g. before this process exits
+    async fn shutdown_rooms(&self, reason: &'static str) {
+        if !self.rooms.is_empty() {
+            warn!("shutdown_rooms(..): Ending {} room(s)\nReason: {}", self.rooms.len(), reason);
+        }
+        for bus in self.rooms.buses() {
+            bus.send(InternalMessage::RoomShutdown {reason}).await.expect("shutdown_rooms(..): Sending internal message failed");
+        }
+    }
+
+    /// Closes the clients and hosts of the room, which closes with them
+    async fn handle_room_shutdown(&mut self, reason: &'static str) {
+        let addresses: Vec<SocketAddr> = self.clients.keys().copied().collect();
+        let reconnect = self.reconnect.hint(addresses.len());
+        for address in addresses {
+            self.close_client(address, reason, Some(reconnect)).await;
+        }
+        let hosts = self.host.take().into_iter().chain(self.pending_hosts.drain().map(|(_, host)| host));
+        for host in hosts.collect::<Vec<HostConnection>>() {
+            host.close(reason, Some(reconnect)).await;
         }
     }
 
-    async fn handle_host_change_state(&mut self, state_id: i32, address: SocketAddr, content: String) {
-        if let Some(host) = self.host.as_ref() {
-            if host.get_address() == address {
-                info!("handle_host_change_state(..): Host {} send change state\nContent: {}", host.get_address(), content);
-                let msg = BackendMessage::ChangeState {state_id, content};
+    /// Triggers the periodic check in every room
+    async fn tick_rooms(&self, tick: &InternalMessage) {
+        for bus in self.rooms.buses() {
+            let tick = match tick {
+                InternalMessage::ClientPingDue => InternalMessage::ClientPingDue,
+                InternalMessage::LinkFeedbackDue => InternalMessage::LinkFeedbackDue,
+                InternalMessage::ClientIssuesDue => InternalMessage::ClientIssuesDue,
+                InternalMessage::EventSummaryDue => InternalMessage::EventSummaryDue,
+                InternalMessage::MemoryCheckDue => InternalMessage::MemoryCheckDue,
+                _ => return,
+            };
+            bus.send(tick).await.expect("tick_rooms(..): Sending internal message failed");
+        }
+    }
 
-                self.state = Some(msg.clone());
+    async fn reject_host(&mut self, mut host: HostConnection, reason: &str) {
+        info!("reject_host(..): Login of host {} rejected. Closing connection!\nReason: {}", host.get_address(), reason);
+        host.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(reason)})).await;
+        host.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+    }
 
-                if self.clients.is_empty() {
-                    warn!("handle_host_change_state(..): No clients connected");
-                } else {
-                    self.write_to_all_clients(msg).await;
-                }
+    /// Tenant of the running session
+    fn session_tenant(&self) -> Option<&Tenant> {
+        let name = self.session.as_ref()?.tenant.as_deref()?;
+        self.tenants.get(name)
+    }
+
+    /// Accounts the message relayed to every client against the bandwidth quota of the tenant
+    /// The host is told if the message has to be dropped
+    async fn within_bandwidth(&mut self, msg: &BackendMessage) -> bool {
+        let limit = match self.session_tenant().and_then(|tenant| tenant.max_bandwidth) {
+            None => return true,
+            Some(v) => v,
+        };
+        let bytes = (encode_backend_msg(msg.clone()).len() * self.clients.len()) as u64;
+        if self.bandwidth.try_consume(bytes, limit, current_timestamp()) {
+            return true
+        }
+        warn!("within_bandwidth(..): Bandwidth quota of {} bytes/s exceeded, dropping {}", limit, msg);
+        let message = format!("Bandwidth quota of {} bytes/s exceeded, the message was not relayed", limit);
+        self.write_to_hosts(BackendMessage::QuotaExceeded {quota: String::from("bandwidth"), message}).await;
+        false
+    }
+
+    /// Starts a new session and the tasks triggering its 'SessionWarning' and 'SessionExpired' events
+    fn start_session(&mut self, tenant: Option<String>) {
+        let started = current_timestamp();
+        self.session_generation += 1;
+        let session = Session {started, ends_at: self.session_limits.deadline(started), generation: self.session_generation, tenant, template: None};
+        info!("start_session(..): Session {} started, ends at {:?}", session.generation, session.ends_at);
+
+        self.schedule_session_end(&session);
+        self.usage.reset(self.clients.keys().copied(), started);
+        self.attendance.reset(self.clients.values().map(ClientConnection::get_name), started);
+        self.counters = Default::default();
+        if let Some(integrity) = self.integrity.as_mut() {
+            *integrity = Default::default();
+            for client in self.clients.values() {
+                integrity.joined(client.get_name(), client.get_address().ip(), started);
             }
         }
+        self.factory.session_started(session.generation);
+        self.session = Some(session);
     }
 
-    async fn write_to_all_clients(&mut self, msg: BackendMessage) {
-        for (_, client) in self.clients.iter_mut() {
-            client.send_message(msg.clone()).await;
+    /// Spawns the task triggering the 'SessionWarning' and 'SessionExpired' events of the session
+    fn schedule_session_end(&self, session: &Session) {
+        if let Some(ends_at) = session.ends_at {
+            let channel = self.get_bus();
+            let generation = session.generation;
+            let warning = self.session_limits.warning.as_millis() as i64;
+            tokio::spawn(async move {
+                let until_warning = ends_at - warning - current_timestamp();
+                if until_warning > 0 {
+                    tokio::time::sleep(Duration::from_millis(until_warning as u64)).await;
+                }
+                channel.send(InternalMessage::SessionWarning {generation}).await.expect("schedule_session_end(..): Sending internal message failed");
+
+                let until_end = ends_at - current_timestamp();
+                if until_end > 0 {
+                    tokio::time::sleep(Duration::from_millis(until_end as u64)).await;
+                }
+                channel.send(InternalMessage::SessionExpired {generation}).await.expect("schedule_session_end(..): Sending internal message failed");
+            });
         }
     }
 
-}
+    async fn handle_shadow_connected(&mut self, stream: HostStream, address: SocketAddr) {
+        info!("handle_shadow_connected(..): Shadow host {} connected", address);
 
+        if let Some(shadow) = self.shadow.take() {
+            info!("handle_shadow_connected(..): Old shadow host {} still connected. Disconnecting.", shadow.get_address());
+            shadow.close(networking::DISCONNECT_REASON_HOST_OTHER, None).await;
+        }
 
-const CHANNEL_SIZE: usize = 16;
+        tokio::spawn(host_socket_reader(self.get_bus(), stream.read, address, self.host_auth(address)));
 
-#[derive(Debug)]
-pub enum InternalMessage {
-    ClientConnected{read: WsReadHalve, client: ClientConnection},
-    ClientCloseConnection {address: SocketAddr, reason: &'static str},
-    HostConnected{stream: TcpStream, address: SocketAddr},
-    HostCloseConnection {address: SocketAddr, reason: &'static str},
-    ClientInput{state_id: i32, address: SocketAddr, content: String},
-    HostUpdate{state_id: i32, address : SocketAddr, content: String},
-    HostChangeState{state_id: i32, address : SocketAddr, content: String},
+        let mut shadow = HostConnection::new(address, stream.write, self.get_bus(), self.socket_config.send_timeout);
+        if let Some(state) = self.state.as_ref() {
+            shadow.send_message(self.factory.build(state.clone())).await;
+        }
+        self.shadow = Some(shadow);
+    }
+
+    async fn handle_host_close_connection(&mut self, address: SocketAddr, reason: &str) {
+        if self.host.as_ref().map(|host| host.get_address()) == Some(address) {
+            info!("handle_host_closed(..): Disconnecting host {}\nReason: {}", address, reason);
+
+            self.host.take().unwrap().close(reason, None).await;
+
+            assert!(self.host.is_none(), "handle_host_closed(..): Host should have been consumed");
+            self.host_left().await;
+        } else if self.shadow.as_ref().map(|shadow| shadow.get_address()) == Some(address) {
+            info!("handle_host_closed(..): Disconnecting shadow host {}\nReason: {}", address, reason);
+
+            self.shadow.take().unwrap().close(reason, None).await;
+        } else if let Some(host) = self.pending_hosts.remove(&address) {
+            info!("handle_host_closed(..): Disconnecting pending host {}\nReason: {}", address, reason);
+
+            host.close(reason, None).await;
+        }
+    }
+
+    /// Starts a time without host, clients see the configured no-host behavior
+    async fn host_left(&mut self) {
+        self.host_absence += 1;
+        match self.no_host.clone() {
+            NoHostBehavior::Keep => {}
+            NoHostBehavior::Waiting(content) => {
+                if !self.clients.is_empty() {
+                    info!("host_left(..): No host connected, showing the waiting state to {} client(s)", self.clients.len());
+                    self.write_to_all_clients(BackendMessage::ChangeState {state_id: WAITING_STATE_ID, content}).await;
+                }
+            }
+            NoHostBehavior::Disconnect(after) => {
+                let channel = self.get_bus();
+                let absence = self.host_absence;
+                tokio::spawn(async move {
+                    tokio::time::sleep(after).await;
+                    channel.send(InternalMessage::NoHostTimeout {absence}).await.expect("host_left(..): Sending internal message failed");
+                });
+            }
+        }
+    }
+
+    /// Ends the time without host, the waiting state is replaced by the state of the host
+    async fn host_returned(&mut self) {
+        self.host_absence += 1;
+        self.host_absence_expired = false;
+        if !matches!(self.no_host, NoHostBehavior::Waiting(_)) {
+            return
+        }
+        let addresses: Vec<SocketAddr> = self.clients.keys().copied().collect();
+        for address in addresses {
+            self.resend_state(address, false).await;
+        }
+    }
+
+    /// Disconnects the clients if the host stayed away since the timer started
+    async fn handle_no_host_timeout(&mut self, absence: u64) {
+        if self.host.is_some() || absence != self.host_absence {
+            return
+        }
+        warn!("handle_no_host_timeout(..): No host connected for too long, disconnecting {} client(s)", self.clients.len());
+        self.host_absence_expired = true;
+        let addresses: Vec<SocketAddr> = self.clients.keys().copied().collect();
+        let reconnect = self.reconnect.hint(addresses.len());
+        for address in addresses {
+            self.close_client(address, networking::DISCONNECT_REASON_NO_HOST, Some(reconnect)).await;
+        }
+    }
+
+    async fn handle_client_input(&mut self, state_id: i32, address: SocketAddr, content: String, client_ts: Option<i64>, input_id: Option<String>, server_ts: i64) {
+        if let Some(client) = self.clients.get_mut(&address) {
+            if let Some(host) = self.host.as_mut() {
+                if let Some(pause) = self.pause.as_ref() {
+                    info!("handle_client_input(..): Session is paused. Dropping input of client {} ({})!", client.get_name(), address);
+                    client.get_traffic_stats().rejected_input();
+                    client.send_message(self.factory.build(BackendMessage::SessionPaused {message: pause.message.clone(), since: pause.since})).await;
+                    return
+                }
+                if client.is_muted() {
+                    info!("handle_client_input(..): Client {} ({}) is muted. Dropping input!", client.get_name(), address);
+                    client.get_traffic_stats().rejected_input();
+                    client.send_message(self.factory.build(BackendMessage::Muted {state_id, input_id})).await;
+                    return
+                }
+
+                // Retransmitted inputs are only acknowledged again, the host already got them
+                if let Some(input_id) = input_id.as_ref() {
+                    if !client.register_input_id(input_id) {
+                        info!("handle_client_input(..): Client {} ({}) retransmitted input {}. Dropping!", client.get_name(), address, input_id);
+                        client.send_message(self.factory.build(BackendMessage::InputAck {state_id, input_id: input_id.clone()})).await;
+                        return
+                    }
+                }
+
+                info!("handle_client_input(..): Client {} ({}) send input\nContent: {}", client.get_name(), address, content);
+
+                #[cfg(feature = "wasm_plugins")]
+                let plugin_input = self.plugin.is_some()
+                    .then(|| json!({"name": client.get_name(), "state_id": state_id, "input": content, "server_ts": server_ts}));
+
+                if let Some(points) = self.leaderboard.score_input(client.get_identity(), client.get_name(), client.get_team(), state_id, &content, server_ts) {
+                    info!("handle_client_input(..): Client {} ({}) scored {} point(s)", client.get_name(), address, points);
+                }
+
+                self.usage.add_bytes(content.len() as u64);
+                self.counters.inputs += 1;
+                if let Some(integrity) = self.integrity.as_mut() {
+                    integrity.input(client.get_name(), state_id, server_ts);
+                }
+                self.recorder.record(address, RecordedInput {
+                    state_id,
+                    input: content.clone(),
+                    client_ts,
+                    input_id: input_id.clone(),
+                    server_ts,
+                });
+
+                let msg = BackendMessage::Input {
+                    state_id,
+                    input: content,
+                    name: String::from(client.get_name()),
+                    address: address.to_string(),
+                    client_ts,
+                    input_id: input_id.clone(),
+                    server_ts,
+                };
+                let msg = self.factory.build(msg);
+                if let Some(shadow) = self.shadow.as_mut() {
+                    shadow.send_message(msg.clone()).await;
+                }
+                host.send_message(msg).await;
+                client.register_answer(state_id);
+
+                // Acknowledge forwarded input, so the client can stop retrying
+                if let Some(input_id) = input_id {
+                    client.send_message(self.factory.build(BackendMessage::InputAck {state_id, input_id})).await;
+                }
+
+                #[cfg(feature = "wasm_plugins")]
+                if let Some(input) = plugin_input {
+                    self.run_plugin(input).await;
+                }
+                self.evaluate_input_rules(state_id).await;
+            }
+        }
+    }
+
+    /// Fires the rules depending on the share of clients that answered the current state
+    async fn evaluate_input_rules(&mut self, state_id: i32) {
+        if self.current_state_id() != Some(state_id) {
+            return
+        }
+        let answered = self.clients.values().filter(|client| client.has_answered(state_id)).count();
+        let fired = self.rules.inputs_changed(state_id, answered, self.clients.len());
+        for (index, name, action) in fired {
+            self.execute_rule(index, name, action).await;
+        }
+    }
+
+    async fn handle_host_update(&mut self, state_id: i32, address: SocketAddr, content: String) {
+        if self.is_shadow(address) {
+            info!("handle_host_update(..): Discarding update of shadow host {}", address);
+            return
+        }
+        if self.is_host(address) {
+            info!("handle_host_update(..): Host {} send update\nContent: {}", address, content);
+            self.broadcast_update(state_id, content).await;
+        }
+    }
+
+    /// Sends the update to the clients, the shadow host and the live view
+    async fn broadcast_update(&mut self, state_id: i32, content: String) {
+        if self.clients.is_empty() && !self.live_view.is_public() {
+            warn!("broadcast_update(..): No clients connected");
+            return
+        }
+        let msg = BackendMessage::Update {state_id, content};
+        if !self.within_bandwidth(&msg).await {
+            return
+        }
+        self.write_to_shadow(msg.clone()).await;
+        let msg = self.factory.build(msg);
+        self.updates.push(msg.clone());
+        self.live_view.publish(&msg);
+        let mut msg = EncodedMessage::new(msg);
+        for client in self.clients.values_mut() {
+            client.send_encoded(&mut msg).await;
+        }
+    }
+
+    async fn handle_host_change_state(&mut self, state_id: i32, address: SocketAddr, content: String, variants: Option<Variants>) {
+        if self.is_shadow(address) {
+            info!("handle_host_change_state(..): Discarding change state of shadow host {}", address);
+            return
+        }
+        if self.is_host(address) {
+            info!("handle_host_change_state(..): Host {} send change state\nContent: {}", address, content);
+            self.broadcast_state(state_id, content, variants).await;
+        }
+    }
+
+    /// Makes the state the current one and sends it to the clients (with their variants), the
+    /// shadow host and the live view
+    async fn broadcast_state(&mut self, state_id: i32, content: String, variants: Option<Variants>) {
+        let msg = BackendMessage::ChangeState {state_id, content};
+        if !self.within_bandwidth(&msg).await {
+            return
+        }
+
+        self.state = Some(msg.clone());
+        self.updates.state_changed();
+        self.counters.states += 1;
+        self.variants.state_changed(variants);
+        self.restart_rules();
+        self.leaderboard.state_changed(state_id, current_timestamp());
+        if let Some(quorum) = self.quorum.as_mut() {
+            quorum.state_changed(state_id);
+        }
+        self.write_to_shadow(msg.clone()).await;
+        if self.live_view.is_public() {
+            self.live_view.publish(&self.factory.build(msg.clone()));
+        }
+
+        if self.clients.is_empty() {
+            warn!("broadcast_state(..): No clients connected");
+            return
+        }
+
+        let mut msg = EncodedMessage::new(self.factory.build(msg));
+        let mut assignments = vec![];
+        for client in self.clients.values_mut() {
+            match self.variants.assign(client.get_name()) {
+                None => client.send_encoded(&mut msg).await,
+                Some((variant, content)) => {
+                    client.send_message(self.factory.build(BackendMessage::ChangeState {state_id, content})).await;
+                    assignments.push(VariantAssignment {
+                        name: String::from(client.get_name()),
+                        address: client.get_address_as_str(),
+                        variant,
+                    });
+                }
+            }
+        }
+        if !assignments.is_empty() {
+            self.write_to_hosts(BackendMessage::VariantsAssigned {state_id, assignments}).await;
+        }
+    }
+
+    /// The current state as the client has to receive it, with the variant assigned to the client
+    fn state_for_client(&mut self, client: &ClientConnection) -> Option<(BackendMessage, Option<(i32, VariantAssignment)>)> {
+        let (state_id, content) = match self.state.as_ref()? {
+            BackendMessage::ChangeState {state_id, content} => (*state_id, content.clone()),
+            _ => return None,
+        };
+        match self.variants.assign(client.get_name()) {
+            None => Some((BackendMessage::ChangeState {state_id, content}, None)),
+            Some((variant, content)) => {
+                let assignment = VariantAssignment {
+                    name: String::from(client.get_name()),
+                    address: client.get_address_as_str(),
+                    variant,
+                };
+                Some((BackendMessage::ChangeState {state_id, content}, Some((state_id, assignment))))
+            }
+        }
+    }
+
+    fn handle_host_set_scoring(&mut self, address: SocketAddr, state_id: i32, rule: ScoringRule) {
+        if !self.is_host(address) {
+            info!("handle_host_set_scoring(..): Discarding scoring rule of host {}, it is not the active host", address);
+            return
+        }
+        info!("handle_host_set_scoring(..): Host {} set scoring rule for state {}", address, state_id);
+        self.leaderboard.set_rule(state_id, rule);
+    }
+
+    /// Broadcasts the best 'count' clients to all clients and the host(s)
+    async fn handle_host_show_leaderboard(&mut self, address: SocketAddr, count: usize) {
+        if !self.is_host(address) {
+            info!("handle_host_show_leaderboard(..): Discarding leaderboard request of host {}, it is not the active host", address);
+            return
+        }
+        self.show_leaderboard(count).await;
+    }
+
+    async fn show_leaderboard(&mut self, count: usize) {
+        let msg = BackendMessage::Leaderboard {standings: self.leaderboard.standings(count)};
+        self.write_to_hosts(msg.clone()).await;
+        self.write_to_all_clients(msg).await;
+    }
+
+    /// Starts the timer and a task triggering the 'TimerTick' event every TIMER_TICK until it expires
+    async fn handle_host_start_timer(&mut self, address: SocketAddr, id: String, duration: i64) {
+        if !self.is_host(address) {
+            info!("handle_host_start_timer(..): Discarding timer of host {}, it is not the active host", address);
+            return
+        }
+        let ends_at = current_timestamp() + duration.max(0);
+        let generation = self.timers.start(&id, ends_at).generation;
+
+        let channel = self.get_bus();
+        let tick_id = id.clone();
+        tokio::spawn(async move {
+            loop {
+                let remaining = ends_at - current_timestamp();
+                if remaining <= 0 {
+                    break
+                }
+                tokio::time::sleep(TIMER_TICK.min(Duration::from_millis(remaining as u64))).await;
+                channel.send(InternalMessage::TimerTick {id: tick_id.clone(), generation}).await.expect("handle_host_start_timer(..): Sending internal message failed");
+            }
+        });
+
+        self.handle_timer_tick(id, generation).await;
+    }
+
+    async fn handle_host_cancel_timer(&mut self, address: SocketAddr, id: String) {
+        if !self.is_host(address) {
+            info!("handle_host_cancel_timer(..): Discarding timer of host {}, it is not the active host", address);
+            return
+        }
+        if self.timers.cancel(&id) {
+            let msg = BackendMessage::TimerCancelled {id};
+            self.write_to_hosts(msg.clone()).await;
+            self.write_to_all_clients(msg).await;
+        } else {
+            warn!("handle_host_cancel_timer(..): Host {} cancelled unknown timer {}", address, id);
+        }
+    }
+
+    async fn handle_timer_tick(&mut self, id: String, generation: u64) {
+        let now = current_timestamp();
+        if let Some(timer) = self.timers.tick(&id, generation, now) {
+            self.broadcast_timer(id, timer.ends_at, now).await;
+        }
+    }
+
+    /// Schedules the message and answers with the pending ones, or why it was rejected
+    async fn handle_host_schedule_message(&mut self, address: SocketAddr, id: String, at: Option<i64>, interval: Option<u64>, message: HostMessage) {
+        if !self.is_host(address) {
+            info!("handle_host_schedule_message(..): Discarding scheduled message of host {}, it is not the active host", address);
+            return
+        }
+        let interval = interval.map(Duration::from_millis);
+        let error = match self.schedules.schedule(&id, message, at, interval, current_timestamp()) {
+            Ok(scheduled) => {
+                info!("handle_host_schedule_message(..): Message {} scheduled for {}", id, scheduled.next_at);
+                self.arm_schedule(id, scheduled.generation, scheduled.next_at);
+                None
+            }
+            Err(e) => {
+                warn!("handle_host_schedule_message(..): Rejecting message {} of host {}\nReason: {}", id, address, e);
+                Some(e)
+            }
+        };
+        let msg = BackendMessage::ScheduledMessages {messages: self.schedules.entries(), error};
+        self.write_to_host_at(address, msg).await;
+    }
+
+    async fn handle_host_cancel_scheduled_message(&mut self, address: SocketAddr, id: String) {
+        if !self.is_host(address) {
+            info!("handle_host_cancel_scheduled_message(..): Discarding cancellation of host {}, it is not the active host", address);
+            return
+        }
+        if !self.schedules.cancel(&id) {
+            warn!("handle_host_cancel_scheduled_message(..): Host {} cancelled unknown scheduled message {}", address, id);
+        }
+        self.handle_host_list_scheduled_messages(address).await;
+    }
+
+    async fn handle_host_list_scheduled_messages(&mut self, address: SocketAddr) {
+        let msg = BackendMessage::ScheduledMessages {messages: self.schedules.entries(), error: None};
+        self.write_to_host_at(address, msg).await;
+    }
+
+    /// Spawns a task triggering the 'ScheduleDue' event at the timestamp
+    fn arm_schedule(&self, id: String, generation: u64, at: i64) {
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            let delay = (at - current_timestamp()).max(0) as u64;
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            channel.send(InternalMessage::ScheduleDue {id, generation}).await.expect("arm_schedule(..): Sending internal message failed");
+        });
+    }
+
+    /// Executes the scheduled message whether a host is connected or not, recurring ones are armed
+    /// again
+    async fn handle_schedule_due(&mut self, id: String, generation: u64) {
+        let message = match self.schedules.due(&id, generation, current_timestamp()) {
+            None => return,
+            Some(v) => v,
+        };
+        info!("handle_schedule_due(..): Sending scheduled message {}", id);
+        match message {
+            HostMessage::Update {state_id, content} => self.broadcast_update(state_id, content).await,
+            HostMessage::ChangeState {state_id, content, variants} => self.broadcast_state(state_id, content, variants).await,
+            HostMessage::ShowLeaderboard {count} => self.show_leaderboard(count).await,
+            msg => warn!("handle_schedule_due(..): Scheduled messages can not execute {}", msg),
+        }
+        let next_at = self.schedules.next_at(&id);
+        if let Some(next_at) = next_at {
+            self.arm_schedule(id.clone(), generation, next_at);
+        }
+        self.write_to_hosts(BackendMessage::ScheduledMessageSent {id, next_at}).await;
+    }
+
+    /// Sends the remaining time to clients and host(s), or the expiry once no time is left
+    async fn broadcast_timer(&mut self, id: String, ends_at: i64, now: i64) {
+        let remaining = ends_at - now;
+        let msg = if remaining <= 0 {
+            info!("broadcast_timer(..): Timer {} expired", id);
+            BackendMessage::TimerExpired {id}
+        } else {
+            BackendMessage::Timer {id, remaining, ends_at}
+        };
+        self.write_to_hosts(msg.clone()).await;
+        self.write_to_all_clients(msg).await;
+    }
+
+    /// Publishes the session on the live view or takes it off
+    fn handle_host_set_public(&mut self, address: SocketAddr, public: bool) {
+        if !self.is_host(address) {
+            info!("handle_host_set_public(..): Discarding live view change of host {}, it is not the active host", address);
+            return
+        }
+        if self.live_view_port.is_none() {
+            warn!("handle_host_set_public(..): Host {} changed the live view, but no live view port is configured", address);
+        }
+        info!("handle_host_set_public(..): Host {} set the live view public: {}", address, public);
+        self.live_view.set_public(public);
+    }
+
+    /// The current state followed by its updates, as the live view shows them
+    fn live_view_snapshot(&mut self) -> Vec<StampedMessage> {
+        let state = match self.state.clone() {
+            None => return vec![],
+            Some(v) => self.factory.build(v),
+        };
+        let (updates, _) = self.updates.replay();
+        std::iter::once(state).chain(updates).collect()
+    }
+
+    fn handle_live_view_state(&mut self, reply: oneshot::Sender<Option<Vec<String>>>) {
+        let snapshot = match self.live_view.is_public() {
+            false => None,
+            true => Some(self.live_view_snapshot().into_iter().map(StampedMessage::encode).collect()),
+        };
+        let _ = reply.send(snapshot);
+    }
+
+    fn handle_live_viewer_connected(&mut self, address: SocketAddr, reply: oneshot::Sender<Option<mpsc::Receiver<String>>>) {
+        let events = match self.live_view.is_public() {
+            false => None,
+            true => {
+                let snapshot = self.live_view_snapshot();
+                self.live_view.subscribe(address, snapshot)
+            }
+        };
+        if reply.send(events).is_err() {
+            info!("handle_live_viewer_connected(..): Live viewer {} is gone already", address);
+        }
+    }
+
+    /// Starts a break, a running break only gets the new message
+    async fn handle_host_pause_session(&mut self, address: SocketAddr, message: Option<String>) {
+        if !self.is_host(address) {
+            info!("handle_host_pause_session(..): Discarding pause of host {}, it is not the active host", address);
+            return
+        }
+        let since = self.pause.as_ref().map(|pause| pause.since).unwrap_or_else(current_timestamp);
+        info!("handle_host_pause_session(..): Host {} paused the session", address);
+        self.pause = Some(Pause {since, message: message.clone()});
+        let msg = BackendMessage::SessionPaused {message, since};
+        self.write_to_hosts(msg.clone()).await;
+        self.write_to_all_clients(msg).await;
+    }
+
+    async fn handle_host_resume_session(&mut self, address: SocketAddr) {
+        if !self.is_host(address) {
+            info!("handle_host_resume_session(..): Discarding resume of host {}, it is not the active host", address);
+            return
+        }
+        let pause = match self.pause.take() {
+            None => return warn!("handle_host_resume_session(..): Host {} resumed a session that is not paused", address),
+            Some(v) => v,
+        };
+        let paused_for = current_timestamp() - pause.since;
+        info!("handle_host_resume_session(..): Host {} resumed the session after {} ms", address, paused_for);
+        let msg = BackendMessage::SessionResumed {paused_for};
+        self.write_to_hosts(msg.clone()).await;
+        self.write_to_all_clients(msg).await;
+    }
+
+    async fn handle_host_stage_state(&mut self, address: SocketAddr, index: i32, content: String, assets: Vec<PrefetchAsset>) {
+        if !self.is_host(address) {
+            info!("handle_host_stage_state(..): Discarding staged state of host {}, it is not the active host", address);
+            return
+        }
+        let bytes = content.len();
+        if !self.staged.stage(index, content, assets) {
+            warn!("handle_host_stage_state(..): Staging state {} ({} bytes) exceeds {} bytes. Dropping!", index, bytes, MAX_STAGED_BYTES);
+            let message = format!("Staged states are limited to {} bytes, state {} was not staged", MAX_STAGED_BYTES, index);
+            self.write_to_host_at(address, BackendMessage::QuotaExceeded {quota: String::from("staging"), message}).await;
+            return
+        }
+        let count = self.staged.len();
+        self.write_to_host_at(address, BackendMessage::Staged {index, count}).await;
+        // Staged while the presentation already runs, right behind the shown state
+        if self.staged.upcoming().map(|(upcoming, _)| upcoming) == Some(index) {
+            for hint in self.prefetch_hints() {
+                self.write_to_all_clients(hint).await;
+            }
+        }
+    }
+
+    /// Hints for the assets of the staged state likely shown next
+    fn prefetch_hints(&self) -> Vec<BackendMessage> {
+        let assets = self.staged.upcoming().map(|(_, assets)| assets).unwrap_or_default();
+        assets.iter()
+            .map(|asset| BackendMessage::Prefetch {hash: asset.hash.clone(), url: asset.url.clone(), size: asset.size})
+            .collect()
+    }
+
+    /// Shows the staged state as if the host had sent it with 'ChangeState'
+    async fn handle_host_show_staged(&mut self, address: SocketAddr, index: i32) {
+        if !self.is_host(address) {
+            info!("handle_host_show_staged(..): Discarding staged state of host {}, it is not the active host", address);
+            return
+        }
+        match self.staged.show(index) {
+            Some(content) => {
+                self.handle_host_change_state(index, address, content, None).await;
+                for hint in self.prefetch_hints() {
+                    self.write_to_all_clients(hint).await;
+                }
+            }
+            None => {
+                warn!("handle_host_show_staged(..): Host {} requested state {}, it is not staged", address, index);
+                self.write_to_host_at(address, BackendMessage::StageMissing {index}).await;
+            }
+        }
+    }
+
+    /// Initializes the running session from the template: its access policy (for clients joining
+    /// afterwards), rules and polls, the initial state and the timers
+    async fn handle_host_start_from_template(&mut self, address: SocketAddr, name: String) {
+        if !self.is_host(address) {
+            info!("handle_host_start_from_template(..): Discarding template of host {}, it is not the active host", address);
+            return
+        }
+        let template = match self.templates.get(&name) {
+            Some(v) => v.clone(),
+            None => {
+                warn!("handle_host_start_from_template(..): Host {} requested unknown template {}", address, name);
+                let available = self.templates.names();
+                self.write_to_host_at(address, BackendMessage::TemplateUnknown {name, available}).await;
+                return
+            }
+        };
+        info!("handle_host_start_from_template(..): Host {} starts the session from template {}", address, name);
+        if let Some(session) = self.session.as_mut() {
+            session.template = Some(name.clone());
+        }
+        self.rules.set_rules(template.rules);
+        self.restart_rules();
+        for (state_id, rule) in template.polls {
+            self.leaderboard.set_rule(state_id, rule);
+        }
+        if let Some((state_id, content)) = template.state {
+            self.handle_host_change_state(state_id, address, content, None).await;
+        }
+        for (id, duration) in template.timers {
+            self.handle_host_start_timer(address, id, duration).await;
+        }
+        self.write_to_hosts(BackendMessage::TemplateStarted {name}).await;
+    }
+
+    /// Picks among the clients matching the filter and announces the picks to host(s) and clients
+    async fn handle_host_pick_random_clients(&mut self, address: SocketAddr, count: usize, filter: PickFilter) {
+        if !self.is_host(address) {
+            info!("handle_host_pick_random_clients(..): Discarding pick of host {}, it is not the active host", address);
+            return
+        }
+        let state_id = self.current_state_id();
+        let candidates = self.clients.values()
+            .filter(|client| filter.team.is_none() || client.get_team() == filter.team.as_deref())
+            .filter(|client| filter.role.is_none() || client.get_role() == filter.role.as_deref())
+            .filter(|client| match filter.answered {
+                None => true,
+                Some(answered) => state_id.map(|v| client.has_answered(v)).unwrap_or(false) == answered,
+            })
+            .map(|client| (String::from(client.get_name()), client.get_address_as_str()))
+            .collect();
+        let picks = self.lottery.pick(candidates, count, &filter);
+        info!("handle_host_pick_random_clients(..): Picked {} of {} requested client(s)", picks.len(), count);
+
+        let public_picks = picks.iter().map(|pick| PickedClient {name: pick.name.clone(), address: None}).collect();
+        self.write_to_hosts(BackendMessage::ClientsPicked {picks}).await;
+        self.write_to_all_clients(BackendMessage::ClientsPicked {picks: public_picks}).await;
+    }
+
+    /// Answers with members, inputs in the current state and score of every team
+    async fn handle_host_get_team_summary(&mut self, address: SocketAddr) {
+        let teams = self.team_summaries();
+        self.write_to_host_at(address, BackendMessage::TeamSummary {teams}).await;
+    }
+
+    /// Answers with the traffic counters of every connected client, sorted by name
+    /// Only the counters of the requested page are collected
+    async fn handle_host_get_client_list(&mut self, address: SocketAddr, page: Option<Page>) {
+        let mut clients: Vec<&ClientConnection> = self.clients.values().collect();
+        clients.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        let (clients, page) = paginate(clients, page);
+        let clients: Vec<ClientStats> = clients.into_iter().map(ClientConnection::stats).collect();
+        self.write_to_host_at(address, BackendMessage::ClientList {clients, page}).await;
+    }
+
+    /// Answers with the changes of the client list since the version the host holds
+    async fn handle_host_sync_client_list(&mut self, address: SocketAddr, version: Option<u64>) {
+        if !self.is_host(address) {
+            info!("handle_host_sync_client_list(..): Discarding request of host {}, it is not the active host", address);
+            return
+        }
+        let clients = self.clients.values().map(ClientConnection::stats).collect();
+        let diff = self.client_list.sync(version, clients);
+        self.write_to_host_at(address, diff).await;
+    }
+
+    /// Limits the events the host receives one by one to the given classes, unknown ones are ignored
+    async fn handle_host_subscribe(&mut self, address: SocketAddr, events: Vec<String>) {
+        if !self.is_host(address) {
+            info!("handle_host_subscribe(..): Discarding subscription of host {}, it is not the active host", address);
+            return
+        }
+        let mut classes = vec![];
+        for event in &events {
+            match EventClass::parse(event) {
+                Some(class) => classes.push(class),
+                None => info!("handle_host_subscribe(..): Ignoring unknown event class '{}'", event),
+            }
+        }
+        // Events held back so far are summarized under the previous subscription
+        for msg in self.subscriptions.take_summary(self.clients.len()) {
+            self.send_to_hosts(msg).await;
+        }
+        let events: Vec<String> = self.subscriptions.subscribe(&classes).iter().map(|class| String::from(class.as_str())).collect();
+        info!("handle_host_subscribe(..): Host subscribed to {:?}", events);
+        self.write_to_host_at(address, BackendMessage::Subscribed {events}).await;
+    }
+
+    /// Answers with the risk scores of the flagged clients, empty if the heuristics are disabled
+    async fn handle_host_get_integrity(&mut self, address: SocketAddr) {
+        let clients = self.integrity.as_ref().map(Integrity::report).unwrap_or_default();
+        self.write_to_host_at(address, BackendMessage::IntegrityReport {clients}).await;
+    }
+
+    /// Answers with the archive of the running session, it travels over the connection of the
+    /// host only (which is authenticated and may be TLS)
+    async fn handle_host_request_snapshot_export(&mut self, address: SocketAddr) {
+        if !self.is_host(address) {
+            info!("handle_host_request_snapshot_export(..): Discarding request of host {}, it is not the active host", address);
+            return
+        }
+        let archive = self.snapshot_archive(current_timestamp());
+        info!("handle_host_request_snapshot_export(..): Snapshot of the session exported for host {}", address);
+        self.write_to_host_at(address, BackendMessage::SnapshotExport {archive}).await;
+    }
+
+    /// Replaces the access code, new clients need the new one while connected clients stay
+    async fn handle_host_rotate_access_code(&mut self, address: SocketAddr, code: Option<String>, announce: bool) {
+        if !self.is_host(address) {
+            info!("handle_host_rotate_access_code(..): Discarding request of host {}, it is not the active host", address);
+            return
+        }
+        // The code itself is never logged, logs tend to be shared more widely than the code
+        let code = String::from(self.access_code.rotate(code));
+        info!("handle_host_rotate_access_code(..): Host {} rotated the access code (rotation {})", address, self.access_code.get_rotations());
+        if announce {
+            self.write_to_host_at(address, BackendMessage::AccessCode {code}).await;
+        }
+    }
+
+    /// Loads the module of the host, the outcome is answered by 'PluginStatus'
+    async fn handle_host_load_plugin(&mut self, address: SocketAddr, module: String) {
+        if !self.is_host(address) {
+            info!("handle_host_load_plugin(..): Discarding plugin of host {}, it is not the active host", address);
+            return
+        }
+        let status = self.load_plugin(&module);
+        self.write_to_host_at(address, status).await;
+    }
+
+    #[cfg(feature = "wasm_plugins")]
+    fn load_plugin(&mut self, module: &str) -> BackendMessage {
+        let limits = match self.plugin_limits {
+            Some(limits) => limits,
+            None => return BackendMessage::PluginStatus {loaded: false, error: Some(String::from("Plugins are not enabled on this server"))},
+        };
+        match plugins::Plugin::load(module, limits) {
+            Ok(plugin) => {
+                info!("load_plugin(..): Plugin loaded (fuel: {}, memory: {} KiB)", limits.fuel, limits.memory);
+                self.plugin = Some(plugin);
+                BackendMessage::PluginStatus {loaded: true, error: None}
+            }
+            Err(e) => {
+                warn!("load_plugin(..): Loading the plugin failed: {}", e);
+                BackendMessage::PluginStatus {loaded: self.plugin.is_some(), error: Some(e)}
+            }
+        }
+    }
+
+    #[cfg(not(feature = "wasm_plugins"))]
+    fn load_plugin(&mut self, _module: &str) -> BackendMessage {
+        BackendMessage::PluginStatus {loaded: false, error: Some(String::from("This build has no plugin runtime (feature 'wasm_plugins')"))}
+    }
+
+    async fn handle_host_unload_plugin(&mut self, address: SocketAddr) {
+        if !self.is_host(address) {
+            info!("handle_host_unload_plugin(..): Discarding request of host {}, it is not the active host", address);
+            return
+        }
+        #[cfg(feature = "wasm_plugins")]
+        if self.plugin.take().is_some() {
+            info!("handle_host_unload_plugin(..): Host {} unloaded the plugin", address);
+        }
+        self.write_to_host_at(address, BackendMessage::PluginStatus {loaded: false, error: None}).await;
+    }
+
+    /// Hands the input to the plugin and sends its results to host(s) and clients
+    /// A failing plugin is unloaded, the host(s) get the reason
+    #[cfg(feature = "wasm_plugins")]
+    async fn run_plugin(&mut self, input: Value) {
+        let outcome = match self.plugin.as_mut() {
+            Some(plugin) => plugin.on_input(&input),
+            None => return,
+        };
+        match outcome {
+            Ok(results) => {
+                for content in results {
+                    let msg = BackendMessage::PluginResult {content};
+                    self.write_to_hosts(msg.clone()).await;
+                    self.write_to_all_clients(msg).await;
+                }
+            }
+            Err(e) => {
+                warn!("run_plugin(..): Unloading the plugin: {}", e);
+                self.plugin = None;
+                self.write_to_hosts(BackendMessage::PluginStatus {loaded: false, error: Some(e)}).await;
+            }
+        }
+    }
+
+    /// Current state, connected clients and the inputs recorded so far
+    fn snapshot_archive(&self, now: i64) -> Value {
+        let state = match self.state.as_ref() {
+            Some(BackendMessage::ChangeState {state_id, content}) => json!({"state_id": state_id, "content": content}),
+            _ => json!(null),
+        };
+        let clients: Vec<Value> = self.clients.values().map(|client| json!({
+            "name": client.get_name(),
+            "address": client.get_address_as_str(),
+            "team": client.get_team(),
+        })).collect();
+        let inputs: Vec<Value> = self.recorder.all().map(|(address, input)| json!({
+            "name": self.clients.get(&address).map(ClientConnection::get_name),
+            "address": address.to_string(),
+            "state_id": input.state_id,
+            "input": input.input,
+            "client_ts": input.client_ts,
+            "input_id": input.input_id,
+            "server_ts": input.server_ts,
+        })).collect();
+        json!({
+            "exported_at": now,
+            "session": self.session.as_ref().map(|session| json!({"generation": session.generation, "started": session.started, "tenant": session.tenant})),
+            "state": state,
+            "clients": clients,
+            "inputs": inputs,
+        })
+    }
+
+    /// Answers with join count, connected time and connection intervals of every client of the session
+    async fn handle_host_get_attendance(&mut self, address: SocketAddr, page: Option<Page>) {
+        let (clients, page) = paginate(self.attendance.report(current_timestamp()), page);
+        self.write_to_host_at(address, BackendMessage::Attendance {clients, page}).await;
+    }
+
+    fn team_summaries(&self) -> Vec<TeamSummary> {
+        let mut teams: Vec<String> = self.teams.clone();
+        let known = self.clients.values().filter_map(|client| client.get_team()).chain(self.leaderboard.scored_teams());
+        for team in known {
+            if !teams.iter().any(|t| t == team) {
+                teams.push(String::from(team));
+            }
+        }
+
+        let state_id = self.current_state_id();
+        teams.into_iter().map(|team| {
+            let members: Vec<&ClientConnection> = self.clients.values().filter(|client| client.get_team() == Some(team.as_str())).collect();
+            TeamSummary {
+                members: members.len(),
+                answered: members.iter().filter(|client| state_id.map(|v| client.has_answered(v)).unwrap_or(false)).count(),
+                score: self.leaderboard.team_score(&team),
+                team,
+            }
+        }).collect()
+    }
+
+    fn handle_host_set_rules(&mut self, address: SocketAddr, rules: Vec<Rule>) {
+        if !self.is_host(address) {
+            info!("handle_host_set_rules(..): Discarding rules of host {}, it is not the active host", address);
+            return
+        }
+        info!("handle_host_set_rules(..): Host {} set {} rule(s)", address, rules.len());
+        self.rules.set_rules(rules);
+        self.restart_rules();
+    }
+
+    /// Resets the rules for the current state and starts its timers, a rule that changed the
+    /// state itself stays fired
+    /// Every timer triggers the 'RuleTimer' event
+    fn restart_rules(&mut self) {
+        let (generation, timers) = self.rules.restart(self.executing_rule);
+        for (index, delay) in timers {
+            let channel = self.get_bus();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                channel.send(InternalMessage::RuleTimer {generation, index}).await.expect("restart_rules(..): Sending internal message failed");
+            });
+        }
+    }
+
+    async fn handle_rule_timer(&mut self, generation: u64, index: usize) {
+        if let Some((index, name, action)) = self.rules.timer_expired(generation, index) {
+            self.execute_rule(index, name, action).await;
+        }
+    }
+
+    /// Notifies the host about the fired rule and executes its action
+    async fn execute_rule(&mut self, index: usize, name: String, action: Action) {
+        let (host_address, state_id) = match (self.host.as_ref(), self.current_state_id()) {
+            (Some(host), Some(state_id)) => (host.get_address(), state_id),
+            _ => return,
+        };
+        info!("execute_rule(..): Rule '{}' fired in state {}", name, state_id);
+
+        let message = match &action {
+            Action::NotifyHost {message} => Some(message.clone()),
+            Action::Execute(_) => None,
+        };
+        self.write_to_hosts(BackendMessage::RuleTriggered {name, state_id, message}).await;
+
+        match action {
+            Action::NotifyHost {..} => {}
+            Action::Execute(HostMessage::Update {state_id, content}) =>
+                self.handle_host_update(state_id, host_address, content).await,
+            Action::Execute(HostMessage::ChangeState {state_id, content, variants}) => {
+                self.executing_rule = Some(index);
+                self.handle_host_change_state(state_id, host_address, content, variants).await;
+                self.executing_rule = None;
+            }
+            Action::Execute(msg) => warn!("execute_rule(..): Rules can not execute {}", msg),
+        }
+    }
+
+    fn handle_host_mute_client(&mut self, address: SocketAddr, client_id: String, muted: bool) {
+        if !self.is_host(address) {
+            info!("handle_host_mute_client(..): Discarding mute of host {}, it is not the active host", address);
+            return
+        }
+        let client = client_id.parse::<SocketAddr>().ok().and_then(|client| self.clients.get_mut(&client));
+        match client {
+            Some(client) => {
+                info!("handle_host_mute_client(..): Client {} ({}) muted: {}", client.get_name(), client_id, muted);
+                client.set_muted(muted);
+                match muted {
+                    true => self.muted.insert(String::from(client.get_identity())),
+                    false => self.muted.remove(client.get_identity()),
+                };
+            }
+            None => warn!("handle_host_mute_client(..): Host {} tried to mute unknown client {}", address, client_id),
+        }
+    }
+
+    /// Delivers the current state once more to the client, e.g. after it reported a blank screen
+    /// The other clients are not affected
+    async fn handle_host_resend_state(&mut self, address: SocketAddr, client_id: String) {
+        if !self.is_host(address) {
+            info!("handle_host_resend_state(..): Discarding resend of host {}, it is not the active host", address);
+            return
+        }
+        let resent = match client_id.parse::<SocketAddr>() {
+            Ok(client) => self.resend_state(client, false).await,
+            Err(_) => false,
+        };
+        if !resent {
+            warn!("handle_host_resend_state(..): Host {} requested resending the state to unknown client {}", address, client_id);
+        }
+    }
+
+    /// Delivers the current state, the updates within it and the latest sequence number
+    async fn handle_client_request_resync(&mut self, address: SocketAddr) {
+        if !self.resend_state(address, true).await {
+            warn!("handle_client_request_resync(..): Resync requested by unknown client {}", address);
+        }
+    }
+
+    async fn handle_client_state_ack(&mut self, address: SocketAddr, state_id: i32) {
+        if !self.clients.contains_key(&address) {
+            return warn!("handle_client_state_ack(..): State acknowledged by unknown client {}", address)
+        }
+        let counted = self.quorum.as_mut().map(|quorum| quorum.acknowledge(address, state_id)).unwrap_or(false);
+        if counted {
+            self.check_quorum().await;
+        }
+    }
+
+    /// Tells the host once enough clients acknowledged the current state
+    async fn check_quorum(&mut self) {
+        let connected = self.clients.len();
+        if let Some((state_id, percent)) = self.quorum.as_mut().and_then(|quorum| quorum.check(connected)) {
+            info!("check_quorum(..): {}% of the clients acknowledged state {}", percent, state_id);
+            self.write_to_hosts(BackendMessage::QuorumReached {state_id, percent}).await;
+        }
+    }
+
+    /// Sends the current state once more to the client, with 'replay' followed by the buffered
+    /// updates of the state (with their original sequence numbers) and a 'Resynced'
+    /// A reassigned variant is reported to the host(s), returns false if the client is unknown
+    async fn resend_state(&mut self, address: SocketAddr, replay: bool) -> bool {
+        // Taken out while the state is prepared for it, the main handler is the only user of the map
+        let mut client = match self.clients.remove(&address) {
+            Some(v) => v,
+            None => return false,
+        };
+        let mut assignment = None;
+        match self.state_for_client(&client) {
+            Some((state, assigned)) => {
+                info!("resend_state(..): Resending the state to client {} ({})", client.get_name(), address);
+                client.send_message(self.factory.build(state)).await;
+                assignment = assigned;
+            }
+            None => warn!("resend_state(..): No state to resend to client {} ({})", client.get_name(), address),
+        }
+        if replay {
+            let (updates, complete) = self.updates.replay();
+            info!("resend_state(..): Replaying {} update(s) to client {} ({}), complete: {}", updates.len(), client.get_name(), address, complete);
+            for update in updates {
+                client.send_message(update).await;
+            }
+            let seq = self.factory.last_seq();
+            client.send_message(self.factory.build(BackendMessage::Resynced {seq, complete})).await;
+        }
+        self.clients.insert(address, client);
+        if let Some((state_id, assignment)) = assignment {
+            self.write_to_hosts(BackendMessage::VariantsAssigned {state_id, assignments: vec![assignment]}).await;
+        }
+        true
+    }
+
+    /// Answers with the recorded inputs of the client, an unknown client has no inputs
+    async fn handle_host_get_client_inputs(&mut self, address: SocketAddr, client_id: String, state_id: i32, page: Option<Page>) {
+        let inputs = match client_id.parse::<SocketAddr>() {
+            Ok(client) => self.recorder.get(client, state_id),
+            Err(_) => {
+                warn!("handle_host_get_client_inputs(..): Host {} requested inputs of invalid client id {}", address, client_id);
+                vec![]
+            }
+        };
+        let (inputs, page) = paginate(inputs, page);
+        info!("handle_host_get_client_inputs(..): Sending {} input(s) of client {} to host {}", inputs.len(), client_id, address);
+        self.write_to_host_at(address, BackendMessage::ClientInputs {client_id, state_id, inputs, page}).await;
+    }
+
+    async fn handle_host_resync(&mut self, address: SocketAddr, count: u32) {
+        warn!("handle_host_resync(..): Stream of host {} lost synchronization {} time(s)", address, count);
+        self.write_to_host_at(address, BackendMessage::Resync {count}).await;
+    }
+
+    async fn handle_host_retransmit(&mut self, address: SocketAddr, frame: u64) {
+        info!("handle_host_retransmit(..): Requesting retransmission of frame {} from host {}", frame, address);
+        self.write_to_host_at(address, BackendMessage::Retransmit {frame}).await;
+    }
+
+    /// Sends the host feedback on its link and probes the round trip time anew
+    async fn handle_link_feedback_due(&mut self) {
+        if self.host.is_none() {
+            return
+        }
+        let size = self.state.as_ref().map(|state| encode_backend_msg(state.clone()).len()).unwrap_or(0);
+        let feedback = self.link.feedback(size, self.clients.len());
+        if let BackendMessage::LinkQuality {warning: Some(warning), ..} = &feedback {
+            warn!("handle_link_feedback_due(..): {}", warning);
+        }
+        let probe = self.link.probe(current_timestamp());
+        if let Some(host) = self.host.as_mut() {
+            host.send_message(self.factory.build(feedback)).await;
+            host.send_message(self.factory.build(probe)).await;
+        }
+    }
+
+    /// Tells every client the quality of its link and pings it anew
+    async fn handle_client_ping_due(&mut self) {
+        let now = current_timestamp();
+        for client in self.clients.values_mut() {
+            let quality = client.connection_quality();
+            client.send_message(self.factory.build(quality)).await;
+            client.ping(now);
+        }
+    }
+
+    /// Reports the clients whose traffic was dropped since the last report to the host
+    /// Without host the issues add up until one is connected
+    async fn handle_client_issues_due(&mut self) {
+        if self.host.is_none() {
+            return
+        }
+        let clients: Vec<ClientIssues> = self.clients.values()
+            .filter_map(|client| client.get_traffic_stats().take_issues(client.get_name(), client.get_address_as_str()))
+            .collect();
+        if !clients.is_empty() {
+            info!("handle_client_issues_due(..): Reporting dropped traffic of {} client(s) to the host", clients.len());
+            self.write_to_hosts(BackendMessage::ClientIssues {clients}).await;
+        }
+    }
+
+    fn handle_host_link_sample(&mut self, address: SocketAddr, bytes: usize, elapsed: Duration) {
+        if self.is_host(address) {
+            self.link.sample(bytes, elapsed);
+        }
+    }
+
+    fn handle_host_link_probe_ack(&mut self, address: SocketAddr, probe: u64) {
+        if !self.is_host(address) {
+            return
+        }
+        if let Some(rtt) = self.link.probe_acked(probe, current_timestamp()) {
+            info!("handle_host_link_probe_ack(..): Round trip time to host {} is {}ms", address, rtt);
+        }
+    }
+
+    async fn handle_admin_request(&mut self, request: AdminRequest, reply: oneshot::Sender<Value>) {
+        info!("handle_admin_request(..): Admin requested {:?}", request);
+        let response = match request {
+            AdminRequest::DebugQueues => self.debug_queues(),
+            AdminRequest::Health => self.health(),
+            AdminRequest::Migrate {url} => self.migrate_clients(url).await,
+            AdminRequest::Failover => self.failover().await,
+            AdminRequest::Announce {message, duration} => self.announce(message, duration).await,
+            AdminRequest::RejoinLinks => self.issue_rejoin_links().await,
+            AdminRequest::Rooms => self.rooms.to_json(),
+            AdminRequest::ClientCommand {action, min_version} => self.broadcast_client_command(action, min_version).await,
+            AdminRequest::Rebind {ip, ports} => self.rebind_listeners(ip, ports).await,
+            AdminRequest::Upgrade => self.upgrade(),
+            AdminRequest::DebugTls => self.handshakes.report(),
+            AdminRequest::DebugListeners => self.debug_listeners(),
+            AdminRequest::DebugMemory => self.session_memory().report(self.memory_limit.get_limit()),
+            AdminRequest::Retention => return self.enforce_retention(Some(reply)),
+        };
+        if reply.send(response).is_err() {
+            warn!("handle_admin_request(..): Admin connection closed before reply");
+        }
+    }
+
+    /// Bound addresses of all listeners and the number of connections
+    fn health(&self) -> Value {
+        let address = |listener: Option<&Listener>| listener.map(|listener| listener.get_address().to_string());
+        let listeners = self.listeners.as_ref();
+        json!({
+            "status": "ok",
+            "listeners": {
+                "clients": address(listeners.map(|v| &v[0])),
+                "hosts": address(listeners.map(|v| &v[1])),
+                "shadow": address(listeners.map(|v| &v[2])),
+                "admin": address(self.admin_listener.as_ref()),
+                "replication": address(self.replication_listener.as_ref()),
+                "live_view": address(self.live_view_listener.as_ref()),
+                "acme": address(self.acme_listener.as_ref()),
+            },
+            "clients": self.clients.len(),
+            "standby": self.standby,
+            "standbys": self.standbys.len(),
+            "live_viewers": self.live_view.viewers(),
+            "tls_failures": self.handshakes.totals(),
+            "host_tls": self.host_tls.is_some(),
+            "host_secret_required": self.host_secret.is_some(),
+            "host_certificates": self.tls.host_ca.is_some(),
+            "file_descriptors": self.descriptors.report(),
+            "access_code_required": self.access_code.is_required(),
+            "rejoin_links": self.rejoin.pending(),
+            "session_memory": self.session_memory().total(),
+            "host_connected": self.host.is_some(),
+            "rooms": self.rooms.len(),
+            "session": self.session.as_ref().map(|session| session.generation),
+            "session_started": self.session.as_ref().map(|session| self.time_format.human(session.started)),
+        })
+    }
+
+    /// Address, state and restarts of every listener
+    fn debug_listeners(&mut self) -> Value {
+        let listeners: Vec<Value> = self.listeners_mut().into_iter().map(|(role, listener)| json!({
+            "role": role.to_string(),
+            "address": listener.get_address().to_string(),
+            "running": listener.is_running(),
+            "restarts": listener.get_restarts(),
+        })).collect();
+        json!({"listeners": listeners})
+    }
+
+    /// Occupancy of the internal channel and of every client's outbound queue
+    /// Clients are sorted by queue length, the slowest one first
+    fn debug_queues(&self) -> Value {
+        let mut clients: Vec<&ClientConnection> = self.clients.values().collect();
+        clients.sort_by_key(|client| std::cmp::Reverse(client.get_queue_stats().len()));
+
+        let largest = clients.iter()
+            .map(|client| client.get_queue_stats().largest())
+            .max()
+            .unwrap_or(0);
+        let clients: Vec<Value> = clients.iter().map(|client| json!({
+            "name": client.get_name(),
+            "address": client.get_address_as_str(),
+            "queued_messages": client.get_queue_stats().len(),
+            "largest_queued_bytes": client.get_queue_stats().largest(),
+        })).collect();
+
+        json!({
+            "internal_channel": {
+                "size": self.channel_size,
+                "queued": self.bus.queued(),
+            },
+            "clients": clients,
+            "largest_queued_bytes": largest,
+        })
+    }
+
+    /// Sends 'Migrate' to every client and closes its connection afterwards
+    /// The host is notified about every leaving client as usual
+    async fn migrate_clients(&mut self, url: String) -> Value {
+        warn!("migrate_clients(..): Migrating {} client(s) to {}", self.clients.len(), url);
+        let addresses: Vec<SocketAddr> = self.clients.keys().copied().collect();
+        let reconnect = self.reconnect.hint(addresses.len());
+        for address in addresses.iter() {
+            if let Some(client) = self.clients.get_mut(address) {
+                client.send_message(self.factory.build(BackendMessage::Migrate {url: url.clone(), from: self.advertised_url.clone(), reconnect: Some(reconnect)})).await;
+            }
+            self.close_client(*address, networking::DISCONNECT_REASON_MIGRATED, Some(reconnect)).await;
+        }
+        json!({
+            "url": url,
+            "migrated_clients": addresses.len(),
+        })
+    }
+
+    async fn handle_host_client_command(&mut self, address: SocketAddr, action: ClientAction, min_version: Option<String>) {
+        if !self.is_host(address) {
+            info!("handle_host_client_command(..): Discarding client command of host {}, it is not the active host", address);
+            return
+        }
+        self.broadcast_client_command(action, min_version).await;
+    }
+
+    /// Tells every connected client to reload, e.g. after a deployment of the WebApp
+    async fn broadcast_client_command(&mut self, action: ClientAction, min_version: Option<String>) -> Value {
+        warn!("broadcast_client_command(..): Sending '{}' (min version: {:?}) to {} client(s)", action.as_str(), min_version, self.clients.len());
+        self.write_to_all_clients(BackendMessage::ClientCommand {action, min_version: min_version.clone()}).await;
+        json!({
+            "action": action.as_str(),
+            "min_version": min_version,
+            "clients": self.clients.len(),
+        })
+    }
+
+    /// Hands every connected client a one-time link restoring its identity, e.g. before a restart
+    async fn issue_rejoin_links(&mut self) -> Value {
+        let now = current_timestamp();
+        let expires_at = now + self.rejoin_ttl.as_millis() as i64;
+        for client in self.clients.values_mut() {
+            let token = self.rejoin.issue(client.rejoin_grant(expires_at), now, &self.secret_key);
+            let url = rejoin_url(self.advertised_url.as_deref(), &token);
+            client.send_message(self.factory.build(BackendMessage::RejoinLink {url, expires_at})).await;
+        }
+        warn!("issue_rejoin_links(..): Sent rejoin links to {} client(s), valid until {}", self.clients.len(), self.time_format.human(expires_at));
+        json!({
+            "clients": self.clients.len(),
+            "expires_at": expires_at,
+        })
+    }
+
+    /// Replaces (or clears with None) the operator announcement and sends it to everybody
+    async fn announce(&mut self, message: Option<String>, duration: Option<Duration>) -> Value {
+        let msg = match message {
+            Some(message) => {
+                let expires_at = duration.map(|duration| current_timestamp() + duration.as_millis() as i64);
+                warn!("announce(..): Announcing '{}' (expires at {:?}) to {} client(s)", message, expires_at, self.clients.len());
+                self.announcement = Some((message.clone(), expires_at));
+                BackendMessage::Announcement {message, expires_at}
+            }
+            None => {
+                warn!("announce(..): Clearing announcement {:?}", self.announcement);
+                self.announcement = None;
+                BackendMessage::AnnouncementCleared
+            }
+        };
+        self.write_to_hosts(msg.clone()).await;
+        self.write_to_all_clients(msg).await;
+        json!({
+            "announcement": self.announcement.as_ref().map(|(message, _)| message),
+            "expires_at": self.announcement.as_ref().and_then(|(_, expires_at)| *expires_at),
+            "clients": self.clients.len(),
+        })
+    }
+
+    /// The announcement for late joiners, unless it expired
+    fn current_announcement(&self) -> Option<BackendMessage> {
+        let now = current_timestamp();
+        self.announcement.as_ref()
+            .filter(|(_, expires_at)| expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|(message, expires_at)| BackendMessage::Announcement {message: message.clone(), expires_at: *expires_at})
+    }
+
+    /// Warns host(s) and clients that the session is about to end
+    async fn handle_session_warning(&mut self, generation: u64) {
+        let ends_at = match self.session.as_ref() {
+            Some(session) if session.generation == generation => session.ends_at.unwrap_or_else(current_timestamp),
+            _ => return,
+        };
+        warn!("handle_session_warning(..): Session {} ends at {}", generation, ends_at);
+        let msg = BackendMessage::SessionEnding {ends_at};
+        self.write_to_hosts(msg.clone()).await;
+        self.write_to_all_clients(msg).await;
+    }
+
+    /// Exports the results to the host(s), disconnects everybody and drops all data of the session
+    async fn handle_session_expired(&mut self, generation: u64) {
+        let started = match self.session.as_ref() {
+            Some(session) if session.generation == generation => session.started,
+            _ => return,
+        };
+        let standings = self.leaderboard.standings(usize::MAX);
+        let teams = self.team_summaries();
+        let attendance = self.attendance.report(current_timestamp());
+        let participants = attendance.len();
+        let results = BackendMessage::SessionResults {standings, teams, attendance};
+        warn!("handle_session_expired(..): Session {} (started at {}) ended, tearing it down\nResults: {}", generation, started, results);
+        self.write_to_hosts(results).await;
+
+        let reason = networking::DISCONNECT_REASON_SESSION_ENDED;
+        let now = current_timestamp();
+        for (address, client) in self.clients.drain() {
+            self.usage.client_disconnected(address, client.get_bytes_sent(), now);
+            client.close(reason, None).await;
+        }
+        self.export_usage(true);
+        self.send_digest(participants);
+        if let Some(host) = self.host.take() {
+            host.close(reason, None).await;
+        }
+        if let Some(shadow) = self.shadow.take() {
+            shadow.close(reason, None).await;
+        }
+
+        self.session = None;
+        self.state = None;
+        self.factory.session_ended();
+        self.updates.state_changed();
+        self.staged = Default::default();
+        self.pause = None;
+        self.live_view.set_public(false);
+        self.departed.clear();
+        self.muted.clear();
+        self.recorder = Default::default();
+        self.attendance = Default::default();
+        if let Some(integrity) = self.integrity.as_mut() {
+            *integrity = Default::default();
+        }
+        self.leaderboard = Default::default();
+        self.lottery = Default::default();
+        self.timers.clear();
+        self.schedules.clear();
+        self.variants.state_changed(None);
+        self.rules.set_rules(vec![]);
+        self.restart_rules();
+    }
+
+    async fn refuse_client_on_standby(&mut self, mut client: ClientConnection) {
+        info!("refuse_client_on_standby(..): Rejecting client {}, the server is on standby", client.get_address_as_str());
+        client.send_message(self.factory.build(BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_STANDBY)})).await;
+        client.close(networking::DISCONNECT_REASON_LOGIN_REJECTED, None).await;
+    }
+
+    async fn refuse_host_on_standby(&mut self, stream: HostStream, address: SocketAddr) {
+        info!("refuse_host_on_standby(..): Rejecting host {}, the server is on standby", address);
+        HostConnection::new(address, stream.write, self.get_bus(), self.socket_config.send_timeout).close(REJECT_REASON_STANDBY, None).await;
+    }
+
+    /// Sends the current snapshot to every standby, standbys whose connection is gone are dropped
+    fn handle_replication_due(&mut self) {
+        if self.standbys.is_empty() || self.standby {
+            return
+        }
+        let line = ReplicationMessage::Snapshot(Box::new(self.snapshot())).encode(&self.secret_key);
+        self.standbys.retain(|_, (_, stream)| stream.send(line.clone()).is_ok());
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let state = match self.state.as_ref() {
+            Some(BackendMessage::ChangeState {state_id, content}) => Some((*state_id, content.clone())),
+            _ => None,
+        };
+        let clients = self.clients.values()
+            .map(|client| ReplicatedClient {
+                name: String::from(client.get_name()),
+                address: client.get_address_as_str(),
+                team: client.get_team().map(String::from),
+            })
+            .collect();
+        let access_code = self.access_code.get_code().map(String::from);
+        Snapshot {session: self.session.clone(), state, clients, announcement: self.announcement.clone(), access_code, rejoin: self.rejoin.clone()}
+    }
+
+    fn handle_standby_connected(&mut self, address: SocketAddr, url: Option<String>, stream: UnboundedSender<String>) {
+        info!("handle_standby_connected(..): Replicating to standby {} ({:?})", address, url);
+        self.standbys.insert(address, (url, stream));
+    }
+
+    fn handle_standby_disconnected(&mut self, address: SocketAddr) {
+        if self.standbys.remove(&address).is_some() {
+            warn!("handle_standby_disconnected(..): Standby {} is gone, {} standby(s) left", address, self.standbys.len());
+        }
+    }
+
+    /// Continues the state and the announcement of the primary, the session is taken over later
+    fn handle_replicated(&mut self, snapshot: Snapshot) {
+        if !self.standby {
+            return
+        }
+        self.state = snapshot.state.clone().map(|(state_id, content)| BackendMessage::ChangeState {state_id, content});
+        self.updates.state_changed();
+        self.announcement = snapshot.announcement.clone();
+        self.restore_access_code(&snapshot);
+        self.rejoin = snapshot.rejoin.clone();
+        self.replicated = Some(snapshot);
+    }
+
+    /// Becomes the active server, continuing the session of the primary with its deadline
+    fn handle_takeover(&mut self, reason: String) {
+        if !self.standby {
+            return
+        }
+        self.standby = false;
+        let snapshot = self.replicated.take().unwrap_or_default();
+        warn!("handle_takeover(..): Taking over from the primary, {} client(s) were connected to it\nReason: {}", snapshot.clients.len(), reason);
+        self.continue_session(snapshot);
+    }
+
+    /// Continues the session of the snapshot with its deadline
+    fn continue_session(&mut self, snapshot: Snapshot) {
+        if let Some(session) = snapshot.session {
+            self.session_generation = self.session_generation.max(session.generation);
+            self.schedule_session_end(&session);
+            self.usage.reset(std::iter::empty(), current_timestamp());
+            self.session = Some(session);
+        }
+        self.factory.restore(self.session.as_ref().map(|session| session.generation), self.current_state_id());
+    }
+
+    /// Continues the state and the session of the process this one replaces
+    fn continue_upgrade(&mut self, snapshot: Snapshot) {
+        warn!("continue_upgrade(..): Taking over from the previous process, {} client(s) were connected to it", snapshot.clients.len());
+        self.state = snapshot.state.clone().map(|(state_id, content)| BackendMessage::ChangeState {state_id, content});
+        self.updates.state_changed();
+        self.announcement = snapshot.announcement.clone();
+        self.restore_access_code(&snapshot);
+        self.rejoin = snapshot.rejoin.clone();
+        self.continue_session(snapshot);
+    }
+
+    /// Takes the access code of the snapshot, the own one is kept if the snapshot has none (e.g.
+    /// of a version without access codes)
+    fn restore_access_code(&mut self, snapshot: &Snapshot) {
+        if let Some(code) = snapshot.access_code.as_ref() {
+            if self.access_code.get_code() != Some(code.as_str()) {
+                self.access_code = AccessCode::new(Some(code.clone()));
+            }
+        }
+    }
+
+    /// Starts a new process of the running binary taking over the listeners and the session
+    /// The handoff follows with the 'UpgradeReady' event
+    fn upgrade(&mut self) -> Value {
+        if self.standby || self.upgrading {
+            return json!({"error": "Server is on standby or being upgraded already"})
+        }
+        let binary = match std::env::current_exe() {
+            Ok(v) => v,
+            Err(e) => return json!({"error": format!("Locating the running binary failed: {}", e)}),
+        };
+        let listeners: Vec<(ListenerRole, RawFd)> = self.listeners_mut().into_iter()
+            .map(|(role, listener)| (role, listener.get_fd()))
+            .collect();
+        let (child, state) = match spawn_upgrade(&binary, &listeners, &self.snapshot(), &self.secret_key) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("upgrade(..): Starting the new process failed\nError: {}", e);
+                return json!({"error": e})
+            }
+        };
+        let pid = child.id();
+        warn!("upgrade(..): Started {} (pid {:?}) to take over {} listener(s)", binary.display(), pid, listeners.len());
+        self.upgrading = true;
+        let channel = self.get_bus();
+        tokio::spawn(async move {
+            let result = wait_until_ready(child, state).await;
+            channel.send(InternalMessage::UpgradeReady {result}).await.expect("upgrade(..): Sending internal message failed");
+        });
+        json!({
+            "binary": binary.display().to_string(),
+            "pid": pid,
+            "listeners": listeners.len(),
+        })
+    }
+
+    /// Hands over to the new process once it accepts: stops accepting, moves the clients, closes
+    /// the hosts, ends the rooms and exits shortly after
+    async fn handle_upgrade_ready(&mut self, result: Result<(), String>) {
+        self.upgrading = false;
+        if let Err(e) = result {
+            error!("handle_upgrade_ready(..): Upgrade failed, continuing in this process\nError: {}", e);
+            return
+        }
+        warn!("handle_upgrade_ready(..): New process is ready, handing over {} client(s)", self.clients.len());
+        let listeners = self.listeners.take().into_iter().flatten()
+            .chain(self.admin_listener.take())
+            .chain(self.replication_listener.take())
+            .chain(self.live_view_listener.take())
+            .chain(self.acme_listener.take());
+        for listener in listeners {
+            listener.stop();
+        }
+        match self.advertised_url.clone() {
+            Some(url) => {
+                self.migrate_clients(url).await;
+            }
+            None => {
+                self.broadcast_client_command(ClientAction::Reload, None).await;
+                let addresses: Vec<SocketAddr> = self.clients.keys().copied().collect();
+                let reconnect = self.reconnect.hint(addresses.len());
+                for address in addresses {
+                    self.close_client(address, networking::DISCONNECT_REASON_UPGRADE, Some(reconnect)).await;
+                }
+            }
+        }
+        // The hosts reconnect to the new process like the clients
+        let reconnect = self.reconnect.hint(1);
+        if let Some(host) = self.host.take() {
+            host.close(networking::DISCONNECT_REASON_UPGRADE, Some(reconnect)).await;
+        }
+        if let Some(shadow) = self.shadow.take() {
+            shadow.close(networking::DISCONNECT_REASON_UPGRADE, Some(reconnect)).await;
+        }
+        // Rooms are not part of the snapshot, they end with this process
+        self.shutdown_rooms(networking::DISCONNECT_REASON_UPGRADE).await;
+        tokio::spawn(async {
+            tokio::time::sleep(UPGRADE_EXIT_DELAY).await;
+            warn!("handle_upgrade_ready(..): Handed over, exiting");
+            std::process::exit(0);
+        });
+    }
+
+    /// Planned failover: the first standby reachable by clients takes over and the clients are
+    /// migrated to it
+    /// Rooms are not replicated, they end
+    async fn failover(&mut self) -> Value {
+        let (address, url) = match self.standbys.iter().find_map(|(address, (url, _))| url.clone().map(|url| (*address, url))) {
+            None => return json!({"error": "No standby with an advertised url connected"}),
+            Some(v) => v,
+        };
+        warn!("failover(..): Handing over to standby {} ({})", address, url);
+        let takeover = ReplicationMessage::Takeover.encode(&self.secret_key);
+        if let Some((_, stream)) = self.standbys.remove(&address) {
+            if stream.send(takeover).is_err() {
+                return json!({"error": format!("Standby {} disconnected", address)})
+            }
+        }
+        let mut response = self.migrate_clients(url).await;
+        self.shutdown_rooms(networking::DISCONNECT_REASON_SESSION_ENDED).await;
+        response["standby"] = json!(address.to_string());
+        response
+    }
+
+    /// Tells the host(s) when the public addresses of the server changed
+    async fn handle_advertised_resolved(&mut self, addresses: Vec<IpAddr>) {
+        let msg = match self.advertised_host.as_mut() {
+            Some((host, known)) if *known != addresses => {
+                info!("handle_advertised_resolved(..): Advertised host {} resolved to {:?}, was {:?}", host, addresses, known);
+                *known = addresses.clone();
+                BackendMessage::AdvertisedAddress {host: host.clone(), addresses}
+            }
+            _ => return,
+        };
+        self.write_to_hosts(msg).await;
+    }
+
+    fn handle_usage_report_due(&mut self) {
+        if self.session.is_some() {
+            self.export_usage(false);
+        }
+    }
+
+    /// Prunes the stored data past its retention age in the background
+    /// The report of the deletions is logged and sent to 'reply', if given
+    fn enforce_retention(&self, reply: Option<oneshot::Sender<Value>>) {
+        let policy = self.retention.clone();
+        let usage_export = self.usage_export.clone();
+        tokio::spawn(async move {
+            let report = match retention::enforce(&policy, usage_export.as_ref(), current_timestamp()).await {
+                Ok(report) => {
+                    info!("enforce_retention(..): Retention enforced\nReport: {}", report);
+                    report
+                }
+                Err(e) => {
+                    warn!("enforce_retention(..): Enforcing retention failed!\nError: {}", e);
+                    json!({"error": e})
+                }
+            };
+            if let Some(reply) = reply {
+                if reply.send(report).is_err() {
+                    warn!("enforce_retention(..): Admin connection closed before reply");
+                }
+            }
+        });
+    }
+
+    /// Exports the usage of the running session in the background
+    fn export_usage(&self, final_report: bool) {
+        let (session, target) = match (self.session.as_ref(), self.usage_export.clone()) {
+            (Some(session), Some(target)) => (session, target),
+            _ => return,
+        };
+        let pending_bytes = self.clients.values().map(ClientConnection::get_bytes_sent).sum();
+        let report = self.usage.report(session.tenant.clone(), session.generation, session.started, pending_bytes, current_timestamp(), final_report);
+        info!("export_usage(..): Usage of session {}: {}", session.generation, report.to_json());
+        let time_format = self.time_format;
+        tokio::spawn(async move {
+            if let Err(e) = target.export(&report, &time_format).await {
+                warn!("export_usage(..): Exporting usage failed!\nError: {}", e);
+            }
+        });
+    }
+
+    /// Sends the summary of the ending session to the digest target, if configured
+    fn send_digest(&self, participants: usize) {
+        let (session, target) = match (self.session.as_ref(), self.digest.clone()) {
+            (Some(session), Some(target)) => (session, target),
+            _ => return,
+        };
+        let digest = SessionDigest {
+            tenant: session.tenant.clone(),
+            session: session.generation,
+            started: session.started,
+            ended: current_timestamp(),
+            participants,
+            states: self.counters.states,
+            inputs: self.counters.inputs,
+            export_link: self.digest_link.as_ref().map(|link| link.replace("{session}", &session.generation.to_string())),
+            time_format: self.time_format,
+        };
+        info!("send_digest(..): Digest of session {}: {}", session.generation, digest.to_json());
+        let from = self.digest_from.clone();
+        tokio::spawn(async move {
+            if let Err(e) = target.send(&digest, &from).await {
+                warn!("send_digest(..): Sending the digest failed!\nError: {}", e);
+            }
+        });
+    }
+
+    /// Moves the client, host and shadow listener to the given ip and ports (None keeps the current)
+    /// All new listeners are bound before any old one is stopped, so a failing bind changes nothing.
+    /// Established connections are not affected and stay open until they close.
+    async fn rebind_listeners(&mut self, ip: Option<IpAddr>, ports: [Option<u16>; 3]) -> Value {
+        let current = match self.listeners.as_ref() {
+            None => return json!({"error": "Listeners are not running"}),
+            Some(v) => v,
+        };
+        let targets: Vec<SocketAddr> = current.iter().zip(ports)
+            .map(|(listener, port)| {
+                let address = listener.get_address();
+                SocketAddr::new(ip.unwrap_or(address.ip()), port.unwrap_or(address.port()))
+            })
+            .collect();
+        let changed: Vec<bool> = current.iter().zip(targets.iter()).map(|(listener, target)| listener.get_address() != *target).collect();
+
+        let mut bound = Vec::new();
+        for (index, target) in targets.iter().enumerate() {
+            if !changed[index] {
+                continue
+            }
+            let listener = match index {
+                0 => self.create_client_listener(*target).await,
+                _ => create_host_listener(self.get_bus(), self.socket_config.clone(), self.host_tls.clone(), *target, index == 2).await,
+            };
+            match listener {
+                Ok(v) => bound.push((index, v)),
+                Err(e) => {
+                    warn!("rebind_listeners(..): Binding {} failed, keeping the current listeners\nError: {}", target, e);
+                    bound.into_iter().for_each(|(_, listener)| listener.stop());
+                    return json!({"error": format!("Binding {} failed: {}", target, e)})
+                }
+            }
+        }
+
+        let listeners = self.listeners.as_mut().expect("rebind_listeners(..): Listeners should be running");
+        let rebound = bound.len();
+        for (index, listener) in bound {
+            std::mem::replace(&mut listeners[index], listener).stop();
+        }
+        warn!("rebind_listeners(..): Rebound {} listener(s), clients on {}, hosts on {}, shadow hosts on {}",
+            rebound, listeners[0].get_address(), listeners[1].get_address(), listeners[2].get_address());
+        json!({
+            "clients": listeners[0].get_address().to_string(),
+            "hosts": listeners[1].get_address().to_string(),
+            "shadow": listeners[2].get_address().to_string(),
+            "rebound": rebound,
+        })
+    }
+
+    /// Id of the state last set by the host
+    fn current_state_id(&self) -> Option<i32> {
+        match self.state.as_ref() {
+            Some(BackendMessage::ChangeState {state_id, ..}) => Some(*state_id),
+            _ => None,
+        }
+    }
+
+    fn is_host(&self, address: SocketAddr) -> bool {
+        self.host.as_ref().map(|host| host.get_address()) == Some(address)
+    }
+
+    fn is_shadow(&self, address: SocketAddr) -> bool {
+        self.shadow.as_ref().map(|shadow| shadow.get_address()) == Some(address)
+    }
+
+    /// Sends the message to the host and the shadow host (if connected), unless the host opted out
+    /// of its class
+    async fn write_to_hosts(&mut self, msg: BackendMessage) {
+        if let Some(msg) = self.subscriptions.filter(msg) {
+            self.send_to_hosts(msg).await;
+        }
+    }
+
+    /// Sends the message to the host and the shadow host (if connected)
+    async fn send_to_hosts(&mut self, msg: BackendMessage) {
+        let msg = self.factory.build(msg);
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.send_message(msg.clone()).await;
+        }
+        if let Some(host) = self.host.as_mut() {
+            host.send_message(msg).await;
+        }
+    }
+
+    /// Sends the message to the host or shadow host connected from the given address
+    async fn write_to_host_at(&mut self, address: SocketAddr, msg: BackendMessage) {
+        let msg = self.factory.build(msg);
+        if let Some(host) = self.host.as_mut().filter(|host| host.get_address() == address) {
+            host.send_message(msg).await;
+        } else if let Some(shadow) = self.shadow.as_mut().filter(|shadow| shadow.get_address() == address) {
+            shadow.send_message(msg).await;
+        }
+    }
+
+    async fn write_to_shadow(&mut self, msg: BackendMessage) {
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.send_message(self.factory.build(msg)).await;
+        }
+    }
+
+    async fn write_to_all_clients(&mut self, msg: BackendMessage) {
+        let mut msg = EncodedMessage::new(self.factory.build(msg));
+        for (_, client) in self.clients.iter_mut() {
+            client.send_encoded(&mut msg).await;
+        }
+    }
+}
+
+
+pub const CHANNEL_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum InternalMessage {
+    ClientConnected{read: WsReadHalve, client: ClientConnection},
+    ClientCloseConnection {address: SocketAddr, reason: &'static str},
+    ClientGraceExpired {identity: String, departed_at: i64},
+    /// 'guest_until' identifies the connection the TTL was started for
+    GuestExpired {address: SocketAddr, guest_until: i64},
+    SessionWarning {generation: u64},
+    UsageReportDue,
+    RetentionDue,
+    LinkFeedbackDue,
+    ClientPingDue,
+    /// A frame of 'bytes' took 'elapsed' to arrive from the host
+    HostLinkSample {address: SocketAddr, bytes: usize, elapsed: Duration},
+    HostLinkProbeAck {address: SocketAddr, probe: u64},
+    AdvertisedResolved {addresses: Vec<IpAddr>},
+    SessionExpired {generation: u64},
+    HostConnected{stream: HostStream, address: SocketAddr, shadow: bool},
+    /// 'decision' of the AuthProvider about the token the host presented
+    HostLogin {address: SocketAddr, api_key: Option<String>, decision: AuthDecision, version: Option<u32>, capabilities: Option<Vec<String>>, room: Option<RoomRequest>},
+    /// The host logged in for the room handling this event, it was authenticated by the server
+    RoomHostJoined {host: HostConnection, api_key: Option<String>, version: Option<u32>, capabilities: Option<Vec<String>>},
+    /// The room of the session id closed, its main handler is gone
+    RoomClosed {session: String},
+    /// The server ends the room handling this event, its clients and hosts are closed with 'reason'
+    RoomShutdown {reason: &'static str},
+    HostCloseConnection {address: SocketAddr, reason: &'static str},
+    ClientInput{state_id: i32, address: SocketAddr, content: String, client_ts: Option<i64>, input_id: Option<String>, server_ts: i64},
+    HostUpdate{state_id: i32, address : SocketAddr, content: String},
+    HostChangeState{state_id: i32, address : SocketAddr, content: String, variants: Option<Variants>},
+    HostMuteClient{address: SocketAddr, client_id: String, muted: bool},
+    HostResendState{address: SocketAddr, client_id: String},
+    ClientRequestResync{address: SocketAddr},
+    ClientStateAck{address: SocketAddr, state_id: i32},
+    HostSetRules{address: SocketAddr, rules: Vec<Rule>},
+    HostSetScoring{address: SocketAddr, state_id: i32, rule: ScoringRule},
+    HostGetTeamSummary{address: SocketAddr},
+    HostGetAttendance{address: SocketAddr, page: Option<Page>},
+    HostGetIntegrity{address: SocketAddr},
+    HostRequestSnapshotExport{address: SocketAddr},
+    HostRotateAccessCode{address: SocketAddr, code: Option<String>, announce: bool},
+    HostLoadPlugin{address: SocketAddr, module: String},
+    HostUnloadPlugin{address: SocketAddr},
+    HostGetClientList{address: SocketAddr, page: Option<Page>},
+    HostSyncClientList{address: SocketAddr, version: Option<u64>},
+    HostSubscribe{address: SocketAddr, events: Vec<String>},
+    HostClientCommand{address: SocketAddr, action: ClientAction, min_version: Option<String>},
+    HostPickRandomClients{address: SocketAddr, count: usize, filter: PickFilter},
+    HostStartFromTemplate{address: SocketAddr, name: String},
+    NoHostTimeout{absence: u64},
+    UpgradeReady{result: Result<(), String>},
+    ClientIssuesDue,
+    ListenerCheckDue,
+    DescriptorCheckDue,
+    EventSummaryDue,
+    MemoryCheckDue,
+    HostProtocolDetected{address: SocketAddr, protocol: HostProtocol},
+    HostSetPublic{address: SocketAddr, public: bool},
+    LiveViewState{reply: oneshot::Sender<Option<Vec<String>>>},
+    LiveViewerConnected{address: SocketAddr, reply: oneshot::Sender<Option<mpsc::Receiver<String>>>},
+    HostPauseSession{address: SocketAddr, message: Option<String>},
+    HostResumeSession{address: SocketAddr},
+    HostStageState{address: SocketAddr, index: i32, content: String, assets: Vec<PrefetchAsset>},
+    HostShowStaged{address: SocketAddr, index: i32},
+    HostStartTimer{address: SocketAddr, id: String, duration: i64},
+    HostCancelTimer{address: SocketAddr, id: String},
+    TimerTick{id: String, generation: u64},
+    HostScheduleMessage{address: SocketAddr, id: String, at: Option<i64>, interval: Option<u64>, message: Box<HostMessage>},
+    HostCancelScheduledMessage{address: SocketAddr, id: String},
+    HostListScheduledMessages{address: SocketAddr},
+    ScheduleDue{id: String, generation: u64},
+    HostShowLeaderboard{address: SocketAddr, count: usize},
+    RuleTimer{generation: u64, index: usize},
+    HostGetClientInputs{address: SocketAddr, client_id: String, state_id: i32, page: Option<Page>},
+    HostResync{address: SocketAddr, count: u32},
+    HostRetransmit{address: SocketAddr, frame: u64},
+    AdminRequest{request: AdminRequest, reply: oneshot::Sender<Value>},
+    ReplicationDue,
+    StandbyConnected {address: SocketAddr, url: Option<String>, sender: UnboundedSender<String>},
+    StandbyDisconnected {address: SocketAddr},
+    Replicated {snapshot: Snapshot},
+    Takeover {reason: String},
+}
+
+impl InternalMessage {
+    /// Host or client connection the event is about, None for events of the server itself and
+    /// for new connections
+    fn connection(&self) -> Option<SocketAddr> {
+        match self {
+            InternalMessage::ClientCloseConnection {address, ..}
+            | InternalMessage::GuestExpired {address, ..}
+            | InternalMessage::HostLinkSample {address, ..}
+            | InternalMessage::HostLinkProbeAck {address, ..}
+            | InternalMessage::HostLogin {address, ..}
+            | InternalMessage::HostCloseConnection {address, ..}
+            | InternalMessage::ClientInput {address, ..}
+            | InternalMessage::HostUpdate {address, ..}
+            | InternalMessage::HostChangeState {address, ..}
+            | InternalMessage::HostMuteClient {address, ..}
+            | InternalMessage::HostResendState {address, ..}
+            | InternalMessage::ClientRequestResync {address}
+            | InternalMessage::ClientStateAck {address, ..}
+            | InternalMessage::HostSetRules {address, ..}
+            | InternalMessage::HostSetScoring {address, ..}
+            | InternalMessage::HostGetTeamSummary {address}
+            | InternalMessage::HostGetAttendance {address, ..}
+            | InternalMessage::HostGetIntegrity {address}
+            | InternalMessage::HostRequestSnapshotExport {address}
+            | InternalMessage::HostRotateAccessCode {address, ..}
+            | InternalMessage::HostLoadPlugin {address, ..}
+            | InternalMessage::HostUnloadPlugin {address}
+            | InternalMessage::HostGetClientList {address, ..}
+            | InternalMessage::HostSyncClientList {address, ..}
+            | InternalMessage::HostSubscribe {address, ..}
+            | InternalMessage::HostClientCommand {address, ..}
+            | InternalMessage::HostPickRandomClients {address, ..}
+            | InternalMessage::HostStartFromTemplate {address, ..}
+            | InternalMessage::HostProtocolDetected {address, ..}
+            | InternalMessage::HostSetPublic {address, ..}
+            | InternalMessage::HostPauseSession {address, ..}
+            | InternalMessage::HostResumeSession {address}
+            | InternalMessage::HostStageState {address, ..}
+            | InternalMessage::HostShowStaged {address, ..}
+            | InternalMessage::HostStartTimer {address, ..}
+            | InternalMessage::HostCancelTimer {address, ..}
+            | InternalMessage::HostScheduleMessage {address, ..}
+            | InternalMessage::HostCancelScheduledMessage {address, ..}
+            | InternalMessage::HostListScheduledMessages {address}
+            | InternalMessage::HostShowLeaderboard {address, ..}
+            | InternalMessage::HostGetClientInputs {address, ..}
+            | InternalMessage::HostResync {address, ..}
+            | InternalMessage::HostRetransmit {address, ..} => Some(*address),
+            _ => None,
+        }
+    }
 }
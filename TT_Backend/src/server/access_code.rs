@@ -0,0 +1,86 @@
+//!
+//! Access code of the session.
+//! With an access code (TT_BACKEND_ACCESS_CODE, or set by the host) clients have to give it as
+//! 'code' of their 'ClientLogin' to join. Once a code leaks (e.g. it was shown on a public stream)
+//! the host replaces it by 'RotateAccessCode': new joins need the new code right away, connected
+//! clients stay. A code the host does not pick is generated, the new code is sent back to the
+//! rotating host ('AccessCode') unless it asks not to, it never reaches any client.
+//! Codes are compared case insensitively, surrounding whitespace is ignored.
+//! The code may also be part of the websocket path ('/session/<code>', e.g. in a link or a QR
+//! code), it is taken if the login gives none. A reverse proxy routing the paths to one backend
+//! per session thereby serves several sessions behind one address.
+//!
+
+use rand::Rng;
+use subtle::ConstantTimeEq;
+
+pub const REJECT_REASON_ACCESS_CODE: &str = "Invalid access code";
+/// Prefix of websocket paths carrying the access code
+pub const SESSION_PATH_PREFIX: &str = "/session/";
+/// Length of generated codes
+pub const ACCESS_CODE_LENGTH: usize = 6;
+/// Characters of generated codes, without the easily confused 0/O and 1/I/L, codes are read from
+/// a screen and typed on phones
+const ACCESS_CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+#[derive(Debug, Default)]
+pub struct AccessCode {
+    /// Code joining clients have to give, everybody may join if None
+    code: Option<String>,
+    /// Rotations since the start
+    rotations: u32,
+}
+
+impl AccessCode {
+    pub fn new(code: Option<String>) -> Self {
+        AccessCode {code: code.map(|code| normalize(&code)).filter(|code| !code.is_empty()), rotations: 0}
+    }
+
+    pub fn get_code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.code.is_some()
+    }
+
+    pub fn get_rotations(&self) -> u32 {
+        self.rotations
+    }
+
+    /// Whether a client giving 'given' may join
+    pub fn admits(&self, given: Option<&str>) -> bool {
+        match (self.code.as_deref(), given) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(code), Some(given)) => bool::from(code.as_bytes().ct_eq(normalize(given).as_bytes())),
+        }
+    }
+
+    /// Replaces the code by 'code', or by a generated one if None or blank, returns the new code
+    pub fn rotate(&mut self, code: Option<String>) -> &str {
+        let code = code.map(|code| normalize(&code)).filter(|code| !code.is_empty()).unwrap_or_else(generate);
+        self.rotations += 1;
+        self.code.insert(code)
+    }
+}
+
+fn normalize(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+fn generate() -> String {
+    let mut rng = rand::thread_rng();
+    (0..ACCESS_CODE_LENGTH)
+        .map(|_| ACCESS_CODE_ALPHABET[rng.gen_range(0..ACCESS_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Access code given by the websocket path '/session/<code>', None for other paths
+pub fn from_path(path: &str) -> Option<String> {
+    let code = path.strip_prefix(SESSION_PATH_PREFIX)?.trim_end_matches('/');
+    if code.is_empty() || code.contains('/') {
+        return None
+    }
+    Some(String::from(code))
+}
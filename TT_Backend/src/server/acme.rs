@@ -0,0 +1,374 @@
+//!
+//! Certificates provisioned by ACME (RFC 8555, e.g. Let's Encrypt), instead of an external certbot.
+//! With TT_BACKEND_ACME_DOMAIN set, the server orders a certificate for the domain, proves control
+//! over it with the HTTP-01 challenge (answered on TT_BACKEND_ACME_HTTP_PORT, the CA connects to
+//! port 80 of the domain) and writes certificate and key where the client port reads them
+//! (TT_BACKEND_CERT_PATH and TT_BACKEND_KEY_PATH), the certificate reload picks them up. Until the
+//! first certificate is issued clients are refused.
+//! Certificates are renewed ACME_RENEW_BEFORE_DAYS before they expire, failed orders are retried
+//! after ACME_RETRY_DELAY. The account key is created on first use and kept in
+//! TT_BACKEND_ACME_ACCOUNT_KEY, TT_BACKEND_ACME_EMAIL is the contact of the account and
+//! TT_BACKEND_ACME_DIRECTORY the CA (e.g. the Let's Encrypt staging directory for testing).
+//!
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use log::{error, info, warn};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509, X509NameBuilder, X509ReqBuilder};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use crate::server::admin::read_request_head;
+use crate::server::certificates;
+use crate::server::config::TlsConfig;
+use crate::server::http_client::{request, Response, Url};
+use crate::server::networking::{accept_exhausted, bind_tcp, Listener};
+
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const DEFAULT_ACME_HTTP_PORT: u16 = 80;
+pub const DEFAULT_ACME_ACCOUNT_KEY: &str = "res/cert/acme_account.pem";
+/// Remaining validity at which the certificate is renewed
+const ACME_RENEW_BEFORE_DAYS: i32 = 30;
+/// Time between checks of the remaining validity
+const ACME_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+const ACME_RETRY_DELAY: Duration = Duration::from_secs(3600);
+const ACME_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time between checks of pending authorizations and orders, and the checks done at most
+const ACME_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const ACME_POLL_ATTEMPTS: usize = 30;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    /// Domain the certificate is issued for
+    pub domain: String,
+    /// Contact of the account, notified by the CA about expiring certificates
+    pub email: Option<String>,
+    /// Directory url of the CA
+    pub directory: String,
+    /// Port the HTTP-01 challenges are answered on
+    pub http_port: u16,
+    /// Private key (pem) of the account, created if missing
+    pub account_key: PathBuf,
+}
+
+impl AcmeConfig {
+    pub fn new(domain: String) -> Self {
+        AcmeConfig {
+            domain,
+            email: None,
+            directory: String::from(LETS_ENCRYPT_DIRECTORY),
+            http_port: DEFAULT_ACME_HTTP_PORT,
+            account_key: PathBuf::from(DEFAULT_ACME_ACCOUNT_KEY),
+        }
+    }
+}
+
+/// Key authorizations of the pending HTTP-01 challenges, by token
+pub type AcmeChallenges = Arc<Mutex<HashMap<String, String>>>;
+
+/// Create a listener on the challenge port answering the CA
+pub async fn create_acme_listener(challenges: AcmeChallenges, addr: SocketAddr) -> std::io::Result<Listener> {
+    // TCP listener
+    let listener = bind_tcp(addr).await?;
+    info!("create_acme_listener(..): Listening for ACME challenges on {}", addr);
+
+    // Spawn listener, restarted by the supervision if it ends
+    Listener::start(addr, listener, move |listener| listen(challenges.clone(), listener))
+}
+
+/// Waiting for incoming connections
+async fn listen(challenges: AcmeChallenges, listener: TcpListener) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) if accept_exhausted(&e) => {
+                error!("listen(..): Could not accept connection, stopping until restarted\nError: {}", e);
+                return
+            }
+            Err(e) => {
+                warn!("listen(..): Could not accept connection\nError: {}", e);
+                continue
+            },
+        };
+
+        tokio::spawn(challenge_connection(challenges.clone(), stream, address));
+    }
+}
+
+/// Answers the request for a challenge with its key authorization
+async fn challenge_connection(challenges: AcmeChallenges, mut stream: TcpStream, address: SocketAddr) {
+    let head = match timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await {
+        Ok(Some(v)) => v,
+        _ => {
+            warn!("challenge_connection(..): Request by {} is malformed or timed out. Dropping!", address);
+            return
+        }
+    };
+
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    let key_authorization = match (method, target.strip_prefix(CHALLENGE_PATH)) {
+        ("GET", Some(token)) => challenges.lock().expect("challenge_connection(..): Challenges are poisoned").get(token).cloned(),
+        _ => None,
+    };
+    info!("challenge_connection(..): {} requested {} {} ({})", address, method, target, if key_authorization.is_some() { "answered" } else { "unknown" });
+    let response = match key_authorization {
+        Some(body) => format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body),
+        None => String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Keeps the certificate of the client port valid, forever
+pub async fn keep_certificate(config: AcmeConfig, tls: TlsConfig, challenges: AcmeChallenges) {
+    loop {
+        let delay = match remaining_days(&tls.cert, &config.domain).await {
+            Some(days) if days > ACME_RENEW_BEFORE_DAYS => ACME_CHECK_INTERVAL,
+            remaining => {
+                info!("keep_certificate(..): Ordering a certificate for {} (valid for {:?} more days)", config.domain, remaining);
+                match provision(&config, &tls, &challenges).await {
+                    Ok(()) => {
+                        info!("keep_certificate(..): Certificate for {} issued", config.domain);
+                        certificates::reload();
+                        ACME_CHECK_INTERVAL
+                    }
+                    Err(e) => {
+                        warn!("keep_certificate(..): Ordering a certificate for {} failed, retrying in {:?}\nError: {}", config.domain, ACME_RETRY_DELAY, e);
+                        ACME_RETRY_DELAY
+                    }
+                }
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Days the certificate stays valid, None if there is none or it does not cover the domain
+async fn remaining_days(path: &Path, domain: &str) -> Option<i32> {
+    let certificate = X509::from_pem(&tokio::fs::read(path).await.ok()?).ok()?;
+    let covered = certificate.subject_alt_names()?.iter().any(|name| name.dnsname() == Some(domain));
+    if !covered {
+        return None
+    }
+    Asn1Time::days_from_now(0).ok()?.diff(certificate.not_after()).ok().map(|diff| diff.days)
+}
+
+/// Orders, authorizes and downloads a certificate of the domain and stores it with its key
+async fn provision(config: &AcmeConfig, tls: &TlsConfig, challenges: &AcmeChallenges) -> Result<(), String> {
+    let mut client = AcmeClient::connect(config).await?;
+    client.register(config.email.as_deref()).await?;
+
+    let order = json!({"identifiers": [{"type": "dns", "value": config.domain}]});
+    let response = client.post(&client.endpoint("newOrder")?, Some(&order)).await?;
+    let order_url = response.header("location").map(String::from).ok_or("Order has no location")?;
+    let order = parse(&response)?;
+    for authorization in order["authorizations"].as_array().ok_or("Order has no authorizations")? {
+        let url = authorization.as_str().ok_or("Authorization url is malformed")?;
+        client.authorize(url, challenges).await?;
+    }
+
+    let (key, csr) = certificate_request(&config.domain).map_err(|e| format!("Creating the certificate request failed: {}", e))?;
+    let finalize = order["finalize"].as_str().ok_or("Order has no finalize url")?;
+    client.post(finalize, Some(&json!({"csr": URL_SAFE_NO_PAD.encode(csr)}))).await?;
+    let order = client.poll(&order_url, &["pending", "ready", "processing"]).await?;
+    let certificate = order["certificate"].as_str().ok_or("Order has no certificate url")?;
+    let chain = client.post(certificate, None).await?.body;
+
+    let key = key.private_key_to_pem_pkcs8().map_err(|e| format!("Encoding the key failed: {}", e))?;
+    write_private(&tls.key, &key).await?;
+    write_private(&tls.cert, chain.as_bytes()).await
+}
+
+/// Replaces the file at once, readable by the owner only
+async fn write_private(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        tokio::fs::create_dir_all(directory).await.map_err(|e| format!("Creating {} failed: {}", directory.display(), e))?;
+    }
+    let temporary = path.with_extension("tmp");
+    let mut file = tokio::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&temporary).await
+        .map_err(|e| format!("Creating {} failed: {}", temporary.display(), e))?;
+    file.write_all(data).await.map_err(|e| format!("Writing {} failed: {}", temporary.display(), e))?;
+    tokio::fs::rename(&temporary, path).await.map_err(|e| format!("Replacing {} failed: {}", path.display(), e))
+}
+
+/// Key and CSR (der) of a certificate for the domain
+fn certificate_request(domain: &str) -> Result<(PKey<Private>, Vec<u8>), ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, domain)?;
+    let mut request = X509ReqBuilder::new()?;
+    request.set_subject_name(&name.build())?;
+    request.set_pubkey(&key)?;
+    let mut extensions = Stack::new()?;
+    extensions.push(SubjectAlternativeName::new().dns(domain).build(&request.x509v3_context(None))?)?;
+    request.add_extensions(&extensions)?;
+    request.sign(&key, MessageDigest::sha256())?;
+    Ok((key, request.build().to_der()?))
+}
+
+fn parse(response: &Response) -> Result<Value, String> {
+    serde_json::from_str(&response.body).map_err(|e| format!("Response is no json: {}", e))
+}
+
+/// Account session with the CA, requests are signed with the account key (ES256)
+struct AcmeClient {
+    directory: Value,
+    key: EcKey<Private>,
+    /// Public account key (JWK) in the canonical form of its thumbprint
+    jwk: String,
+    /// Account url, once registered
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    async fn connect(config: &AcmeConfig) -> Result<Self, String> {
+        let key = account_key(&config.account_key).await?;
+        let jwk = public_jwk(&key).map_err(|e| format!("Encoding the account key failed: {}", e))?;
+        let url = Url::parse(&config.directory)?;
+        let response = request(&url, "GET", None, ACME_REQUEST_TIMEOUT).await?;
+        if response.status != 200 {
+            return Err(format!("Directory {} answered {}", config.directory, response.status))
+        }
+        Ok(AcmeClient {directory: parse(&response)?, key, jwk, kid: None, nonce: None})
+    }
+
+    fn endpoint(&self, name: &str) -> Result<String, String> {
+        self.directory[name].as_str().map(String::from).ok_or(format!("Directory has no '{}'", name))
+    }
+
+    /// Registers the account, or finds the existing one of the key
+    async fn register(&mut self, email: Option<&str>) -> Result<(), String> {
+        let mut account = json!({"termsOfServiceAgreed": true});
+        if let Some(email) = email {
+            account["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let response = self.post(&self.endpoint("newAccount")?, Some(&account)).await?;
+        self.kid = Some(response.header("location").map(String::from).ok_or("Account has no location")?);
+        Ok(())
+    }
+
+    /// Answers the HTTP-01 challenge of the authorization and waits until the CA checked it
+    async fn authorize(&mut self, url: &str, challenges: &AcmeChallenges) -> Result<(), String> {
+        let authorization = parse(&self.post(url, None).await?)?;
+        if authorization["status"] == "valid" {
+            return Ok(())
+        }
+        let challenge = authorization["challenges"].as_array().into_iter().flatten()
+            .find(|challenge| challenge["type"] == "http-01")
+            .ok_or("CA offers no http-01 challenge")?;
+        let token = challenge["token"].as_str().ok_or("Challenge has no token")?;
+        let challenge_url = challenge["url"].as_str().ok_or("Challenge has no url")?;
+
+        let key_authorization = format!("{}.{}", token, URL_SAFE_NO_PAD.encode(openssl::sha::sha256(self.jwk.as_bytes())));
+        challenges.lock().expect("authorize(..): Challenges are poisoned").insert(String::from(token), key_authorization);
+        let result = match self.post(challenge_url, Some(&json!({}))).await {
+            Ok(_) => self.poll(url, &["pending"]).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        challenges.lock().expect("authorize(..): Challenges are poisoned").remove(token);
+        result
+    }
+
+    /// Fetches the resource until its status leaves the given ones, fails unless it is valid then
+    async fn poll(&mut self, url: &str, waiting: &[&str]) -> Result<Value, String> {
+        for _ in 0..ACME_POLL_ATTEMPTS {
+            let resource = parse(&self.post(url, None).await?)?;
+            match resource["status"].as_str() {
+                Some("valid") => return Ok(resource),
+                Some(status) if waiting.contains(&status) => tokio::time::sleep(ACME_POLL_INTERVAL).await,
+                _ => return Err(format!("{} failed: {}", url, resource)),
+            }
+        }
+        Err(format!("{} is still pending", url))
+    }
+
+    /// Signed POST, POST-as-GET without payload
+    /// A rejected nonce is replaced once
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response, String> {
+        let target = Url::parse(url)?;
+        for retry in [false, true] {
+            let nonce = match self.nonce.take() {
+                Some(v) => v,
+                None => self.new_nonce().await?,
+            };
+            let body = self.sign(url, &nonce, payload).map_err(|e| format!("Signing the request failed: {}", e))?;
+            let response = request(&target, "POST", Some(("application/jose+json", &body)), ACME_REQUEST_TIMEOUT).await?;
+            self.nonce = response.header("replay-nonce").map(String::from);
+            if response.status < 400 {
+                return Ok(response)
+            }
+            if !retry && response.body.contains("urn:ietf:params:acme:error:badNonce") {
+                continue
+            }
+            return Err(format!("{} answered {}: {}", url, response.status, response.body))
+        }
+        Err(format!("{} rejected every nonce", url))
+    }
+
+    async fn new_nonce(&self) -> Result<String, String> {
+        let url = Url::parse(&self.endpoint("newNonce")?)?;
+        let response = request(&url, "HEAD", None, ACME_REQUEST_TIMEOUT).await?;
+        response.header("replay-nonce").map(String::from).ok_or(String::from("CA sent no nonce"))
+    }
+
+    /// Flattened JWS, identified by the account url once registered and by the key before
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<String, ErrorStack> {
+        let mut protected = json!({"alg": "ES256", "nonce": nonce, "url": url});
+        match self.kid.as_ref() {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = serde_json::from_str(&self.jwk).expect("sign(..): The jwk is valid json"),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload.map(|payload| URL_SAFE_NO_PAD.encode(payload.to_string())).unwrap_or_default();
+
+        let signature = EcdsaSig::sign(&openssl::sha::sha256(format!("{}.{}", protected, payload).as_bytes()), &self.key)?;
+        let mut raw = signature.r().to_vec_padded(32)?;
+        raw.extend(signature.s().to_vec_padded(32)?);
+        Ok(json!({"protected": protected, "payload": payload, "signature": URL_SAFE_NO_PAD.encode(raw)}).to_string())
+    }
+}
+
+/// Loads the account key, creating it on first use
+async fn account_key(path: &Path) -> Result<EcKey<Private>, String> {
+    if let Ok(pem) = tokio::fs::read(path).await {
+        return PKey::private_key_from_pem(&pem).and_then(|key| key.ec_key())
+            .map_err(|e| format!("Account key {} is no P-256 key: {}", path.display(), e))
+    }
+    let key = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).and_then(|group| EcKey::generate(&group))
+        .map_err(|e| format!("Creating the account key failed: {}", e))?;
+    let pem = PKey::from_ec_key(key.clone()).and_then(|key| key.private_key_to_pem_pkcs8())
+        .map_err(|e| format!("Encoding the account key failed: {}", e))?;
+    write_private(path, &pem).await?;
+    info!("account_key(..): Created the ACME account key {}", path.display());
+    Ok(key)
+}
+
+/// Members in lexicographic order without whitespace, as the thumbprint requires (RFC 7638)
+fn public_jwk(key: &EcKey<Private>) -> Result<String, ErrorStack> {
+    let (mut x, mut y, mut context) = (BigNum::new()?, BigNum::new()?, BigNumContext::new()?);
+    key.public_key().affine_coordinates_gfp(key.group(), &mut x, &mut y, &mut context)?;
+    Ok(format!("{{\"crv\":\"P-256\",\"kty\":\"EC\",\"x\":\"{}\",\"y\":\"{}\"}}",
+        URL_SAFE_NO_PAD.encode(x.to_vec_padded(32)?), URL_SAFE_NO_PAD.encode(y.to_vec_padded(32)?)))
+}
@@ -0,0 +1,341 @@
+//!
+//! Minimal HTTP interface for the server operator.
+//! Only the request line (method, path and query) is evaluated, every response body is json.
+//! Requests needing server state are forwarded to the main handler as 'AdminRequest' events and
+//! answered with whatever the handler replies.
+//! The interface listens on the loopback address unless TT_BACKEND_ADMIN_IP says otherwise. Every
+//! request besides '/health' has to carry 'Authorization: Bearer <TT_BACKEND_ADMIN_SECRET>', without
+//! a configured secret only '/health' is answered.
+//!
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use crate::server::InternalMessage;
+use crate::server::bus::Bus;
+use crate::server::dns::resolve_listen_ip;
+use crate::server::logging;
+use crate::server::messages::ClientAction;
+use crate::server::networking::{accept_exhausted, bind_tcp, Listener};
+
+const MAX_REQUEST_HEAD: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Every request the admin interface can forward to the main handler
+#[derive(Debug, Clone)]
+pub enum AdminRequest {
+    DebugQueues,
+    /// Failed TLS handshakes by cause and the most recent ones
+    DebugTls,
+    /// Accept loops with their state and restarts
+    DebugListeners,
+    /// Approximate memory of the session by what holds it
+    DebugMemory,
+    Health,
+    /// Moves every connected client to the server instance at 'url'
+    Migrate { url: String },
+    /// Tells every connected client to reload
+    ClientCommand { action: ClientAction, min_version: Option<String> },
+    /// Hands the session over to a standby and migrates the clients to it
+    Failover,
+    /// Shows the message to everybody until 'duration' is over, None clears the announcement
+    Announce { message: Option<String>, duration: Option<Duration> },
+    /// Prunes stored data past its retention age right away
+    Retention,
+    /// Moves the listeners, ports are given for clients, hosts and shadow hosts
+    Rebind { ip: Option<IpAddr>, ports: [Option<u16>; 3] },
+    /// Hands the session and the listeners over to a new process of the running binary, which
+    /// was replaced on disk beforehand
+    Upgrade,
+    /// Sends every connected client a one-time rejoin link
+    RejoinLinks,
+    /// Rooms opened by hosts with their join code, tenant and connections
+    Rooms,
+}
+
+/// Create a listener on the admin port waiting for operator requests
+/// The admin listener is not moved by 'Rebind'
+pub async fn create_admin_listener(channel: Bus, secret: Option<Arc<str>>, addr: SocketAddr) -> std::io::Result<Listener> {
+    // TCP listener
+    let listener = bind_tcp(addr).await?;
+    info!("create_admin_listener(..): Listening for admin requests on {}", addr);
+    if secret.is_none() {
+        warn!("create_admin_listener(..): No admin secret configured, only '/health' is answered");
+    }
+
+    // Spawn listener, restarted by the supervision if it ends
+    Listener::start(addr, listener, move |listener| listen(channel.clone(), secret.clone(), listener))
+}
+
+/// Waiting for incoming connections
+/// Every connection is answered in its own task
+async fn listen(channel: Bus, secret: Option<Arc<str>>, listener: TcpListener) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) if accept_exhausted(&e) => {
+                error!("listen(..): Could not accept connection, stopping until restarted\nError: {}", e);
+                return
+            }
+            Err(e) => {
+                warn!("listen(..): Could not accept connection\nError: {}", e);
+                continue
+            },
+        };
+
+        tokio::spawn(admin_connection(channel.clone(), secret.clone(), stream, address));
+    }
+}
+
+/// Reads one request, lets the main handler answer it and closes the connection
+/// Requests besides '/health' without the admin secret are refused
+async fn admin_connection(channel: Bus, secret: Option<Arc<str>>, mut stream: TcpStream, address: SocketAddr) {
+    let head = match timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            warn!("admin_connection(..): Request by {} is malformed. Dropping!", address);
+            return
+        }
+        Err(_) => {
+            warn!("admin_connection(..): Request by {} timed out. Dropping!", address);
+            return
+        }
+    };
+
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    info!("admin_connection(..): {} requested {} {}", address, method, target);
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    if path != "/health" && !authorized(&head, secret.as_deref()) {
+        warn!("admin_connection(..): Refusing unauthorized request {} {} by {}", method, path, address);
+        let error = match secret {
+            None => "No admin secret configured",
+            Some(_) => "Missing or invalid admin secret",
+        };
+        if let Err(e) = write_response(&mut stream, 401, json!({"error": error})).await {
+            warn!("admin_connection(..): Sending response to {} failed!\nError: {}", address, e);
+        }
+        return
+    }
+
+    let (status, body) = match (method, path) {
+        ("GET", "/debug/queues") => forward_request(&channel, AdminRequest::DebugQueues).await,
+        ("GET", "/debug/tls") => forward_request(&channel, AdminRequest::DebugTls).await,
+        ("GET", "/debug/listeners") => forward_request(&channel, AdminRequest::DebugListeners).await,
+        ("GET", "/debug/memory") => forward_request(&channel, AdminRequest::DebugMemory).await,
+        ("GET", "/health") => forward_request(&channel, AdminRequest::Health).await,
+        ("GET", "/rooms") => forward_request(&channel, AdminRequest::Rooms).await,
+        ("GET", "/log-level") => (200, json!({"filter": logging::get_filter()})),
+        ("POST", "/log-level") => set_log_level(query),
+        ("POST", "/migrate") => migrate(&channel, query).await,
+        ("POST", "/client-command") => client_command(&channel, query).await,
+        ("POST", "/announcement") => announce(&channel, query).await,
+        ("POST", "/rejoin-links") => forward_request(&channel, AdminRequest::RejoinLinks).await,
+        ("DELETE", "/announcement") => forward_request(&channel, AdminRequest::Announce {message: None, duration: None}).await,
+        ("POST", "/failover") => match forward_request(&channel, AdminRequest::Failover).await {
+            (200, body) if body.get("error").is_some() => (500, body),
+            response => response,
+        },
+        ("POST", "/rebind") => rebind(&channel, query).await,
+        ("POST", "/upgrade") => match forward_request(&channel, AdminRequest::Upgrade).await {
+            (200, body) if body.get("error").is_some() => (500, body),
+            response => response,
+        },
+        ("POST", "/retention") => match forward_request(&channel, AdminRequest::Retention).await {
+            (200, body) if body.get("error").is_some() => (500, body),
+            response => response,
+        },
+        ("GET", _) | ("POST", _) | ("DELETE", _) => (404, json!({"error": "Not found"})),
+        _ => (405, json!({"error": "Method not allowed"})),
+    };
+
+    if let Err(e) = write_response(&mut stream, status, body).await {
+        warn!("admin_connection(..): Sending response to {} failed!\nError: {}", address, e);
+    }
+}
+
+/// Triggers the 'AdminRequest' event and waits for the reply of the main handler
+async fn forward_request(channel: &Bus, request: AdminRequest) -> (u16, Value) {
+    let (reply, reply_rcv) = oneshot::channel();
+    channel.send(InternalMessage::AdminRequest {request, reply}).await.expect("forward_request(..): Sending internal message failed");
+    match reply_rcv.await {
+        Ok(v) => (200, v),
+        Err(_) => (500, json!({"error": "No reply from main handler"})),
+    }
+}
+
+/// Replaces the log filter with the 'filter' query parameter
+/// Handled directly, so the log level can be raised even while the main handler is stuck
+fn set_log_level(query: &str) -> (u16, Value) {
+    let filter = match query_param(query, "filter") {
+        None => return (400, json!({"error": "Missing query parameter 'filter'"})),
+        Some(v) => v,
+    };
+    match logging::set_filter(&filter) {
+        Ok(_) => {
+            warn!("set_log_level(..): Log filter changed to '{}'", filter);
+            (200, json!({"filter": filter}))
+        }
+        Err(e) => (400, json!({"error": e})),
+    }
+}
+
+/// Instructs all clients to reconnect to the instance given by the 'url' query parameter
+/// The clients log in again with their token, so the current instance can be shut down afterwards
+async fn migrate(channel: &Bus, query: &str) -> (u16, Value) {
+    let url = match query_param(query, "url") {
+        None => return (400, json!({"error": "Missing query parameter 'url'"})),
+        Some(v) => v,
+    };
+    if !url.starts_with("ws://") && !url.starts_with("wss://") {
+        return (400, json!({"error": format!("Invalid url '{}', expected 'ws://..' or 'wss://..'", url)}))
+    }
+    forward_request(channel, AdminRequest::Migrate {url}).await
+}
+
+/// Broadcasts the 'action' query parameter ('reload' or 'update_required') to all clients,
+/// 'min_version' is passed along if given
+async fn client_command(channel: &Bus, query: &str) -> (u16, Value) {
+    let action = match query_param(query, "action") {
+        None => return (400, json!({"error": "Missing query parameter 'action'"})),
+        Some(v) => v,
+    };
+    let action = match ClientAction::parse(&action) {
+        None => return (400, json!({"error": format!("Invalid action '{}', expected 'reload' or 'update_required'", action)})),
+        Some(v) => v,
+    };
+    let min_version = query_param(query, "min_version");
+    forward_request(channel, AdminRequest::ClientCommand {action, min_version}).await
+}
+
+/// Announces the 'message' query parameter to all hosts and clients, independent of the host
+/// The optional 'duration' (in seconds) lets the announcement expire
+async fn announce(channel: &Bus, query: &str) -> (u16, Value) {
+    let message = match query_param(query, "message").filter(|message| !message.trim().is_empty()) {
+        None => return (400, json!({"error": "Missing query parameter 'message'"})),
+        Some(v) => v,
+    };
+    let duration = match query_param(query, "duration") {
+        None => None,
+        Some(value) => match value.parse() {
+            Ok(v) => Some(Duration::from_secs(v)),
+            Err(_) => return (400, json!({"error": format!("Invalid duration '{}'", value)})),
+        },
+    };
+    forward_request(channel, AdminRequest::Announce {message: Some(message), duration}).await
+}
+
+/// Moves the listeners to the 'ip' (or host name) and the ports 'ws_port', 'tcp_port' and
+/// 'shadow_port' query parameters, omitted ones are kept
+async fn rebind(channel: &Bus, query: &str) -> (u16, Value) {
+    let ip = match query_param(query, "ip") {
+        None => None,
+        Some(host) => match resolve_listen_ip(&host).await {
+            Ok(v) => Some(v),
+            Err(e) => return (400, json!({"error": e})),
+        },
+    };
+    let mut ports = [None; 3];
+    for (port, key) in ports.iter_mut().zip(["ws_port", "tcp_port", "shadow_port"]) {
+        if let Some(value) = query_param(query, key) {
+            match value.parse() {
+                Ok(v) => *port = Some(v),
+                Err(_) => return (400, json!({"error": format!("Invalid port '{}' for '{}'", value, key)})),
+            }
+        }
+    }
+    if ip.is_none() && ports.iter().all(Option::is_none) {
+        return (400, json!({"error": "Expecting at least one of 'ip', 'ws_port', 'tcp_port' or 'shadow_port'"}))
+    }
+    match forward_request(channel, AdminRequest::Rebind {ip, ports}).await {
+        (200, body) if body.get("error").is_some() => (500, body),
+        response => response,
+    }
+}
+
+/// Returns the percent-decoded value of the first query parameter with the given key
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(v)) => {
+                decoded.push(v);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Whether the request head carries 'Authorization: Bearer <secret>', never without a secret
+fn authorized(head: &str, secret: Option<&str>) -> bool {
+    let secret = match secret {
+        None => return false,
+        Some(v) => v,
+    };
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .filter_map(|(_, value)| value.trim().strip_prefix("Bearer "))
+        .any(|given| bool::from(given.trim().as_bytes().ct_eq(secret.as_bytes())))
+}
+
+/// Reads until the end of the request head
+/// Returns None if the connection is closed early or the head exceeds MAX_REQUEST_HEAD
+pub async fn read_request_head(stream: &mut TcpStream) -> Option<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_HEAD {
+            return None
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    String::from_utf8(buf).ok()
+}
+
+async fn write_response(stream: &mut TcpStream, status: u16, body: Value) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    let response = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
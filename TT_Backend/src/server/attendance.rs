@@ -0,0 +1,86 @@
+//!
+//! Attendance of the running session.
+//! Every connection of a client is recorded as an interval between join and leave (by server
+//! timestamps), attributed to the client name, so reconnects add up to one participant. The report
+//! lists the total connected time and the number of joins per name, e.g. for lecture attendance.
+//!
+
+use std::collections::HashMap;
+
+/// One connection of a client, 'left' is None while it is still connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttendanceInterval {
+    pub joined: i64,
+    pub left: Option<i64>,
+}
+
+/// Attendance of one client name up to 'now' of the report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttendanceEntry {
+    pub name: String,
+    pub joins: usize,
+    /// Total connected time in milliseconds, overlapping connections count once
+    pub connected: i64,
+    pub intervals: Vec<AttendanceInterval>,
+}
+
+#[derive(Debug, Default)]
+pub struct Attendance {
+    intervals: HashMap<String, Vec<AttendanceInterval>>,
+}
+
+impl Attendance {
+    /// Starts recording anew, the given clients count as joined at 'now'
+    pub fn reset<'a>(&mut self, connected: impl Iterator<Item = &'a str>, now: i64) {
+        self.intervals.clear();
+        for name in connected {
+            self.joined(name, now);
+        }
+    }
+
+    pub fn joined(&mut self, name: &str, now: i64) {
+        self.intervals.entry(String::from(name)).or_default().push(AttendanceInterval {joined: now, left: None});
+    }
+
+    /// Closes the oldest open interval of the name
+    pub fn left(&mut self, name: &str, now: i64) {
+        let open = self.intervals.get_mut(name)
+            .and_then(|intervals| intervals.iter_mut().find(|interval| interval.left.is_none()));
+        if let Some(interval) = open {
+            interval.left = Some(now);
+        }
+    }
+
+    /// Attendance of every client that joined, sorted by name
+    pub fn report(&self, now: i64) -> Vec<AttendanceEntry> {
+        let mut entries: Vec<AttendanceEntry> = self.intervals.iter()
+            .map(|(name, intervals)| AttendanceEntry {
+                name: name.clone(),
+                joins: intervals.len(),
+                connected: connected_time(intervals, now),
+                intervals: intervals.clone(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+}
+
+/// Length of the union of the intervals, open intervals end at 'now'
+fn connected_time(intervals: &[AttendanceInterval], now: i64) -> i64 {
+    let mut spans: Vec<(i64, i64)> = intervals.iter()
+        .map(|interval| (interval.joined, interval.left.unwrap_or(now)))
+        .collect();
+    spans.sort_unstable();
+
+    let mut total = 0;
+    let mut covered_until = i64::MIN;
+    for (start, end) in spans {
+        let start = start.max(covered_until);
+        if end > start {
+            total += end - start;
+            covered_until = end;
+        }
+    }
+    total
+}
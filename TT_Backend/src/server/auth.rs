@@ -0,0 +1,427 @@
+//!
+//! Authentication of logins.
+//! The login handlers only ask the configured AuthProvider whether the presented credentials are
+//! accepted, so new verification schemes can be added without touching the networking code.
+//! Hosts are asked for the 'token' of their 'HostLogin', which has to be their first frame, before
+//! they replace the active host. A host secret (TT_BACKEND_HOST_SECRET) takes the place of the
+//! configured provider for hosts, otherwise the provider decides about hosts as well.
+//!
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use crate::server::config::AuthConfig;
+use crate::server::http_client::{post_json, Url};
+use crate::server::messages::current_timestamp;
+
+pub const REJECT_REASON_MISSING_TOKEN: &str = "Missing token";
+pub const REJECT_REASON_INVALID_TOKEN: &str = "Invalid token";
+pub const REJECT_REASON_EXPIRED_TOKEN: &str = "Token expired";
+pub const REJECT_REASON_UNAVAILABLE: &str = "Authorization service unavailable";
+pub const REJECT_REASON_HOST_TOKEN: &str = "Invalid host token";
+
+/// Role of the JWT claims admitting hosts
+pub const HOST_ROLE: &str = "host";
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Everything presented by a connection trying to log in
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// Empty for hosts
+    pub name: String,
+    pub token: Option<String>,
+    pub address: SocketAddr,
+    /// Whether a host is logging in
+    pub host: bool,
+}
+
+/// Result of an authentication
+/// An accepted login may get a canonical name and a role assigned by the provider
+#[derive(Debug, Clone)]
+pub enum AuthDecision {
+    Allow { name: Option<String>, role: Option<String> },
+    Deny { reason: String },
+}
+
+impl AuthDecision {
+    pub(crate) fn allow() -> Self {
+        AuthDecision::Allow { name: None, role: None }
+    }
+
+    fn deny(reason: &str) -> Self {
+        AuthDecision::Deny { reason: String::from(reason) }
+    }
+}
+
+/// Verification scheme for logins
+#[async_trait]
+pub trait AuthProvider: Debug + Send + Sync {
+    async fn authenticate(&self, credentials: &Credentials) -> AuthDecision;
+}
+
+/// Creates the provider selected in the config
+/// 'advertised_url' is passed to external services, so they know which server is asking
+pub fn create_auth_provider(config: &AuthConfig, advertised_url: Option<&str>) -> Result<Arc<dyn AuthProvider>, String> {
+    let provider: Arc<dyn AuthProvider> = match config {
+        AuthConfig::None => Arc::new(AllowAll),
+        AuthConfig::StaticSecret(secret) => Arc::new(StaticSecret { secret: secret.clone() }),
+        AuthConfig::TokenFile(path) => Arc::new(TokenFile::load(path)?),
+        AuthConfig::Jwt(key) => Arc::new(JwtVerifier { key: key.as_bytes().to_vec() }),
+        AuthConfig::Webhook(url) => Arc::new(Webhook { url: Url::parse(url)?, server: advertised_url.map(String::from) }),
+    };
+    Ok(provider)
+}
+
+/// Accepts everybody, the behaviour without configured authentication
+#[derive(Debug)]
+pub struct AllowAll;
+
+#[async_trait]
+impl AuthProvider for AllowAll {
+    async fn authenticate(&self, _credentials: &Credentials) -> AuthDecision {
+        AuthDecision::allow()
+    }
+}
+
+/// Accepts logins presenting one shared secret
+#[derive(Debug)]
+pub struct StaticSecret {
+    secret: String,
+}
+
+#[async_trait]
+impl AuthProvider for StaticSecret {
+    async fn authenticate(&self, credentials: &Credentials) -> AuthDecision {
+        match credentials.token.as_ref() {
+            None => AuthDecision::deny(REJECT_REASON_MISSING_TOKEN),
+            Some(token) if constant_time_eq(token, &self.secret) => AuthDecision::allow(),
+            Some(_) => {
+                warn!("authenticate(..): {} presented an invalid secret", credentials.address);
+                AuthDecision::deny(REJECT_REASON_INVALID_TOKEN)
+            }
+        }
+    }
+}
+
+/// Accepts logins presenting one of the tokens listed in a file
+/// Each line holds a token, optionally followed by whitespace and the canonical name of its owner.
+/// Empty lines and lines starting with '#' are ignored.
+#[derive(Debug)]
+pub struct TokenFile {
+    tokens: HashMap<String, Option<String>>,
+}
+
+impl TokenFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Reading token file {} failed: {}", path.display(), e))?;
+        Ok(Self::parse(&content))
+    }
+
+    fn parse(content: &str) -> Self {
+        let tokens = content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once(char::is_whitespace) {
+                None => (String::from(line), None),
+                Some((token, name)) => (String::from(token), Some(String::from(name.trim()))),
+            })
+            .collect();
+        TokenFile { tokens }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for TokenFile {
+    async fn authenticate(&self, credentials: &Credentials) -> AuthDecision {
+        let token = match credentials.token.as_ref() {
+            None => return AuthDecision::deny(REJECT_REASON_MISSING_TOKEN),
+            Some(v) => v,
+        };
+        match self.tokens.get(token) {
+            None => {
+                warn!("authenticate(..): {} presented an unknown token", credentials.address);
+                AuthDecision::deny(REJECT_REASON_INVALID_TOKEN)
+            }
+            Some(name) => AuthDecision::Allow { name: name.clone(), role: None },
+        }
+    }
+}
+
+/// Accepts logins presenting a JWT signed with the configured key (HS256)
+/// The optional claims 'name' (or 'sub') and 'role' are used as canonical name and role, 'exp' and
+/// 'nbf' are checked if present. Hosts need the role 'host'.
+#[derive(Debug)]
+pub struct JwtVerifier {
+    key: Vec<u8>,
+}
+
+impl JwtVerifier {
+    /// Returns the claims of a valid token
+    fn verify(&self, token: &str) -> Result<Value, &'static str> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s), None) => (h, p, s),
+            _ => return Err(REJECT_REASON_INVALID_TOKEN),
+        };
+
+        let header = decode_json_part(header).ok_or(REJECT_REASON_INVALID_TOKEN)?;
+        if header["alg"].as_str() != Some("HS256") {
+            warn!("verify(..): JWT uses unsupported algorithm {}", header["alg"]);
+            return Err(REJECT_REASON_INVALID_TOKEN)
+        }
+
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| REJECT_REASON_INVALID_TOKEN)?;
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key).expect("verify(..): HMAC accepts keys of any size");
+        mac.update(header_and_payload(token).as_bytes());
+        if mac.verify_slice(&signature).is_err() {
+            return Err(REJECT_REASON_INVALID_TOKEN)
+        }
+
+        let claims = decode_json_part(payload).ok_or(REJECT_REASON_INVALID_TOKEN)?;
+        let now = current_timestamp() / 1000;
+        if claims["exp"].as_i64().is_some_and(|exp| exp <= now) {
+            return Err(REJECT_REASON_EXPIRED_TOKEN)
+        }
+        if claims["nbf"].as_i64().is_some_and(|nbf| nbf > now) {
+            return Err(REJECT_REASON_INVALID_TOKEN)
+        }
+        Ok(claims)
+    }
+}
+
+#[async_trait]
+impl AuthProvider for JwtVerifier {
+    async fn authenticate(&self, credentials: &Credentials) -> AuthDecision {
+        let token = match credentials.token.as_ref() {
+            None => return AuthDecision::deny(REJECT_REASON_MISSING_TOKEN),
+            Some(v) => v,
+        };
+        match self.verify(token) {
+            Err(reason) => {
+                warn!("authenticate(..): {} presented a rejected JWT ({})", credentials.address, reason);
+                AuthDecision::deny(reason)
+            }
+            Ok(claims) if credentials.host && claims["role"] != HOST_ROLE => {
+                warn!("authenticate(..): Host {} presented a JWT without the role '{}'", credentials.address, HOST_ROLE);
+                AuthDecision::deny(REJECT_REASON_HOST_TOKEN)
+            }
+            Ok(claims) => {
+                let name = claims["name"].as_str().or_else(|| claims["sub"].as_str()).map(String::from);
+                let role = claims["role"].as_str().map(String::from);
+                AuthDecision::Allow { name, role }
+            }
+        }
+    }
+}
+
+/// Asks an external HTTP endpoint about every login of clients and hosts
+/// The endpoint receives the credentials and connection metadata (including the advertised url of
+/// the server and whether a host logs in) as json via POST and answers with
+/// '{"allow": bool, "name": .., "role": .., "reason": ..}' (all but 'allow' optional). Unreachable
+/// endpoints and non 2xx responses deny the login.
+#[derive(Debug)]
+pub struct Webhook {
+    url: Url,
+    /// Public url of this server
+    server: Option<String>,
+}
+
+#[async_trait]
+impl AuthProvider for Webhook {
+    async fn authenticate(&self, credentials: &Credentials) -> AuthDecision {
+        let request = json!({
+            "name": credentials.name,
+            "token": credentials.token,
+            "address": credentials.address.to_string(),
+            "host": credentials.host,
+            "server": self.server,
+        });
+
+        let (status, body) = match post_json(&self.url, &request, WEBHOOK_TIMEOUT).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("authenticate(..): Calling authorization webhook failed!\nError: {}", e);
+                return AuthDecision::deny(REJECT_REASON_UNAVAILABLE)
+            }
+        };
+        if !(200..300).contains(&status) {
+            warn!("authenticate(..): Authorization webhook answered with status {}", status);
+            return AuthDecision::deny(REJECT_REASON_UNAVAILABLE)
+        }
+
+        let response: Value = match serde_json::from_str(&body) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("authenticate(..): Response of authorization webhook is malformed!\nResponse: {}\nError: {}", body, e);
+                return AuthDecision::deny(REJECT_REASON_UNAVAILABLE)
+            }
+        };
+        if response["allow"].as_bool() == Some(true) {
+            AuthDecision::Allow {
+                name: response["name"].as_str().map(String::from),
+                role: response["role"].as_str().map(String::from),
+            }
+        } else {
+            AuthDecision::deny(response["reason"].as_str().unwrap_or(REJECT_REASON_INVALID_TOKEN))
+        }
+    }
+}
+
+fn decode_json_part(part: &str) -> Option<Value> {
+    let bytes = URL_SAFE_NO_PAD.decode(part).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+fn header_and_payload(token: &str) -> &str {
+    match token.rfind('.') {
+        None => token,
+        Some(i) => &token[..i],
+    }
+}
+
+/// Accepts hosts presenting the host secret
+#[derive(Debug)]
+pub struct HostSecret {
+    pub secret: String,
+}
+
+#[async_trait]
+impl AuthProvider for HostSecret {
+    async fn authenticate(&self, credentials: &Credentials) -> AuthDecision {
+        match credentials.token.as_ref() {
+            Some(token) if constant_time_eq(token, &self.secret) => {
+                info!("authenticate(..): Host {} presented the host secret", credentials.address);
+                AuthDecision::allow()
+            }
+            _ => AuthDecision::deny(REJECT_REASON_HOST_TOKEN),
+        }
+    }
+}
+
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "127.0.0.1:4000";
+
+    fn credentials(token: &str, host: bool) -> Credentials {
+        Credentials {name: String::from("alice"), token: Some(String::from(token)), address: ADDRESS.parse().unwrap(), host}
+    }
+
+    fn sign(header: Value, claims: Value, key: &[u8]) -> String {
+        let unsigned = format!("{}.{}", URL_SAFE_NO_PAD.encode(header.to_string()), URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+        mac.update(unsigned.as_bytes());
+        format!("{}.{}", unsigned, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    fn hs256(claims: Value, key: &[u8]) -> String {
+        sign(json!({"alg": "HS256", "typ": "JWT"}), claims, key)
+    }
+
+    fn verifier() -> JwtVerifier {
+        JwtVerifier { key: b"jwt key".to_vec() }
+    }
+
+    #[test]
+    fn jwt_signed_with_the_key_is_accepted() {
+        let token = hs256(json!({"sub": "alice", "role": "moderator", "exp": current_timestamp() / 1000 + 60}), b"jwt key");
+        let claims = verifier().verify(&token).expect("Token was rejected");
+        assert_eq!(claims["role"], "moderator");
+
+        let forged = hs256(json!({"sub": "alice"}), b"other key");
+        assert_eq!(verifier().verify(&forged).err(), Some(REJECT_REASON_INVALID_TOKEN));
+        let (unsigned, _) = token.rsplit_once('.').unwrap();
+        assert_eq!(verifier().verify(unsigned).err(), Some(REJECT_REASON_INVALID_TOKEN));
+    }
+
+    #[test]
+    fn jwt_after_exp_or_before_nbf_is_rejected() {
+        let now = current_timestamp() / 1000;
+        let expired = hs256(json!({"sub": "alice", "exp": now - 1}), b"jwt key");
+        assert_eq!(verifier().verify(&expired).err(), Some(REJECT_REASON_EXPIRED_TOKEN));
+        let early = hs256(json!({"sub": "alice", "nbf": now + 60}), b"jwt key");
+        assert_eq!(verifier().verify(&early).err(), Some(REJECT_REASON_INVALID_TOKEN));
+    }
+
+    #[test]
+    fn jwt_with_other_algorithm_is_rejected() {
+        let none = sign(json!({"alg": "none"}), json!({"sub": "alice"}), b"jwt key");
+        assert_eq!(verifier().verify(&none).err(), Some(REJECT_REASON_INVALID_TOKEN));
+        let hs512 = sign(json!({"alg": "HS512"}), json!({"sub": "alice"}), b"jwt key");
+        assert_eq!(verifier().verify(&hs512).err(), Some(REJECT_REASON_INVALID_TOKEN));
+    }
+
+    #[tokio::test]
+    async fn jwt_admits_hosts_with_the_host_role() {
+        let participant = hs256(json!({"sub": "alice"}), b"jwt key");
+        let host = hs256(json!({"sub": "quizmaster", "role": HOST_ROLE}), b"jwt key");
+        assert!(matches!(verifier().authenticate(&credentials(&participant, false)).await, AuthDecision::Allow {..}));
+        assert!(matches!(verifier().authenticate(&credentials(&participant, true)).await, AuthDecision::Deny {..}));
+        assert!(matches!(verifier().authenticate(&credentials(&host, true)).await, AuthDecision::Allow {..}));
+    }
+
+    #[tokio::test]
+    async fn webhook_is_asked_about_hosts() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+        let endpoint = tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            let request = loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                if text.ends_with('}') {
+                    break text
+                }
+            };
+            let body = r#"{"allow": false, "reason": "Hosts log in elsewhere"}"#;
+            let response = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n", body.len(), body);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            request
+        });
+
+        let webhook = Webhook { url: Url::parse(&url).unwrap(), server: None };
+        match webhook.authenticate(&credentials("abc", true)).await {
+            AuthDecision::Deny {reason} => assert_eq!(reason, "Hosts log in elsewhere"),
+            decision => panic!("Host was accepted: {:?}", decision),
+        }
+        let request = endpoint.await.unwrap();
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["host"], true);
+        assert_eq!(body["token"], "abc");
+    }
+
+    #[tokio::test]
+    async fn token_file_skips_comments_and_reads_names() {
+        let tokens = TokenFile::parse("# participants\n\n  abc123   Alice Smith \nxyz789\n#disabled\n");
+        assert_eq!(tokens.tokens.len(), 2);
+        match tokens.authenticate(&credentials("abc123", false)).await {
+            AuthDecision::Allow {name, ..} => assert_eq!(name.as_deref(), Some("Alice Smith")),
+            decision => panic!("Token was rejected: {:?}", decision),
+        }
+        assert!(matches!(tokens.authenticate(&credentials("xyz789", false)).await, AuthDecision::Allow {name: None, ..}));
+        assert!(matches!(tokens.authenticate(&credentials("#disabled", false)).await, AuthDecision::Deny {..}));
+    }
+}
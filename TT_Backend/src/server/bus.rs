@@ -0,0 +1,150 @@
+//!
+//! Event bus between the connection tasks and the main handler.
+//! Tasks publish 'InternalMessage' events on the bus, the main handler consumes them one at a time.
+//! The local bus is a bounded tokio channel. Other transports (e.g. a distributed bus for
+//! clustering, or an inspectable bus in tests) only implement 'EventBus' and 'EventSource', the
+//! handler logic stays the same.
+//!
+
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use crate::server::InternalMessage;
+
+/// The main handler is gone, no event can be delivered anymore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusClosed;
+
+impl Display for BusClosed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Event bus closed")
+    }
+}
+
+impl std::error::Error for BusClosed {}
+
+/// Sending side of the bus, shared by all tasks
+#[async_trait]
+pub trait EventBus: Debug + Send + Sync {
+    /// Delivers the event to the main handler, waits while the bus is full
+    async fn send(&self, message: InternalMessage) -> Result<(), BusClosed>;
+    /// Events waiting for the main handler, None if the bus can not tell
+    fn queued(&self) -> Option<usize>;
+}
+
+/// Receiving side of the bus, owned by the main handler
+#[async_trait]
+pub trait EventSource: Debug + Send {
+    /// Next event, None once every sending side is gone
+    async fn recv(&mut self) -> Option<InternalMessage>;
+}
+
+/// Cloneable handle tasks publish their events with
+#[derive(Debug, Clone)]
+pub struct Bus(Arc<dyn EventBus>);
+
+impl Bus {
+    pub fn new(bus: Arc<dyn EventBus>) -> Self {
+        Bus(bus)
+    }
+
+    pub async fn send(&self, message: InternalMessage) -> Result<(), BusClosed> {
+        self.0.send(message).await
+    }
+
+    pub fn queued(&self) -> Option<usize> {
+        self.0.queued()
+    }
+}
+
+/// Creates a bus within this process holding up to 'capacity' events
+pub fn local_bus(capacity: usize) -> (Bus, Box<dyn EventSource>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    (Bus::new(Arc::new(LocalBus { sender, capacity })), Box::new(LocalSource { receiver }))
+}
+
+#[derive(Debug)]
+struct LocalBus {
+    sender: mpsc::Sender<InternalMessage>,
+    capacity: usize,
+}
+
+#[async_trait]
+impl EventBus for LocalBus {
+    async fn send(&self, message: InternalMessage) -> Result<(), BusClosed> {
+        self.sender.send(message).await.map_err(|_| BusClosed)
+    }
+
+    fn queued(&self) -> Option<usize> {
+        Some(self.capacity - self.sender.capacity())
+    }
+}
+
+#[derive(Debug)]
+struct LocalSource {
+    receiver: mpsc::Receiver<InternalMessage>,
+}
+
+#[async_trait]
+impl EventSource for LocalSource {
+    async fn recv(&mut self) -> Option<InternalMessage> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Forwards every event to the inner bus and keeps a description of it
+    #[derive(Debug)]
+    struct RecordingBus {
+        inner: Bus,
+        events: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EventBus for RecordingBus {
+        async fn send(&self, message: InternalMessage) -> Result<(), BusClosed> {
+            self.events.lock().unwrap().push(format!("{:?}", message));
+            self.inner.send(message).await
+        }
+
+        fn queued(&self) -> Option<usize> {
+            self.inner.queued()
+        }
+    }
+
+    #[tokio::test]
+    async fn local_bus_delivers_in_order() {
+        let (bus, mut source) = local_bus(4);
+        bus.send(InternalMessage::UsageReportDue).await.unwrap();
+        bus.send(InternalMessage::ReplicationDue).await.unwrap();
+        assert_eq!(bus.queued(), Some(2));
+
+        assert!(matches!(source.recv().await, Some(InternalMessage::UsageReportDue)));
+        assert!(matches!(source.recv().await, Some(InternalMessage::ReplicationDue)));
+        drop(bus);
+        assert!(source.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sending_fails_once_the_source_is_gone() {
+        let (bus, source) = local_bus(4);
+        drop(source);
+        assert_eq!(bus.send(InternalMessage::UsageReportDue).await.unwrap_err(), BusClosed);
+    }
+
+    #[tokio::test]
+    async fn events_can_be_inspected() {
+        let (inner, mut source) = local_bus(4);
+        let recording = Arc::new(RecordingBus { inner, events: Mutex::new(vec![]) });
+        let bus = Bus::new(recording.clone());
+
+        bus.send(InternalMessage::Takeover {reason: String::from("test")}).await.unwrap();
+        assert!(matches!(source.recv().await, Some(InternalMessage::Takeover {..})));
+        assert_eq!(*recording.events.lock().unwrap(), vec![String::from("Takeover { reason: \"test\" }")]);
+    }
+}
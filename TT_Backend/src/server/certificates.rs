@@ -0,0 +1,223 @@
+//!
+//! TLS certificate of the client port, reloaded while the server runs.
+//! The certificate and key are checked for changes every 'reload_interval'
+//! (TT_BACKEND_CERT_RELOAD_INTERVAL, 'tls.reload_interval') and re-read on SIGHUP or once ACME
+//! issued a new one, so renewed certificates (e.g. by certbot) are picked up without a restart.
+//! New connections are accepted with the new certificate, established ones keep theirs. A
+//! certificate that fails to load is logged and the previous one kept, clients are refused while
+//! there is none. At startup an unusable certificate (unreadable, no pem, or a key that does not
+//! belong to it) stops the server, unless ACME is about to provide one.
+//! With host TLS (TT_BACKEND_HOST_TLS, 'tls.host_tls') the host ports speak TLS with the same
+//! certificate, so states and inputs do not cross the network in the clear. A CA bundle for hosts
+//! (TT_BACKEND_HOST_CA, 'tls.host_ca') implies it and only accepts hosts presenting a certificate
+//! issued by that bundle. Their acceptor is built once at startup, a renewed certificate reaches
+//! them with the next restart.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use log::{info, warn};
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+use openssl::x509::X509;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{watch, Notify};
+use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+use crate::server::config::TlsConfig;
+
+/// Time between checks for a renewed certificate
+pub const DEFAULT_CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Acceptor of the current certificate, replaced on reload, None until a certificate is usable
+pub type AcceptorWatch = watch::Receiver<Option<Arc<tokio_native_tls::TlsAcceptor>>>;
+
+/// Wakes the watchers to re-read the certificate
+static RELOAD: Notify = Notify::const_new();
+
+/// Re-reads the certificate right away, e.g. once it was replaced by the server itself
+pub fn reload() {
+    RELOAD.notify_waiters();
+}
+
+/// Certificate or key of the client port that can not be used
+#[derive(Debug)]
+pub enum CertificateError {
+    Unreadable { path: PathBuf, error: std::io::Error },
+    /// No pem of the expected kind, or certificate and key do not belong together
+    Invalid { path: PathBuf, reason: String },
+}
+
+impl Display for CertificateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CertificateError::Unreadable {path, error} => write!(f, "Reading {} failed: {}", path.display(), error),
+            CertificateError::Invalid {path, reason} => write!(f, "{} is unusable: {}", path.display(), reason),
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+/// Reads certificate and key (pem) and builds the acceptor
+/// The key has to be PKCS#8 and belong to the first certificate of the chain
+pub async fn load_acceptor(tls: &TlsConfig) -> Result<Arc<tokio_native_tls::TlsAcceptor>, CertificateError> {
+    let cert = read(&tls.cert).await?;
+    info!("load_acceptor(..): Reading cert successful, {} bytes", cert.len());
+    let key = read(&tls.key).await?;
+    info!("load_acceptor(..): Reading key successful, {} bytes", key.len());
+    parse_identity(tls, &cert, &key)?;
+
+    let invalid = |path: &PathBuf, reason: String| CertificateError::Invalid {path: path.clone(), reason};
+    let identity = Identity::from_pkcs8(&cert, &key).map_err(|e| invalid(&tls.key, format!("No PKCS#8 key ({})", e)))?;
+    let acceptor = TlsAcceptor::builder(identity).build().map_err(|e| invalid(&tls.cert, format!("Creating the TLS acceptor failed ({})", e)))?;
+    Ok(Arc::new(tokio_native_tls::TlsAcceptor::from(acceptor)))
+}
+
+/// Builds the acceptor of the host ports from certificate, key and the CA bundle if any (all pem)
+/// With a bundle, hosts without a certificate issued by one of the CAs fail the handshake
+pub async fn load_host_acceptor(tls: &TlsConfig) -> Result<Arc<SslAcceptor>, CertificateError> {
+    let cert = read(&tls.cert).await?;
+    let key = read(&tls.key).await?;
+    let (_, private_key) = parse_identity(tls, &cert, &key)?;
+    let authorities = match tls.host_ca.as_ref() {
+        Some(ca) => parse_bundle(ca, &read(ca).await?)?,
+        None => vec![],
+    };
+
+    let invalid = |path: &PathBuf, reason: String| CertificateError::Invalid {path: path.clone(), reason};
+    let chain = X509::stack_from_pem(&cert).map_err(|e| invalid(&tls.cert, format!("No pem certificate ({})", e)))?;
+
+    let setup = || -> Result<SslAcceptor, ErrorStack> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        let mut chain = chain.iter();
+        if let Some(certificate) = chain.next() {
+            builder.set_certificate(certificate)?;
+        }
+        for certificate in chain {
+            builder.add_extra_chain_cert(certificate.clone())?;
+        }
+        builder.set_private_key(&private_key)?;
+        builder.check_private_key()?;
+        for authority in &authorities {
+            builder.cert_store_mut().add_cert(authority.clone())?;
+            builder.add_client_ca(authority)?;
+        }
+        if tls.host_ca.is_some() {
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+        Ok(builder.build())
+    };
+    let acceptor = setup().map_err(|e| invalid(&tls.key, format!("Creating the host TLS acceptor failed ({})", e)))?;
+    match tls.host_ca.as_ref() {
+        Some(ca) => info!("load_host_acceptor(..): Hosts have to present a certificate issued by {} ({} certificates)", ca.display(), authorities.len()),
+        None => info!("load_host_acceptor(..): Hosts connect over TLS without a certificate"),
+    }
+    Ok(Arc::new(acceptor))
+}
+
+/// Checks the files without building acceptors: certificate and key (if 'client' or the host ports
+/// speak TLS) and the CA bundle for hosts (if any), every problem found is returned
+pub fn check_files(tls: &TlsConfig, client: bool) -> Vec<CertificateError> {
+    let read = |path: &PathBuf| std::fs::read(path).map_err(|error| CertificateError::Unreadable {path: path.clone(), error});
+    let mut errors = vec![];
+    if client || tls.host_tls_enabled() {
+        match (read(&tls.cert), read(&tls.key)) {
+            (Ok(cert), Ok(key)) => errors.extend(parse_identity(tls, &cert, &key).err()),
+            (cert, key) => errors.extend(cert.err().into_iter().chain(key.err())),
+        }
+    }
+    if let Some(ca) = tls.host_ca.as_ref() {
+        errors.extend(read(ca).and_then(|bundle| parse_bundle(ca, &bundle)).err());
+    }
+    errors
+}
+
+/// Parses certificate and key (pem), the key has to belong to the first certificate
+fn parse_identity(tls: &TlsConfig, cert: &[u8], key: &[u8]) -> Result<(X509, PKey<Private>), CertificateError> {
+    let invalid = |path: &PathBuf, reason: String| CertificateError::Invalid {path: path.clone(), reason};
+    let certificate = X509::from_pem(cert).map_err(|e| invalid(&tls.cert, format!("No pem certificate ({})", e)))?;
+    let private_key = PKey::private_key_from_pem(key).map_err(|e| invalid(&tls.key, format!("No pem private key ({})", e)))?;
+    let matching = certificate.public_key().map(|public_key| public_key.public_eq(&private_key)).unwrap_or(false);
+    if !matching {
+        return Err(invalid(&tls.key, format!("The key does not belong to the certificate {}", tls.cert.display())))
+    }
+    Ok((certificate, private_key))
+}
+
+fn parse_bundle(path: &Path, bundle: &[u8]) -> Result<Vec<X509>, CertificateError> {
+    let invalid = |reason: String| CertificateError::Invalid {path: path.to_path_buf(), reason};
+    let authorities = X509::stack_from_pem(bundle).map_err(|e| invalid(format!("No pem certificates ({})", e)))?;
+    if authorities.is_empty() {
+        return Err(invalid(String::from("The bundle holds no certificate")))
+    }
+    Ok(authorities)
+}
+
+async fn read(path: &PathBuf) -> Result<Vec<u8>, CertificateError> {
+    tokio::fs::read(path).await.map_err(|error| CertificateError::Unreadable {path: path.clone(), error})
+}
+
+/// Watches the certificate files from the initial acceptor on, in an own task
+pub fn watch_certificate(tls: TlsConfig, acceptor: Option<Arc<tokio_native_tls::TlsAcceptor>>) -> AcceptorWatch {
+    let (sender, receiver) = watch::channel(acceptor);
+    // Registered before the accept loop starts, SIGHUP terminates the process otherwise
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("watch_certificate(..): Listening for SIGHUP failed, the certificate is only reloaded on change\nError: {}", e);
+            None
+        }
+    };
+    tokio::spawn(async move {
+        let mut modified = modification_times(&tls).await;
+        loop {
+            let forced = tokio::select! {
+                _ = hangup_or_forever(&mut hangup) => true,
+                _ = RELOAD.notified() => true,
+                _ = sleep_or_forever(tls.reload_interval) => false,
+            };
+            if sender.is_closed() {
+                return
+            }
+            let current = modification_times(&tls).await;
+            if !forced && current == modified {
+                continue
+            }
+            // Not retried until the files change again, a renewal may write them one by one
+            modified = current;
+            match load_acceptor(&tls).await {
+                Ok(acceptor) => {
+                    info!("watch_certificate(..): Reloaded the certificate {}", tls.cert.display());
+                    let _ = sender.send(Some(acceptor));
+                }
+                Err(e) => warn!("watch_certificate(..): Reloading the certificate failed, keeping the previous one\nError: {}", e),
+            }
+        }
+    });
+    receiver
+}
+
+async fn modification_times(tls: &TlsConfig) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |metadata: std::io::Result<std::fs::Metadata>| metadata.ok().and_then(|m| m.modified().ok());
+    (modified(tokio::fs::metadata(&tls.cert).await), modified(tokio::fs::metadata(&tls.key).await))
+}
+
+async fn hangup_or_forever(hangup: &mut Option<Signal>) {
+    match hangup {
+        Some(hangup) => {
+            hangup.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Polling is disabled without an interval, only SIGHUP reloads then
+async fn sleep_or_forever(interval: Option<Duration>) {
+    match interval {
+        Some(interval) => tokio::time::sleep(interval).await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,128 @@
+//!
+//! Challenge clients have to solve before their login is accepted.
+//! Optional (TT_BACKEND_CHALLENGE), meant for fully public sessions: every websocket gets a
+//! 'Challenge' right after the upgrade and the 'ClientLogin' has to carry the solution as 'proof'
+//! (logins without a proof are ignored, a wrong proof rejects the login).
+//! The proof-of-work nonce is an HMAC of the connection under a key only the server knows, the
+//! client has to find a proof whose SHA-256 with the nonce starts with 'difficulty' zero bits.
+//! A CAPTCHA is verified with the siteverify endpoint of the provider instead.
+//!
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use log::warn;
+use rand::RngCore;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use crate::server::http_client::{post_json, Url};
+use crate::server::messages::current_timestamp;
+
+pub const REJECT_REASON_CHALLENGE_FAILED: &str = "Challenge not solved";
+/// Difficulties above make logins take minutes on phones
+pub const MAX_DIFFICULTY: u32 = 32;
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Challenge configured for the client listener
+#[derive(Debug, Clone)]
+pub enum ChallengeConfig {
+    ProofOfWork { difficulty: u32 },
+    Captcha { verify_url: Url, site_key: String, secret: String },
+}
+
+impl ChallengeConfig {
+    /// Parses 'pow:<difficulty>' or 'captcha:<verify url>', the site key and the secret of the
+    /// CAPTCHA are configured separately
+    pub fn parse(spec: &str, site_key: Option<String>, secret: Option<String>) -> Result<Self, String> {
+        match spec.split_once(':') {
+            Some(("pow", difficulty)) => match difficulty.trim().parse::<u32>() {
+                Ok(difficulty) if (1..=MAX_DIFFICULTY).contains(&difficulty) => Ok(ChallengeConfig::ProofOfWork {difficulty}),
+                _ => Err(format!("Invalid proof-of-work difficulty '{}', expected 1 to {}", difficulty, MAX_DIFFICULTY)),
+            },
+            Some(("captcha", url)) => match (site_key, secret) {
+                (Some(site_key), Some(secret)) => Ok(ChallengeConfig::Captcha {verify_url: Url::parse(url)?, site_key, secret}),
+                _ => Err(String::from("A CAPTCHA challenge needs a site key and a secret")),
+            },
+            _ => Err(format!("Invalid challenge '{}', expected 'pow:<difficulty>' or 'captcha:<verify url>'", spec)),
+        }
+    }
+}
+
+/// Challenge sent to one client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssuedChallenge {
+    ProofOfWork { nonce: String, difficulty: u32 },
+    Captcha { site_key: String },
+}
+
+/// Issues and checks the challenges of the client listener
+#[derive(Debug)]
+pub struct LoginChallenge {
+    config: ChallengeConfig,
+    /// Random per server start, nonces of another instance are useless
+    key: [u8; 32],
+}
+
+impl LoginChallenge {
+    pub fn new(config: ChallengeConfig) -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        LoginChallenge { config, key }
+    }
+
+    /// Challenge for the connection, unique per connection and moment
+    pub fn issue(&self, address: SocketAddr) -> IssuedChallenge {
+        match &self.config {
+            ChallengeConfig::ProofOfWork {difficulty} => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key).expect("issue(..): HMAC accepts keys of any size");
+                mac.update(format!("{}|{}", address, current_timestamp()).as_bytes());
+                IssuedChallenge::ProofOfWork {nonce: URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()), difficulty: *difficulty}
+            }
+            ChallengeConfig::Captcha {site_key, ..} => IssuedChallenge::Captcha {site_key: site_key.clone()},
+        }
+    }
+
+    /// Checks the proof presented for the issued challenge
+    pub async fn verify(&self, issued: &IssuedChallenge, proof: Option<&str>, address: SocketAddr) -> bool {
+        let proof = match proof {
+            None => return false,
+            Some(v) => v,
+        };
+        match (issued, &self.config) {
+            (IssuedChallenge::ProofOfWork {nonce, difficulty}, _) => leading_zero_bits(&Sha256::digest(format!("{}:{}", nonce, proof))) >= *difficulty,
+            (IssuedChallenge::Captcha {..}, ChallengeConfig::Captcha {verify_url, secret, ..}) => verify_captcha(verify_url, secret, proof, address).await,
+            (IssuedChallenge::Captcha {..}, _) => false,
+        }
+    }
+}
+
+/// Asks the CAPTCHA provider whether the response token is valid
+/// An unreachable provider fails the challenge
+async fn verify_captcha(url: &Url, secret: &str, response: &str, address: SocketAddr) -> bool {
+    let request = json!({"secret": secret, "response": response, "remoteip": address.ip().to_string()});
+    let body = match post_json(url, &request, VERIFY_TIMEOUT).await {
+        Ok((status, body)) if (200..300).contains(&status) => body,
+        Ok((status, _)) => {
+            warn!("verify_captcha(..): CAPTCHA provider answered with status {}", status);
+            return false
+        }
+        Err(e) => {
+            warn!("verify_captcha(..): Calling CAPTCHA provider failed!\nError: {}", e);
+            return false
+        }
+    };
+    serde_json::from_str::<Value>(&body).ok().and_then(|response| response["success"].as_bool()) == Some(true)
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break
+        }
+    }
+    bits
+}
@@ -0,0 +1,66 @@
+//!
+//! Incremental sync of the client list for the host.
+//! Hosts tracking thousands of clients poll 'SyncClientList' with the version of the list they
+//! hold and are answered with 'ClientListDiff': the clients that joined ('added'), left ('removed',
+//! by address) or whose counters changed ('updated') since that version. The server remembers the
+//! list it sent last, a host asking for another version (or none) gets the whole list as 'added'
+//! with 'full' set and replaces its list. The baseline is dropped whenever another host takes over.
+//!
+
+use std::collections::HashMap;
+use crate::server::messages::BackendMessage;
+use crate::server::networking::ClientStats;
+
+/// The client list as last sent to the host
+#[derive(Debug, Default)]
+pub struct ClientListSync {
+    version: u64,
+    /// Clients by address, None until the host synced once
+    sent: Option<HashMap<String, ClientStats>>,
+}
+
+impl ClientListSync {
+    /// 'ClientListDiff' from the version the host holds to the current clients, which become the
+    /// baseline
+    pub fn sync(&mut self, known: Option<u64>, current: Vec<ClientStats>) -> BackendMessage {
+        let current: HashMap<String, ClientStats> = current.into_iter().map(|client| (client.address.clone(), client)).collect();
+        let baseline = self.sent.take().filter(|_| known == Some(self.version));
+        self.version += 1;
+
+        let diff = match baseline {
+            None => BackendMessage::ClientListDiff {
+                version: self.version,
+                full: true,
+                added: sorted(current.values().cloned().collect()),
+                updated: vec![],
+                removed: vec![],
+            },
+            Some(sent) => {
+                let mut added = vec![];
+                let mut updated = vec![];
+                for (address, client) in &current {
+                    match sent.get(address) {
+                        None => added.push(client.clone()),
+                        Some(previous) if previous != client => updated.push(client.clone()),
+                        Some(_) => {}
+                    }
+                }
+                let mut removed: Vec<String> = sent.into_keys().filter(|address| !current.contains_key(address)).collect();
+                removed.sort();
+                BackendMessage::ClientListDiff {version: self.version, full: false, added: sorted(added), updated: sorted(updated), removed}
+            }
+        };
+        self.sent = Some(current);
+        diff
+    }
+
+    /// Forgets the list sent, the next sync is a full one
+    pub fn reset(&mut self) {
+        self.sent = None;
+    }
+}
+
+fn sorted(mut clients: Vec<ClientStats>) -> Vec<ClientStats> {
+    clients.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.address.cmp(&b.address)));
+    clients
+}
@@ -0,0 +1,143 @@
+//!
+//! Serialization formats of the client websocket.
+//! Messages are built as json values and encoded by the codec of the connection: JSON as text
+//! frames, MessagePack and CBOR as binary frames. Clients pick the codec with the optional 'codec'
+//! of 'ClientLogin' ("json" if missing), everything before the login is JSON. Clients may always
+//! send text frames (JSON), binary frames are decoded with the codec of the connection.
+//! Broadcasts are encoded once per codec in use, not once per client.
+//!
+
+use std::fmt::Debug;
+use serde_json::Value;
+use crate::server::factory::StampedMessage;
+use crate::server::messages::BackendMessage;
+
+pub const CODEC_JSON: &str = "json";
+pub const CODEC_MSGPACK: &str = "msgpack";
+pub const CODEC_CBOR: &str = "cbor";
+
+/// Encoding of the messages of one connection
+pub trait WireCodec: Send + Sync + Debug {
+    fn name(&self) -> &'static str;
+    fn encode(&self, json: &Value) -> WireFrame;
+    /// None if the frame is no valid encoding
+    fn decode(&self, frame: &[u8]) -> Option<Value>;
+}
+
+/// An encoded message, sent as a text or binary websocket frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl WireFrame {
+    pub fn len(&self) -> usize {
+        match self {
+            WireFrame::Text(text) => text.len(),
+            WireFrame::Binary(bytes) => bytes.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonCodec;
+
+impl WireCodec for JsonCodec {
+    fn name(&self) -> &'static str {
+        CODEC_JSON
+    }
+
+    fn encode(&self, json: &Value) -> WireFrame {
+        WireFrame::Text(json.to_string())
+    }
+
+    fn decode(&self, frame: &[u8]) -> Option<Value> {
+        serde_json::from_slice(frame).ok()
+    }
+}
+
+/// MessagePack with maps keyed by field name, like the JSON objects
+#[derive(Debug)]
+pub struct MsgPackCodec;
+
+impl WireCodec for MsgPackCodec {
+    fn name(&self) -> &'static str {
+        CODEC_MSGPACK
+    }
+
+    fn encode(&self, json: &Value) -> WireFrame {
+        WireFrame::Binary(rmp_serde::to_vec_named(json).expect("encode(..): Json values are always encodable"))
+    }
+
+    fn decode(&self, frame: &[u8]) -> Option<Value> {
+        rmp_serde::from_slice(frame).ok()
+    }
+}
+
+#[derive(Debug)]
+pub struct CborCodec;
+
+impl WireCodec for CborCodec {
+    fn name(&self) -> &'static str {
+        CODEC_CBOR
+    }
+
+    fn encode(&self, json: &Value) -> WireFrame {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(json, &mut bytes).expect("encode(..): Json values are always encodable");
+        WireFrame::Binary(bytes)
+    }
+
+    fn decode(&self, frame: &[u8]) -> Option<Value> {
+        ciborium::de::from_reader(frame).ok()
+    }
+}
+
+pub static JSON: JsonCodec = JsonCodec;
+pub static MSGPACK: MsgPackCodec = MsgPackCodec;
+pub static CBOR: CborCodec = CborCodec;
+
+const CODECS: [&'static dyn WireCodec; 3] = [&JSON, &MSGPACK, &CBOR];
+
+/// Codec of the given name, None if it is not supported
+pub fn codec_by_name(name: &str) -> Option<&'static dyn WireCodec> {
+    CODECS.iter().copied().find(|codec| codec.name() == name)
+}
+
+/// Names of the supported codecs, separated by ', '
+pub fn supported_codecs() -> String {
+    CODECS.iter().map(|codec| codec.name()).collect::<Vec<&str>>().join(", ")
+}
+
+/// A stamped message with its encodings, each codec encodes it at most once
+#[derive(Debug)]
+pub struct EncodedMessage {
+    /// State delivered by the message, if it is a 'ChangeState'
+    state_id: Option<i32>,
+    json: Value,
+    frames: Vec<(&'static str, WireFrame)>,
+}
+
+impl EncodedMessage {
+    pub fn new(msg: StampedMessage) -> Self {
+        let state_id = match msg.message() {
+            BackendMessage::ChangeState {state_id, ..} => Some(*state_id),
+            _ => None,
+        };
+        EncodedMessage {state_id, json: msg.into_json(), frames: Vec::new()}
+    }
+
+    pub fn state_id(&self) -> Option<i32> {
+        self.state_id
+    }
+
+    pub fn frame(&mut self, codec: &dyn WireCodec) -> WireFrame {
+        if let Some((_, frame)) = self.frames.iter().find(|(name, _)| *name == codec.name()) {
+            return frame.clone()
+        }
+        let frame = codec.encode(&self.json);
+        self.frames.push((codec.name(), frame.clone()));
+        frame
+    }
+}
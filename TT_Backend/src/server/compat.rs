@@ -0,0 +1,52 @@
+//!
+//! Compatibility with the installed base of HostApps speaking the protocol before the frame magic.
+//! The protocol is detected from the first frame of a host: current hosts start every frame with
+//! HOST_FRAME_MAGIC (or HOST_CHUNK_MAGIC), legacy hosts send the bare length prefix. Legacy hosts
+//! keep their framing (no magic, no checksums, no chunks) and never send a 'HostLogin', the reader
+//! logs them in without an api key. Their messages are a subset of the current ones and parse as
+//! they are, only the way back is translated: a legacy host gets the messages it knows, without
+//! 'meta', and inputs carry the 'content' field it reads. Messages to a host are held back until
+//! its first frame tells the protocol.
+//! Clients need no translation, the web app is loaded with every visit and ignores unknown
+//! messages and fields anyway.
+//!
+
+use crate::server::messages::{BackendMessage, encode_backend_json};
+use crate::server::networking::{HOST_CHUNK_MAGIC, HOST_FRAME_MAGIC, HOST_MAX_FRAME_SIZE};
+
+/// Messages held back per host until its protocol is known, older ones are dropped
+pub const MAX_HELD_MESSAGES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostProtocol {
+    /// Length prefixed frames without magic
+    Legacy,
+    Current,
+}
+
+impl HostProtocol {
+    /// Protocol of a host by the first four bytes it sent
+    /// Anything neither magic nor a possible length is garbage, scanned past as by current hosts
+    pub fn detect(first: [u8; 4]) -> Self {
+        if first == HOST_FRAME_MAGIC || first == HOST_CHUNK_MAGIC || u32::from_be_bytes(first) > HOST_MAX_FRAME_SIZE {
+            HostProtocol::Current
+        } else {
+            HostProtocol::Legacy
+        }
+    }
+}
+
+/// Wire format of the message for a legacy host, None if the legacy host does not know it
+/// The metadata of stamped messages is left out
+pub fn encode_legacy(msg: BackendMessage) -> Option<String> {
+    let known = matches!(msg, BackendMessage::ClientConnected {..} | BackendMessage::ClientDisconnected {..}
+        | BackendMessage::Disconnect {..} | BackendMessage::Input {..});
+    if !known {
+        return None
+    }
+    let mut json = encode_backend_json(msg);
+    if json["type"] == "Input" {
+        json["content"] = json["input"].clone();
+    }
+    Some(json.to_string())
+}
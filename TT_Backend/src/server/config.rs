@@ -0,0 +1,819 @@
+//!
+//! Settings of the server.
+//! Every setting has a default, so the server runs without any configuration.
+//! Before the server starts the settings are validated together (ports, certificate files,
+//! conflicting options), every problem found is reported at once.
+//!
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::server::CHANNEL_SIZE;
+use crate::server::challenge::ChallengeConfig;
+use crate::server::config_file::ConfigFile;
+use crate::server::connection_limit::ConnectionLimit;
+use crate::server::digest::{DEFAULT_DIGEST_FROM, DigestTarget};
+use crate::server::descriptors::DEFAULT_FD_RESERVE;
+use crate::server::rejoin::DEFAULT_REJOIN_TTL;
+use crate::server::dns::DEFAULT_DNS_REFRESH;
+use crate::server::http_client::Url;
+use crate::server::logging::validate_filter;
+use crate::server::networking::ListenerRole;
+use crate::server::no_host::NoHostBehavior;
+use crate::server::reconnect::ReconnectBackoff;
+use crate::server::proxy::TrustedProxies;
+use crate::server::replication::DEFAULT_FAILOVER_TIMEOUT;
+use crate::server::retention::DEFAULT_RETENTION_INTERVAL;
+use crate::server::certificates::{check_files, DEFAULT_CERT_RELOAD_INTERVAL};
+use crate::server::acme::AcmeConfig;
+use crate::server::session::SessionLimits;
+use crate::server::stdio_host::HostStdio;
+use crate::server::timefmt::{Locale, TimeFormat};
+use crate::server::usage::{DEFAULT_USAGE_INTERVAL, UsageExport};
+#[cfg(feature = "soak")]
+use crate::server::soak::SoakRates;
+#[cfg(feature = "wasm_plugins")]
+use crate::server::plugins::PluginLimits;
+
+pub const AUTH_ENV: &str = "TT_BACKEND_AUTH";
+pub const KEEPALIVE_ENV: &str = "TT_BACKEND_KEEPALIVE";
+pub const KEEPALIVE_INTERVAL_ENV: &str = "TT_BACKEND_KEEPALIVE_INTERVAL";
+pub const HOST_NODELAY_ENV: &str = "TT_BACKEND_HOST_NODELAY";
+pub const SEND_BUFFER_ENV: &str = "TT_BACKEND_SEND_BUFFER";
+pub const RECV_BUFFER_ENV: &str = "TT_BACKEND_RECV_BUFFER";
+pub const SEND_TIMEOUT_ENV: &str = "TT_BACKEND_SEND_TIMEOUT";
+pub const DISCONNECT_GRACE_ENV: &str = "TT_BACKEND_DISCONNECT_GRACE";
+pub const TEAMS_ENV: &str = "TT_BACKEND_TEAMS";
+pub const TENANTS_ENV: &str = "TT_BACKEND_TENANTS";
+pub const TEMPLATES_ENV: &str = "TT_BACKEND_TEMPLATES";
+pub const LISTEN_HOST_ENV: &str = "TT_BACKEND_LISTEN_HOST";
+/// Alias of LISTEN_HOST_ENV, which wins if both are set
+pub const LISTEN_IP_ENV: &str = "TT_BACKEND_IP";
+pub const WS_PORT_ENV: &str = "TT_BACKEND_WS_PORT";
+pub const TLS_CERT_ENV: &str = "TT_BACKEND_CERT_PATH";
+pub const TLS_KEY_ENV: &str = "TT_BACKEND_KEY_PATH";
+pub const INSECURE_WS_ENV: &str = "TT_BACKEND_INSECURE_WS";
+pub const CERT_RELOAD_INTERVAL_ENV: &str = "TT_BACKEND_CERT_RELOAD_INTERVAL";
+pub const HOST_CA_ENV: &str = "TT_BACKEND_HOST_CA";
+pub const HOST_TLS_ENV: &str = "TT_BACKEND_HOST_TLS";
+pub const CHANNEL_SIZE_ENV: &str = "TT_BACKEND_CHANNEL_SIZE";
+/// Log filter (RUST_LOG syntax), RUST_LOG itself takes precedence
+pub const LOG_LEVEL_ENV: &str = "TT_BACKEND_LOG_LEVEL";
+pub const TCP_PORT_ENV: &str = "TT_BACKEND_TCP_PORT";
+pub const SHADOW_PORT_ENV: &str = "TT_BACKEND_SHADOW_PORT";
+pub const ADMIN_PORT_ENV: &str = "TT_BACKEND_ADMIN_PORT";
+pub const ADMIN_IP_ENV: &str = "TT_BACKEND_ADMIN_IP";
+pub const ADMIN_SECRET_ENV: &str = "TT_BACKEND_ADMIN_SECRET";
+pub const ADVERTISED_HOST_ENV: &str = "TT_BACKEND_ADVERTISED_HOST";
+pub const DNS_REFRESH_ENV: &str = "TT_BACKEND_DNS_REFRESH";
+pub const ADVERTISED_URL_ENV: &str = "TT_BACKEND_ADVERTISED_URL";
+pub const BIND_RETRIES_ENV: &str = "TT_BACKEND_BIND_RETRIES";
+pub const WS_ALTERNATIVE_PORTS_ENV: &str = "TT_BACKEND_WS_ALTERNATIVE_PORTS";
+pub const TCP_ALTERNATIVE_PORTS_ENV: &str = "TT_BACKEND_TCP_ALTERNATIVE_PORTS";
+pub const SHADOW_ALTERNATIVE_PORTS_ENV: &str = "TT_BACKEND_SHADOW_ALTERNATIVE_PORTS";
+pub const ADMIN_ALTERNATIVE_PORTS_ENV: &str = "TT_BACKEND_ADMIN_ALTERNATIVE_PORTS";
+pub const TRUSTED_PROXIES_ENV: &str = "TT_BACKEND_TRUSTED_PROXIES";
+pub const USAGE_EXPORT_ENV: &str = "TT_BACKEND_USAGE_EXPORT";
+pub const USAGE_INTERVAL_ENV: &str = "TT_BACKEND_USAGE_INTERVAL";
+pub const SESSION_MAX_DURATION_ENV: &str = "TT_BACKEND_SESSION_MAX_DURATION";
+pub const SESSION_END_AT_ENV: &str = "TT_BACKEND_SESSION_END_AT";
+pub const SESSION_WARNING_ENV: &str = "TT_BACKEND_SESSION_WARNING";
+pub const ANNOUNCEMENT_ENV: &str = "TT_BACKEND_ANNOUNCEMENT";
+pub const REPLICATION_PORT_ENV: &str = "TT_BACKEND_REPLICATION_PORT";
+pub const REPLICATION_IP_ENV: &str = "TT_BACKEND_REPLICATION_IP";
+pub const LIVE_VIEW_PORT_ENV: &str = "TT_BACKEND_LIVE_VIEW_PORT";
+pub const STANDBY_OF_ENV: &str = "TT_BACKEND_STANDBY_OF";
+pub const FAILOVER_TIMEOUT_ENV: &str = "TT_BACKEND_FAILOVER_TIMEOUT";
+pub const DIGEST_ENV: &str = "TT_BACKEND_DIGEST";
+pub const NO_HOST_ENV: &str = "TT_BACKEND_NO_HOST";
+pub const RECONNECT_BACKOFF_ENV: &str = "TT_BACKEND_RECONNECT_BACKOFF";
+pub const DIGEST_FROM_ENV: &str = "TT_BACKEND_DIGEST_FROM";
+pub const DIGEST_LINK_ENV: &str = "TT_BACKEND_DIGEST_LINK";
+pub const GUEST_TTL_ENV: &str = "TT_BACKEND_GUEST_TTL";
+pub const MAX_CLIENTS_PER_IP_ENV: &str = "TT_BACKEND_MAX_CLIENTS_PER_IP";
+pub const SHARED_DEVICES_ENV: &str = "TT_BACKEND_SHARED_DEVICES";
+pub const INTEGRITY_ENV: &str = "TT_BACKEND_INTEGRITY";
+pub const TIMEZONE_ENV: &str = "TT_BACKEND_TIMEZONE";
+pub const LOCALE_ENV: &str = "TT_BACKEND_LOCALE";
+pub const CHALLENGE_ENV: &str = "TT_BACKEND_CHALLENGE";
+pub const CAPTCHA_SITE_KEY_ENV: &str = "TT_BACKEND_CAPTCHA_SITE_KEY";
+pub const CAPTCHA_SECRET_ENV: &str = "TT_BACKEND_CAPTCHA_SECRET";
+pub const RETENTION_DAYS_ENV: &str = "TT_BACKEND_RETENTION_DAYS";
+pub const RETENTION_INTERVAL_ENV: &str = "TT_BACKEND_RETENTION_INTERVAL";
+pub const HOST_STDIO_ENV: &str = "TT_BACKEND_HOST_STDIO";
+pub const STATE_QUORUM_ENV: &str = "TT_BACKEND_STATE_QUORUM";
+pub const ACME_DOMAIN_ENV: &str = "TT_BACKEND_ACME_DOMAIN";
+pub const ACME_EMAIL_ENV: &str = "TT_BACKEND_ACME_EMAIL";
+pub const ACME_DIRECTORY_ENV: &str = "TT_BACKEND_ACME_DIRECTORY";
+pub const ACME_HTTP_PORT_ENV: &str = "TT_BACKEND_ACME_HTTP_PORT";
+pub const ACME_ACCOUNT_KEY_ENV: &str = "TT_BACKEND_ACME_ACCOUNT_KEY";
+pub const FD_RESERVE_ENV: &str = "TT_BACKEND_FD_RESERVE";
+pub const ACCESS_CODE_ENV: &str = "TT_BACKEND_ACCESS_CODE";
+pub const REJOIN_TTL_ENV: &str = "TT_BACKEND_REJOIN_TTL";
+pub const HOST_SECRET_ENV: &str = "TT_BACKEND_HOST_SECRET";
+pub const SOAK_ENV: &str = "TT_BACKEND_SOAK";
+pub const PLUGINS_ENV: &str = "TT_BACKEND_PLUGINS";
+pub const SESSION_MEMORY_LIMIT_ENV: &str = "TT_BACKEND_SESSION_MEMORY_LIMIT";
+pub use crate::server::secrets::SECRET_KEY_ENV;
+
+/// Problems of a configuration, all of them are reported at once
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl Display for ConfigErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration")?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// Typed configuration handed to Server::new
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub auth: AuthConfig,
+    pub socket: SocketConfig,
+    pub tls: TlsConfig,
+    /// Events the internal channel holds before the tasks wait for the main handler
+    pub channel_size: usize,
+    /// Initial log filter (RUST_LOG syntax), RUST_LOG takes precedence
+    pub log_filter: Option<String>,
+    /// Time the inputs of a disconnected client stay attributed before the host is told to
+    /// remove the client from its results
+    pub disconnect_grace: Duration,
+    /// Teams clients are assigned to, any team picked by a client is accepted if empty
+    pub teams: Vec<String>,
+    pub session: SessionLimits,
+    /// File listing the tenants, every host is accepted if None
+    pub tenants: Option<PathBuf>,
+    /// Directory of the session templates, there are no templates if None
+    pub templates: Option<PathBuf>,
+    /// Target of the usage reports, usage is not exported if None
+    pub usage_export: Option<UsageExport>,
+    pub usage_interval: Duration,
+    /// Host name or IP address to listen on, the default of the binary is used if None
+    pub listen_host: Option<String>,
+    /// Ports of the client, host, shadow and admin listener, the defaults of the binary are used
+    /// for None
+    pub ports: [Option<u16>; 4],
+    /// Host name or IP address of the admin interface, the loopback address if None
+    pub admin_host: Option<String>,
+    /// Bearer secret of the admin requests besides '/health', they are all refused if None
+    pub admin_secret: Option<String>,
+    /// Public host name of the server, re-resolved every 'dns_refresh'
+    /// Defaults to the host of 'advertised_url'
+    pub advertised_host: Option<String>,
+    /// Url clients use to reach the server (e.g. behind NAT or a reverse proxy), independent of
+    /// the address the server listens on
+    pub advertised_url: Option<String>,
+    pub dns_refresh: Duration,
+    /// Proxies whose forwarding headers are honored
+    pub trusted_proxies: TrustedProxies,
+    pub bind: BindConfig,
+    /// Operator announcement shown to everybody from the start, e.g. about planned maintenance
+    pub announcement: Option<String>,
+    /// What clients see while no host is connected
+    pub no_host: NoHostBehavior,
+    /// Backoff suggested to disconnected clients, '<min_ms>:<max_ms>:<jitter>'
+    pub reconnect: ReconnectBackoff,
+    /// Port standbys connect to, the server does not replicate if None
+    pub replication_port: Option<u16>,
+    /// Host name or IP address of the replication listener (internal network), the loopback
+    /// address if None
+    pub replication_host: Option<String>,
+    /// Port of the public live view (http), there is no live view if None
+    pub live_view_port: Option<u16>,
+    /// Replication address (host:port) of the primary, the server starts as its standby if set
+    pub standby_of: Option<String>,
+    /// Silence of the primary after which the standby takes over
+    pub failover_timeout: Duration,
+    /// Target of the summary sent at the end of every session, no summary is sent if None
+    pub digest: Option<DigestTarget>,
+    /// Sender address of digest mails
+    pub digest_from: String,
+    /// Export link included in the digest, '{session}' is replaced by the session number
+    pub digest_link: Option<String>,
+    /// Time clients logged in without a token stay connected before they have to log in again,
+    /// unlimited if None
+    pub guest_ttl: Option<Duration>,
+    /// Simultaneous client connections per source IP, unlimited by default
+    pub connection_limit: ConnectionLimit,
+    /// Whether joins and inputs are checked for vote stuffing
+    pub integrity: bool,
+    /// Challenge clients solve before their login is accepted, none if None
+    pub challenge: Option<ChallengeConfig>,
+    /// Age after which stored data is deleted, unless the tenant has an own age
+    pub retention: Option<Duration>,
+    pub retention_interval: Duration,
+    /// Timezone and locale of the timestamps in csv reports, digest mails and the status page
+    pub time_format: TimeFormat,
+    /// Host connected over standard streams instead of the host port, 'parent' or
+    /// 'exec:<command>', none if None
+    pub host_stdio: Option<HostStdio>,
+    /// Percentage of the clients acknowledging a state the host is told about, not reported if
+    /// None
+    pub state_quorum: Option<u8>,
+    /// Certificate provisioning of the client port, the certificate is managed externally if None
+    pub acme: Option<AcmeConfig>,
+    /// File descriptors kept free, new clients are rejected once fewer are left
+    pub fd_reserve: usize,
+    /// Code clients have to give to join, the host may replace it during the session, everybody
+    /// may join if None
+    pub access_code: Option<String>,
+    /// Time a rejoin link can be used
+    pub rejoin_ttl: Duration,
+    /// Secret hosts have to present with 'HostLogin' before they become the active host, the
+    /// AuthProvider decides about hosts if None
+    pub host_secret: Option<String>,
+    /// Bytes the session may hold before its histories are trimmed, unlimited if None
+    pub session_memory_limit: Option<usize>,
+    /// Key material protecting the secrets of snapshots, generated per process if None
+    pub secret_key: Option<String>,
+    /// Rates of the synthetic clients, no soak test if None
+    #[cfg(feature = "soak")]
+    pub soak: Option<SoakRates>,
+    /// Limits of host-uploaded plugins, hosts can not load any if None
+    #[cfg(feature = "wasm_plugins")]
+    pub plugins: Option<PluginLimits>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            auth: Default::default(),
+            socket: Default::default(),
+            tls: Default::default(),
+            channel_size: CHANNEL_SIZE,
+            log_filter: None,
+            disconnect_grace: Default::default(),
+            teams: vec![],
+            session: Default::default(),
+            tenants: None,
+            templates: None,
+            usage_export: None,
+            usage_interval: DEFAULT_USAGE_INTERVAL,
+            listen_host: None,
+            admin_host: None,
+            admin_secret: None,
+            ports: [None; 4],
+            advertised_host: None,
+            advertised_url: None,
+            dns_refresh: DEFAULT_DNS_REFRESH,
+            trusted_proxies: Default::default(),
+            bind: Default::default(),
+            announcement: None,
+            no_host: Default::default(),
+            reconnect: Default::default(),
+            replication_port: None,
+            replication_host: None,
+            live_view_port: None,
+            standby_of: None,
+            failover_timeout: DEFAULT_FAILOVER_TIMEOUT,
+            digest: None,
+            digest_from: String::from(DEFAULT_DIGEST_FROM),
+            digest_link: None,
+            guest_ttl: None,
+            connection_limit: Default::default(),
+            integrity: false,
+            challenge: None,
+            retention: None,
+            retention_interval: DEFAULT_RETENTION_INTERVAL,
+            time_format: Default::default(),
+            host_stdio: None,
+            state_quorum: None,
+            acme: None,
+            fd_reserve: DEFAULT_FD_RESERVE,
+            access_code: None,
+            rejoin_ttl: DEFAULT_REJOIN_TTL,
+            host_secret: None,
+            session_memory_limit: None,
+            secret_key: None,
+            #[cfg(feature = "soak")]
+            soak: None,
+            #[cfg(feature = "wasm_plugins")]
+            plugins: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the configuration file (if any), the environment and the command line, in order of
+    /// increasing precedence
+    pub fn load(args: &[String]) -> Result<Self, String> {
+        let mut config = ServerConfig::default();
+        if let Some(path) = ConfigFile::locate(args) {
+            config.apply_file(ConfigFile::load(&path)?)?;
+        }
+        config.apply_env()?;
+        config.apply_args(args)?;
+        Ok(config)
+    }
+
+    /// Checks the combination of settings and the files they point to, 'default_ports' are the
+    /// ports of the client, host, shadow and admin listener used for None
+    /// Every problem is collected instead of failing at the first one
+    pub fn validate(&self, default_ports: [u16; 4]) -> Result<(), ConfigErrors> {
+        let mut errors = vec![];
+
+        let [ws, tcp, shadow, admin] = self.ports;
+        let ports = [
+            (WS_PORT_ENV, Some(ws.unwrap_or(default_ports[0]))),
+            (TCP_PORT_ENV, Some(tcp.unwrap_or(default_ports[1]))),
+            (SHADOW_PORT_ENV, Some(shadow.unwrap_or(default_ports[2]))),
+            (ADMIN_PORT_ENV, Some(admin.unwrap_or(default_ports[3]))),
+            (REPLICATION_PORT_ENV, self.replication_port),
+            (LIVE_VIEW_PORT_ENV, self.live_view_port),
+            (ACME_HTTP_PORT_ENV, self.acme.as_ref().map(|acme| acme.http_port)),
+        ];
+        let ports: Vec<(&str, u16)> = ports.into_iter().filter_map(|(name, port)| port.map(|port| (name, port))).collect();
+        for (index, (name, port)) in ports.iter().enumerate() {
+            if *port == 0 {
+                errors.push(format!("{} is 0, expected a port from 1 to 65535", name));
+            } else if let Some((other, _)) = ports[..index].iter().find(|(_, other)| other == port) {
+                errors.push(format!("{} and {} are both {}, every listener needs its own port", other, name, port));
+            }
+        }
+
+        if self.tls.insecure_ws {
+            if self.acme.is_some() {
+                errors.push(format!("{} conflicts with {}, the certificate would not be used", INSECURE_WS_ENV, ACME_DOMAIN_ENV));
+            }
+            // Plain websockets are meant for local development or behind a proxy terminating TLS
+            let public = self.listen_host.as_deref()
+                .and_then(|host| host.parse::<IpAddr>().ok())
+                .map(|ip| !ip.is_loopback());
+            if public == Some(true) && self.trusted_proxies.is_empty() {
+                errors.push(format!("{} on the public address {} sends logins in plain text, listen on a loopback address or set {} to the proxy terminating TLS",
+                    INSECURE_WS_ENV, self.listen_host.as_deref().unwrap_or_default(), TRUSTED_PROXIES_ENV));
+            }
+        }
+        // The admin interface changes the running server, outside of the machine only with a secret
+        let public_admin = self.admin_host.as_deref()
+            .map(|host| host != "localhost" && host.parse::<IpAddr>().map(|ip| !ip.is_loopback()).unwrap_or(true));
+        if public_admin == Some(true) && self.admin_secret.is_none() {
+            errors.push(format!("{} is the public address {}, set {} to authenticate the admin requests",
+                ADMIN_IP_ENV, self.admin_host.as_deref().unwrap_or_default(), ADMIN_SECRET_ENV));
+        }
+        // Primary and standbys decrypt each other's snapshots, a generated key would differ
+        if (self.replication_port.is_some() || self.standby_of.is_some()) && self.secret_key.is_none() {
+            errors.push(format!("Replication needs {}, the same on the primary and its standbys", SECRET_KEY_ENV));
+        }
+        // ACME provides a missing certificate itself
+        let client_certificate = !self.tls.insecure_ws && self.acme.is_none();
+        errors.extend(check_files(&self.tls, client_certificate).into_iter().map(|e| e.to_string()));
+
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors))
+        }
+        Ok(())
+    }
+
+    /// Takes the settings of the configuration file, unknown settings are rejected
+    pub fn apply_file(&mut self, mut file: ConfigFile) -> Result<(), String> {
+        if let Some(v) = file.take_string("listen.host")? {
+            self.listen_host = Some(v);
+        }
+        if let Some(v) = file.take_string("listen.admin_ip")? {
+            self.admin_host = Some(v);
+        }
+        for (port, key) in self.ports.iter_mut().zip(["listen.ws_port", "listen.tcp_port", "listen.shadow_port", "listen.admin_port"]) {
+            if let Some(v) = file.take_integer(key)? {
+                *port = Some(v);
+            }
+        }
+        if let Some(v) = file.take_string("tls.cert")? {
+            self.tls.cert = PathBuf::from(v);
+        }
+        if let Some(v) = file.take_string("tls.key")? {
+            self.tls.key = PathBuf::from(v);
+        }
+        if let Some(v) = file.take_boolean("tls.insecure_ws")? {
+            self.tls.insecure_ws = v;
+        }
+        if let Some(v) = file.take_integer("tls.reload_interval")? {
+            self.tls.reload_interval = reload_interval(v);
+        }
+        if let Some(v) = file.take_boolean("tls.host_tls")? {
+            self.tls.host_tls = v;
+        }
+        if let Some(v) = file.take_string("tls.host_ca")? {
+            self.tls.host_ca = Some(PathBuf::from(v)).filter(|path| !path.as_os_str().is_empty());
+        }
+        if let Some(v) = file.take_integer("server.channel_size")? {
+            self.channel_size = v;
+        }
+        if let Some(v) = file.take_string("server.log_level")? {
+            validate_filter(&v)?;
+            self.log_filter = Some(v);
+        }
+        if let Some(v) = file.take_string("host.stdio")? {
+            self.host_stdio = Some(HostStdio::parse(&v)?);
+        }
+        file.finish()
+    }
+
+    /// Takes the settings of the environment, unset variables keep the current ones
+    fn apply_env(&mut self) -> Result<(), String> {
+        let config = self;
+        if let Ok(spec) = env::var(AUTH_ENV) {
+            config.auth = AuthConfig::parse(&spec)?;
+        }
+        if let Ok(v) = env::var(KEEPALIVE_ENV) {
+            config.socket.keepalive_time = Some(Duration::from_secs(parse_env(KEEPALIVE_ENV, &v)?));
+        }
+        if let Ok(v) = env::var(KEEPALIVE_INTERVAL_ENV) {
+            config.socket.keepalive_interval = Some(Duration::from_secs(parse_env(KEEPALIVE_INTERVAL_ENV, &v)?));
+        }
+        if let Ok(v) = env::var(HOST_NODELAY_ENV) {
+            config.socket.host_nodelay = parse_env(HOST_NODELAY_ENV, &v)?;
+        }
+        if let Ok(v) = env::var(SEND_BUFFER_ENV) {
+            config.socket.send_buffer_size = Some(parse_env(SEND_BUFFER_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(RECV_BUFFER_ENV) {
+            config.socket.recv_buffer_size = Some(parse_env(RECV_BUFFER_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(SEND_TIMEOUT_ENV) {
+            config.socket.send_timeout = Some(Duration::from_secs(parse_env(SEND_TIMEOUT_ENV, &v)?)).filter(|timeout| !timeout.is_zero());
+        }
+        if let Ok(v) = env::var(DISCONNECT_GRACE_ENV) {
+            config.disconnect_grace = Duration::from_secs(parse_env(DISCONNECT_GRACE_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(TEAMS_ENV) {
+            config.teams = v.split(',').map(str::trim).filter(|team| !team.is_empty()).map(String::from).collect();
+        }
+        if let Ok(v) = env::var(TENANTS_ENV) {
+            config.tenants = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = env::var(TEMPLATES_ENV) {
+            config.templates = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = env::var(LISTEN_HOST_ENV).or_else(|_| env::var(LISTEN_IP_ENV)) {
+            config.listen_host = Some(v);
+        }
+        for (port, key) in config.ports.iter_mut().zip([WS_PORT_ENV, TCP_PORT_ENV, SHADOW_PORT_ENV, ADMIN_PORT_ENV]) {
+            if let Ok(v) = env::var(key) {
+                *port = Some(parse_env(key, &v)?);
+            }
+        }
+        if let Ok(v) = env::var(ADVERTISED_HOST_ENV) {
+            config.advertised_host = Some(v);
+        }
+        if let Ok(v) = env::var(ADVERTISED_URL_ENV) {
+            let host = advertised_url_host(&v)?;
+            config.advertised_host.get_or_insert(host);
+            config.advertised_url = Some(v);
+        }
+        if let Ok(v) = env::var(BIND_RETRIES_ENV) {
+            config.bind.retries = parse_env(BIND_RETRIES_ENV, &v)?;
+        }
+        let alternatives = [
+            (ListenerRole::Clients, WS_ALTERNATIVE_PORTS_ENV),
+            (ListenerRole::Hosts, TCP_ALTERNATIVE_PORTS_ENV),
+            (ListenerRole::ShadowHosts, SHADOW_ALTERNATIVE_PORTS_ENV),
+            (ListenerRole::Admin, ADMIN_ALTERNATIVE_PORTS_ENV),
+        ];
+        for (role, key) in alternatives {
+            if let Ok(v) = env::var(key) {
+                let ports = v.split(',').filter(|port| !port.trim().is_empty()).map(|port| parse_env(key, port)).collect::<Result<Vec<u16>, String>>()?;
+                config.bind.alternative_ports.insert(role, ports);
+            }
+        }
+        if let Ok(v) = env::var(TRUSTED_PROXIES_ENV) {
+            config.trusted_proxies = TrustedProxies::parse(&v)?;
+        }
+        if let Ok(v) = env::var(DNS_REFRESH_ENV) {
+            config.dns_refresh = Duration::from_secs(parse_env(DNS_REFRESH_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(USAGE_EXPORT_ENV) {
+            config.usage_export = Some(UsageExport::parse(&v)?);
+        }
+        if let Ok(v) = env::var(USAGE_INTERVAL_ENV) {
+            config.usage_interval = Duration::from_secs(parse_env(USAGE_INTERVAL_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(SESSION_MAX_DURATION_ENV) {
+            config.session.max_duration = Some(Duration::from_secs(parse_env(SESSION_MAX_DURATION_ENV, &v)?));
+        }
+        if let Ok(v) = env::var(SESSION_END_AT_ENV) {
+            // Unix timestamp in seconds
+            config.session.end_at = Some(parse_env::<i64>(SESSION_END_AT_ENV, &v)? * 1000);
+        }
+        if let Ok(v) = env::var(SESSION_WARNING_ENV) {
+            config.session.warning = Duration::from_secs(parse_env(SESSION_WARNING_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(ANNOUNCEMENT_ENV) {
+            config.announcement = Some(v).filter(|message| !message.trim().is_empty());
+        }
+        if let Ok(v) = env::var(REPLICATION_PORT_ENV) {
+            config.replication_port = Some(parse_env(REPLICATION_PORT_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(REPLICATION_IP_ENV) {
+            config.replication_host = Some(v);
+        }
+        if let Ok(v) = env::var(LIVE_VIEW_PORT_ENV) {
+            config.live_view_port = Some(parse_env(LIVE_VIEW_PORT_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(STANDBY_OF_ENV) {
+            config.standby_of = Some(v);
+        }
+        if let Ok(v) = env::var(FAILOVER_TIMEOUT_ENV) {
+            config.failover_timeout = Duration::from_secs(parse_env(FAILOVER_TIMEOUT_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(NO_HOST_ENV) {
+            config.no_host = NoHostBehavior::parse(&v)?;
+        }
+        if let Ok(v) = env::var(RECONNECT_BACKOFF_ENV) {
+            config.reconnect = ReconnectBackoff::parse(&v)?;
+        }
+        if let Ok(v) = env::var(DIGEST_ENV) {
+            config.digest = Some(DigestTarget::parse(&v)?);
+        }
+        if let Ok(v) = env::var(DIGEST_FROM_ENV) {
+            config.digest_from = v;
+        }
+        if let Ok(v) = env::var(DIGEST_LINK_ENV) {
+            config.digest_link = Some(v).filter(|link| !link.trim().is_empty());
+        }
+        if let Ok(v) = env::var(GUEST_TTL_ENV) {
+            config.guest_ttl = Some(Duration::from_secs(parse_env(GUEST_TTL_ENV, &v)?)).filter(|ttl| !ttl.is_zero());
+        }
+        let per_ip = match env::var(MAX_CLIENTS_PER_IP_ENV) {
+            Ok(v) => Some(parse_env(MAX_CLIENTS_PER_IP_ENV, &v)?),
+            Err(_) => None,
+        };
+        let shared = match env::var(SHARED_DEVICES_ENV) {
+            Ok(v) => ConnectionLimit::parse_shared(&v)?,
+            Err(_) => vec![],
+        };
+        config.connection_limit = ConnectionLimit::new(per_ip, shared);
+        if let Ok(v) = env::var(INTEGRITY_ENV) {
+            config.integrity = parse_env(INTEGRITY_ENV, &v)?;
+        }
+        if let Ok(v) = env::var(STATE_QUORUM_ENV) {
+            match parse_env(STATE_QUORUM_ENV, &v)? {
+                percent @ 1..=100 => config.state_quorum = Some(percent),
+                _ => return Err(format!("Invalid value '{}' for {}: expected a percentage from 1 to 100", v, STATE_QUORUM_ENV)),
+            }
+        }
+        if let Ok(v) = env::var(FD_RESERVE_ENV) {
+            config.fd_reserve = parse_env(FD_RESERVE_ENV, &v)?;
+        }
+        if let Ok(v) = env::var(ACCESS_CODE_ENV) {
+            config.access_code = Some(v).filter(|code| !code.trim().is_empty());
+        }
+        if let Ok(v) = env::var(REJOIN_TTL_ENV) {
+            config.rejoin_ttl = Duration::from_secs(parse_env(REJOIN_TTL_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(ADMIN_IP_ENV) {
+            config.admin_host = Some(v);
+        }
+        if let Ok(v) = env::var(ADMIN_SECRET_ENV) {
+            config.admin_secret = Some(v).filter(|secret| !secret.is_empty());
+        }
+        if let Ok(v) = env::var(HOST_SECRET_ENV) {
+            config.host_secret = Some(v).filter(|secret| !secret.is_empty());
+        }
+        if let Ok(v) = env::var(SECRET_KEY_ENV) {
+            config.secret_key = Some(v).filter(|key| !key.is_empty());
+        }
+        if let Ok(v) = env::var(SESSION_MEMORY_LIMIT_ENV) {
+            config.session_memory_limit = Some(parse_env::<usize>(SESSION_MEMORY_LIMIT_ENV, &v)? * 1024 * 1024).filter(|limit| *limit > 0);
+        }
+        if let Ok(v) = env::var(SOAK_ENV) {
+            #[cfg(feature = "soak")]
+            {
+                config.soak = Some(SoakRates::parse(&v).map_err(|e| format!("Invalid value '{}' for {}: {}", v, SOAK_ENV, e))?);
+            }
+            #[cfg(not(feature = "soak"))]
+            return Err(format!("{} is '{}', but this build has no soak mode (feature 'soak')", SOAK_ENV, v));
+        }
+        if let Ok(v) = env::var(PLUGINS_ENV) {
+            #[cfg(feature = "wasm_plugins")]
+            {
+                config.plugins = Some(PluginLimits::parse(&v).map_err(|e| format!("Invalid value '{}' for {}: {}", v, PLUGINS_ENV, e))?);
+            }
+            #[cfg(not(feature = "wasm_plugins"))]
+            return Err(format!("{} is '{}', but this build has no plugin runtime (feature 'wasm_plugins')", PLUGINS_ENV, v));
+        }
+        if let Ok(domain) = env::var(ACME_DOMAIN_ENV) {
+            let mut acme = AcmeConfig::new(domain);
+            acme.email = env::var(ACME_EMAIL_ENV).ok();
+            if let Ok(v) = env::var(ACME_DIRECTORY_ENV) {
+                Url::parse(&v)?;
+                acme.directory = v;
+            }
+            if let Ok(v) = env::var(ACME_HTTP_PORT_ENV) {
+                acme.http_port = parse_env(ACME_HTTP_PORT_ENV, &v)?;
+            }
+            if let Ok(v) = env::var(ACME_ACCOUNT_KEY_ENV) {
+                acme.account_key = PathBuf::from(v);
+            }
+            config.acme = Some(acme);
+        }
+        if let Ok(v) = env::var(RETENTION_DAYS_ENV) {
+            config.retention = Some(Duration::from_secs(parse_env::<u64>(RETENTION_DAYS_ENV, &v)? * 24 * 3600));
+        }
+        if let Ok(v) = env::var(RETENTION_INTERVAL_ENV) {
+            config.retention_interval = Duration::from_secs(parse_env(RETENTION_INTERVAL_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(CHALLENGE_ENV) {
+            config.challenge = Some(ChallengeConfig::parse(&v, env::var(CAPTCHA_SITE_KEY_ENV).ok(), env::var(CAPTCHA_SECRET_ENV).ok())?);
+        }
+        if let Ok(v) = env::var(TIMEZONE_ENV) {
+            config.time_format.timezone = TimeFormat::parse_timezone(&v)?;
+        }
+        if let Ok(v) = env::var(LOCALE_ENV) {
+            config.time_format.locale = Locale::parse(&v)?;
+        }
+        if let Ok(v) = env::var(TLS_CERT_ENV) {
+            config.tls.cert = PathBuf::from(v);
+        }
+        if let Ok(v) = env::var(TLS_KEY_ENV) {
+            config.tls.key = PathBuf::from(v);
+        }
+        if let Ok(v) = env::var(INSECURE_WS_ENV) {
+            config.tls.insecure_ws = parse_env(INSECURE_WS_ENV, &v)?;
+        }
+        if let Ok(v) = env::var(CERT_RELOAD_INTERVAL_ENV) {
+            config.tls.reload_interval = reload_interval(parse_env(CERT_RELOAD_INTERVAL_ENV, &v)?);
+        }
+        if let Ok(v) = env::var(HOST_TLS_ENV) {
+            config.tls.host_tls = parse_env(HOST_TLS_ENV, &v)?;
+        }
+        if let Ok(v) = env::var(HOST_CA_ENV) {
+            config.tls.host_ca = Some(PathBuf::from(v)).filter(|path| !path.as_os_str().is_empty());
+        }
+        if let Ok(v) = env::var(CHANNEL_SIZE_ENV) {
+            config.channel_size = parse_env(CHANNEL_SIZE_ENV, &v)?;
+        }
+        if let Ok(v) = env::var(LOG_LEVEL_ENV) {
+            validate_filter(&v).map_err(|e| format!("Invalid value '{}' for {}: {}", v, LOG_LEVEL_ENV, e))?;
+            config.log_filter = Some(v);
+        }
+        if let Ok(v) = env::var(HOST_STDIO_ENV) {
+            config.host_stdio = Some(HostStdio::parse(&v)?);
+        }
+        if config.channel_size == 0 {
+            return Err(String::from("The channel size must be positive"))
+        }
+        Ok(())
+    }
+
+    /// Overrides the listen address and ports with '--ip', '--ws-port', '--tcp-port',
+    /// '--shadow-port', '--admin-ip' and '--admin-port' and the stdio host with '--host-stdio' (each followed by
+    /// its value), '--insecure-ws' accepts clients without TLS. The command line takes precedence
+    /// over the environment and the configuration file ('--config')
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--ip" => self.listen_host = Some(value()?.clone()),
+                "--ws-port" => self.ports[0] = Some(parse_env(arg, value()?)?),
+                "--tcp-port" => self.ports[1] = Some(parse_env(arg, value()?)?),
+                "--shadow-port" => self.ports[2] = Some(parse_env(arg, value()?)?),
+                "--admin-ip" => self.admin_host = Some(value()?.clone()),
+                "--admin-port" => self.ports[3] = Some(parse_env(arg, value()?)?),
+                "--host-stdio" => self.host_stdio = Some(HostStdio::parse(value()?)?),
+                "--insecure-ws" => self.tls.insecure_ws = true,
+                // Read before the environment
+                "--config" => {
+                    value()?;
+                }
+                _ => return Err(format!("Unknown argument {}, see --help", arg)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Usage of the command line, the remaining settings are read from the environment
+pub const USAGE: &str = "Usage: tt_online [--config <file>] [--ip <host>] [--ws-port <port>] [--tcp-port <port>] [--shadow-port <port>] [--admin-ip <host>] [--admin-port <port>] [--host-stdio parent|exec:<command>] [--insecure-ws]
+       tt_online --estimate [--config <file>] [--clients <n>] [--input-rate <n>] [--input-size <bytes>] [--update-rate <n>] [--update-size <bytes>]";
+
+/// Seconds between checks for a renewed certificate, 0 disables them
+fn reload_interval(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// Host of a 'http(s)://' or 'ws(s)://' url
+fn advertised_url_host(url: &str) -> Result<String, String> {
+    let http_url = if let Some(rest) = url.strip_prefix("wss://") {
+        format!("https://{}", rest)
+    } else if let Some(rest) = url.strip_prefix("ws://") {
+        format!("http://{}", rest)
+    } else {
+        String::from(url)
+    };
+    Url::parse(&http_url)
+        .map(|parsed| parsed.host)
+        .map_err(|_| format!("Invalid value '{}' for {}, expected a http(s) or ws(s) url", url, ADVERTISED_URL_ENV))
+}
+
+fn parse_env<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("Invalid value '{}' for {}", value, key))
+}
+
+/// Certificate and private key (pem) of the client port
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Accepts plain websocket connections (ws://), for local development or behind a reverse
+    /// proxy terminating TLS. The certificate is not read then
+    /// Builds with the 'insecure_ws' feature default to it
+    pub insecure_ws: bool,
+    /// Time between checks for a renewed certificate, only SIGHUP reloads it if None
+    pub reload_interval: Option<Duration>,
+    /// The host ports speak TLS with the certificate of the client port, hosts connect over plain
+    /// tcp otherwise
+    pub host_tls: bool,
+    /// CA bundle (pem) issuing the certificates hosts have to present, implies 'host_tls'
+    pub host_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Whether the host ports speak TLS
+    pub fn host_tls_enabled(&self) -> bool {
+        self.host_tls || self.host_ca.is_some()
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {cert: PathBuf::from("res/cert/cert.pem"), key: PathBuf::from("res/cert/key.pem"), insecure_ws: cfg!(feature = "insecure_ws"), reload_interval: Some(DEFAULT_CERT_RELOAD_INTERVAL), host_tls: false, host_ca: None}
+    }
+}
+
+/// Socket options applied to every accepted client and host connection
+/// Options left at None keep the defaults of the operating system
+#[derive(Debug, Clone, Default)]
+pub struct SocketConfig {
+    /// Idle time before the first keepalive probe, keepalive is disabled if None
+    pub keepalive_time: Option<Duration>,
+    /// Time between keepalive probes
+    pub keepalive_interval: Option<Duration>,
+    /// Disables Nagle's algorithm on the host connection
+    pub host_nodelay: bool,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// Time a single write may take before the connection is closed as stalled, writes wait
+    /// for the socket indefinitely if None
+    pub send_timeout: Option<Duration>,
+}
+
+/// How listeners are bound at startup
+#[derive(Debug, Clone)]
+pub struct BindConfig {
+    /// Repetitions after all ports of a listener failed
+    pub retries: u32,
+    /// Delay before the first repetition, doubled for every further one
+    pub retry_delay: Duration,
+    /// Ports tried, in order, if the port of the listener is not available
+    pub alternative_ports: HashMap<ListenerRole, Vec<u16>>,
+}
+
+impl Default for BindConfig {
+    fn default() -> Self {
+        BindConfig { retries: 0, retry_delay: Duration::from_millis(500), alternative_ports: HashMap::new() }
+    }
+}
+
+impl BindConfig {
+    pub fn alternatives(&self, role: ListenerRole) -> &[u16] {
+        self.alternative_ports.get(&role).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Selects the AuthProvider used for logins
+#[derive(Debug, Clone, Default)]
+pub enum AuthConfig {
+    #[default]
+    None,
+    StaticSecret(String),
+    TokenFile(PathBuf),
+    Jwt(String),
+    Webhook(String),
+}
+
+impl AuthConfig {
+    /// Parses 'none', 'static:<secret>', 'file:<path>', 'jwt:<key>' or 'webhook:<url>'
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, value) = spec.split_once(':').unwrap_or((spec, ""));
+        match (kind, value) {
+            ("none", _) => Ok(AuthConfig::None),
+            ("static", v) if !v.is_empty() => Ok(AuthConfig::StaticSecret(String::from(v))),
+            ("file", v) if !v.is_empty() => Ok(AuthConfig::TokenFile(PathBuf::from(v))),
+            ("jwt", v) if !v.is_empty() => Ok(AuthConfig::Jwt(String::from(v))),
+            ("webhook", v) if !v.is_empty() => Ok(AuthConfig::Webhook(String::from(v))),
+            _ => Err(format!("Invalid auth provider '{}', expected 'none', 'static:<secret>', 'file:<path>', 'jwt:<key>' or 'webhook:<url>'", spec)),
+        }
+    }
+}
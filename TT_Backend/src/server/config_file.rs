@@ -0,0 +1,199 @@
+//!
+//! Configuration file (TOML) of the server.
+//! The file is taken from '--config <path>', TT_BACKEND_CONFIG or 'config.toml' in the working
+//! directory (if it exists). Its settings are overridden by the environment, which is overridden
+//! by the command line. Supported are tables of 'key = value' pairs with strings, integers and
+//! booleans, which covers every setting (with the variable overriding it):
+//!
+//! [listen]
+//! host = "0.0.0.0"            # TT_BACKEND_IP or TT_BACKEND_LISTEN_HOST
+//! ws_port = 443               # TT_BACKEND_WS_PORT
+//! tcp_port = 9000             # TT_BACKEND_TCP_PORT
+//! shadow_port = 9001          # TT_BACKEND_SHADOW_PORT
+//! admin_ip = "127.0.0.1"      # TT_BACKEND_ADMIN_IP (needs TT_BACKEND_ADMIN_SECRET unless loopback)
+//! admin_port = 9002           # TT_BACKEND_ADMIN_PORT
+//!
+//! [tls]
+//! cert = "/etc/tt_online/cert.pem"    # TT_BACKEND_CERT_PATH
+//! key = "/etc/tt_online/key.pem"      # TT_BACKEND_KEY_PATH
+//! insecure_ws = false         # TT_BACKEND_INSECURE_WS
+//! reload_interval = 60        # TT_BACKEND_CERT_RELOAD_INTERVAL (seconds, 0 reloads on SIGHUP only)
+//! host_tls = false            # TT_BACKEND_HOST_TLS (host ports speak TLS)
+//! host_ca = "/etc/tt_online/hosts.pem"    # TT_BACKEND_HOST_CA (hosts need a certificate by it)
+//!
+//! [server]
+//! channel_size = 64           # TT_BACKEND_CHANNEL_SIZE
+//! log_level = "info"          # TT_BACKEND_LOG_LEVEL (RUST_LOG still wins)
+//!
+//! [host]
+//! stdio = "exec:java -jar host.jar"   # TT_BACKEND_HOST_STDIO
+//!
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_ENV: &str = "TT_BACKEND_CONFIG";
+/// File read if neither '--config' nor TT_BACKEND_CONFIG is given, it may be missing
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TomlValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+/// Settings of the file by 'table.key'
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    values: HashMap<String, TomlValue>,
+}
+
+impl ConfigFile {
+    /// Path of the configuration file, None if there is none
+    pub fn locate(args: &[String]) -> Option<PathBuf> {
+        let from_args = args.iter().position(|arg| arg == "--config").and_then(|index| args.get(index + 1));
+        match from_args.cloned().or_else(|| std::env::var(CONFIG_ENV).ok()) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Reading configuration file {} failed: {}", path.display(), e))?;
+        ConfigFile::parse(&content).map_err(|e| format!("Configuration file {} is invalid: {}", path.display(), e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut values = HashMap::new();
+        let mut table = String::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            if let Some(name) = line.strip_prefix('[') {
+                let name = strip_comment(name).strip_suffix(']').ok_or_else(|| error("unterminated table header"))?.trim();
+                if !is_bare_key(name) {
+                    return Err(error("invalid table name"))
+                }
+                table = String::from(name);
+                continue
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected 'key = value'"))?;
+            let key = key.trim();
+            if !is_bare_key(key) {
+                return Err(error("invalid key"))
+            }
+            let value = parse_value(value.trim()).map_err(|e| error(&e))?;
+            let key = if table.is_empty() { String::from(key) } else { format!("{}.{}", table, key) };
+            if values.insert(key.clone(), value).is_some() {
+                return Err(error(&format!("duplicate key '{}'", key)))
+            }
+        }
+        Ok(ConfigFile {values})
+    }
+
+    /// Takes the value of the key, so the keys left over can be reported as unknown
+    pub fn take(&mut self, key: &str) -> Option<TomlValue> {
+        self.values.remove(key)
+    }
+
+    pub fn take_string(&mut self, key: &str) -> Result<Option<String>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(TomlValue::String(v)) => Ok(Some(v)),
+            Some(_) => Err(format!("'{}' must be a string", key)),
+        }
+    }
+
+    pub fn take_boolean(&mut self, key: &str) -> Result<Option<bool>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(TomlValue::Boolean(v)) => Ok(Some(v)),
+            Some(_) => Err(format!("'{}' must be a boolean", key)),
+        }
+    }
+
+    pub fn take_integer<T: TryFrom<i64>>(&mut self, key: &str) -> Result<Option<T>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(TomlValue::Integer(v)) => T::try_from(v).map(Some).map_err(|_| format!("'{}' is out of range", key)),
+            Some(_) => Err(format!("'{}' must be an integer", key)),
+        }
+    }
+
+    /// Fails on the first key no setting took
+    pub fn finish(self) -> Result<(), String> {
+        let mut unknown: Vec<String> = self.values.into_keys().collect();
+        unknown.sort();
+        match unknown.first() {
+            None => Ok(()),
+            Some(key) => Err(format!("Unknown setting '{}' in the configuration file", key)),
+        }
+    }
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Removes a trailing comment outside of strings
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '#' {
+            return text[..index].trim_end()
+        }
+    }
+    text
+}
+
+fn parse_value(value: &str) -> Result<TomlValue, String> {
+    let value = strip_comment(value);
+    if let Some(rest) = value.strip_prefix('"') {
+        let content = rest.strip_suffix('"').ok_or("unterminated string")?;
+        return unescape(content).map(TomlValue::String)
+    }
+    match value {
+        "true" => Ok(TomlValue::Boolean(true)),
+        "false" => Ok(TomlValue::Boolean(false)),
+        _ => value.replace('_', "").parse().map(TomlValue::Integer)
+            .map_err(|_| format!("unsupported value '{}', expected a string, integer or boolean", value)),
+    }
+}
+
+fn unescape(content: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            return Err(String::from("unescaped '\"' in string"))
+        }
+        if c != '\\' {
+            result.push(c);
+            continue
+        }
+        match chars.next() {
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            other => return Err(format!("unsupported escape '\\{}'", other.map(String::from).unwrap_or_default())),
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,99 @@
+//!
+//! Limit of simultaneous client connections per source IP.
+//! Makes voting with many tabs of one device tedious. Venues where many devices share one public
+//! address (NAT, campus networks) are listed as shared ranges with their own, usually higher,
+//! limit or no limit at all.
+//!
+
+use std::net::IpAddr;
+use ipnet::IpNet;
+
+pub const REJECT_REASON_IP_LIMIT: &str = "Too many connections from this address";
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimit {
+    /// Connections per IP outside the shared ranges, unlimited if None
+    per_ip: Option<usize>,
+    /// Ranges of shared devices and their limit per IP, unlimited if None
+    shared: Vec<(IpNet, Option<usize>)>,
+}
+
+impl ConnectionLimit {
+    pub fn new(per_ip: Option<usize>, shared: Vec<(IpNet, Option<usize>)>) -> Self {
+        ConnectionLimit { per_ip, shared }
+    }
+
+    /// Parses a comma separated list of IPs and CIDR ranges, each optionally followed by
+    /// '=<limit>', e.g. '10.0.0.0/8=200,192.0.2.7'
+    /// Ranges without a limit are unlimited
+    pub fn parse_shared(spec: &str) -> Result<Vec<(IpNet, Option<usize>)>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (range, limit) = match entry.split_once('=') {
+                    None => (entry, None),
+                    Some((range, limit)) => (range.trim(), Some(limit.trim().parse::<usize>()
+                        .map_err(|_| format!("Invalid limit in shared range '{}'", entry))?)),
+                };
+                let range = range.parse::<IpNet>()
+                    .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("Invalid shared range '{}', expected an IP or a CIDR range", entry))?;
+                Ok((range, limit))
+            })
+            .collect()
+    }
+
+    /// Connections allowed from the ip, the most specific shared range containing it wins
+    pub fn limit_for(&self, ip: IpAddr) -> Option<usize> {
+        self.per_ip?;
+        match self.shared.iter().filter(|(range, _)| range.contains(&ip)).max_by_key(|(range, _)| range.prefix_len()) {
+            Some((_, limit)) => *limit,
+            None => self.per_ip,
+        }
+    }
+
+    /// Whether another connection from the ip is allowed, given the ones it already has
+    pub fn allows(&self, ip: IpAddr, connected: usize) -> bool {
+        self.limit_for(ip).is_none_or(|limit| connected < limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn most_specific_shared_range_wins() {
+        let shared = ConnectionLimit::parse_shared("10.0.0.0/8=200, 10.1.0.0/16=50, 10.1.2.3").unwrap();
+        let limit = ConnectionLimit::new(Some(3), shared);
+        assert_eq!(limit.limit_for(ip("10.2.0.1")), Some(200));
+        assert_eq!(limit.limit_for(ip("10.1.0.1")), Some(50));
+        // A shared range without a limit is unlimited
+        assert_eq!(limit.limit_for(ip("10.1.2.3")), None);
+        assert_eq!(limit.limit_for(ip("192.0.2.1")), Some(3));
+        assert!(limit.allows(ip("192.0.2.1"), 2));
+        assert!(!limit.allows(ip("192.0.2.1"), 3));
+        assert!(limit.allows(ip("10.1.2.3"), 10_000));
+    }
+
+    #[test]
+    fn no_limit_per_ip_disables_the_shared_ranges() {
+        let limit = ConnectionLimit::new(None, ConnectionLimit::parse_shared("10.0.0.0/8=2").unwrap());
+        assert_eq!(limit.limit_for(ip("10.0.0.1")), None);
+        assert!(limit.allows(ip("10.0.0.1"), 100));
+    }
+
+    #[test]
+    fn malformed_shared_ranges_are_reported() {
+        assert!(ConnectionLimit::parse_shared("10.0.0.0/8=many").unwrap_err().contains("Invalid limit"));
+        assert!(ConnectionLimit::parse_shared("10.0.0.0/8=").unwrap_err().contains("Invalid limit"));
+        assert!(ConnectionLimit::parse_shared("campus=20").unwrap_err().contains("Invalid shared range"));
+        assert_eq!(ConnectionLimit::parse_shared(" 192.0.2.7 = 5 ,").unwrap(), vec![("192.0.2.7/32".parse().unwrap(), Some(5))]);
+        assert!(ConnectionLimit::parse_shared("").unwrap().is_empty());
+    }
+}
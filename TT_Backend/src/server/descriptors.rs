@@ -0,0 +1,152 @@
+//!
+//! Protection against running out of file descriptors.
+//! Every connection holds a descriptor, once the process reaches its limit (RLIMIT_NOFILE) accepting
+//! fails with EMFILE and the accept loops stop. The open descriptors are counted every
+//! DESCRIPTOR_CHECK_INTERVAL, connections accepted in between are added. Once fewer than
+//! 'reserve' (TT_BACKEND_FD_RESERVE) descriptors are left, new clients are answered with
+//! 'Disconnecting' (REJECT_REASON_AT_CAPACITY and a reconnect hint) and closed right away, the
+//! reserve keeps hosts, the admin interface and files working. Reaching a level is logged, reaching
+//! the capacity is reported to the hosts as 'QuotaExceeded' ('file_descriptors').
+//! Without a limit or a way to count the descriptors (no /proc/self/fd or /dev/fd) nothing is
+//! rejected.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::server::reconnect::ReconnectHint;
+
+pub const DEFAULT_FD_RESERVE: usize = 64;
+pub const DESCRIPTOR_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+pub const REJECT_REASON_AT_CAPACITY: &str = "Server is at capacity, please try again later";
+/// Time a rejected client has for its websocket upgrade
+pub const CAPACITY_REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Share (in percent) of the usable descriptors at which the usage is logged as high
+const HIGH_USAGE_PERCENT: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorLevel {
+    Normal,
+    High,
+    /// New clients are rejected
+    Capacity,
+}
+
+impl Display for DescriptorLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DescriptorLevel::Normal => "normal",
+            DescriptorLevel::High => "high",
+            DescriptorLevel::Capacity => "capacity",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Descriptor usage, shared by the client listener and the main handler
+#[derive(Debug)]
+pub struct DescriptorGuard {
+    /// Soft limit of the process, None if unlimited or unknown
+    limit: Option<usize>,
+    reserve: usize,
+    /// Hint sent to rejected clients
+    reconnect: ReconnectHint,
+    /// Descriptors open at the latest check, None if they can not be counted
+    open: Mutex<Option<usize>>,
+    /// Connections accepted since the latest check
+    accepted: AtomicUsize,
+    rejected: AtomicU64,
+    level: Mutex<DescriptorLevel>,
+}
+
+impl DescriptorGuard {
+    pub fn new(reserve: usize, reconnect: ReconnectHint) -> Self {
+        DescriptorGuard {
+            limit: descriptor_limit(),
+            reserve,
+            reconnect,
+            open: Mutex::new(count_open_descriptors()),
+            accepted: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            level: Mutex::new(DescriptorLevel::Normal),
+        }
+    }
+
+    pub fn get_limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn get_reconnect(&self) -> ReconnectHint {
+        self.reconnect
+    }
+
+    /// Counts the connection towards the open descriptors, returns false if it has to be rejected
+    pub fn admit(&self) -> bool {
+        let accepted = self.accepted.fetch_add(1, Ordering::Relaxed) + 1;
+        let open = *self.open.lock().expect("admit(..): Lock is poisoned");
+        if self.level_of(open.map(|open| open + accepted)) == DescriptorLevel::Capacity {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return false
+        }
+        true
+    }
+
+    /// Counts the open descriptors, returns the level and the count if the level changed
+    pub fn check(&self) -> Option<(DescriptorLevel, usize)> {
+        let open = count_open_descriptors();
+        *self.open.lock().expect("check(..): Lock is poisoned") = open;
+        self.accepted.store(0, Ordering::Relaxed);
+
+        let level = self.level_of(open);
+        let mut current = self.level.lock().expect("check(..): Lock is poisoned");
+        if *current == level {
+            return None
+        }
+        *current = level;
+        Some((level, open.unwrap_or(0)))
+    }
+
+    fn level_of(&self, open: Option<usize>) -> DescriptorLevel {
+        let (limit, open) = match (self.limit, open) {
+            (Some(limit), Some(open)) => (limit, open),
+            _ => return DescriptorLevel::Normal,
+        };
+        let usable = limit.saturating_sub(self.reserve);
+        if open >= usable {
+            DescriptorLevel::Capacity
+        } else if open * 100 >= usable * HIGH_USAGE_PERCENT {
+            DescriptorLevel::High
+        } else {
+            DescriptorLevel::Normal
+        }
+    }
+
+    pub fn report(&self) -> Value {
+        json!({
+            "open": *self.open.lock().expect("report(..): Lock is poisoned"),
+            "limit": self.limit,
+            "reserve": self.reserve,
+            "level": self.level.lock().expect("report(..): Lock is poisoned").to_string(),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Soft limit of open descriptors, None if unlimited
+fn descriptor_limit() -> Option<usize> {
+    let mut limit = libc::rlimit {rlim_cur: 0, rlim_max: 0};
+    // SAFETY: getrlimit only writes the passed struct
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        return None
+    }
+    usize::try_from(limit.rlim_cur).ok()
+}
+
+fn count_open_descriptors() -> Option<usize> {
+    ["/proc/self/fd", "/dev/fd"].iter()
+        .find_map(|path| std::fs::read_dir(path).ok())
+        // The directory itself is open while it is read
+        .map(|entries| entries.count().saturating_sub(1))
+}
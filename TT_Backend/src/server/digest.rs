@@ -0,0 +1,140 @@
+//!
+//! Summary of a session, sent once it ended.
+//! The digest (participants, duration, states, inputs and an optional export link) is posted as
+//! json to a webhook or mailed via SMTP, so presenters get a record without access to the server.
+//!
+
+use std::time::Duration;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use serde_json::{json, Value};
+use crate::server::http_client::{post_json, Url};
+use crate::server::timefmt::{rfc3339, TimeFormat};
+
+/// Sender of digest mails, if not configured
+pub const DEFAULT_DIGEST_FROM: &str = "tt-online@localhost";
+/// Maximum time delivering a digest may take
+const DIGEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where digests are sent to
+#[derive(Debug, Clone)]
+pub enum DigestTarget {
+    Webhook(Url),
+    /// Mail via the relay at 'host', 'tls' selects implicit TLS (smtps) over plain SMTP
+    Smtp { host: String, port: u16, tls: bool, credentials: Option<(String, String)>, to: String },
+}
+
+impl DigestTarget {
+    /// Parses a 'http(s)://' url or 'smtp(s)://[user:password@]host[:port]/recipient'
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            return Ok(DigestTarget::Webhook(Url::parse(spec)?))
+        }
+        let (tls, rest) = if let Some(rest) = spec.strip_prefix("smtps://") {
+            (true, rest)
+        } else if let Some(rest) = spec.strip_prefix("smtp://") {
+            (false, rest)
+        } else {
+            return Err(format!("Invalid digest target '{}', expected a http(s) url or 'smtp(s)://host/recipient'", spec))
+        };
+
+        let (authority, to) = rest.split_once('/')
+            .filter(|(_, to)| to.contains('@'))
+            .ok_or_else(|| format!("Digest target '{}' contains no recipient", spec))?;
+        let (credentials, address) = match authority.rsplit_once('@') {
+            None => (None, authority),
+            Some((user, address)) => match user.split_once(':') {
+                Some((user, password)) => (Some((String::from(user), String::from(password))), address),
+                None => return Err(format!("Digest target '{}' contains a user without password", spec)),
+            },
+        };
+        let (host, port) = match address.split_once(':') {
+            None => (address, if tls { 465 } else { 25 }),
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("Digest target '{}' contains an invalid port", spec))?),
+        };
+        if host.is_empty() {
+            return Err(format!("Digest target '{}' contains no host", spec))
+        }
+        Ok(DigestTarget::Smtp { host: String::from(host), port, tls, credentials, to: String::from(to) })
+    }
+
+    /// Posts the digest to the webhook or mails it to the recipient
+    pub async fn send(&self, digest: &SessionDigest, from: &str) -> Result<(), String> {
+        match self {
+            DigestTarget::Webhook(url) => match post_json(url, &digest.to_json(), DIGEST_TIMEOUT).await? {
+                (status, _) if (200..300).contains(&status) => Ok(()),
+                (status, body) => Err(format!("Digest webhook answered {}: {}", status, body)),
+            },
+            DigestTarget::Smtp { host, port, tls, credentials, to } => {
+                let message = Message::builder()
+                    .from(from.parse::<Mailbox>().map_err(|e| format!("Invalid sender '{}': {}", from, e))?)
+                    .to(to.parse::<Mailbox>().map_err(|e| format!("Invalid recipient '{}': {}", to, e))?)
+                    .subject(format!("Session {} summary", digest.session))
+                    .body(digest.to_text())
+                    .map_err(|e| e.to_string())?;
+                let mut transport = match tls {
+                    true => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(|e| e.to_string())?,
+                    false => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+                }.port(*port).timeout(Some(DIGEST_TIMEOUT));
+                if let Some((user, password)) = credentials {
+                    transport = transport.credentials(Credentials::new(user.clone(), password.clone()));
+                }
+                transport.build().send(message).await.map(|_| ()).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Counted while a session runs
+#[derive(Debug, Default)]
+pub struct SessionCounters {
+    pub states: u64,
+    pub inputs: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionDigest {
+    pub tenant: Option<String>,
+    pub session: u64,
+    pub started: i64,
+    pub ended: i64,
+    /// Distinct client names that joined
+    pub participants: usize,
+    pub states: u64,
+    pub inputs: u64,
+    pub export_link: Option<String>,
+    /// Format of the timestamps in the mail
+    pub time_format: TimeFormat,
+}
+
+impl SessionDigest {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "tenant": self.tenant,
+            "session": self.session,
+            "started": rfc3339(self.started),
+            "ended": rfc3339(self.ended),
+            "duration": self.ended - self.started,
+            "participants": self.participants,
+            "states": self.states,
+            "inputs": self.inputs,
+            "export_link": self.export_link,
+        })
+    }
+
+    /// Body of the digest mail
+    pub fn to_text(&self) -> String {
+        let minutes = (self.ended - self.started).max(0) / 60_000;
+        let mut text = format!("Session {} ended after {} h {} min.\n\nParticipants: {}\nStates: {}\nInputs: {}\n",
+            self.session, minutes / 60, minutes % 60, self.participants, self.states, self.inputs);
+        text.push_str(&format!("Started: {}\nEnded: {}\n", self.time_format.human(self.started), self.time_format.human(self.ended)));
+        if let Some(tenant) = self.tenant.as_ref() {
+            text.push_str(&format!("Tenant: {}\n", tenant));
+        }
+        if let Some(link) = self.export_link.as_ref() {
+            text.push_str(&format!("\nExport: {}\n", link));
+        }
+        text
+    }
+}
@@ -0,0 +1,37 @@
+//!
+//! Resolution of the configured host names.
+//! Listen and advertised addresses may be IPv4 or IPv6 literals (the latter optionally in
+//! brackets) or host names. The listen address is resolved once at startup, the advertised host is
+//! re-resolved periodically, so dynamic DNS deployments notice when their public IP changes.
+//!
+
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::net::lookup_host;
+
+/// Interval between two resolutions of the advertised host, if not configured
+pub const DEFAULT_DNS_REFRESH: Duration = Duration::from_secs(300);
+
+/// Returns all addresses of the host, IP literals are returned as they are
+pub async fn resolve(host: &str) -> Result<Vec<IpAddr>, String> {
+    let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![ip])
+    }
+    let mut addresses: Vec<IpAddr> = lookup_host((host, 0)).await
+        .map_err(|e| format!("Resolving '{}' failed: {}", host, e))?
+        .map(|address| address.ip())
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    if addresses.is_empty() {
+        return Err(format!("Resolving '{}' returned no addresses", host))
+    }
+    Ok(addresses)
+}
+
+/// Returns the address to listen on, IPv4 addresses are preferred for host names
+pub async fn resolve_listen_ip(host: &str) -> Result<IpAddr, String> {
+    let addresses = resolve(host).await?;
+    Ok(addresses.iter().find(|ip| ip.is_ipv4()).copied().unwrap_or(addresses[0]))
+}
@@ -0,0 +1,183 @@
+//!
+//! Capacity planning for a session.
+//! Projects memory, bandwidth and queue usage from the expected audience and message rates and
+//! compares them with the limits of the server as configured (file and environment). The numbers are rough estimates meant
+//! for sizing a deployment, not exact measurements.
+//!
+
+use std::fmt::{Display, Formatter};
+use crate::server::config::CHANNEL_SIZE_ENV;
+use crate::server::networking::INPUT_ID_WINDOW;
+
+/// Approximate memory of one websocket connection (tls and websocket buffers, task, bookkeeping)
+const CONNECTION_MEMORY: f64 = 64.0 * 1024.0;
+/// Approximate size of one remembered input id
+const INPUT_ID_MEMORY: f64 = 48.0;
+/// Framing and json overhead added to every forwarded input
+const INPUT_OVERHEAD: f64 = 160.0;
+/// Time a slow client may stall before its outbound queue is considered in the memory estimate
+const STALL_SECONDS: f64 = 10.0;
+/// Common default of the open file limit, every client needs one file descriptor
+const DEFAULT_FILE_LIMIT: usize = 1024;
+/// Share of the audience answering within the same instant, e.g. right after a question is shown
+const BURST_SHARE: f64 = 0.5;
+
+/// Expected load of a session
+#[derive(Debug, Clone)]
+pub struct EstimateParams {
+    pub clients: usize,
+    /// Inputs per client and second
+    pub input_rate: f64,
+    /// Average size of an input in bytes
+    pub input_size: usize,
+    /// Updates and state changes sent by the host per second
+    pub update_rate: f64,
+    /// Average size of an update in bytes
+    pub update_size: usize,
+}
+
+impl Default for EstimateParams {
+    fn default() -> Self {
+        EstimateParams { clients: 100, input_rate: 0.2, input_size: 64, update_rate: 1.0, update_size: 2048 }
+    }
+}
+
+impl EstimateParams {
+    /// Parses '--clients', '--input-rate', '--input-size', '--update-rate' and '--update-size'
+    /// (each followed by its value), missing arguments keep their defaults
+    /// '--config <file>' selects the configuration file as for the server and is skipped here
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut params = EstimateParams::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--clients" => params.clients = parse(arg, value()?)?,
+                "--input-rate" => params.input_rate = parse(arg, value()?)?,
+                "--input-size" => params.input_size = parse(arg, value()?)?,
+                "--update-rate" => params.update_rate = parse(arg, value()?)?,
+                "--update-size" => params.update_size = parse(arg, value()?)?,
+                "--config" => { value()?; },
+                _ => return Err(format!("Unknown argument {}", arg)),
+            }
+        }
+        Ok(params)
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, arg))
+}
+
+/// Projected resource usage and the warnings derived from it
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub params: EstimateParams,
+    /// Configured capacity of the main handler's channel
+    pub channel_size: usize,
+    /// Internal events per second processed by the main handler
+    pub events_per_second: f64,
+    /// Internal events arriving at once when a share of the audience answers simultaneously
+    pub burst_events: usize,
+    /// Bytes per second written to all clients together
+    pub broadcast_bandwidth: f64,
+    /// Bytes per second written to the host
+    pub host_bandwidth: f64,
+    /// Bytes of memory in steady state
+    pub memory: f64,
+    /// Bytes of memory if one client stalls for STALL_SECONDS
+    pub stalled_queue_memory: f64,
+    pub warnings: Vec<String>,
+}
+
+/// 'channel_size' is the configured one (TT_BACKEND_CHANNEL_SIZE)
+pub fn estimate(params: EstimateParams, channel_size: usize) -> Estimate {
+    let clients = params.clients as f64;
+    let events_per_second = clients * params.input_rate + params.update_rate;
+    let burst_events = (clients * BURST_SHARE).ceil() as usize;
+    let broadcast_bandwidth = clients * params.update_rate * params.update_size as f64;
+    let host_bandwidth = clients * params.input_rate * (params.input_size as f64 + INPUT_OVERHEAD);
+    let memory = clients * (CONNECTION_MEMORY + INPUT_ID_WINDOW as f64 * INPUT_ID_MEMORY);
+    let stalled_queue_memory = params.update_rate * params.update_size as f64 * STALL_SECONDS;
+
+    let mut warnings = Vec::new();
+    if burst_events > channel_size {
+        warnings.push(format!("{} ({}) is smaller than the expected input burst ({} events), client readers will wait for the main handler",
+            CHANNEL_SIZE_ENV, channel_size, burst_events));
+    }
+    if events_per_second > channel_size as f64 * 100.0 {
+        warnings.push(format!("{:.0} events per second are processed by a single handler, consider splitting the audience",
+            events_per_second));
+    }
+    if params.clients + 16 > DEFAULT_FILE_LIMIT {
+        warnings.push(format!("{} clients need a file descriptor each, make sure the open file limit is above the common default of {}",
+            params.clients, DEFAULT_FILE_LIMIT));
+    }
+    if params.update_rate * params.update_size as f64 > 1024.0 * 1024.0 {
+        warnings.push(String::from("Host updates exceed 1 MiB per second per client, slow client networks will fall behind"));
+    }
+
+    Estimate {
+        params,
+        channel_size,
+        events_per_second,
+        burst_events,
+        broadcast_bandwidth,
+        host_bandwidth,
+        memory,
+        stalled_queue_memory,
+        warnings,
+    }
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Capacity estimate for {} clients", self.params.clients)?;
+        writeln!(f, "  inputs:  {} per client and second, {} bytes each", self.params.input_rate, self.params.input_size)?;
+        writeln!(f, "  updates: {} per second, {} bytes each", self.params.update_rate, self.params.update_size)?;
+        writeln!(f)?;
+        writeln!(f, "  main handler events:   {:.1} per second, bursts of {} (channel size {})", self.events_per_second, self.burst_events, self.channel_size)?;
+        writeln!(f, "  broadcast bandwidth:   {}/s", format_bytes(self.broadcast_bandwidth))?;
+        writeln!(f, "  host link bandwidth:   {}/s", format_bytes(self.host_bandwidth))?;
+        writeln!(f, "  memory (steady state): {}", format_bytes(self.memory))?;
+        writeln!(f, "  memory per stalled client ({}s): {}", STALL_SECONDS, format_bytes(self.stalled_queue_memory))?;
+        writeln!(f)?;
+        if self.warnings.is_empty() {
+            writeln!(f, "No mismatches with the configured limits found")
+        } else {
+            for warning in self.warnings.iter() {
+                writeln!(f, "WARNING: {}", warning)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_compared_with_the_configured_channel_size() {
+        let params = EstimateParams { clients: 200, ..Default::default() };
+        assert!(estimate(params.clone(), 1000).warnings.iter().all(|warning| !warning.contains(CHANNEL_SIZE_ENV)));
+        assert!(estimate(params, 50).warnings.iter().any(|warning| warning.contains(CHANNEL_SIZE_ENV)));
+    }
+
+    #[test]
+    fn config_argument_is_left_to_the_configuration() {
+        let args: Vec<String> = ["--config", "server.toml", "--clients", "30"].iter().map(|arg| String::from(*arg)).collect();
+        assert_eq!(EstimateParams::from_args(&args).unwrap().clients, 30);
+    }
+}
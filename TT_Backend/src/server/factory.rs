@@ -0,0 +1,94 @@
+//!
+//! Construction of the messages sent to connected hosts and clients.
+//! Every message is stamped by the MessageFactory with its metadata: a sequence number (monotonic
+//! per server, the recipients of a broadcast share it), the server timestamp and the session and
+//! state it was sent in. Connections only accept stamped messages and only the factory can stamp
+//! them, so no call site can forget the metadata and new metadata is added here only.
+//! The metadata is sent as the 'meta' object next to the fields of the message.
+//!
+
+use serde_json::{json, Value};
+use crate::server::messages::{BackendMessage, current_timestamp, encode_backend_json};
+
+/// Metadata of a sent message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageMeta {
+    pub seq: u64,
+    pub sent_at: i64,
+    /// Generation of the running session, None outside of a session
+    pub session: Option<u64>,
+    /// Id of the latest state, None before the first state
+    pub state_id: Option<i32>,
+}
+
+/// A message with its metadata, built by the MessageFactory
+#[derive(Debug, Clone)]
+pub struct StampedMessage {
+    msg: BackendMessage,
+    meta: MessageMeta,
+}
+
+impl StampedMessage {
+    pub fn message(&self) -> &BackendMessage {
+        &self.msg
+    }
+
+    /// Wire format of the message, with the metadata as 'meta'
+    pub fn encode(self) -> String {
+        self.into_json().to_string()
+    }
+
+    /// Json form of the message, with the metadata as 'meta'
+    pub fn into_json(self) -> Value {
+        let mut json = encode_backend_json(self.msg);
+        let mut meta = json!({"seq": self.meta.seq, "sent_at": self.meta.sent_at});
+        if let Some(session) = self.meta.session {
+            meta["session"] = json!(session);
+        }
+        if let Some(state_id) = self.meta.state_id {
+            meta["state_id"] = json!(state_id);
+        }
+        json["meta"] = meta;
+        json
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MessageFactory {
+    next_seq: u64,
+    session: Option<u64>,
+    state_id: Option<i32>,
+}
+
+impl MessageFactory {
+    /// Stamps the message, a 'ChangeState' moves the following messages into its state
+    pub fn build(&mut self, msg: BackendMessage) -> StampedMessage {
+        if let BackendMessage::ChangeState {state_id, ..} = &msg {
+            self.state_id = Some(*state_id);
+        }
+        let meta = MessageMeta {seq: self.next_seq, sent_at: current_timestamp(), session: self.session, state_id: self.state_id};
+        self.next_seq += 1;
+        StampedMessage {msg, meta}
+    }
+
+    /// Sequence number of the latest stamped message, 0 before the first one
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq.saturating_sub(1)
+    }
+
+    pub fn session_started(&mut self, session: u64) {
+        self.session = Some(session);
+    }
+
+    /// The state ends with the session
+    pub fn session_ended(&mut self) {
+        self.session = None;
+        self.state_id = None;
+    }
+
+    /// Continues the session and state taken over from the primary
+    pub fn restore(&mut self, session: Option<u64>, state_id: Option<i32>) {
+        self.session = session;
+        self.state_id = state_id;
+    }
+}
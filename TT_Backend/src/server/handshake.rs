@@ -0,0 +1,144 @@
+//!
+//! Diagnostics of failed TLS handshakes on the client port.
+//! Failures are classified by cause and counted, the most recent ones are kept with address and
+//! error, so "client can't connect" reports can be traced ('GET /debug/tls' on the admin
+//! interface, totals in '/health'). The first bytes of every connection are peeked before the
+//! handshake: plain HTTP (a ws:// url or a browser probing http://) is answered with a hint to use
+//! the TLS scheme instead of a failing handshake.
+//!
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde_json::{json, Value};
+use crate::server::messages::current_timestamp;
+
+/// Failures kept for the diagnostics endpoint, older ones are dropped
+pub const MAX_RECENT_FAILURES: usize = 50;
+/// Bytes peeked to tell plain HTTP from a TLS ClientHello
+pub const PEEK_SIZE: usize = 8;
+/// Answer to plain HTTP requests on the TLS port
+pub const PLAIN_HTTP_RESPONSE: &str = "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nThis port only accepts TLS. Connect with wss:// (or https://) instead of ws:// (or http://).\r\n";
+
+const HTTP_METHODS: [&[u8]; 9] = [b"GET ", b"POST", b"PUT ", b"HEAD", b"DELE", b"OPTI", b"PATC", b"CONN", b"TRAC"];
+/// Content type of the TLS handshake record every ClientHello starts with
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    /// Plain HTTP or websocket request without TLS
+    PlainHttp,
+    /// Neither HTTP nor a TLS record
+    NotTls,
+    /// No common protocol version or cipher suite
+    ProtocolMismatch,
+    /// The client rejected the certificate chain of the server
+    BadCertificate,
+    /// The client closed the connection during the handshake
+    Aborted,
+    Other,
+}
+
+const FAILURES: [HandshakeFailure; 6] = [
+    HandshakeFailure::PlainHttp,
+    HandshakeFailure::NotTls,
+    HandshakeFailure::ProtocolMismatch,
+    HandshakeFailure::BadCertificate,
+    HandshakeFailure::Aborted,
+    HandshakeFailure::Other,
+];
+
+impl HandshakeFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeFailure::PlainHttp => "plain_http",
+            HandshakeFailure::NotTls => "not_tls",
+            HandshakeFailure::ProtocolMismatch => "protocol_mismatch",
+            HandshakeFailure::BadCertificate => "bad_certificate",
+            HandshakeFailure::Aborted => "aborted",
+            HandshakeFailure::Other => "other",
+        }
+    }
+
+    /// Cause by the first bytes of the connection, None if they may start a TLS handshake
+    pub fn from_peek(first: &[u8]) -> Option<Self> {
+        match first.first() {
+            None | Some(&TLS_HANDSHAKE_RECORD) => None,
+            Some(_) if HTTP_METHODS.iter().any(|method| first.starts_with(method)) => Some(HandshakeFailure::PlainHttp),
+            Some(_) => Some(HandshakeFailure::NotTls),
+        }
+    }
+
+    /// Cause by the error of the TLS library
+    pub fn from_error(error: &str) -> Self {
+        let error = error.to_lowercase();
+        let any = |patterns: &[&str]| patterns.iter().any(|pattern| error.contains(pattern));
+        if any(&["certificate unknown", "unknown ca", "bad certificate", "certificate expired", "certificate revoked", "unsupported certificate"]) {
+            HandshakeFailure::BadCertificate
+        } else if any(&["wrong version number", "unsupported protocol", "version too low", "no shared cipher", "no protocols available", "protocol version", "inappropriate fallback", "http request"]) {
+            HandshakeFailure::ProtocolMismatch
+        } else if any(&["unexpected eof", "connection reset", "broken pipe"]) {
+            HandshakeFailure::Aborted
+        } else {
+            HandshakeFailure::Other
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RecentFailure {
+    address: SocketAddr,
+    failure: HandshakeFailure,
+    error: String,
+    at: i64,
+}
+
+/// Counters of the failed handshakes, shared by the client listener and the main handler
+#[derive(Debug, Default)]
+pub struct HandshakeStats {
+    succeeded: AtomicU64,
+    failed: [AtomicU64; FAILURES.len()],
+    recent: Mutex<VecDeque<RecentFailure>>,
+}
+
+impl HandshakeStats {
+    pub fn succeeded(&self) {
+        self.succeeded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self, address: SocketAddr, failure: HandshakeFailure, error: String) {
+        let index = FAILURES.iter().position(|f| *f == failure).unwrap_or(FAILURES.len() - 1);
+        self.failed[index].fetch_add(1, Ordering::Relaxed);
+        let mut recent = self.recent.lock().expect("failed(..): Lock is poisoned");
+        if recent.len() >= MAX_RECENT_FAILURES {
+            recent.pop_front();
+        }
+        recent.push_back(RecentFailure {address, failure, error, at: current_timestamp()});
+    }
+
+    /// Failures per cause
+    pub fn totals(&self) -> Value {
+        let mut totals = json!({});
+        for (failure, count) in FAILURES.iter().zip(self.failed.iter()) {
+            totals[failure.as_str()] = json!(count.load(Ordering::Relaxed));
+        }
+        totals
+    }
+
+    /// Totals and the most recent failures, the latest one first
+    pub fn report(&self) -> Value {
+        let recent = self.recent.lock().expect("report(..): Lock is poisoned");
+        let recent: Vec<Value> = recent.iter().rev().map(|failure| json!({
+            "address": failure.address.to_string(),
+            "cause": failure.failure.as_str(),
+            "error": failure.error,
+            "at": failure.at,
+        })).collect();
+        json!({
+            "succeeded": self.succeeded.load(Ordering::Relaxed),
+            "failed": self.totals(),
+            "recent": recent,
+        })
+    }
+}
@@ -0,0 +1,106 @@
+//!
+//! Handshake of a connecting host.
+//! Every host is greeted with 'ServerHello': the protocol version, the capabilities the server
+//! offers and its limits. Like every message to a host it is held back until the first frame of
+//! the host tells its protocol, so a host does not have to wait for it before logging in. The host
+//! answers with its own 'version' and 'capabilities' in 'HostLogin', only then it replaces the
+//! active host and messages are relayed to it. The agreed capabilities are those both sides named,
+//! notifications of capabilities the host left out are not sent to it. A host naming no
+//! capabilities (e.g. one from before the handshake) keeps receiving everything, legacy hosts never
+//! see the hello.
+//!
+
+use std::collections::HashSet;
+use std::time::Duration;
+use crate::server::messages::BackendMessage;
+use crate::server::networking::HOST_MAX_FRAME_SIZE;
+
+/// Version of the host protocol, 1 is the legacy protocol without frame magic
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Frames ending with a CRC32, enabled by 'checksum' of 'HostLogin'
+    Checksums,
+    /// Messages split into chunks
+    Chunks,
+    /// 'Subscribe' to event classes
+    Subscriptions,
+    /// 'SyncClientList'
+    ClientListSync,
+    /// Periodic 'ClientIssues' reports
+    ClientIssues,
+    /// 'QuorumReached' notifications
+    Quorum,
+    /// 'JoinInfo' and 'AdvertisedAddress' on login
+    JoinInfo,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 7] = [Capability::Checksums, Capability::Chunks, Capability::Subscriptions,
+        Capability::ClientListSync, Capability::ClientIssues, Capability::Quorum, Capability::JoinInfo];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Capability::ALL.into_iter().find(|capability| capability.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Checksums => "checksums",
+            Capability::Chunks => "chunks",
+            Capability::Subscriptions => "subscriptions",
+            Capability::ClientListSync => "client_list_sync",
+            Capability::ClientIssues => "client_issues",
+            Capability::Quorum => "quorum",
+            Capability::JoinInfo => "join_info",
+        }
+    }
+
+    /// Capability the notification belongs to, None for messages every host receives
+    fn of(msg: &BackendMessage) -> Option<Self> {
+        match msg {
+            BackendMessage::ClientIssues {..} => Some(Capability::ClientIssues),
+            BackendMessage::QuorumReached {..} => Some(Capability::Quorum),
+            BackendMessage::JoinInfo {..} | BackendMessage::AdvertisedAddress {..} => Some(Capability::JoinInfo),
+            _ => None,
+        }
+    }
+}
+
+/// Capabilities agreed with a host, every one if the host named none
+#[derive(Debug, Clone, Default)]
+pub struct Agreed {
+    capabilities: Option<HashSet<Capability>>,
+}
+
+impl Agreed {
+    /// Capabilities named by the host and offered by the server, unknown names are left out
+    pub fn negotiate(offered: Option<&[String]>) -> Self {
+        let capabilities = offered.map(|offered| offered.iter().filter_map(|name| Capability::parse(name)).collect());
+        Agreed {capabilities}
+    }
+
+    /// Names of the agreed capabilities, None if the host named none
+    pub fn names(&self) -> Option<Vec<&'static str>> {
+        let capabilities = self.capabilities.as_ref()?;
+        Some(Capability::ALL.into_iter().filter(|capability| capabilities.contains(capability)).map(|capability| capability.as_str()).collect())
+    }
+
+    /// Whether the message may be sent to the host
+    pub fn admits(&self, msg: &BackendMessage) -> bool {
+        match (self.capabilities.as_ref(), Capability::of(msg)) {
+            (Some(capabilities), Some(capability)) => capabilities.contains(&capability),
+            _ => true,
+        }
+    }
+}
+
+/// Greeting of a connecting host
+pub fn server_hello(send_timeout: Option<Duration>) -> BackendMessage {
+    BackendMessage::ServerHello {
+        version: PROTOCOL_VERSION,
+        capabilities: Capability::ALL.iter().map(|capability| String::from(capability.as_str())).collect(),
+        max_frame_size: HOST_MAX_FRAME_SIZE,
+        send_timeout: send_timeout.map(|timeout| timeout.as_millis() as u64),
+    }
+}
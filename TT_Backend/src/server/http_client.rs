@@ -0,0 +1,198 @@
+//!
+//! Minimal HTTP/1.1 client for calling external services (webhooks, verifiers, ACME).
+//! Supports 'http' and 'https' urls, one request per connection.
+//!
+
+use std::time::Duration;
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Parsed form of an 'http(s)://host[:port][/path]' url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub secure: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("Url '{}' has to start with 'http://' or 'https://'", url))
+        };
+
+        let (authority, path) = match rest.find('/') {
+            None => (rest, "/"),
+            Some(i) => (&rest[..i], &rest[i..]),
+        };
+        // IPv6 hosts are enclosed in brackets, the port follows after the closing one
+        let port_separator = match authority.rfind(']') {
+            None => authority.rfind(':'),
+            Some(i) => authority[i..].find(':').map(|j| i + j),
+        };
+        let (host, port) = match port_separator {
+            None => (authority, if secure { 443 } else { 80 }),
+            Some(i) => {
+                let port = authority[i + 1..].parse().map_err(|_| format!("Url '{}' contains an invalid port", url))?;
+                (&authority[..i], port)
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("Url '{}' contains no host", url))
+        }
+
+        Ok(Url { secure, host: String::from(host), port, path: String::from(path) })
+    }
+}
+
+/// Status, headers and body of a response
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    /// Names in lower case
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    /// Value of the first header of the name (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers.iter().find(|(n, _)| *n == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Sends the json body via POST and returns status code and response body
+pub async fn post_json(url: &Url, body: &Value, limit: Duration) -> Result<(u16, String), String> {
+    let response = request(url, "POST", Some(("application/json", &body.to_string())), limit).await?;
+    Ok((response.status, response.body))
+}
+
+/// Sends the request with an optional body (content type and content)
+pub async fn request(url: &Url, method: &str, body: Option<(&str, &str)>, limit: Duration) -> Result<Response, String> {
+    match timeout(limit, request_unlimited(url, method, body)).await {
+        Ok(v) => v,
+        Err(_) => Err(format!("Request to {}:{} timed out", url.host, url.port)),
+    }
+}
+
+async fn request_unlimited(url: &Url, method: &str, body: Option<(&str, &str)>) -> Result<Response, String> {
+    let request = match body {
+        None => format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", method, url.path, url.host),
+        Some((content_type, body)) => format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method, url.path, url.host, content_type, body.len(), body),
+    };
+
+    let host = url.host.trim_start_matches('[').trim_end_matches(']');
+    let stream = TcpStream::connect((host, url.port)).await
+        .map_err(|e| format!("Connecting to {}:{} failed: {}", url.host, url.port, e))?;
+
+    let response = if url.secure {
+        let connector = native_tls::TlsConnector::new().map_err(|e| format!("Creating tls connector failed: {}", e))?;
+        let connector = tokio_native_tls::TlsConnector::from(connector);
+        let stream = connector.connect(host, stream).await
+            .map_err(|e| format!("Tls handshake with {} failed: {}", url.host, e))?;
+        exchange(stream, &request).await?
+    } else {
+        exchange(stream, &request).await?
+    };
+
+    parse_response(&response)
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, request: &str) -> Result<Vec<u8>, String> {
+    stream.write_all(request.as_bytes()).await.map_err(|e| format!("Sending request failed: {}", e))?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.map_err(|e| format!("Reading response failed: {}", e))?;
+    Ok(response)
+}
+
+/// Works on the raw bytes, chunks may split multi-byte characters of the body
+fn parse_response(response: &[u8]) -> Result<Response, String> {
+    let split = find(response, b"\r\n\r\n").ok_or("Response is malformed")?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let body = &response[split + 4..];
+
+    let status = head.split_whitespace().nth(1)
+        .and_then(|v| v.parse().ok())
+        .ok_or("Response contains no status code")?;
+
+    let headers: Vec<(String, String)> = head.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), String::from(value.trim())))
+        .collect();
+    let chunked = headers.iter().any(|(name, value)| name == "transfer-encoding" && value.to_ascii_lowercase().contains("chunked"));
+    let body = if chunked { decode_chunked(body)? } else { body.to_vec() };
+
+    Ok(Response {status, headers, body: String::from_utf8_lossy(&body).into_owned()})
+}
+
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = find(body, b"\r\n").ok_or("Chunked body is malformed")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16).ok())
+            .ok_or("Chunk size is malformed")?;
+        if size == 0 {
+            return Ok(decoded)
+        }
+        let rest = &body[line_end + 2..];
+        let chunk = rest.get(..size).ok_or("Chunk is truncated")?;
+        decoded.extend_from_slice(chunk);
+        body = rest[size..].strip_prefix(b"\r\n").ok_or("Chunk is not terminated")?;
+    }
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes.windows(pattern.len()).position(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_takes_default_port_and_path() {
+        assert_eq!(Url::parse("https://auth.example.org").unwrap(), Url {secure: true, host: String::from("auth.example.org"), port: 443, path: String::from("/")});
+        assert_eq!(Url::parse("http://[::1]:8080/hook?x=1").unwrap(), Url {secure: false, host: String::from("[::1]"), port: 8080, path: String::from("/hook?x=1")});
+        assert!(Url::parse("ftp://example.org").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+    }
+
+    #[test]
+    fn response_with_length_is_parsed() {
+        let response = parse_response(b"HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nContent-Length: 13\r\n\r\n{\"allow\":true}").unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("content-TYPE"), Some("application/json"));
+        assert_eq!(response.body, "{\"allow\":true}");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn chunks_splitting_a_character_are_joined() {
+        // 'ü' is c3 bc, the chunks split it
+        let mut response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        response.extend_from_slice(b"3;ext=1\r\nGr\xc3\r\n");
+        response.extend_from_slice(b"4\r\n\xbc\xc3\x9fe\r\n");
+        response.extend_from_slice(b"0\r\n\r\n");
+        assert_eq!(parse_response(&response).unwrap().body, "Grüße");
+    }
+
+    #[test]
+    fn malformed_chunks_are_rejected() {
+        assert!(decode_chunked(b"5\r\nabc").is_err());
+        assert!(decode_chunked(b"zz\r\nabc\r\n0\r\n\r\n").is_err());
+        assert!(decode_chunked(b"3\r\nabcdef\r\n0\r\n\r\n").is_err());
+        assert_eq!(decode_chunked(b"3\r\n\r\n\r\r\n0\r\n\r\n").unwrap(), b"\r\n\r");
+    }
+}
@@ -0,0 +1,226 @@
+//!
+//! Vote-stuffing heuristics.
+//! Optional (TT_BACKEND_INTEGRITY), records joins and first inputs of the session and flags
+//! suspicious patterns: many identities from one IP, inputs of different clients arriving at the
+//! same time for several states and rapid rejoin cycles. The server only reports a risk score per
+//! client, the host decides whether to discount or mute anybody.
+//!
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+
+/// Distinct names from one IP tolerated before they are flagged
+const SHARED_IP_IDENTITIES: usize = 3;
+/// Inputs of two clients this close together count as synchronized
+const SYNC_WINDOW: i64 = 50;
+/// States with synchronized inputs of the same pair before both are flagged
+const SYNC_STATES: usize = 3;
+/// Joins of one name within 'REJOIN_WINDOW' flagged as rejoin cycle
+const REJOIN_COUNT: usize = 5;
+const REJOIN_WINDOW: i64 = 60_000;
+const MAX_SCORE: u32 = 100;
+
+/// Suspicious pattern found for a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskFlag {
+    SharedIp,
+    SynchronizedInputs,
+    RapidRejoin,
+}
+
+impl RiskFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RiskFlag::SharedIp => "shared_ip",
+            RiskFlag::SynchronizedInputs => "synchronized_inputs",
+            RiskFlag::RapidRejoin => "rapid_rejoin",
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        match self {
+            RiskFlag::SharedIp => 30,
+            RiskFlag::SynchronizedInputs => 50,
+            RiskFlag::RapidRejoin => 30,
+        }
+    }
+}
+
+/// Risk of one client name, 0 (nothing suspicious) to 100
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskScore {
+    pub name: String,
+    pub score: u32,
+    pub flags: Vec<RiskFlag>,
+}
+
+#[derive(Debug, Default)]
+pub struct Integrity {
+    /// Names that logged in from an IP
+    identities: HashMap<IpAddr, BTreeSet<String>>,
+    /// Server timestamps of the joins of a name
+    joins: HashMap<String, Vec<i64>>,
+    /// Server timestamp of the first input of every name, by state
+    inputs: HashMap<i32, HashMap<String, i64>>,
+}
+
+impl Integrity {
+    pub fn joined(&mut self, name: &str, ip: IpAddr, now: i64) {
+        self.identities.entry(ip).or_default().insert(String::from(name));
+        self.joins.entry(String::from(name)).or_default().push(now);
+    }
+
+    /// Only the first input of a name per state is kept
+    pub fn input(&mut self, name: &str, state_id: i32, server_ts: i64) {
+        self.inputs.entry(state_id).or_default().entry(String::from(name)).or_insert(server_ts);
+    }
+
+    /// Flagged clients, highest score first
+    pub fn report(&self) -> Vec<RiskScore> {
+        let mut flags: HashMap<&str, BTreeSet<RiskFlag>> = HashMap::new();
+
+        for names in self.identities.values().filter(|names| names.len() > SHARED_IP_IDENTITIES) {
+            for name in names {
+                flags.entry(name).or_default().insert(RiskFlag::SharedIp);
+            }
+        }
+
+        let mut synchronized: HashMap<(&str, &str), usize> = HashMap::new();
+        for inputs in self.inputs.values() {
+            let mut times: Vec<(i64, &str)> = inputs.iter().map(|(name, ts)| (*ts, name.as_str())).collect();
+            times.sort_unstable();
+            for (index, (ts, name)) in times.iter().enumerate() {
+                for (_, other) in times[index + 1..].iter().take_while(|(other_ts, _)| other_ts - ts <= SYNC_WINDOW) {
+                    *synchronized.entry(if name < other { (name, other) } else { (other, name) }).or_default() += 1;
+                }
+            }
+        }
+        for ((a, b), _) in synchronized.into_iter().filter(|(_, states)| *states >= SYNC_STATES) {
+            flags.entry(a).or_default().insert(RiskFlag::SynchronizedInputs);
+            flags.entry(b).or_default().insert(RiskFlag::SynchronizedInputs);
+        }
+
+        for (name, joins) in self.joins.iter() {
+            if joins.windows(REJOIN_COUNT).any(|window| window[REJOIN_COUNT - 1] - window[0] <= REJOIN_WINDOW) {
+                flags.entry(name).or_default().insert(RiskFlag::RapidRejoin);
+            }
+        }
+
+        let mut scores: Vec<RiskScore> = flags.into_iter()
+            .map(|(name, flags)| RiskScore {
+                name: String::from(name),
+                score: flags.iter().map(RiskFlag::weight).sum::<u32>().min(MAX_SCORE),
+                flags: flags.into_iter().collect(),
+            })
+            .collect();
+        scores.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    fn score_of<'a>(report: &'a [RiskScore], name: &str) -> Option<&'a RiskScore> {
+        report.iter().find(|score| score.name == name)
+    }
+
+    /// A class answering on its own devices, over several minutes and states
+    fn normal_session(integrity: &mut Integrity) {
+        let names = ["ada", "ben", "cleo", "dev", "eli"];
+        for (index, name) in names.iter().enumerate() {
+            integrity.joined(name, ip(index as u8), index as i64 * 1_000);
+        }
+        // Three devices behind one NAT are tolerated
+        integrity.joined("fay", ip(100), 0);
+        integrity.joined("gus", ip(100), 0);
+        integrity.joined("hal", ip(100), 0);
+        for state_id in 0..5 {
+            for (index, name) in names.iter().enumerate() {
+                integrity.input(name, state_id, state_id as i64 * 60_000 + index as i64 * 700);
+            }
+        }
+        // One reconnect is no cycle
+        integrity.joined("ada", ip(0), 30_000);
+    }
+
+    #[test]
+    fn normal_traffic_is_not_flagged() {
+        let mut integrity = Integrity::default();
+        normal_session(&mut integrity);
+        assert_eq!(integrity.report(), vec![]);
+    }
+
+    #[test]
+    fn many_identities_from_one_ip_are_flagged() {
+        let mut integrity = Integrity::default();
+        normal_session(&mut integrity);
+        integrity.joined("ivy", ip(100), 5_000);
+        let report = integrity.report();
+        assert_eq!(report.len(), 4);
+        let score = score_of(&report, "ivy").expect("Shared IP not flagged");
+        assert_eq!(score.flags, vec![RiskFlag::SharedIp]);
+        assert!(score.score > 0);
+        assert!(score_of(&report, "ada").is_none());
+    }
+
+    #[test]
+    fn identical_input_timing_is_flagged() {
+        let mut integrity = Integrity::default();
+        normal_session(&mut integrity);
+        for state_id in 0..SYNC_STATES as i32 {
+            integrity.input("bot_1", state_id, state_id as i64 * 60_000 + 10_000);
+            integrity.input("bot_2", state_id, state_id as i64 * 60_000 + 10_000 + SYNC_WINDOW);
+        }
+        let report = integrity.report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(score_of(&report, "bot_1").map(|score| score.flags.clone()), Some(vec![RiskFlag::SynchronizedInputs]));
+        assert!(score_of(&report, "bot_2").is_some());
+    }
+
+    #[test]
+    fn synchronized_inputs_of_too_few_states_are_not_flagged() {
+        let mut integrity = Integrity::default();
+        for state_id in 0..SYNC_STATES as i32 - 1 {
+            integrity.input("ada", state_id, 1_000);
+            integrity.input("ben", state_id, 1_000);
+        }
+        assert_eq!(integrity.report(), vec![]);
+    }
+
+    #[test]
+    fn rapid_rejoin_cycles_are_flagged() {
+        let mut integrity = Integrity::default();
+        normal_session(&mut integrity);
+        for join in 0..REJOIN_COUNT as i64 {
+            integrity.joined("ben", ip(1), 100_000 + join * 10_000);
+        }
+        let report = integrity.report();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].flags, vec![RiskFlag::RapidRejoin]);
+        assert_eq!(report[0].name, "ben");
+    }
+
+    #[test]
+    fn patterns_add_up_to_the_maximum() {
+        let mut integrity = Integrity::default();
+        for join in 0..REJOIN_COUNT as i64 {
+            integrity.joined("bot", ip(7), join);
+            integrity.joined(&format!("bot_{}", join), ip(7), join);
+        }
+        for state_id in 0..SYNC_STATES as i32 {
+            integrity.input("bot", state_id, 0);
+            integrity.input("bot_0", state_id, 0);
+        }
+        let report = integrity.report();
+        assert_eq!(report[0].name, "bot");
+        assert_eq!(report[0].flags, vec![RiskFlag::SharedIp, RiskFlag::SynchronizedInputs, RiskFlag::RapidRejoin]);
+        assert_eq!(report[0].score, MAX_SCORE);
+        assert!(report[1].score < report[0].score);
+    }
+}
@@ -0,0 +1,181 @@
+//!
+//! Optional built-in leaderboard.
+//! The host declares a scoring rule per state with 'SetScoring'. The first input of every client in
+//! such a state is scored: a correct answer earns the points of the rule plus a speed bonus that
+//! shrinks linearly from the moment the state was set (by server timestamps) until the bonus window
+//! is over. Scores are cumulative per participant, identified like resumed clients so they survive
+//! reconnects, and per team. Points and bonus are bounded by MAX_POINTS, the window by
+//! MAX_BONUS_WINDOW.
+//!
+
+use std::collections::{HashMap, HashSet};
+
+/// Largest number of points (and speed bonus) of a scoring rule, positive or negative
+pub const MAX_POINTS: i64 = 1_000_000_000;
+/// Longest bonus window in milliseconds
+pub const MAX_BONUS_WINDOW: i64 = 24 * 60 * 60 * 1000;
+
+/// How inputs of one state are scored
+#[derive(Debug, Clone)]
+pub struct ScoringRule {
+    /// Expected input, compared ignoring case and surrounding whitespace
+    pub correct: String,
+    pub points: i64,
+    /// Bonus for answering right when the state was set
+    pub speed_bonus: i64,
+    /// Time (in milliseconds) after which no bonus is granted anymore
+    pub bonus_window: i64,
+}
+
+impl ScoringRule {
+    /// Fails if the values are out of bounds
+    pub fn new(correct: String, points: i64, speed_bonus: i64, bonus_window: i64) -> Result<Self, String> {
+        let bounds = -MAX_POINTS..=MAX_POINTS;
+        if !bounds.contains(&points) || !bounds.contains(&speed_bonus) {
+            return Err(format!("Points and speed bonus have to be within +/-{}", MAX_POINTS))
+        }
+        if !(0..=MAX_BONUS_WINDOW).contains(&bonus_window) {
+            return Err(format!("Bonus window has to be within 0 and {} ms", MAX_BONUS_WINDOW))
+        }
+        Ok(ScoringRule {correct, points, speed_bonus, bonus_window})
+    }
+
+    /// Points for the input, sent 'elapsed' milliseconds after the state was set
+    pub fn score(&self, input: &str, elapsed: Option<i64>) -> i64 {
+        if !input.trim().eq_ignore_ascii_case(self.correct.trim()) {
+            return 0
+        }
+        let bonus = match elapsed {
+            Some(elapsed) if self.bonus_window > 0 && elapsed < self.bonus_window => {
+                let remaining = i128::from(self.bonus_window) - i128::from(elapsed.max(0));
+                (i128::from(self.speed_bonus) * remaining / i128::from(self.bonus_window)) as i64
+            }
+            _ => 0,
+        };
+        self.points.saturating_add(bonus)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Leaderboard {
+    rules: HashMap<i32, ScoringRule>,
+    /// Current state and the server timestamp it was set at
+    current_state: Option<(i32, i64)>,
+    /// Identities already scored in the current state
+    scored: HashSet<String>,
+    /// Latest name and cumulative score by identity
+    scores: HashMap<String, (String, i64)>,
+    team_scores: HashMap<String, i64>,
+}
+
+impl Leaderboard {
+    pub fn set_rule(&mut self, state_id: i32, rule: ScoringRule) {
+        self.rules.insert(state_id, rule);
+    }
+
+    pub fn state_changed(&mut self, state_id: i32, server_ts: i64) {
+        self.current_state = Some((state_id, server_ts));
+        self.scored.clear();
+    }
+
+    /// Scores the input if its state has a scoring rule and the client was not scored in it yet
+    /// Returns the points earned, None if the input was not scored
+    pub fn score_input(&mut self, identity: &str, name: &str, team: Option<&str>, state_id: i32, input: &str, server_ts: i64) -> Option<i64> {
+        let rule = self.rules.get(&state_id)?;
+        let started = match self.current_state {
+            Some((current, started)) if current == state_id => started,
+            _ => return None,
+        };
+        if !self.scored.insert(String::from(identity)) {
+            return None
+        }
+
+        let points = rule.score(input, Some(server_ts.saturating_sub(started)));
+        let (scored_name, score) = self.scores.entry(String::from(identity)).or_default();
+        *scored_name = String::from(name);
+        *score = score.saturating_add(points);
+        if let Some(team) = team {
+            let team_score = self.team_scores.entry(String::from(team)).or_default();
+            *team_score = team_score.saturating_add(points);
+        }
+        Some(points)
+    }
+
+    /// Cumulative score of all inputs scored for the team
+    pub fn team_score(&self, team: &str) -> i64 {
+        self.team_scores.get(team).copied().unwrap_or(0)
+    }
+
+    /// All teams that scored at least once
+    pub fn scored_teams(&self) -> impl Iterator<Item = &str> {
+        self.team_scores.keys().map(String::as_str)
+    }
+
+    /// The best 'count' participants with their latest name and cumulative score, ties ordered by
+    /// name
+    pub fn standings(&self, count: usize) -> Vec<(String, i64)> {
+        let mut standings: Vec<(String, i64)> = self.scores.values().cloned().collect();
+        standings.sort_by(|(name_a, score_a), (name_b, score_b)| score_b.cmp(score_a).then(name_a.cmp(name_b)));
+        standings.truncate(count);
+        standings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(points: i64, speed_bonus: i64, bonus_window: i64) -> ScoringRule {
+        ScoringRule::new(String::from("Paris"), points, speed_bonus, bonus_window).expect("Rule was rejected")
+    }
+
+    #[test]
+    fn bonus_shrinks_over_the_window() {
+        let rule = rule(100, 50, 10_000);
+        assert_eq!(rule.score(" paris ", Some(0)), 150);
+        assert_eq!(rule.score("Paris", Some(5_000)), 125);
+        assert_eq!(rule.score("Paris", Some(10_000)), 100);
+        assert_eq!(rule.score("Paris", Some(-5)), 150);
+        assert_eq!(rule.score("Paris", None), 100);
+        assert_eq!(rule.score("London", Some(0)), 0);
+    }
+
+    #[test]
+    fn out_of_bound_rules_are_rejected() {
+        assert!(ScoringRule::new(String::from("a"), i64::MAX, 0, 0).is_err());
+        assert!(ScoringRule::new(String::from("a"), 1, i64::MIN, 1).is_err());
+        assert!(ScoringRule::new(String::from("a"), 1, 1, -1).is_err());
+        assert!(ScoringRule::new(String::from("a"), 1, 1, i64::MAX).is_err());
+        assert_eq!(rule(MAX_POINTS, MAX_POINTS, MAX_BONUS_WINDOW).score("Paris", Some(i64::MIN)), 2 * MAX_POINTS);
+    }
+
+    #[test]
+    fn totals_saturate() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.set_rule(1, rule(MAX_POINTS, 0, 0));
+        leaderboard.scores.insert(String::from("alice-id"), (String::from("alice"), i64::MAX - 1));
+        leaderboard.team_scores.insert(String::from("red"), i64::MAX - 1);
+        leaderboard.state_changed(1, 0);
+        assert_eq!(leaderboard.score_input("alice-id", "alice", Some("red"), 1, "Paris", i64::MAX), Some(MAX_POINTS));
+        assert_eq!(leaderboard.standings(1), vec![(String::from("alice"), i64::MAX)]);
+        assert_eq!(leaderboard.team_score("red"), i64::MAX);
+    }
+
+    #[test]
+    fn participants_sharing_a_name_score_separately() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.set_rule(1, rule(10, 0, 0));
+        leaderboard.set_rule(2, rule(10, 0, 0));
+        leaderboard.state_changed(1, 0);
+        assert_eq!(leaderboard.score_input("a", "alex", Some("red"), 1, "Paris", 5), Some(10));
+        assert_eq!(leaderboard.score_input("b", "alex", Some("red"), 1, "Paris", 5), Some(10));
+        assert_eq!(leaderboard.score_input("a", "alex", Some("red"), 1, "Paris", 6), None);
+        // Inputs for a state that is not the current one are not scored
+        assert_eq!(leaderboard.score_input("a", "alex", None, 2, "Paris", 7), None);
+
+        leaderboard.state_changed(2, 10);
+        assert_eq!(leaderboard.score_input("a", "alexandra", None, 2, "Paris", 11), Some(10));
+        assert_eq!(leaderboard.standings(5), vec![(String::from("alexandra"), 20), (String::from("alex"), 10)]);
+        assert_eq!(leaderboard.team_score("red"), 20);
+    }
+}
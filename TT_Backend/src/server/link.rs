@@ -0,0 +1,81 @@
+//!
+//! Quality of the link to the host.
+//! The uplink of the host is estimated from the time large frames take to arrive, the round trip
+//! time from 'LinkProbe's the host answers with 'LinkProbeAck'. Every feedback interval the host
+//! gets both in a 'LinkQuality', with a warning if its uplink can not deliver the current state to
+//! every client within the broadcast budget, so the presenter can reduce media sizes in time.
+//!
+
+use std::time::Duration;
+use crate::server::messages::BackendMessage;
+
+/// Interval between two feedbacks (and probes)
+pub const LINK_FEEDBACK_INTERVAL: Duration = Duration::from_secs(10);
+/// Frames below arrive in one piece, their timing says nothing about the throughput
+pub const LINK_SAMPLE_MIN_BYTES: usize = 16 * 1024;
+/// Time a state may take to reach every client
+const BROADCAST_BUDGET: Duration = Duration::from_secs(2);
+/// Weight of a new throughput sample
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Default)]
+pub struct LinkQuality {
+    /// Smoothed throughput in bytes per second
+    throughput: Option<f64>,
+    /// Latest round trip time in milliseconds
+    rtt: Option<i64>,
+    /// Id and server timestamp of the unanswered probe
+    probe: Option<(u64, i64)>,
+    next_probe: u64,
+}
+
+impl LinkQuality {
+    /// The frame of 'bytes' took 'elapsed' from its first to its last byte
+    pub fn sample(&mut self, bytes: usize, elapsed: Duration) {
+        if bytes < LINK_SAMPLE_MIN_BYTES || elapsed.is_zero() {
+            return
+        }
+        let sample = bytes as f64 / elapsed.as_secs_f64();
+        self.throughput = Some(match self.throughput {
+            None => sample,
+            Some(throughput) => throughput * (1.0 - SMOOTHING) + sample * SMOOTHING,
+        });
+    }
+
+    /// Returns the probe to send, an unanswered probe is given up
+    pub fn probe(&mut self, now: i64) -> BackendMessage {
+        let probe = self.next_probe;
+        self.next_probe += 1;
+        self.probe = Some((probe, now));
+        BackendMessage::LinkProbe {probe, sent_at: now}
+    }
+
+    /// Returns the round trip time, None if the probe is not the latest one
+    pub fn probe_acked(&mut self, probe: u64, now: i64) -> Option<i64> {
+        match self.probe {
+            Some((latest, sent_at)) if latest == probe => {
+                self.probe = None;
+                self.rtt = Some(now - sent_at);
+                self.rtt
+            }
+            _ => None,
+        }
+    }
+
+    /// Feedback for broadcasting 'broadcast_size' bytes to 'clients' clients
+    pub fn feedback(&self, broadcast_size: usize, clients: usize) -> BackendMessage {
+        let required = (broadcast_size * clients) as f64 / BROADCAST_BUDGET.as_secs_f64();
+        let warning = match self.throughput {
+            Some(uplink) if uplink < required => Some(format!(
+                "Uplink of about {} bytes/s can not deliver {} bytes to {} clients within {:?}, consider reducing media sizes",
+                uplink as u64, broadcast_size, clients, BROADCAST_BUDGET)),
+            _ => None,
+        };
+        BackendMessage::LinkQuality {
+            rtt: self.rtt,
+            uplink: self.throughput.map(|throughput| throughput as u64),
+            required: required as u64,
+            warning,
+        }
+    }
+}
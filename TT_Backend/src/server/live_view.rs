@@ -0,0 +1,194 @@
+//!
+//! Watch-only public live view.
+//! Overflow displays and website embeds mirror the presentation over plain HTTP instead of a client
+//! websocket, so they neither take a client slot nor count as participants. Nothing is shown until
+//! the host marks the presentation public with 'SetPublic'.
+//! 'GET /state' answers the current state followed by the updates sent within it as json array,
+//! 'GET /events' streams the same messages and every following 'ChangeState' and 'Update' as
+//! server-sent events. Messages have the wire format of the clients, so embeds can reuse the client
+//! code. The stream ends with a 'Hidden' event once the host withdraws the presentation or the
+//! session ends. Viewers not keeping up are dropped, EventSource reconnects on its own.
+//!
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use log::{error, info, warn};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+use crate::server::InternalMessage;
+use crate::server::admin::read_request_head;
+use crate::server::bus::Bus;
+use crate::server::factory::StampedMessage;
+use crate::server::networking::{accept_exhausted, bind_tcp, Listener};
+use crate::server::resync::UPDATE_BUFFER_SIZE;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Events queued per viewer, holds a full snapshot with room to spare
+const VIEWER_BUFFER: usize = UPDATE_BUFFER_SIZE + 64;
+/// Interval of the comments keeping idle streams open through proxies
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Public flag of the presentation and the streams of the connected viewers
+#[derive(Debug, Default)]
+pub struct LiveView {
+    public: bool,
+    viewers: HashMap<SocketAddr, mpsc::Sender<String>>,
+}
+
+impl LiveView {
+    pub fn is_public(&self) -> bool {
+        self.public
+    }
+
+    /// Hiding the presentation ends every stream with a 'Hidden' event
+    pub fn set_public(&mut self, public: bool) {
+        self.public = public;
+        if !public {
+            for (_, viewer) in self.viewers.drain() {
+                let _ = viewer.try_send(hidden());
+            }
+        }
+    }
+
+    /// Stream of the viewer, starting with the snapshot, None while the presentation is not public
+    pub fn subscribe(&mut self, address: SocketAddr, snapshot: Vec<StampedMessage>) -> Option<mpsc::Receiver<String>> {
+        if !self.public {
+            return None
+        }
+        let (sender, receiver) = mpsc::channel(VIEWER_BUFFER);
+        for msg in snapshot {
+            let _ = sender.try_send(msg.encode());
+        }
+        self.viewers.insert(address, sender);
+        Some(receiver)
+    }
+
+    /// Sends the message to every viewer, viewers that are gone or not keeping up are dropped
+    pub fn publish(&mut self, msg: &StampedMessage) {
+        if !self.public || self.viewers.is_empty() {
+            return
+        }
+        let event = msg.clone().encode();
+        self.viewers.retain(|address, viewer| match viewer.try_send(event.clone()) {
+            Ok(_) => true,
+            Err(_) => {
+                info!("publish(..): Dropping live viewer {}", address);
+                false
+            }
+        });
+    }
+
+    /// Connected viewers, the ones gone since the last message included
+    pub fn viewers(&self) -> usize {
+        self.viewers.len()
+    }
+}
+
+fn hidden() -> String {
+    json!({"type": "Hidden"}).to_string()
+}
+
+/// Create a listener on the live view port waiting for viewers
+pub async fn create_live_view_listener(channel: Bus, addr: SocketAddr) -> std::io::Result<Listener> {
+    // TCP listener
+    let listener = bind_tcp(addr).await?;
+    info!("create_live_view_listener(..): Listening for live viewers on {}", addr);
+
+    // Spawn listener, restarted by the supervision if it ends
+    Listener::start(addr, listener, move |listener| listen(channel.clone(), listener))
+}
+
+/// Waiting for incoming connections
+/// Every viewer is served in its own task
+async fn listen(channel: Bus, listener: TcpListener) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) if accept_exhausted(&e) => {
+                error!("listen(..): Could not accept connection, stopping until restarted\nError: {}", e);
+                return
+            }
+            Err(e) => {
+                warn!("listen(..): Could not accept connection\nError: {}", e);
+                continue
+            },
+        };
+
+        tokio::spawn(viewer_connection(channel.clone(), stream, address));
+    }
+}
+
+/// Reads one request and answers it with the snapshot or the event stream
+async fn viewer_connection(channel: Bus, mut stream: TcpStream, address: SocketAddr) {
+    let head = match timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            warn!("viewer_connection(..): Request by {} is malformed. Dropping!", address);
+            return
+        }
+        Err(_) => {
+            warn!("viewer_connection(..): Request by {} timed out. Dropping!", address);
+            return
+        }
+    };
+
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    let path = target.split_once('?').map(|(path, _)| path).unwrap_or(target);
+
+    let result = match (method, path) {
+        ("GET", "/state") => {
+            let (reply, reply_rcv) = oneshot::channel();
+            channel.send(InternalMessage::LiveViewState {reply}).await.expect("viewer_connection(..): Sending internal message failed");
+            match reply_rcv.await.ok().flatten() {
+                None => write_response(&mut stream, 404, "Not Found", &json!({"error": "Nothing public"}).to_string()).await,
+                Some(snapshot) => write_response(&mut stream, 200, "OK", &format!("[{}]", snapshot.join(","))).await,
+            }
+        }
+        ("GET", "/events") => {
+            let (reply, reply_rcv) = oneshot::channel();
+            channel.send(InternalMessage::LiveViewerConnected {address, reply}).await.expect("viewer_connection(..): Sending internal message failed");
+            match reply_rcv.await.ok().flatten() {
+                None => write_response(&mut stream, 404, "Not Found", &json!({"error": "Nothing public"}).to_string()).await,
+                Some(events) => {
+                    info!("viewer_connection(..): Live viewer {} connected", address);
+                    stream_events(&mut stream, events).await
+                }
+            }
+        }
+        ("GET", _) => write_response(&mut stream, 404, "Not Found", &json!({"error": "Not found"}).to_string()).await,
+        _ => write_response(&mut stream, 405, "Method Not Allowed", &json!({"error": "Method not allowed"}).to_string()).await,
+    };
+    if let Err(e) = result {
+        info!("viewer_connection(..): Live viewer {} is gone\nError: {}", address, e);
+    }
+}
+
+/// Writes the events until the stream ends or the viewer is gone
+async fn stream_events(stream: &mut TcpStream, mut events: mpsc::Receiver<String>) -> std::io::Result<()> {
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes()).await?;
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                None => break,
+                Some(event) => stream.write_all(format!("data: {}\n\n", event).as_bytes()).await?,
+            },
+            _ = keepalive.tick() => stream.write_all(b": keepalive\n\n").await?,
+        }
+    }
+    stream.shutdown().await
+}
+
+async fn write_response(stream: &mut TcpStream, status: u16, reason: &str, body: &str) -> std::io::Result<()> {
+    let response = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nAccess-Control-Allow-Origin: *\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,84 @@
+//!
+//! Logger whose filter can be changed while the server is running.
+//! Filters use the RUST_LOG syntax (e.g. 'info,tt_online::server::networking::websockets=debug'),
+//! the initial filter is taken from RUST_LOG.
+//!
+
+use std::env;
+use std::sync::{OnceLock, RwLock};
+use env_logger::filter::{Builder, Filter};
+use log::{LevelFilter, Log, Metadata, Record};
+
+const DEFAULT_FILTER: &str = "error";
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+struct ReloadableLogger {
+    inner: env_logger::Logger,
+    filter: RwLock<(String, Filter)>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.read().unwrap().1.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.read().unwrap().1.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger, further calls have no effect
+/// RUST_LOG takes precedence over the configured filter
+pub fn init(configured: Option<&str>) {
+    let spec = env::var(env_logger::DEFAULT_FILTER_ENV).ok()
+        .or_else(|| configured.map(String::from))
+        .unwrap_or_else(|| String::from(DEFAULT_FILTER));
+    let logger = LOGGER.get_or_init(|| {
+        // The inner logger only formats, filtering is done by the reloadable filter
+        let inner = env_logger::Builder::new().filter_level(LevelFilter::Trace).build();
+        let filter = Builder::new().parse(&spec).build();
+        ReloadableLogger { inner, filter: RwLock::new((spec, filter)) }
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.filter.read().unwrap().1.filter());
+    }
+}
+
+/// Returns the currently active filter
+pub fn get_filter() -> Option<String> {
+    LOGGER.get().map(|logger| logger.filter.read().unwrap().0.clone())
+}
+
+/// Replaces the active filter
+/// Fails if the filter is malformed or the logger is not installed
+pub fn set_filter(spec: &str) -> Result<(), String> {
+    validate_filter(spec)?;
+    let logger = LOGGER.get().ok_or("Logger is not installed")?;
+
+    let filter = Builder::new().parse(spec).build();
+    log::set_max_level(filter.filter());
+    *logger.filter.write().unwrap() = (String::from(spec), filter);
+    Ok(())
+}
+
+/// Checks every directive of the filter, env_logger itself silently ignores malformed ones
+pub fn validate_filter(spec: &str) -> Result<(), String> {
+    let directives = spec.split('/').next().unwrap_or_default();
+    for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let level = match directive.split_once('=') {
+            None => continue,
+            Some((_, level)) => level,
+        };
+        if level.parse::<LevelFilter>().is_err() {
+            return Err(format!("Invalid log level '{}' in directive '{}'", level, directive))
+        }
+    }
+    Ok(())
+}
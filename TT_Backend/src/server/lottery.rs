@@ -0,0 +1,45 @@
+//!
+//! Random selection among the connected clients.
+//! Every eligible client is picked with the same probability. Winners are remembered by name, so
+//! following draws can exclude them even if they reconnected in between.
+//!
+
+use std::collections::HashSet;
+use rand::seq::SliceRandom;
+
+/// Restricts the clients eligible for a draw, unset fields do not restrict
+#[derive(Debug, Clone, Default)]
+pub struct PickFilter {
+    pub team: Option<String>,
+    pub role: Option<String>,
+    /// Only clients that did (or did not) send an input in the current state
+    pub answered: Option<bool>,
+    pub exclude_winners: bool,
+}
+
+/// A picked client, the address is only revealed to the host
+#[derive(Debug, Clone)]
+pub struct PickedClient {
+    pub name: String,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Lottery {
+    winners: HashSet<String>,
+}
+
+impl Lottery {
+    /// Picks up to 'count' of the candidates (name and address) uniformly at random
+    /// Previous winners are skipped if the filter says so, all picks become winners
+    pub fn pick(&mut self, candidates: Vec<(String, String)>, count: usize, filter: &PickFilter) -> Vec<PickedClient> {
+        let candidates: Vec<(String, String)> = candidates.into_iter()
+            .filter(|(name, _)| !filter.exclude_winners || !self.winners.contains(name))
+            .collect();
+        let picks: Vec<PickedClient> = candidates.choose_multiple(&mut rand::thread_rng(), count)
+            .map(|(name, address)| PickedClient { name: name.clone(), address: Some(address.clone()) })
+            .collect();
+        self.winners.extend(picks.iter().map(|pick| pick.name.clone()));
+        picks
+    }
+}
@@ -0,0 +1,96 @@
+//!
+//! Approximate memory held by the session.
+//! Counts the bytes of what the session keeps: messages queued for clients, the current state, the
+//! updates buffered for resyncs, the recorded inputs and the staged states. Struct overhead and
+//! allocator slack are left out, the numbers show where the memory goes rather than matching the
+//! resident memory of the process. They are listed at '/debug/memory' of the admin port, the total
+//! is part of '/health'.
+//! With TT_BACKEND_SESSION_MEMORY_LIMIT (MiB) the session is checked every MEMORY_CHECK_INTERVAL.
+//! Above the limit the histories are trimmed, oldest first, down to MEMORY_TRIM_TARGET percent of
+//! it: first the recorded inputs (the host can no longer look them up), then the buffered updates
+//! (resyncing clients recover only partially). Queued messages, the current state and the staged
+//! states are still needed and never dropped, if the session exceeds the limit without histories
+//! the host is told with 'QuotaExceeded' once.
+//!
+
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::server::messages::BackendMessage;
+
+/// Interval between two checks against the limit
+pub const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Percentage of the limit the histories are trimmed down to, leaves room until the next check
+pub const MEMORY_TRIM_TARGET: usize = 80;
+
+/// Bytes held by the session, by what holds them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Messages waiting to be written to clients
+    pub queued: usize,
+    /// Content of the current state
+    pub state: usize,
+    /// Updates buffered for resyncing clients
+    pub updates: usize,
+    /// Inputs recorded for the host
+    pub inputs: usize,
+    /// Content of the staged states
+    pub staged: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.queued + self.state + self.updates + self.inputs + self.staged
+    }
+
+    pub fn report(&self, limit: Option<usize>) -> Value {
+        json!({
+            "total": self.total(),
+            "limit": limit,
+            "queued_messages": self.queued,
+            "state": self.state,
+            "updates": self.updates,
+            "recorded_inputs": self.inputs,
+            "staged_states": self.staged,
+        })
+    }
+}
+
+/// Limit of the session memory, and whether the host was told it is exceeded
+#[derive(Debug, Default)]
+pub struct MemoryLimit {
+    limit: Option<usize>,
+    exceeded: bool,
+}
+
+impl MemoryLimit {
+    pub fn new(limit: Option<usize>) -> Self {
+        MemoryLimit {limit, exceeded: false}
+    }
+
+    pub fn get_limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Bytes to trim to get back to MEMORY_TRIM_TARGET, 0 if the usage is within the limit
+    pub fn excess(&self, usage: &MemoryUsage) -> usize {
+        match self.limit {
+            Some(limit) if usage.total() > limit => usage.total() - limit / 100 * MEMORY_TRIM_TARGET,
+            _ => 0,
+        }
+    }
+
+    /// Remembers whether the usage is above the limit, returns true if it was not before
+    pub fn set_exceeded(&mut self, exceeded: bool) -> bool {
+        let newly = exceeded && !self.exceeded;
+        self.exceeded = exceeded;
+        newly
+    }
+}
+
+/// Approximate bytes of the message, the content for states and updates
+pub fn message_bytes(msg: &BackendMessage) -> usize {
+    match msg {
+        BackendMessage::ChangeState {content, ..} | BackendMessage::Update {content, ..} => content.len(),
+        msg => msg.to_string().len(),
+    }
+}
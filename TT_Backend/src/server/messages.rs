@@ -1,13 +1,38 @@
 use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use log::warn;
 use serde_json::{json, Value};
+use crate::server::attendance::AttendanceEntry;
+use crate::server::challenge::IssuedChallenge;
+use crate::server::integrity::RiskScore;
+use crate::server::leaderboard::ScoringRule;
+use crate::server::lottery::{PickedClient, PickFilter};
+use crate::server::networking::{ClientIssues, ClientStats};
+use crate::server::paging::{Page, PageInfo};
+use crate::server::reconnect::ReconnectHint;
+use crate::server::recording::RecordedInput;
+use crate::server::rooms::RoomRequest;
+use crate::server::rules::Rule;
+use crate::server::staging::PrefetchAsset;
+use crate::server::teams::TeamSummary;
+use crate::server::variants::{Distribution, VariantAssignment, Variants};
+use crate::server::schedule::ScheduleEntry;
 
 /// Representation of every possible message send by a client
 #[derive(Debug, Clone)]
 pub enum ClientMessage {
-    ClientLogin{ name: String },
+    /// 'codec' is the name of the wire codec for the connection, JSON if None
+    /// 'name' may be left out with a 'rejoin' token, which restores it
+    /// 'resume' is the token of a previous 'Resume', continuing as the participant that dropped
+    /// 'room' is the join code of the room to join, the session of the server if None
+    ClientLogin{ name: String, token: Option<String>, team: Option<String>, proof: Option<String>, codec: Option<String>, code: Option<String>, rejoin: Option<String>, resume: Option<String>, room: Option<String> },
     Disconnect { reason: String },
-    Input{ state_id: i32, content: String },
+    Input{ state_id: i32, content: String, client_ts: Option<i64>, input_id: Option<String> },
+    /// The client lost track of the current state
+    RequestResync,
+    /// The state is on screen
+    StateAck { state_id: i32 },
 }
 
 impl Display for ClientMessage {
@@ -16,12 +41,81 @@ impl Display for ClientMessage {
     }
 }
 
+/// What clients are told to do by 'ClientCommand'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAction {
+    /// Reload the page
+    Reload,
+    /// Reload the page if the app is older than the given minimal version
+    UpdateRequired,
+}
+
+impl ClientAction {
+    /// Parses 'reload' or 'update_required'
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reload" => Some(ClientAction::Reload),
+            "update_required" => Some(ClientAction::UpdateRequired),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientAction::Reload => "reload",
+            ClientAction::UpdateRequired => "update_required",
+        }
+    }
+}
+
 /// Representation of every possible message send by the host
 #[derive(Debug, Clone)]
 pub enum HostMessage {
+    /// 'token' is the host secret, if the server requires one
+    /// Answer to 'ServerHello', 'capabilities' None if the host names none
+    /// 'room' opens a room or returns to one, the host takes the session of the server if None
+    HostLogin { checksum: bool, api_key: Option<String>, token: Option<String>, version: Option<u32>, capabilities: Option<Vec<String>>, room: Option<RoomRequest> },
     Disconnect { reason: String },
     Update { state_id: i32, content: String },
-    ChangeState { state_id: i32, content: String },
+    ChangeState { state_id: i32, content: String, variants: Option<Variants> },
+    GetClientInputs { client_id: String, state_id: i32, page: Option<Page> },
+    MuteClient { id: String, muted: bool },
+    ResendState { client_id: String },
+    SetRules { rules: Vec<Rule> },
+    SetScoring { state_id: i32, rule: ScoringRule },
+    ShowLeaderboard { count: usize },
+    GetTeamSummary,
+    GetAttendance { page: Option<Page> },
+    GetClientList { page: Option<Page> },
+    /// Changes of the client list since 'version', the whole list if None or unknown
+    SyncClientList { version: Option<u64> },
+    /// Event classes sent one by one, the others are summarized periodically
+    Subscribe { events: Vec<String> },
+    GetIntegrity,
+    LinkProbeAck { probe: u64 },
+    StartTimer { id: String, duration: i64 },
+    CancelTimer { id: String },
+    /// 'message' sent by the server at the timestamp 'at' and/or every 'interval' milliseconds
+    ScheduleMessage { id: String, at: Option<i64>, interval: Option<u64>, message: Box<HostMessage> },
+    CancelScheduledMessage { id: String },
+    ListScheduledMessages,
+    PickRandomClients { count: usize, filter: PickFilter },
+    ClientCommand { action: ClientAction, min_version: Option<String> },
+    StartFromTemplate { name: String },
+    StageState { index: i32, content: String, assets: Vec<PrefetchAsset> },
+    /// Shows the presentation on the live view or withdraws it
+    SetPublic { public: bool },
+    PauseSession { message: Option<String> },
+    ResumeSession,
+    ShowStaged { index: i32 },
+    /// Archive of the running session for the host to download
+    RequestSnapshotExport,
+    /// Replaces the access code, by a generated one if 'code' is None, the new code is sent back
+    /// unless 'announce' is false
+    RotateAccessCode { code: Option<String>, announce: bool },
+    /// WASM module (base64) processing the inputs of the session, replaces the loaded one
+    LoadPlugin { module: String },
+    UnloadPlugin,
 }
 
 impl Display for HostMessage {
@@ -33,12 +127,96 @@ impl Display for HostMessage {
 /// Representation of every possible message send by the backend
 #[derive(Debug, Clone)]
 pub enum BackendMessage {
-    ClientConnected { name: String, address: String },
-    ClientDisconnected { name: String, address: String, reason: String },
-    Disconnect { reason: String },
-    Input { state_id: i32, input: String, name: String, address: String },
+    ClientConnected { name: String, address: String, role: Option<String>, team: Option<String> },
+    ClientDisconnected { name: String, address: String, reason: String, answered: bool, last_state_id: Option<i32> },
+    ClientExpired { name: String, address: String },
+    ClientInputs { client_id: String, state_id: i32, inputs: Vec<RecordedInput>, page: Option<PageInfo> },
+    RuleTriggered { name: String, state_id: i32, message: Option<String> },
+    Leaderboard { standings: Vec<(String, i64)> },
+    Team { team: String },
+    TeamSummary { teams: Vec<TeamSummary> },
+    Timer { id: String, remaining: i64, ends_at: i64 },
+    TimerExpired { id: String },
+    TimerCancelled { id: String },
+    ClientsPicked { picks: Vec<PickedClient> },
+    VariantsAssigned { state_id: i32, assignments: Vec<VariantAssignment> },
+    SessionEnding { ends_at: i64 },
+    QuotaExceeded { quota: String, message: String },
+    AdvertisedAddress { host: String, addresses: Vec<IpAddr> },
+    Attendance { clients: Vec<AttendanceEntry>, page: Option<PageInfo> },
+    ClientList { clients: Vec<ClientStats>, page: Option<PageInfo> },
+    /// Changes of the client list up to 'version', replaces the list of the host if 'full'
+    ClientListDiff { version: u64, full: bool, added: Vec<ClientStats>, updated: Vec<ClientStats>, removed: Vec<String> },
+    /// Clients whose traffic was dropped since the last report
+    ClientIssues { clients: Vec<ClientIssues> },
+    IntegrityReport { clients: Vec<RiskScore> },
+    SessionResults { standings: Vec<(String, i64)>, teams: Vec<TeamSummary>, attendance: Vec<AttendanceEntry> },
+    /// 'reconnect' tells clients how to spread their reconnect attempts, None if they should not
+    Disconnect { reason: String, reconnect: Option<ReconnectHint> },
+    LoginRejected { reason: String },
+    Challenge { challenge: IssuedChallenge },
+    Input { state_id: i32, input: String, name: String, address: String, client_ts: Option<i64>, input_id: Option<String>, server_ts: i64 },
+    InputAck { state_id: i32, input_id: String },
+    Muted { state_id: i32, input_id: Option<String> },
     Update { state_id: i32, content: String },
     ChangeState { state_id: i32, content: String },
+    /// 'from' is the advertised url of the server the clients leave
+    Migrate { url: String, from: Option<String>, reconnect: Option<ReconnectHint> },
+    JoinInfo { url: String },
+    ClientCommand { action: ClientAction, min_version: Option<String> },
+    /// Message of the server operator, not of the host, 'expires_at' is a server timestamp
+    Announcement { message: String, expires_at: Option<i64> },
+    AnnouncementCleared,
+    Resync { count: u32 },
+    Retransmit { frame: u64 },
+    /// Ends a resync, 'complete' is false if updates of the state were no longer buffered
+    Resynced { seq: u64, complete: bool },
+    LinkProbe { probe: u64, sent_at: i64 },
+    /// 'rtt' in milliseconds, 'uplink' and 'required' in bytes per second
+    LinkQuality { rtt: Option<i64>, uplink: Option<u64>, required: u64, warning: Option<String> },
+    /// 'since' is the server timestamp the break started at
+    SessionPaused { message: Option<String>, since: i64 },
+    /// 'paused_for' in milliseconds
+    SessionResumed { paused_for: i64 },
+    /// 'count' is the number of states staged so far
+    Staged { index: i32, count: usize },
+    StageMissing { index: i32 },
+    /// Asset of the state likely shown next, for clients to fetch ahead of time
+    Prefetch { hash: String, url: String, size: Option<u64> },
+    TemplateStarted { name: String },
+    /// 'available' lists the names of all templates
+    TemplateUnknown { name: String, available: Vec<String> },
+    /// Link of a client, 'rtt_ms' of the latest answered ping, 'missed_pings' unanswered in a row
+    ConnectionQuality { rtt_ms: Option<i64>, missed_pings: u64 },
+    /// Archive of the running session: current state, connected clients and the inputs recorded
+    /// so far
+    SnapshotExport { archive: Value },
+    /// 'percent' of the connected clients acknowledged the state
+    QuorumReached { state_id: i32, percent: u8 },
+    /// Access code new clients have to give, only sent to the host
+    AccessCode { code: String },
+    /// One-time link restoring the identity of the client, 'expires_at' is a server timestamp
+    RejoinLink { url: String, expires_at: i64 },
+    /// Token the client presents as 'resume' when it reconnects after a dropped connection
+    Resume { token: String },
+    /// Join code and session id of the room the host opened, only sent to the host
+    /// The host returns to the room with the session id
+    RoomOpened { code: String, session: String },
+    /// Event classes the host receives one by one from now on
+    Subscribed { events: Vec<String> },
+    /// Presence changes held back since the last summary, 'clients' is the current count
+    PresenceSummary { joined: u64, left: u64, expired: u64, clients: usize },
+    /// Greeting of a connecting host, 'send_timeout' in milliseconds
+    ServerHello { version: u32, capabilities: Vec<String>, max_frame_size: u32, send_timeout: Option<u64> },
+    /// Pending scheduled messages, 'error' tells why the latest 'ScheduleMessage' was rejected
+    ScheduledMessages { messages: Vec<ScheduleEntry>, error: Option<String> },
+    /// The scheduled message was sent, 'next_at' is the next send of a recurring one
+    ScheduledMessageSent { id: String, next_at: Option<i64> },
+    /// Whether a plugin is loaded, 'error' tells why loading failed or the plugin was unloaded
+    PluginStatus { loaded: bool, error: Option<String> },
+    /// Result emitted by the plugin, sent to the host(s) and all clients
+    #[cfg_attr(not(feature = "wasm_plugins"), allow(dead_code))]
+    PluginResult { content: Value },
 }
 
 impl Display for BackendMessage {
@@ -55,40 +233,47 @@ pub fn parse_client_msg(msg_str: &str) -> Option<ClientMessage> {
             return None
         }
     };
+    parse_client_json(&json)
+}
 
-    let type_str = match get_string(&json, "type") {
-        None => return None,
-        Some(v) => v
-    };
+/// Parses the message decoded by the wire codec of the connection
+pub fn parse_client_json(json: &Value) -> Option<ClientMessage> {
+    let type_str = get_string(json, "type")?;
 
     match type_str.as_str() {
         "ClientLogin" => {
-            let name = match get_string(&json, "name") {
-                None => return None,
-                Some(v) => v
+            let rejoin = get_optional_string(json, "rejoin")?;
+            let name = match rejoin {
+                Some(_) => get_optional_string(json, "name")?.unwrap_or_default(),
+                None => get_string(json, "name")?,
             };
-            Some(ClientMessage::ClientLogin{name})
+            let token = get_optional_string(json, "token")?;
+            let team = get_optional_string(json, "team")?;
+            let proof = get_optional_string(json, "proof")?;
+            let codec = get_optional_string(json, "codec")?;
+            let code = get_optional_string(json, "code")?;
+            let resume = get_optional_string(json, "resume")?;
+            let room = get_optional_string(json, "room")?;
+            Some(ClientMessage::ClientLogin{name, token, team, proof, codec, code, rejoin, resume, room})
         }
         "Disconnecting" => {
-            let reason = match get_string(&json, "reason") {
-                None => return None,
-                Some(v) => v
-            };
+            let reason = get_string(json, "reason")?;
             Some(ClientMessage::Disconnect {reason})
         }
         "Input" => {
-            let state_id = match get_i32(&json, "state_id") {
-                None => return None,
-                Some(v) => v
-            };
-            let content = match get_string(&json, "content") {
-                None => return None,
-                Some(v) => v
-            };
-            Some(ClientMessage::Input{state_id, content})
+            let state_id = get_i32(json, "state_id")?;
+            let content = get_string(json, "content")?;
+            let client_ts = get_optional_i64(json, "client_ts")?;
+            let input_id = get_optional_string(json, "input_id")?;
+            Some(ClientMessage::Input{state_id, content, client_ts, input_id})
+        }
+        "RequestResync" => Some(ClientMessage::RequestResync),
+        "StateAck" => {
+            let state_id = get_i32(json, "state_id")?;
+            Some(ClientMessage::StateAck {state_id})
         }
         _ => {
-            warn!("parse_client_msg(..): Message 'type' {} is not supported!\nmsg: {}", type_str, msg_str);
+            warn!("parse_client_json(..): Message 'type' {} is not supported!\nmsg: {}", type_str, json);
             None
         }
     }
@@ -103,40 +288,222 @@ pub fn parse_host_msg(msg_str: &str) -> Option<HostMessage> {
         }
     };
 
-    let type_str = match get_string(&json, "type") {
-        None => return None,
-        Some(v) => v
-    };
+    let type_str = get_string(&json, "type")?;
 
     match type_str.as_str() {
-        "Disconnecting" => {
-            let reason = match get_string(&json, "reason") {
-                None => return None,
-                Some(v) => v
+        "HostLogin" => {
+            let checksum = get_optional_bool(&json, "checksum")?.unwrap_or(false);
+            let api_key = get_optional_string(&json, "api_key")?;
+            let token = get_optional_string(&json, "token")?;
+            let version = match get_optional_u64(&json, "version")? {
+                None => None,
+                Some(version) => Some(u32::try_from(version).ok()?),
+            };
+            let capabilities = match &json["capabilities"] {
+                Value::Null => None,
+                Value::Array(capabilities) => Some(capabilities.iter().map(|capability| capability.as_str().map(String::from)).collect::<Option<Vec<String>>>()?),
+                _ => {
+                    warn!("parse_host_msg(..): Message is malformed, 'capabilities' field contains not an Array!\nmsg: {}", msg_str);
+                    return None
+                }
             };
+            let room = match &json["room"] {
+                Value::Null => None,
+                room => match RoomRequest::parse(room) {
+                    Some(v) => Some(v),
+                    None => {
+                        warn!("parse_host_msg(..): Message is malformed, 'room' is neither true nor a join code!\nmsg: {}", msg_str);
+                        return None
+                    }
+                },
+            };
+            Some(HostMessage::HostLogin {checksum, api_key, token, version, capabilities, room})
+        }
+        "Disconnecting" => {
+            let reason = get_string(&json, "reason")?;
             Some(HostMessage::Disconnect {reason})
         }
         "Update" => {
-            let state_id = match get_i32(&json, "state_id") {
-                None => return None,
-                Some(v) => v
-            };
-            let content = match get_string(&json, "content") {
-                None => return None,
-                Some(v) => v
-            };
+            let state_id = get_i32(&json, "state_id")?;
+            let content = get_string(&json, "content")?;
             Some(HostMessage::Update{state_id, content})
         }
         "ChangeState" => {
-            let state_id = match get_i32(&json, "state_id") {
-                None => return None,
+            let state_id = get_i32(&json, "state_id")?;
+            let content = get_string(&json, "content")?;
+            let variants = match json["variants"].as_array() {
+                None => None,
+                Some(contents) => {
+                    let contents = contents.iter().map(|v| v.as_str().map(String::from)).collect::<Option<Vec<String>>>();
+                    let distribution = get_optional_string(&json, "distribution")?.unwrap_or_else(|| String::from("random"));
+                    match (contents, Distribution::parse(&distribution)) {
+                        (Some(contents), Some(distribution)) => Some(Variants {contents, distribution}),
+                        _ => {
+                            warn!("parse_host_msg(..): Message is malformed, invalid 'variants' or 'distribution'!\nmsg: {}", msg_str);
+                            return None
+                        }
+                    }
+                }
+            };
+            Some(HostMessage::ChangeState{state_id, content, variants})
+        }
+        "GetClientInputs" => {
+            let client_id = get_string(&json, "client_id")?;
+            let state_id = get_i32(&json, "state_id")?;
+            let page = get_page(&json)?;
+            Some(HostMessage::GetClientInputs{client_id, state_id, page})
+        }
+        "ResendState" => {
+            let client_id = get_string(&json, "client_id")?;
+            Some(HostMessage::ResendState{client_id})
+        }
+        "MuteClient" => {
+            let id = get_string(&json, "id")?;
+            let muted = get_bool(&json, "muted")?;
+            Some(HostMessage::MuteClient{id, muted})
+        }
+        "SetRules" => {
+            let rules = match json["rules"].as_array() {
+                None => {
+                    warn!("parse_host_msg(..): Message is malformed, 'rules' field contains not an Array!\nmsg: {}", msg_str);
+                    return None
+                }
                 Some(v) => v
             };
-            let content = match get_string(&json, "content") {
-                None => return None,
+            let rules = rules.iter().map(Rule::parse).collect::<Option<Vec<Rule>>>()?;
+            Some(HostMessage::SetRules{rules})
+        }
+        "SetScoring" => {
+            let state_id = get_i32(&json, "state_id")?;
+            let correct = get_string(&json, "correct")?;
+            let points = get_i64(&json, "points")?;
+            let speed_bonus = get_optional_i64(&json, "speed_bonus")?.unwrap_or(0);
+            let bonus_window = get_optional_i64(&json, "bonus_window")?.unwrap_or(0);
+            let rule = ScoringRule::new(correct, points, speed_bonus, bonus_window).ok()?;
+            Some(HostMessage::SetScoring{state_id, rule})
+        }
+        "ShowLeaderboard" => {
+            let count = get_i64(&json, "count")?.max(0) as usize;
+            Some(HostMessage::ShowLeaderboard{count})
+        }
+        "GetTeamSummary" => Some(HostMessage::GetTeamSummary),
+        "GetAttendance" => {
+            let page = get_page(&json)?;
+            Some(HostMessage::GetAttendance{page})
+        }
+        "GetClientList" => {
+            let page = get_page(&json)?;
+            Some(HostMessage::GetClientList{page})
+        }
+        "SyncClientList" => {
+            let version = get_optional_u64(&json, "version")?;
+            Some(HostMessage::SyncClientList{version})
+        }
+        "Subscribe" => {
+            let events = match json["events"].as_array() {
+                None => {
+                    warn!("parse_host_msg(..): Message is malformed, 'events' field contains not an Array!\nmsg: {}", msg_str);
+                    return None
+                }
                 Some(v) => v
             };
-            Some(HostMessage::ChangeState{state_id, content})
+            let events = events.iter().map(|event| event.as_str().map(String::from)).collect::<Option<Vec<String>>>()?;
+            Some(HostMessage::Subscribe{events})
+        }
+        "GetIntegrity" => Some(HostMessage::GetIntegrity),
+        "LinkProbeAck" => {
+            let probe = get_u64(&json, "probe")?;
+            Some(HostMessage::LinkProbeAck{probe})
+        }
+        "ClientCommand" => {
+            let action = get_string(&json, "action")?;
+            let min_version = get_optional_string(&json, "min_version")?;
+            match ClientAction::parse(&action) {
+                Some(action) => Some(HostMessage::ClientCommand{action, min_version}),
+                None => {
+                    warn!("parse_host_msg(..): Message is malformed, invalid 'action'!\nmsg: {}", msg_str);
+                    None
+                }
+            }
+        }
+        "StartTimer" => {
+            let id = get_string(&json, "id")?;
+            let duration = get_i64(&json, "duration")?;
+            Some(HostMessage::StartTimer{id, duration})
+        }
+        "CancelTimer" => {
+            let id = get_string(&json, "id")?;
+            Some(HostMessage::CancelTimer{id})
+        }
+        "ScheduleMessage" => {
+            let id = get_string(&json, "id")?;
+            let at = get_optional_i64(&json, "at")?;
+            let interval = get_optional_u64(&json, "interval")?;
+            let message = Box::new(parse_host_msg(&json["message"].to_string())?);
+            Some(HostMessage::ScheduleMessage{id, at, interval, message})
+        }
+        "CancelScheduledMessage" => {
+            let id = get_string(&json, "id")?;
+            Some(HostMessage::CancelScheduledMessage{id})
+        }
+        "ListScheduledMessages" => Some(HostMessage::ListScheduledMessages),
+        "StartFromTemplate" => {
+            let name = get_string(&json, "name")?;
+            Some(HostMessage::StartFromTemplate{name})
+        }
+        "SetPublic" => {
+            let public = get_bool(&json, "public")?;
+            Some(HostMessage::SetPublic{public})
+        }
+        "PauseSession" => {
+            let message = get_optional_string(&json, "message")?;
+            Some(HostMessage::PauseSession{message})
+        }
+        "ResumeSession" => Some(HostMessage::ResumeSession),
+        "RequestSnapshotExport" => Some(HostMessage::RequestSnapshotExport),
+        "RotateAccessCode" => {
+            let code = get_optional_string(&json, "code")?;
+            let announce = get_optional_bool(&json, "announce")?.unwrap_or(true);
+            Some(HostMessage::RotateAccessCode{code, announce})
+        }
+        "LoadPlugin" => {
+            let module = get_string(&json, "module")?;
+            Some(HostMessage::LoadPlugin{module})
+        }
+        "UnloadPlugin" => Some(HostMessage::UnloadPlugin),
+        "StageState" => {
+            let index = get_i32(&json, "index")?;
+            let content = get_string(&json, "content")?;
+            let assets = match &json["assets"] {
+                Value::Null => vec![],
+                Value::Array(assets) => assets.iter()
+                    .map(|asset| Some(PrefetchAsset {
+                        url: get_string(asset, "url")?,
+                        hash: get_string(asset, "hash")?,
+                        size: get_optional_u64(asset, "size")?,
+                    }))
+                    .collect::<Option<Vec<PrefetchAsset>>>()?,
+                _ => {
+                    warn!("parse_host_msg(..): Message is malformed, 'assets' field contains not an Array!\nmsg: {}", msg_str);
+                    return None
+                }
+            };
+            Some(HostMessage::StageState{index, content, assets})
+        }
+        "ShowStaged" => {
+            let index = get_i32(&json, "index")?;
+            Some(HostMessage::ShowStaged{index})
+        }
+        "PickRandomClients" => {
+            let count = get_i64(&json, "count")?.max(0) as usize;
+            let filter = &json["filter"];
+            let filter = PickFilter {
+                team: get_optional_string(filter, "team")?,
+                role: get_optional_string(filter, "role")?,
+                answered: get_optional_bool(filter, "answered")?,
+                exclude_winners: get_optional_bool(filter, "exclude_winners")?.unwrap_or(false),
+            };
+            Some(HostMessage::PickRandomClients{count, filter})
         }
         _ => {
             warn!("parse_host_msg(..): Message 'type' {} is not supported!\nmsg: {}", type_str, msg_str);
@@ -146,54 +513,612 @@ pub fn parse_host_msg(msg_str: &str) -> Option<HostMessage> {
 }
 
 pub fn encode_backend_msg(msg: BackendMessage) -> String {
+    encode_backend_json(msg).to_string()
+}
+
+/// The json object of the message, for callers adding fields before serializing it
+pub fn encode_backend_json(msg: BackendMessage) -> Value {
     match msg {
-        BackendMessage::ClientConnected{name, address} => {
+        BackendMessage::ClientConnected{name, address, role, team} => {
             let mut json = json!(null);
             json["type"] = json!("ClientConnected");
             json["name"] = json!(name);
             json["address"] = json!(address);
-            String::from(json.to_string())
+            if let Some(role) = role {
+                json["role"] = json!(role);
+            }
+            if let Some(team) = team {
+                json["team"] = json!(team);
+            }
+            json
         }
-        BackendMessage::ClientDisconnected{name, address, reason} => {
+        BackendMessage::ClientDisconnected{name, address, reason, answered, last_state_id} => {
             let mut json = json!(null);
             json["type"] = json!("ClientDisconnected");
             json["name"] = json!(name);
             json["address"] = json!(address);
             json["reason"] = json!(reason);
-            String::from(json.to_string())
+            json["answered"] = json!(answered);
+            if let Some(last_state_id) = last_state_id {
+                json["last_state_id"] = json!(last_state_id);
+            }
+            json
+        }
+        BackendMessage::ClientExpired{name, address} => {
+            let mut json = json!(null);
+            json["type"] = json!("ClientExpired");
+            json["name"] = json!(name);
+            json["address"] = json!(address);
+            json
         }
-        BackendMessage::Disconnect {reason} => {
+        BackendMessage::ClientInputs{client_id, state_id, inputs, page} => {
+            let inputs: Vec<Value> = inputs.into_iter().map(|recorded| {
+                let mut json = json!(null);
+                json["input"] = json!(recorded.input);
+                if let Some(client_ts) = recorded.client_ts {
+                    json["client_ts"] = json!(client_ts);
+                }
+                if let Some(input_id) = recorded.input_id {
+                    json["input_id"] = json!(input_id);
+                }
+                json["server_ts"] = json!(recorded.server_ts);
+                json
+            }).collect();
+            let mut json = json!(null);
+            json["type"] = json!("ClientInputs");
+            json["client_id"] = json!(client_id);
+            json["state_id"] = json!(state_id);
+            json["inputs"] = json!(inputs);
+            encode_page(&mut json, page);
+            json
+        }
+        BackendMessage::RuleTriggered{name, state_id, message} => {
+            let mut json = json!(null);
+            json["type"] = json!("RuleTriggered");
+            json["name"] = json!(name);
+            json["state_id"] = json!(state_id);
+            if let Some(message) = message {
+                json["message"] = json!(message);
+            }
+            json
+        }
+        BackendMessage::Leaderboard{standings} => {
+            let mut json = json!(null);
+            json["type"] = json!("Leaderboard");
+            json["standings"] = encode_standings(standings);
+            json
+        }
+        BackendMessage::Team{team} => {
+            let mut json = json!(null);
+            json["type"] = json!("Team");
+            json["team"] = json!(team);
+            json
+        }
+        BackendMessage::TeamSummary{teams} => {
+            let mut json = json!(null);
+            json["type"] = json!("TeamSummary");
+            json["teams"] = encode_team_summaries(teams);
+            json
+        }
+        BackendMessage::AdvertisedAddress{host, addresses} => {
+            let addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+            let mut json = json!(null);
+            json["type"] = json!("AdvertisedAddress");
+            json["host"] = json!(host);
+            json["addresses"] = json!(addresses);
+            json
+        }
+        BackendMessage::QuotaExceeded{quota, message} => {
+            let mut json = json!(null);
+            json["type"] = json!("QuotaExceeded");
+            json["quota"] = json!(quota);
+            json["message"] = json!(message);
+            json
+        }
+        BackendMessage::SessionEnding{ends_at} => {
+            let mut json = json!(null);
+            json["type"] = json!("SessionEnding");
+            json["ends_at"] = json!(ends_at);
+            json
+        }
+        BackendMessage::Attendance{clients, page} => {
+            let mut json = json!(null);
+            json["type"] = json!("Attendance");
+            json["clients"] = encode_attendance(clients);
+            encode_page(&mut json, page);
+            json
+        }
+        BackendMessage::IntegrityReport{clients} => {
+            let clients: Vec<Value> = clients.into_iter()
+                .map(|client| json!({
+                    "name": client.name,
+                    "score": client.score,
+                    "flags": client.flags.iter().map(|flag| flag.as_str()).collect::<Vec<&str>>(),
+                }))
+                .collect();
+            let mut json = json!(null);
+            json["type"] = json!("IntegrityReport");
+            json["clients"] = json!(clients);
+            json
+        }
+        BackendMessage::ClientList{clients, page} => {
+            let clients: Vec<Value> = clients.into_iter().map(encode_client_stats).collect();
+            let mut json = json!(null);
+            json["type"] = json!("ClientList");
+            json["clients"] = json!(clients);
+            encode_page(&mut json, page);
+            json
+        }
+        BackendMessage::ClientListDiff{version, full, added, updated, removed} => {
+            let added: Vec<Value> = added.into_iter().map(encode_client_stats).collect();
+            let updated: Vec<Value> = updated.into_iter().map(encode_client_stats).collect();
+            let mut json = json!(null);
+            json["type"] = json!("ClientListDiff");
+            json["version"] = json!(version);
+            json["full"] = json!(full);
+            json["added"] = json!(added);
+            json["updated"] = json!(updated);
+            json["removed"] = json!(removed);
+            json
+        }
+        BackendMessage::ClientIssues{clients} => {
+            let clients: Vec<Value> = clients.into_iter()
+                .map(|client| json!({
+                    "name": client.name,
+                    "address": client.address,
+                    "malformed": client.malformed,
+                    "rejected_inputs": client.rejected_inputs,
+                    "violations": client.violations,
+                }))
+                .collect();
+            let mut json = json!(null);
+            json["type"] = json!("ClientIssues");
+            json["clients"] = json!(clients);
+            json
+        }
+        BackendMessage::SessionResults{standings, teams, attendance} => {
+            let mut json = json!(null);
+            json["type"] = json!("SessionResults");
+            json["standings"] = encode_standings(standings);
+            json["teams"] = encode_team_summaries(teams);
+            json["attendance"] = encode_attendance(attendance);
+            json
+        }
+        BackendMessage::Timer{id, remaining, ends_at} => {
+            let mut json = json!(null);
+            json["type"] = json!("Timer");
+            json["id"] = json!(id);
+            json["remaining"] = json!(remaining);
+            json["ends_at"] = json!(ends_at);
+            json
+        }
+        BackendMessage::TimerExpired{id} => {
+            let mut json = json!(null);
+            json["type"] = json!("TimerExpired");
+            json["id"] = json!(id);
+            json
+        }
+        BackendMessage::TimerCancelled{id} => {
+            let mut json = json!(null);
+            json["type"] = json!("TimerCancelled");
+            json["id"] = json!(id);
+            json
+        }
+        BackendMessage::ClientsPicked{picks} => {
+            let picks: Vec<Value> = picks.into_iter()
+                .map(|pick| {
+                    let mut json = json!({"name": pick.name});
+                    if let Some(address) = pick.address {
+                        json["address"] = json!(address);
+                    }
+                    json
+                })
+                .collect();
+            let mut json = json!(null);
+            json["type"] = json!("ClientsPicked");
+            json["picks"] = json!(picks);
+            json
+        }
+        BackendMessage::VariantsAssigned{state_id, assignments} => {
+            let assignments: Vec<Value> = assignments.into_iter()
+                .map(|assignment| json!({
+                    "name": assignment.name,
+                    "address": assignment.address,
+                    "variant": assignment.variant,
+                }))
+                .collect();
+            let mut json = json!(null);
+            json["type"] = json!("VariantsAssigned");
+            json["state_id"] = json!(state_id);
+            json["assignments"] = json!(assignments);
+            json
+        }
+        BackendMessage::Disconnect {reason, reconnect} => {
             let mut json = json!(null);
             json["type"] = json!("Disconnecting");
             json["reason"] = json!(reason);
-            String::from(json.to_string())
+            if let Some(reconnect) = reconnect {
+                json["reconnect"] = reconnect.to_json();
+            }
+            json
+        }
+        BackendMessage::LoginRejected {reason} => {
+            let mut json = json!(null);
+            json["type"] = json!("LoginRejected");
+            json["reason"] = json!(reason);
+            json
         }
-        BackendMessage::Input{state_id, input, name, address} => {
+        BackendMessage::Challenge {challenge} => {
+            let mut json = json!(null);
+            json["type"] = json!("Challenge");
+            match challenge {
+                IssuedChallenge::ProofOfWork {nonce, difficulty} => {
+                    json["kind"] = json!("pow");
+                    json["nonce"] = json!(nonce);
+                    json["difficulty"] = json!(difficulty);
+                }
+                IssuedChallenge::Captcha {site_key} => {
+                    json["kind"] = json!("captcha");
+                    json["site_key"] = json!(site_key);
+                }
+            }
+            json
+        }
+        BackendMessage::Input{state_id, input, name, address, client_ts, input_id, server_ts} => {
             let mut json = json!(null);
             json["type"] = json!("Input");
             json["state_id"] = json!(state_id);
             json["input"] = json!(input);
             json["name"] = json!(name);
             json["address"] = json!(address);
-            String::from(json.to_string())
+            if let Some(client_ts) = client_ts {
+                json["client_ts"] = json!(client_ts);
+            }
+            if let Some(input_id) = input_id {
+                json["input_id"] = json!(input_id);
+            }
+            json["server_ts"] = json!(server_ts);
+            json
+        }
+        BackendMessage::InputAck{state_id, input_id} => {
+            let mut json = json!(null);
+            json["type"] = json!("InputAck");
+            json["state_id"] = json!(state_id);
+            json["input_id"] = json!(input_id);
+            json
+        }
+        BackendMessage::Muted{state_id, input_id} => {
+            let mut json = json!(null);
+            json["type"] = json!("Muted");
+            json["state_id"] = json!(state_id);
+            if let Some(input_id) = input_id {
+                json["input_id"] = json!(input_id);
+            }
+            json
         }
         BackendMessage::Update{state_id, content} => {
             let mut json = json!(null);
             json["type"] = json!("Update");
             json["state_id"] = json!(state_id);
             json["content"] = json!(content);
-            String::from(json.to_string())
+            json
         }
         BackendMessage::ChangeState{state_id, content} => {
             let mut json = json!(null);
             json["type"] = json!("ChangeState");
             json["state_id"] = json!(state_id);
             json["content"] = json!(content);
-            String::from(json.to_string())
+            json
+        }
+        BackendMessage::Migrate{url, from, reconnect} => {
+            let mut json = json!(null);
+            json["type"] = json!("Migrate");
+            json["url"] = json!(url);
+            if let Some(from) = from {
+                json["from"] = json!(from);
+            }
+            if let Some(reconnect) = reconnect {
+                json["reconnect"] = reconnect.to_json();
+            }
+            json
+        }
+        BackendMessage::SnapshotExport{archive} => {
+            let mut json = json!(null);
+            json["type"] = json!("SnapshotExport");
+            json["archive"] = archive;
+            json
+        }
+        BackendMessage::QuorumReached{state_id, percent} => {
+            let mut json = json!(null);
+            json["type"] = json!("QuorumReached");
+            json["state_id"] = json!(state_id);
+            json["percent"] = json!(percent);
+            json
+        }
+        BackendMessage::AccessCode{code} => {
+            let mut json = json!(null);
+            json["type"] = json!("AccessCode");
+            json["code"] = json!(code);
+            json
+        }
+        BackendMessage::RejoinLink{url, expires_at} => {
+            let mut json = json!(null);
+            json["type"] = json!("RejoinLink");
+            json["url"] = json!(url);
+            json["expires_at"] = json!(expires_at);
+            json
+        }
+        BackendMessage::Resume{token} => {
+            let mut json = json!(null);
+            json["type"] = json!("Resume");
+            json["token"] = json!(token);
+            json
+        }
+        BackendMessage::RoomOpened{code, session} => {
+            let mut json = json!(null);
+            json["type"] = json!("RoomOpened");
+            json["code"] = json!(code);
+            json["session"] = json!(session);
+            json
+        }
+        BackendMessage::Subscribed{events} => {
+            let mut json = json!(null);
+            json["type"] = json!("Subscribed");
+            json["events"] = json!(events);
+            json
+        }
+        BackendMessage::ScheduledMessages{messages, error} => {
+            let messages: Vec<Value> = messages.into_iter()
+                .map(|entry| json!({
+                    "id": entry.id,
+                    "message_type": entry.message_type,
+                    "next_at": entry.next_at,
+                    "interval": entry.interval,
+                }))
+                .collect();
+            let mut json = json!(null);
+            json["type"] = json!("ScheduledMessages");
+            json["messages"] = json!(messages);
+            if let Some(error) = error {
+                json["error"] = json!(error);
+            }
+            json
+        }
+        BackendMessage::ScheduledMessageSent{id, next_at} => {
+            let mut json = json!(null);
+            json["type"] = json!("ScheduledMessageSent");
+            json["id"] = json!(id);
+            json["next_at"] = json!(next_at);
+            json
+        }
+        BackendMessage::PluginStatus{loaded, error} => {
+            let mut json = json!(null);
+            json["type"] = json!("PluginStatus");
+            json["loaded"] = json!(loaded);
+            if let Some(error) = error {
+                json["error"] = json!(error);
+            }
+            json
+        }
+        BackendMessage::PluginResult{content} => {
+            let mut json = json!(null);
+            json["type"] = json!("PluginResult");
+            json["content"] = content;
+            json
+        }
+        BackendMessage::ServerHello{version, capabilities, max_frame_size, send_timeout} => {
+            let mut json = json!(null);
+            json["type"] = json!("ServerHello");
+            json["version"] = json!(version);
+            json["capabilities"] = json!(capabilities);
+            json["limits"] = json!({"max_frame_size": max_frame_size, "send_timeout": send_timeout});
+            json
+        }
+        BackendMessage::PresenceSummary{joined, left, expired, clients} => {
+            let mut json = json!(null);
+            json["type"] = json!("PresenceSummary");
+            json["joined"] = json!(joined);
+            json["left"] = json!(left);
+            json["expired"] = json!(expired);
+            json["clients"] = json!(clients);
+            json
+        }
+        BackendMessage::JoinInfo{url} => {
+            let mut json = json!(null);
+            json["type"] = json!("JoinInfo");
+            json["url"] = json!(url);
+            json
+        }
+        BackendMessage::Resync{count} => {
+            let mut json = json!(null);
+            json["type"] = json!("Resync");
+            json["count"] = json!(count);
+            json
+        }
+        BackendMessage::ClientCommand{action, min_version} => {
+            let mut json = json!(null);
+            json["type"] = json!("ClientCommand");
+            json["action"] = json!(action.as_str());
+            if let Some(min_version) = min_version {
+                json["min_version"] = json!(min_version);
+            }
+            json
+        }
+        BackendMessage::Announcement{message, expires_at} => {
+            let mut json = json!(null);
+            json["type"] = json!("Announcement");
+            json["message"] = json!(message);
+            if let Some(expires_at) = expires_at {
+                json["expires_at"] = json!(expires_at);
+            }
+            json
+        }
+        BackendMessage::AnnouncementCleared => {
+            let mut json = json!(null);
+            json["type"] = json!("AnnouncementCleared");
+            json
+        }
+        BackendMessage::LinkProbe{probe, sent_at} => {
+            let mut json = json!(null);
+            json["type"] = json!("LinkProbe");
+            json["probe"] = json!(probe);
+            json["sent_at"] = json!(sent_at);
+            json
+        }
+        BackendMessage::LinkQuality{rtt, uplink, required, warning} => {
+            let mut json = json!(null);
+            json["type"] = json!("LinkQuality");
+            if let Some(rtt) = rtt {
+                json["rtt"] = json!(rtt);
+            }
+            if let Some(uplink) = uplink {
+                json["uplink"] = json!(uplink);
+            }
+            json["required"] = json!(required);
+            if let Some(warning) = warning {
+                json["warning"] = json!(warning);
+            }
+            json
+        }
+        BackendMessage::SessionPaused{message, since} => {
+            let mut json = json!(null);
+            json["type"] = json!("SessionPaused");
+            if let Some(message) = message {
+                json["message"] = json!(message);
+            }
+            json["since"] = json!(since);
+            json
+        }
+        BackendMessage::SessionResumed{paused_for} => {
+            let mut json = json!(null);
+            json["type"] = json!("SessionResumed");
+            json["paused_for"] = json!(paused_for);
+            json
+        }
+        BackendMessage::Staged{index, count} => {
+            let mut json = json!(null);
+            json["type"] = json!("Staged");
+            json["index"] = json!(index);
+            json["count"] = json!(count);
+            json
+        }
+        BackendMessage::StageMissing{index} => {
+            let mut json = json!(null);
+            json["type"] = json!("StageMissing");
+            json["index"] = json!(index);
+            json
+        }
+        BackendMessage::Prefetch{hash, url, size} => {
+            let mut json = json!(null);
+            json["type"] = json!("Prefetch");
+            json["hash"] = json!(hash);
+            json["url"] = json!(url);
+            if let Some(size) = size {
+                json["size"] = json!(size);
+            }
+            json
+        }
+        BackendMessage::TemplateStarted{name} => {
+            let mut json = json!(null);
+            json["type"] = json!("TemplateStarted");
+            json["name"] = json!(name);
+            json
+        }
+        BackendMessage::TemplateUnknown{name, available} => {
+            let mut json = json!(null);
+            json["type"] = json!("TemplateUnknown");
+            json["name"] = json!(name);
+            json["available"] = json!(available);
+            json
+        }
+        BackendMessage::ConnectionQuality{rtt_ms, missed_pings} => {
+            let mut json = json!(null);
+            json["type"] = json!("ConnectionQuality");
+            if let Some(rtt_ms) = rtt_ms {
+                json["rtt_ms"] = json!(rtt_ms);
+            }
+            json["missed_pings"] = json!(missed_pings);
+            json
+        }
+        BackendMessage::Retransmit{frame} => {
+            let mut json = json!(null);
+            json["type"] = json!("Retransmit");
+            json["frame"] = json!(frame);
+            json
+        }
+        BackendMessage::Resynced{seq, complete} => {
+            let mut json = json!(null);
+            json["type"] = json!("Resynced");
+            json["seq"] = json!(seq);
+            json["complete"] = json!(complete);
+            json
         }
     }
 }
 
+fn encode_standings(standings: Vec<(String, i64)>) -> Value {
+    let standings: Vec<Value> = standings.into_iter()
+        .map(|(name, score)| json!({"name": name, "score": score}))
+        .collect();
+    json!(standings)
+}
+
+fn encode_team_summaries(teams: Vec<TeamSummary>) -> Value {
+    let teams: Vec<Value> = teams.into_iter()
+        .map(|summary| json!({
+            "team": summary.team,
+            "members": summary.members,
+            "answered": summary.answered,
+            "score": summary.score,
+        }))
+        .collect();
+    json!(teams)
+}
+
+/// Answers without a requested page have no 'page', the last page has no 'next'
+fn encode_page(json: &mut Value, page: Option<PageInfo>) {
+    if let Some(page) = page {
+        let mut encoded = json!({"offset": page.offset, "total": page.total});
+        if let Some(next) = page.next {
+            encoded["next"] = json!(next);
+        }
+        json["page"] = encoded;
+    }
+}
+
+fn encode_client_stats(client: ClientStats) -> Value {
+    json!({
+        "name": client.name,
+        "address": client.address,
+        "messages_sent": client.messages_sent,
+        "bytes_sent": client.bytes_sent,
+        "messages_received": client.messages_received,
+        "bytes_received": client.bytes_received,
+        "last_activity": client.last_activity,
+    })
+}
+
+/// Intervals still connected have no 'left'
+fn encode_attendance(clients: Vec<AttendanceEntry>) -> Value {
+    let clients: Vec<Value> = clients.into_iter()
+        .map(|entry| {
+            let intervals: Vec<Value> = entry.intervals.iter()
+                .map(|interval| match interval.left {
+                    Some(left) => json!({"joined": interval.joined, "left": left}),
+                    None => json!({"joined": interval.joined}),
+                })
+                .collect();
+            json!({
+                "name": entry.name,
+                "joins": entry.joins,
+                "connected": entry.connected,
+                "intervals": intervals,
+            })
+        })
+        .collect();
+    json!(clients)
+}
+
 fn get_string(json: &Value, key: &str) -> Option<String> {
     let value = json[key].clone();
     if value.is_null() {
@@ -228,4 +1153,634 @@ fn get_i32(json: &Value, key: &str) -> Option<i32> {
     };
 
     Some(value_i64 as i32)
-}
\ No newline at end of file
+}
+
+/// Like get_string(..), but a missing field is not an error
+/// Returns None only if the field exists and contains not a String
+fn get_optional_string(json: &Value, key: &str) -> Option<Option<String>> {
+    if json[key].is_null() {
+        return Some(None)
+    }
+    get_string(json, key).map(Some)
+}
+
+/// Like get_i64(..), but a missing field is not an error
+/// Returns None only if the field exists and contains not an Integer
+fn get_optional_i64(json: &Value, key: &str) -> Option<Option<i64>> {
+    if json[key].is_null() {
+        return Some(None)
+    }
+    get_i64(json, key).map(Some)
+}
+
+/// Like get_u64(..), but a missing field is not an error
+/// Returns None only if the field exists and contains not an unsigned Integer
+fn get_optional_u64(json: &Value, key: &str) -> Option<Option<u64>> {
+    if json[key].is_null() {
+        return Some(None)
+    }
+    get_u64(json, key).map(Some)
+}
+
+/// Page requested by 'offset' and 'limit', both optional (negative values count as 0)
+/// Returns Some(None) if neither is given, None only if one of them contains not an Integer
+fn get_page(json: &Value) -> Option<Option<Page>> {
+    let offset = get_optional_i64(json, "offset")?;
+    let limit = get_optional_i64(json, "limit")?;
+    if offset.is_none() && limit.is_none() {
+        return Some(None)
+    }
+    Some(Some(Page::new(offset.map(|v| v.max(0) as usize), limit.map(|v| v.max(0) as usize))))
+}
+
+/// Like get_bool(..), but a missing field is not an error
+/// Returns None only if the field exists and contains not a Boolean
+fn get_optional_bool(json: &Value, key: &str) -> Option<Option<bool>> {
+    if json[key].is_null() {
+        return Some(None)
+    }
+    get_bool(json, key).map(Some)
+}
+
+fn get_bool(json: &Value, key: &str) -> Option<bool> {
+    let value = json[key].clone();
+    if value.is_null() {
+        warn!("get_value(..): Message is malformed, missing '{}' field!\nmsg: {}", key, json);
+        return None
+    }
+
+    match value.as_bool() {
+        None => {
+            warn!("get_value(..): Message is malformed, '{}' field contains not a Boolean!\nmsg: {}", key, json);
+            None
+        }
+        Some(v) => Some(v)
+    }
+}
+
+fn get_i64(json: &Value, key: &str) -> Option<i64> {
+    let value = json[key].clone();
+    if value.is_null() {
+        warn!("get_value(..): Message is malformed, missing '{}' field!\nmsg: {}", key, json);
+        return None
+    }
+
+    match value.as_i64() {
+        None => {
+            warn!("get_value(..): Message is malformed, '{}' field contains not an Integer!\nmsg: {}", key, json);
+            None
+        }
+        Some(v) => Some(v)
+    }
+}
+
+fn get_u64(json: &Value, key: &str) -> Option<u64> {
+    let value = json[key].clone();
+    if value.is_null() {
+        warn!("get_value(..): Message is malformed, missing '{}' field!\nmsg: {}", key, json);
+        return None
+    }
+
+    match value.as_u64() {
+        None => {
+            warn!("get_value(..): Message is malformed, '{}' field contains not an unsigned Integer!\nmsg: {}", key, json);
+            None
+        }
+        Some(v) => Some(v)
+    }
+}
+
+/// Milliseconds since the unix epoch, used for all timestamps on the wire
+pub fn current_timestamp() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+    use crate::server::attendance::AttendanceInterval;
+    use crate::server::integrity::RiskFlag;
+    use crate::server::paging::MAX_PAGE_SIZE;
+    use std::time::Duration;
+    use crate::server::reconnect::ReconnectBackoff;
+    use super::*;
+
+    /// Wire format of the WebApp
+    fn encode_client_msg(msg: &ClientMessage) -> String {
+        match msg {
+            ClientMessage::ClientLogin {name, token, team, proof, codec, code, rejoin, resume, room} =>
+                json!({"type": "ClientLogin", "name": name, "token": token, "team": team, "proof": proof, "codec": codec, "code": code, "rejoin": rejoin, "resume": resume, "room": room}),
+            ClientMessage::Disconnect {reason} =>
+                json!({"type": "Disconnecting", "reason": reason}),
+            ClientMessage::Input {state_id, content, client_ts, input_id} =>
+                json!({"type": "Input", "state_id": state_id, "content": content, "client_ts": client_ts, "input_id": input_id}),
+            ClientMessage::RequestResync =>
+                json!({"type": "RequestResync"}),
+            ClientMessage::StateAck {state_id} =>
+                json!({"type": "StateAck", "state_id": state_id}),
+        }.to_string()
+    }
+
+    /// Wire format of the HostApp, for the messages without nested structures
+    fn encode_host_msg(msg: &HostMessage) -> String {
+        match msg {
+            HostMessage::HostLogin {checksum, api_key, token, version, capabilities, room} =>
+                json!({"type": "HostLogin", "checksum": checksum, "api_key": api_key, "token": token, "version": version, "capabilities": capabilities, "room": room.as_ref().map(RoomRequest::to_json)}),
+            HostMessage::Disconnect {reason} =>
+                json!({"type": "Disconnecting", "reason": reason}),
+            HostMessage::Update {state_id, content} =>
+                json!({"type": "Update", "state_id": state_id, "content": content}),
+            HostMessage::ChangeState {state_id, content, variants: None} =>
+                json!({"type": "ChangeState", "state_id": state_id, "content": content}),
+            HostMessage::GetClientInputs {client_id, state_id, page: None} =>
+                json!({"type": "GetClientInputs", "client_id": client_id, "state_id": state_id}),
+            HostMessage::GetClientInputs {client_id, state_id, page: Some(page)} =>
+                json!({"type": "GetClientInputs", "client_id": client_id, "state_id": state_id, "offset": page.offset, "limit": page.limit}),
+            HostMessage::MuteClient {id, muted} =>
+                json!({"type": "MuteClient", "id": id, "muted": muted}),
+            HostMessage::ResendState {client_id} =>
+                json!({"type": "ResendState", "client_id": client_id}),
+            HostMessage::ShowLeaderboard {count} =>
+                json!({"type": "ShowLeaderboard", "count": count}),
+            HostMessage::GetTeamSummary =>
+                json!({"type": "GetTeamSummary"}),
+            HostMessage::GetAttendance {page: None} =>
+                json!({"type": "GetAttendance"}),
+            HostMessage::GetAttendance {page: Some(page)} =>
+                json!({"type": "GetAttendance", "offset": page.offset, "limit": page.limit}),
+            HostMessage::GetClientList {page: None} =>
+                json!({"type": "GetClientList"}),
+            HostMessage::GetClientList {page: Some(page)} =>
+                json!({"type": "GetClientList", "offset": page.offset, "limit": page.limit}),
+            HostMessage::SyncClientList {version} =>
+                json!({"type": "SyncClientList", "version": version}),
+            HostMessage::Subscribe {events} =>
+                json!({"type": "Subscribe", "events": events}),
+            HostMessage::GetIntegrity =>
+                json!({"type": "GetIntegrity"}),
+            HostMessage::LinkProbeAck {probe} =>
+                json!({"type": "LinkProbeAck", "probe": probe}),
+            HostMessage::StartTimer {id, duration} =>
+                json!({"type": "StartTimer", "id": id, "duration": duration}),
+            HostMessage::CancelTimer {id} =>
+                json!({"type": "CancelTimer", "id": id}),
+            HostMessage::ScheduleMessage {id, at, interval, message} =>
+                json!({"type": "ScheduleMessage", "id": id, "at": at, "interval": interval, "message": serde_json::from_str::<Value>(&encode_host_msg(message)).unwrap()}),
+            HostMessage::CancelScheduledMessage {id} =>
+                json!({"type": "CancelScheduledMessage", "id": id}),
+            HostMessage::ListScheduledMessages =>
+                json!({"type": "ListScheduledMessages"}),
+            HostMessage::ClientCommand {action, min_version} =>
+                json!({"type": "ClientCommand", "action": action.as_str(), "min_version": min_version}),
+            HostMessage::StartFromTemplate {name} =>
+                json!({"type": "StartFromTemplate", "name": name}),
+            HostMessage::StageState {index, content, assets} => {
+                let assets: Vec<Value> = assets.iter().map(|asset| json!({"url": asset.url, "hash": asset.hash, "size": asset.size})).collect();
+                json!({"type": "StageState", "index": index, "content": content, "assets": assets})
+            }
+            HostMessage::ShowStaged {index} =>
+                json!({"type": "ShowStaged", "index": index}),
+            HostMessage::SetPublic {public} =>
+                json!({"type": "SetPublic", "public": public}),
+            HostMessage::PauseSession {message} =>
+                json!({"type": "PauseSession", "message": message}),
+            HostMessage::ResumeSession =>
+                json!({"type": "ResumeSession"}),
+            HostMessage::RequestSnapshotExport =>
+                json!({"type": "RequestSnapshotExport"}),
+            HostMessage::RotateAccessCode {code, announce} =>
+                json!({"type": "RotateAccessCode", "code": code, "announce": announce}),
+            HostMessage::LoadPlugin {module} =>
+                json!({"type": "LoadPlugin", "module": module}),
+            HostMessage::UnloadPlugin =>
+                json!({"type": "UnloadPlugin"}),
+            other => panic!("encode_host_msg(..): {} is not covered", other),
+        }.to_string()
+    }
+
+    /// The 'type' every BackendMessage has on the wire
+    fn backend_type(msg: &BackendMessage) -> &'static str {
+        match msg {
+            BackendMessage::ClientConnected {..} => "ClientConnected",
+            BackendMessage::ClientDisconnected {..} => "ClientDisconnected",
+            BackendMessage::Disconnect {..} => "Disconnecting",
+            BackendMessage::LoginRejected {..} => "LoginRejected",
+            BackendMessage::Input {..} => "Input",
+            BackendMessage::InputAck {..} => "InputAck",
+            BackendMessage::Muted {..} => "Muted",
+            BackendMessage::Update {..} => "Update",
+            BackendMessage::ChangeState {..} => "ChangeState",
+            BackendMessage::Leaderboard {..} => "Leaderboard",
+            BackendMessage::Timer {..} => "Timer",
+            BackendMessage::QuotaExceeded {..} => "QuotaExceeded",
+            BackendMessage::Migrate {..} => "Migrate",
+            BackendMessage::ConnectionQuality {..} => "ConnectionQuality",
+            other => panic!("backend_type(..): {} is not covered", other),
+        }
+    }
+
+    fn client_msg() -> impl Strategy<Value = ClientMessage> {
+        prop_oneof![
+            (any::<String>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<String>>())
+                .prop_map(|(name, token, team, proof, codec, code, rejoin, resume, room)| ClientMessage::ClientLogin {name, token, team, proof, codec, code, rejoin, resume, room}),
+            any::<String>().prop_map(|reason| ClientMessage::Disconnect {reason}),
+            (any::<i32>(), any::<String>(), any::<Option<i64>>(), any::<Option<String>>())
+                .prop_map(|(state_id, content, client_ts, input_id)| ClientMessage::Input {state_id, content, client_ts, input_id}),
+            Just(ClientMessage::RequestResync),
+            any::<i32>().prop_map(|state_id| ClientMessage::StateAck {state_id}),
+        ]
+    }
+
+    fn host_msg() -> impl Strategy<Value = HostMessage> {
+        prop_oneof![
+            (any::<bool>(), any::<Option<String>>(), any::<Option<String>>(), any::<Option<u32>>(), proptest::option::of(proptest::collection::vec(any::<String>(), 0..3)),
+                proptest::option::of(prop_oneof![Just(RoomRequest::Open), "[A-Z2-9]{6}".prop_map(RoomRequest::Return)]))
+                .prop_map(|(checksum, api_key, token, version, capabilities, room)| HostMessage::HostLogin {checksum, api_key, token, version, capabilities, room}),
+            any::<String>().prop_map(|reason| HostMessage::Disconnect {reason}),
+            (any::<i32>(), any::<String>()).prop_map(|(state_id, content)| HostMessage::Update {state_id, content}),
+            (any::<i32>(), any::<String>()).prop_map(|(state_id, content)| HostMessage::ChangeState {state_id, content, variants: None}),
+            (any::<String>(), any::<i32>(), page()).prop_map(|(client_id, state_id, page)| HostMessage::GetClientInputs {client_id, state_id, page}),
+            (any::<String>(), any::<bool>()).prop_map(|(id, muted)| HostMessage::MuteClient {id, muted}),
+            any::<String>().prop_map(|client_id| HostMessage::ResendState {client_id}),
+            (0..i64::MAX as usize).prop_map(|count| HostMessage::ShowLeaderboard {count}),
+            Just(HostMessage::GetTeamSummary),
+            page().prop_map(|page| HostMessage::GetAttendance {page}),
+            page().prop_map(|page| HostMessage::GetClientList {page}),
+            any::<Option<u64>>().prop_map(|version| HostMessage::SyncClientList {version}),
+            proptest::collection::vec(any::<String>(), 0..3).prop_map(|events| HostMessage::Subscribe {events}),
+            Just(HostMessage::GetIntegrity),
+            any::<u64>().prop_map(|probe| HostMessage::LinkProbeAck {probe}),
+            (any::<String>(), any::<i64>()).prop_map(|(id, duration)| HostMessage::StartTimer {id, duration}),
+            any::<String>().prop_map(|id| HostMessage::CancelTimer {id}),
+            (any::<String>(), any::<Option<i64>>(), any::<Option<u64>>(), any::<i32>(), any::<String>())
+                .prop_map(|(id, at, interval, state_id, content)| HostMessage::ScheduleMessage {id, at, interval, message: Box::new(HostMessage::Update {state_id, content})}),
+            any::<String>().prop_map(|id| HostMessage::CancelScheduledMessage {id}),
+            Just(HostMessage::ListScheduledMessages),
+            (prop_oneof![Just(ClientAction::Reload), Just(ClientAction::UpdateRequired)], any::<Option<String>>())
+                .prop_map(|(action, min_version)| HostMessage::ClientCommand {action, min_version}),
+            any::<String>().prop_map(|name| HostMessage::StartFromTemplate {name}),
+            (any::<i32>(), any::<String>(), proptest::collection::vec((any::<String>(), any::<String>(), any::<Option<u64>>()), 0..4))
+                .prop_map(|(index, content, assets)| HostMessage::StageState {index, content,
+                    assets: assets.into_iter().map(|(url, hash, size)| PrefetchAsset {url, hash, size}).collect()}),
+            any::<i32>().prop_map(|index| HostMessage::ShowStaged {index}),
+            any::<bool>().prop_map(|public| HostMessage::SetPublic {public}),
+            any::<Option<String>>().prop_map(|message| HostMessage::PauseSession {message}),
+            Just(HostMessage::ResumeSession),
+            Just(HostMessage::RequestSnapshotExport),
+            (any::<Option<String>>(), any::<bool>()).prop_map(|(code, announce)| HostMessage::RotateAccessCode {code, announce}),
+            any::<String>().prop_map(|module| HostMessage::LoadPlugin {module}),
+            Just(HostMessage::UnloadPlugin),
+        ]
+    }
+
+    /// Pages as the parser produces them, the limit is always within the maximum page size
+    fn page() -> impl Strategy<Value = Option<Page>> {
+        proptest::option::of((0..i64::MAX as usize, 1..=MAX_PAGE_SIZE).prop_map(|(offset, limit)| Page {offset, limit}))
+    }
+
+    fn reconnect_hint() -> impl Strategy<Value = ReconnectHint> {
+        (any::<u32>(), any::<u32>(), 0.0..=1.0f64, 0.0..=1.0f64).prop_map(|(min, max, jitter, load)| ReconnectHint {
+            min_backoff: Duration::from_millis(min as u64),
+            max_backoff: Duration::from_millis(max as u64),
+            jitter,
+            load,
+        })
+    }
+
+    fn backend_msg() -> impl Strategy<Value = BackendMessage> {
+        prop_oneof![
+            (any::<String>(), any::<String>(), any::<Option<String>>(), any::<Option<String>>())
+                .prop_map(|(name, address, role, team)| BackendMessage::ClientConnected {name, address, role, team}),
+            (any::<String>(), any::<String>(), any::<String>(), any::<bool>(), any::<Option<i32>>())
+                .prop_map(|(name, address, reason, answered, last_state_id)| BackendMessage::ClientDisconnected {name, address, reason, answered, last_state_id}),
+            (any::<String>(), proptest::option::of(reconnect_hint())).prop_map(|(reason, reconnect)| BackendMessage::Disconnect {reason, reconnect}),
+            any::<String>().prop_map(|reason| BackendMessage::LoginRejected {reason}),
+            (any::<i32>(), any::<String>(), any::<String>(), any::<String>(), any::<Option<i64>>(), any::<Option<String>>(), any::<i64>())
+                .prop_map(|(state_id, input, name, address, client_ts, input_id, server_ts)|
+                    BackendMessage::Input {state_id, input, name, address, client_ts, input_id, server_ts}),
+            (any::<i32>(), any::<String>()).prop_map(|(state_id, input_id)| BackendMessage::InputAck {state_id, input_id}),
+            (any::<i32>(), any::<Option<String>>()).prop_map(|(state_id, input_id)| BackendMessage::Muted {state_id, input_id}),
+            (any::<i32>(), any::<String>()).prop_map(|(state_id, content)| BackendMessage::Update {state_id, content}),
+            (any::<i32>(), any::<String>()).prop_map(|(state_id, content)| BackendMessage::ChangeState {state_id, content}),
+            proptest::collection::vec((any::<String>(), any::<i64>()), 0..8).prop_map(|standings| BackendMessage::Leaderboard {standings}),
+            (any::<String>(), any::<i64>(), any::<i64>()).prop_map(|(id, remaining, ends_at)| BackendMessage::Timer {id, remaining, ends_at}),
+            (any::<String>(), any::<String>()).prop_map(|(quota, message)| BackendMessage::QuotaExceeded {quota, message}),
+            (any::<String>(), any::<Option<String>>(), proptest::option::of(reconnect_hint()))
+                .prop_map(|(url, from, reconnect)| BackendMessage::Migrate {url, from, reconnect}),
+            (any::<Option<i64>>(), any::<u64>()).prop_map(|(rtt_ms, missed_pings)| BackendMessage::ConnectionQuality {rtt_ms, missed_pings}),
+        ]
+    }
+
+    fn json_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<f64>().prop_map(Value::from),
+            any::<String>().prop_map(Value::from),
+        ];
+        leaf.prop_recursive(4, 32, 8, |inner| prop_oneof![
+            proptest::collection::vec(inner.clone(), 0..8).prop_map(Value::from),
+            proptest::collection::hash_map(any::<String>(), inner, 0..8).prop_map(|map| Value::Object(map.into_iter().collect())),
+        ])
+    }
+
+    /// Objects with a known 'type' and arbitrary fields, these reach deep into the parsers
+    fn typed_json() -> impl Strategy<Value = Value> {
+        let types = prop_oneof![
+            Just("ClientLogin"), Just("Disconnecting"), Just("Input"), Just("HostLogin"), Just("Update"),
+            Just("ChangeState"), Just("GetClientInputs"), Just("MuteClient"), Just("ResendState"), Just("SetRules"), Just("SetScoring"),
+            Just("ShowLeaderboard"), Just("StartTimer"), Just("CancelTimer"), Just("PickRandomClients"), Just("StartFromTemplate"), Just("StageState"), Just("ShowStaged"), Just("PauseSession"), Just("ResumeSession"), Just("SetPublic"), Just("RequestSnapshotExport"), Just("RotateAccessCode"), Just("LoadPlugin"), Just("UnloadPlugin"), Just("SyncClientList"), Just("Subscribe"), Just("ScheduleMessage"), Just("CancelScheduledMessage"), Just("ListScheduledMessages"),
+            Just("ClientCommand"), Just("RequestResync"), Just("StateAck"),
+        ];
+        let keys = prop_oneof![
+            Just(String::from("name")), Just(String::from("state_id")), Just(String::from("content")),
+            Just(String::from("variants")), Just(String::from("distribution")), Just(String::from("rules")),
+            Just(String::from("filter")), Just(String::from("count")), Just(String::from("id")), Just(String::from("action")), any::<String>(),
+        ];
+        (types, proptest::collection::vec((keys, json_value()), 0..6)).prop_map(|(msg_type, fields)| {
+            let mut json: serde_json::Map<String, Value> = fields.into_iter().collect();
+            json.insert(String::from("type"), json!(msg_type));
+            Value::Object(json)
+        })
+    }
+
+    /// Every variant with sample values, cases with optional fields come with and without them
+    /// Adding a variant fails to compile in 'golden_variant', so no variant can miss its golden file
+    fn golden_cases() -> Vec<(&'static str, BackendMessage)> {
+        let address = String::from("10.0.0.1:50000");
+        let attendance = vec![AttendanceEntry {name: String::from("alice"), joins: 2, connected: 900_000, intervals: vec![
+            AttendanceInterval {joined: 1_700_000_000_000, left: Some(1_700_000_600_000)},
+            AttendanceInterval {joined: 1_700_001_000_000, left: None},
+        ]}];
+        let reconnect = ReconnectBackoff::default().hint(120);
+        vec![
+            ("ClientConnected", BackendMessage::ClientConnected {name: String::from("alice"), address: address.clone(), role: Some(String::from("moderator")), team: Some(String::from("red"))}),
+            ("ClientConnected_minimal", BackendMessage::ClientConnected {name: String::from("alice"), address: address.clone(), role: None, team: None}),
+            ("ClientDisconnected", BackendMessage::ClientDisconnected {name: String::from("alice"), address: address.clone(), reason: String::from("Connection closed gracefully by client"), answered: true, last_state_id: Some(4)}),
+            ("ClientDisconnected_minimal", BackendMessage::ClientDisconnected {name: String::from("alice"), address: address.clone(), reason: String::from("Connection closed gracefully by client"), answered: false, last_state_id: None}),
+            ("ClientExpired", BackendMessage::ClientExpired {name: String::from("alice"), address: address.clone()}),
+            ("ClientInputs", BackendMessage::ClientInputs {client_id: String::from("alice"), state_id: 4, inputs: vec![
+                RecordedInput {state_id: 4, input: String::from("B"), client_ts: Some(1_700_000_000_000), input_id: Some(String::from("i1")), server_ts: 1_700_000_000_050},
+                RecordedInput {state_id: 4, input: String::from("C"), client_ts: None, input_id: None, server_ts: 1_700_000_000_900},
+            ], page: None}),
+            ("ClientInputs_paged", BackendMessage::ClientInputs {client_id: String::from("alice"), state_id: 4, inputs: vec![
+                RecordedInput {state_id: 4, input: String::from("C"), client_ts: None, input_id: None, server_ts: 1_700_000_000_900},
+            ], page: Some(PageInfo {offset: 1, total: 2, next: None})}),
+            ("RuleTriggered", BackendMessage::RuleTriggered {name: String::from("almost_all"), state_id: 4, message: Some(String::from("90% answered"))}),
+            ("RuleTriggered_minimal", BackendMessage::RuleTriggered {name: String::from("timeout"), state_id: 4, message: None}),
+            ("Leaderboard", BackendMessage::Leaderboard {standings: vec![(String::from("alice"), 120), (String::from("bob"), 80)]}),
+            ("Team", BackendMessage::Team {team: String::from("red")}),
+            ("TeamSummary", BackendMessage::TeamSummary {teams: vec![TeamSummary {team: String::from("red"), members: 3, answered: 2, score: 250}]}),
+            ("Timer", BackendMessage::Timer {id: String::from("question"), remaining: 15_000, ends_at: 1_700_000_015_000}),
+            ("TimerExpired", BackendMessage::TimerExpired {id: String::from("question")}),
+            ("TimerCancelled", BackendMessage::TimerCancelled {id: String::from("question")}),
+            ("ClientsPicked", BackendMessage::ClientsPicked {picks: vec![
+                PickedClient {name: String::from("alice"), address: Some(address.clone())},
+                PickedClient {name: String::from("bob"), address: None},
+            ]}),
+            ("VariantsAssigned", BackendMessage::VariantsAssigned {state_id: 4, assignments: vec![VariantAssignment {name: String::from("alice"), address: address.clone(), variant: 1}]}),
+            ("SessionEnding", BackendMessage::SessionEnding {ends_at: 1_700_003_600_000}),
+            ("QuotaExceeded", BackendMessage::QuotaExceeded {quota: String::from("clients"), message: String::from("Client alice rejected, the limit of 50 clients is reached")}),
+            ("AdvertisedAddress", BackendMessage::AdvertisedAddress {host: String::from("quiz.example.org"), addresses: vec!["203.0.113.7".parse().unwrap(), "2001:db8::7".parse().unwrap()]}),
+            ("SessionResults", BackendMessage::SessionResults {standings: vec![(String::from("alice"), 120)], teams: vec![TeamSummary {team: String::from("red"), members: 0, answered: 0, score: 120}], attendance: attendance.clone()}),
+            ("Attendance", BackendMessage::Attendance {clients: attendance.clone(), page: None}),
+            ("Attendance_paged", BackendMessage::Attendance {clients: attendance.clone(), page: Some(PageInfo {offset: 0, total: 1200, next: Some(500)})}),
+            ("ClientList", BackendMessage::ClientList {clients: vec![ClientStats {
+                name: String::from("alice"), address: address.clone(), messages_sent: 12, bytes_sent: 2048,
+                messages_received: 5, bytes_received: 310, last_activity: 1_700_000_000_050,
+            }], page: None}),
+            ("ClientList_paged", BackendMessage::ClientList {clients: vec![ClientStats {
+                name: String::from("alice"), address: address.clone(), messages_sent: 12, bytes_sent: 2048,
+                messages_received: 5, bytes_received: 310, last_activity: 1_700_000_000_050,
+            }], page: Some(PageInfo {offset: 1000, total: 1001, next: None})}),
+            ("ClientListDiff", BackendMessage::ClientListDiff {version: 8, full: false, added: vec![ClientStats {
+                name: String::from("bob"), address: String::from("198.51.100.4:50112"), messages_sent: 1, bytes_sent: 96,
+                messages_received: 1, bytes_received: 40, last_activity: 1_700_000_000_900,
+            }], updated: vec![ClientStats {
+                name: String::from("alice"), address: address.clone(), messages_sent: 12, bytes_sent: 2048,
+                messages_received: 5, bytes_received: 310, last_activity: 1_700_000_000_050,
+            }], removed: vec![String::from("198.51.100.9:61000")]}),
+            ("ClientListDiff_full", BackendMessage::ClientListDiff {version: 1, full: true, added: vec![ClientStats {
+                name: String::from("alice"), address: address.clone(), messages_sent: 12, bytes_sent: 2048,
+                messages_received: 5, bytes_received: 310, last_activity: 1_700_000_000_050,
+            }], updated: vec![], removed: vec![]}),
+            ("ClientIssues", BackendMessage::ClientIssues {clients: vec![ClientIssues {
+                name: String::from("alice"), address: address.clone(), malformed: 3, rejected_inputs: 12, violations: 1,
+            }]}),
+            ("IntegrityReport", BackendMessage::IntegrityReport {clients: vec![RiskScore {
+                name: String::from("alice"), score: 80, flags: vec![RiskFlag::SharedIp, RiskFlag::SynchronizedInputs],
+            }]}),
+            ("Disconnecting", BackendMessage::Disconnect {reason: String::from("Server is shutting down"), reconnect: Some(reconnect)}),
+            ("Disconnecting_minimal", BackendMessage::Disconnect {reason: String::from("Session ended"), reconnect: None}),
+            ("LoginRejected", BackendMessage::LoginRejected {reason: String::from("Invalid token")}),
+            ("Challenge", BackendMessage::Challenge {challenge: IssuedChallenge::ProofOfWork {nonce: String::from("q8Vn2mXbKkR0"), difficulty: 18}}),
+            ("Challenge_captcha", BackendMessage::Challenge {challenge: IssuedChallenge::Captcha {site_key: String::from("0x4AAAAAAA")}}),
+            ("Input", BackendMessage::Input {state_id: 4, input: String::from("B"), name: String::from("alice"), address: address.clone(), client_ts: Some(1_700_000_000_000), input_id: Some(String::from("i1")), server_ts: 1_700_000_000_050}),
+            ("Input_minimal", BackendMessage::Input {state_id: 4, input: String::from("B"), name: String::from("alice"), address: address.clone(), client_ts: None, input_id: None, server_ts: 1_700_000_000_050}),
+            ("InputAck", BackendMessage::InputAck {state_id: 4, input_id: String::from("i1")}),
+            ("Muted", BackendMessage::Muted {state_id: 4, input_id: Some(String::from("i1"))}),
+            ("Muted_minimal", BackendMessage::Muted {state_id: 4, input_id: None}),
+            ("Update", BackendMessage::Update {state_id: 4, content: String::from("{\"progress\":0.5}")}),
+            ("ChangeState", BackendMessage::ChangeState {state_id: 5, content: String::from("{\"question\":\"Capital of France?\"}")}),
+            ("Migrate", BackendMessage::Migrate {url: String::from("wss://b.example.org"), from: Some(String::from("wss://a.example.org")), reconnect: Some(reconnect)}),
+            ("Migrate_minimal", BackendMessage::Migrate {url: String::from("wss://b.example.org"), from: None, reconnect: None}),
+            ("JoinInfo", BackendMessage::JoinInfo {url: String::from("https://quiz.example.org/join")}),
+            ("SnapshotExport", BackendMessage::SnapshotExport {archive: json!({"exported_at": 1_700_000_000_000_i64, "session": null, "state": {"state_id": 3, "content": "question"},
+                "clients": [{"name": "bob", "address": "10.0.0.7:51234", "team": null}],
+                "inputs": [{"name": "bob", "address": "10.0.0.7:51234", "state_id": 3, "input": "answer", "client_ts": null, "input_id": "i1", "server_ts": 1_699_999_990_000_i64}]})}),
+            ("QuorumReached", BackendMessage::QuorumReached {state_id: 7, percent: 82}),
+            ("AccessCode", BackendMessage::AccessCode {code: String::from("K7QX4M")}),
+            ("Subscribed", BackendMessage::Subscribed {events: vec![String::from("variants")]}),
+            ("ScheduledMessages", BackendMessage::ScheduledMessages {messages: vec![ScheduleEntry {
+                id: String::from("reminder"), message_type: String::from("Update"), next_at: 1_700_000_060_000, interval: Some(300_000),
+            }], error: None}),
+            ("ScheduledMessages_rejected", BackendMessage::ScheduledMessages {messages: vec![], error: Some(String::from("Neither 'at' nor 'interval' given"))}),
+            ("ScheduledMessageSent", BackendMessage::ScheduledMessageSent {id: String::from("reveal"), next_at: None}),
+            ("PluginStatus", BackendMessage::PluginStatus {loaded: true, error: None}),
+            ("PluginStatus_failed", BackendMessage::PluginStatus {loaded: false, error: Some(String::from("'on_input' failed: all fuel consumed by WebAssembly"))}),
+            ("PluginResult", BackendMessage::PluginResult {content: json!({"leaderboard": [{"name": "ada", "score": 1200}, {"name": "bob", "score": 950}]})}),
+            ("ServerHello", BackendMessage::ServerHello {version: 2, capabilities: vec![String::from("checksums"), String::from("chunks")], max_frame_size: 16_777_216, send_timeout: Some(10_000)}),
+            ("PresenceSummary", BackendMessage::PresenceSummary {joined: 412, left: 37, expired: 5, clients: 2841}),
+            ("RejoinLink", BackendMessage::RejoinLink {url: String::from("https://quiz.example.org/?rejoin=9c41d87a365f2b0e"), expires_at: 1_700_001_800_000}),
+            ("Resume", BackendMessage::Resume {token: String::from("41d87a365f2b0e9c")}),
+            ("RoomOpened", BackendMessage::RoomOpened {code: String::from("K7QX2M"), session: String::from("3f2a9c0d5e7b8a1c4d6e0f2a3b5c7d9e")}),
+            ("ClientCommand", BackendMessage::ClientCommand {action: ClientAction::UpdateRequired, min_version: Some(String::from("0.2.0"))}),
+            ("ClientCommand_minimal", BackendMessage::ClientCommand {action: ClientAction::Reload, min_version: None}),
+            ("Announcement", BackendMessage::Announcement {message: String::from("Server restarting in 10 min"), expires_at: Some(1_700_000_600_000)}),
+            ("Announcement_minimal", BackendMessage::Announcement {message: String::from("Server restarting in 10 min"), expires_at: None}),
+            ("AnnouncementCleared", BackendMessage::AnnouncementCleared),
+            ("Resync", BackendMessage::Resync {count: 2}),
+            ("Retransmit", BackendMessage::Retransmit {frame: 17}),
+            ("Resynced", BackendMessage::Resynced {seq: 1042, complete: true}),
+            ("LinkProbe", BackendMessage::LinkProbe {probe: 3, sent_at: 1_700_000_000_000}),
+            ("LinkQuality", BackendMessage::LinkQuality {rtt: Some(42), uplink: Some(250_000), required: 1_500_000,
+                warning: Some(String::from("Uplink of about 250000 bytes/s can not deliver 100000 bytes to 30 clients within 2s, consider reducing media sizes"))}),
+            ("LinkQuality_minimal", BackendMessage::LinkQuality {rtt: None, uplink: None, required: 0, warning: None}),
+            ("ConnectionQuality", BackendMessage::ConnectionQuality {rtt_ms: Some(87), missed_pings: 0}),
+            ("ConnectionQuality_unanswered", BackendMessage::ConnectionQuality {rtt_ms: None, missed_pings: 3}),
+            ("SessionPaused", BackendMessage::SessionPaused {message: Some(String::from("Coffee break, back at 10:30")), since: 1_700_000_000_000}),
+            ("SessionPaused_minimal", BackendMessage::SessionPaused {message: None, since: 1_700_000_000_000}),
+            ("SessionResumed", BackendMessage::SessionResumed {paused_for: 900_000}),
+            ("Staged", BackendMessage::Staged {index: 4, count: 12}),
+            ("StageMissing", BackendMessage::StageMissing {index: 13}),
+            ("Prefetch", BackendMessage::Prefetch {hash: String::from("sha256:9f86d081884c7d65"), url: String::from("https://cdn.example.org/q5.webp"), size: Some(482_113)}),
+            ("Prefetch_minimal", BackendMessage::Prefetch {hash: String::from("sha256:60303ae22b998861"), url: String::from("https://cdn.example.org/q5.mp4"), size: None}),
+            ("TemplateStarted", BackendMessage::TemplateStarted {name: String::from("weekly_quiz")}),
+            ("TemplateUnknown", BackendMessage::TemplateUnknown {name: String::from("weekly_qiuz"),
+                available: vec![String::from("exam"), String::from("weekly_quiz")]}),
+        ]
+    }
+
+    /// Name of the golden file without suffix, exhaustive on purpose
+    fn golden_variant(msg: &BackendMessage) -> &'static str {
+        match msg {
+            BackendMessage::ClientConnected {..} => "ClientConnected",
+            BackendMessage::ClientDisconnected {..} => "ClientDisconnected",
+            BackendMessage::ClientExpired {..} => "ClientExpired",
+            BackendMessage::ClientInputs {..} => "ClientInputs",
+            BackendMessage::RuleTriggered {..} => "RuleTriggered",
+            BackendMessage::Leaderboard {..} => "Leaderboard",
+            BackendMessage::Team {..} => "Team",
+            BackendMessage::TeamSummary {..} => "TeamSummary",
+            BackendMessage::Timer {..} => "Timer",
+            BackendMessage::TimerExpired {..} => "TimerExpired",
+            BackendMessage::TimerCancelled {..} => "TimerCancelled",
+            BackendMessage::ClientsPicked {..} => "ClientsPicked",
+            BackendMessage::VariantsAssigned {..} => "VariantsAssigned",
+            BackendMessage::SessionEnding {..} => "SessionEnding",
+            BackendMessage::QuotaExceeded {..} => "QuotaExceeded",
+            BackendMessage::AdvertisedAddress {..} => "AdvertisedAddress",
+            BackendMessage::Attendance {..} => "Attendance",
+            BackendMessage::ClientList {..} => "ClientList",
+            BackendMessage::ClientListDiff {..} => "ClientListDiff",
+            BackendMessage::IntegrityReport {..} => "IntegrityReport",
+            BackendMessage::ClientIssues {..} => "ClientIssues",
+            BackendMessage::SessionResults {..} => "SessionResults",
+            BackendMessage::Disconnect {..} => "Disconnecting",
+            BackendMessage::LoginRejected {..} => "LoginRejected",
+            BackendMessage::Challenge {..} => "Challenge",
+            BackendMessage::Input {..} => "Input",
+            BackendMessage::InputAck {..} => "InputAck",
+            BackendMessage::Muted {..} => "Muted",
+            BackendMessage::Update {..} => "Update",
+            BackendMessage::ChangeState {..} => "ChangeState",
+            BackendMessage::Migrate {..} => "Migrate",
+            BackendMessage::JoinInfo {..} => "JoinInfo",
+            BackendMessage::SnapshotExport {..} => "SnapshotExport",
+            BackendMessage::QuorumReached {..} => "QuorumReached",
+            BackendMessage::AccessCode {..} => "AccessCode",
+            BackendMessage::RejoinLink {..} => "RejoinLink",
+            BackendMessage::Resume {..} => "Resume",
+            BackendMessage::RoomOpened {..} => "RoomOpened",
+            BackendMessage::Subscribed {..} => "Subscribed",
+            BackendMessage::PresenceSummary {..} => "PresenceSummary",
+            BackendMessage::ServerHello {..} => "ServerHello",
+            BackendMessage::ScheduledMessages {..} => "ScheduledMessages",
+            BackendMessage::ScheduledMessageSent {..} => "ScheduledMessageSent",
+            BackendMessage::PluginStatus {..} => "PluginStatus",
+            BackendMessage::PluginResult {..} => "PluginResult",
+            BackendMessage::ClientCommand {..} => "ClientCommand",
+            BackendMessage::Announcement {..} => "Announcement",
+            BackendMessage::AnnouncementCleared => "AnnouncementCleared",
+            BackendMessage::Resync {..} => "Resync",
+            BackendMessage::Resynced {..} => "Resynced",
+            BackendMessage::Retransmit {..} => "Retransmit",
+            BackendMessage::LinkProbe {..} => "LinkProbe",
+            BackendMessage::LinkQuality {..} => "LinkQuality",
+            BackendMessage::ConnectionQuality {..} => "ConnectionQuality",
+            BackendMessage::SessionPaused {..} => "SessionPaused",
+            BackendMessage::SessionResumed {..} => "SessionResumed",
+            BackendMessage::Staged {..} => "Staged",
+            BackendMessage::StageMissing {..} => "StageMissing",
+            BackendMessage::Prefetch {..} => "Prefetch",
+            BackendMessage::TemplateStarted {..} => "TemplateStarted",
+            BackendMessage::TemplateUnknown {..} => "TemplateUnknown",
+        }
+    }
+
+    /// Compares the exact encoding of every case with 'tests/golden/backend/<case>.json'
+    /// Run with TT_UPDATE_GOLDEN=1 to (re)write the files after an intended change of the wire format
+    #[test]
+    fn backend_messages_match_golden_files() {
+        let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/backend");
+        let update = std::env::var("TT_UPDATE_GOLDEN").is_ok();
+        let mut mismatches = vec![];
+        for (case, msg) in golden_cases() {
+            assert!(case.starts_with(golden_variant(&msg)), "Case {} does not belong to {}", case, golden_variant(&msg));
+            let encoded = format!("{}\n", encode_backend_msg(msg));
+            let path = directory.join(format!("{}.json", case));
+            if update {
+                std::fs::create_dir_all(&directory).unwrap();
+                std::fs::write(&path, &encoded).unwrap();
+                continue
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(golden) if golden == encoded => {}
+                Ok(golden) => mismatches.push(format!("{}:\n  golden:  {}  encoded: {}", case, golden, encoded)),
+                Err(e) => mismatches.push(format!("{}: reading {} failed: {}", case, path.display(), e)),
+            }
+        }
+        assert!(mismatches.is_empty(), "Wire format changed, the WebApp and HostApp depend on it:\n{}", mismatches.join("\n"));
+    }
+
+    /// Every variant has at least one golden case
+    #[test]
+    fn golden_cases_cover_all_variants() {
+        let covered: std::collections::HashSet<&str> = golden_cases().iter().map(|(_, msg)| golden_variant(msg)).collect();
+        assert_eq!(covered.len(), 61);
+    }
+
+    proptest! {
+        #[test]
+        fn client_messages_round_trip(msg in client_msg()) {
+            let parsed = parse_client_msg(&encode_client_msg(&msg));
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", Some(msg)));
+        }
+
+        #[test]
+        fn host_messages_round_trip(msg in host_msg()) {
+            let parsed = parse_host_msg(&encode_host_msg(&msg));
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", Some(msg)));
+        }
+
+        #[test]
+        fn backend_messages_encode_to_typed_json(msg in backend_msg()) {
+            let msg_type = backend_type(&msg);
+            let json: Value = serde_json::from_str(&encode_backend_msg(msg)).expect("Encoded message is no json");
+            prop_assert_eq!(json["type"].as_str(), Some(msg_type));
+        }
+
+        /// Update, ChangeState and Disconnecting share their wire format between backend and host
+        #[test]
+        fn relayed_backend_messages_round_trip(state_id in any::<i32>(), content in any::<String>()) {
+            let parsed = parse_host_msg(&encode_backend_msg(BackendMessage::Update {state_id, content: content.clone()}));
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", Some(HostMessage::Update {state_id, content: content.clone()})));
+
+            let parsed = parse_host_msg(&encode_backend_msg(BackendMessage::ChangeState {state_id, content: content.clone()}));
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", Some(HostMessage::ChangeState {state_id, content: content.clone(), variants: None})));
+
+            let parsed = parse_host_msg(&encode_backend_msg(BackendMessage::Disconnect {reason: content.clone(), reconnect: None}));
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", Some(HostMessage::Disconnect {reason: content})));
+        }
+
+        #[test]
+        fn parsers_never_panic_on_arbitrary_strings(msg in any::<String>()) {
+            parse_client_msg(&msg);
+            parse_host_msg(&msg);
+        }
+
+        #[test]
+        fn parsers_never_panic_on_arbitrary_json(json in json_value()) {
+            parse_client_msg(&json.to_string());
+            parse_host_msg(&json.to_string());
+        }
+
+        #[test]
+        fn parsers_never_panic_on_typed_json(json in typed_json()) {
+            parse_client_msg(&json.to_string());
+            parse_host_msg(&json.to_string());
+        }
+    }
+}
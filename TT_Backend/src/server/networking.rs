@@ -1,17 +1,39 @@
 #![allow(dead_code)]
 
-use std::net::SocketAddr;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
 use futures_util::stream::SplitSink;
-use log::warn;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::Sender;
+use log::{info, warn};
+use socket2::{SockRef, TcpKeepalive};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
 use crate::server::InternalMessage;
-use crate::server::messages::BackendMessage;
-use crate::server::networking::tcp_sockets::{host_close_connection, host_send_message};
-use crate::server::networking::websockets::{client_close_connection, client_send_message, WsWriteHalve};
+use crate::server::bus::Bus;
+use crate::server::compat::{encode_legacy, HostProtocol, MAX_HELD_MESSAGES};
+use crate::server::host_hello::Agreed;
+use crate::server::config::{BindConfig, SocketConfig};
+use crate::server::upgrade::{inherited_address, take_inherited};
+use crate::server::codec::{EncodedMessage, WireCodec, WireFrame};
+use crate::server::factory::StampedMessage;
+use crate::server::messages::{BackendMessage, current_timestamp};
+use crate::server::reconnect::ReconnectHint;
+use crate::server::rejoin::RejoinGrant;
+use crate::server::resume::Departed;
+use crate::server::networking::tcp_sockets::{host_close_connection, host_send_frame};
+use crate::server::networking::websockets::{client_socket_writer, WsWriteHalve};
 
 pub const DISCONNECT_REASON_CLI_CLOSED_GRACEFULLY: &str = "Connection closed gracefully by client";
 pub const DISCONNECT_REASON_CLI_CLOSED_FORCEFULLY: &str = "Connection closed forcefully by client";
@@ -20,43 +42,401 @@ pub const DISCONNECT_REASON_HOST_CLOSED_FORCEFULLY: &str = "Connection closed fo
 pub const DISCONNECT_REASON_HOST_OTHER: &str = "Another host connected";
 pub const DISCONNECT_REASON_VIOLATION: &str = "Protocol violation";
 pub const DISCONNECT_REASON_SEND_FAILED: &str = "Sending failed";
+pub const DISCONNECT_REASON_STALLED: &str = "Connection stalled";
+pub const DISCONNECT_REASON_LOGIN_REJECTED: &str = "Login rejected";
+pub const DISCONNECT_REASON_MIGRATED: &str = "Migrated to another server instance";
+pub const DISCONNECT_REASON_SESSION_ENDED: &str = "Session ended";
+pub const DISCONNECT_REASON_GUEST_EXPIRED: &str = "Guest access expired, please log in again";
+pub const DISCONNECT_REASON_NO_HOST: &str = "No host connected";
+pub const DISCONNECT_REASON_UPGRADE: &str = "Server is upgraded, please reconnect";
+pub const DISCONNECT_REASON_RESUMED: &str = "Resumed by a new connection";
+
+/// Number of most recent input ids remembered per client to detect retransmissions
+pub const INPUT_ID_WINDOW: usize = 256;
+
+/// Interval between two websocket pings to every client, each followed by its 'ConnectionQuality'
+pub const CLIENT_PING_INTERVAL: Duration = Duration::from_secs(5);
+/// Interval of the 'ClientIssues' reports to the host, clients without issues are left out
+pub const CLIENT_ISSUES_INTERVAL: Duration = Duration::from_secs(30);
+/// Interval between two checks for ended accept loops, also the delay before their restart
+pub const LISTENER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time a host may take to complete a frame once its first byte arrived
+pub const HOST_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+/// Every frame between host and server starts with these bytes, so a desynchronized stream can
+/// be detected and recovered by scanning for the next occurrence
+pub const HOST_FRAME_MAGIC: [u8; 4] = *b"TTHF";
+/// Larger length prefixes can only be garbage and are treated as lost synchronization
+pub const HOST_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+/// Frames starting with these bytes carry a chunk of a large (bulk) message instead of a whole one
+/// The host sends its control messages as whole frames in between the chunks, so they are not
+/// stuck behind the upload of a large state. Messages of the same kind keep their order, a
+/// control message overtakes the bulk message still in flight
+pub const HOST_CHUNK_MAGIC: [u8; 4] = *b"TTHC";
+/// Flags (first payload byte) of a chunk, the first one discards an incomplete bulk message
+pub const HOST_CHUNK_FIRST: u8 = 0b01;
+pub const HOST_CHUNK_LAST: u8 = 0b10;
 
 type WSSink = SplitSink<WebSocketStream<TcpStream>, Message>;
 
+/// Runs the write, failing with 'TimedOut' if it does not complete within the send timeout
+/// Without a timeout a jammed TCP buffer blocks the writer until the connection breaks
+async fn write_within<E: From<std::io::Error>>(timeout: Option<Duration>, write: impl Future<Output = Result<(), E>>) -> Result<(), E> {
+    let timeout = match timeout {
+        None => return write.await,
+        Some(v) => v,
+    };
+    match tokio::time::timeout(timeout, write).await {
+        Ok(result) => result,
+        Err(_) => Err(E::from(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("Write stalled for {:?}", timeout)))),
+    }
+}
+
+/// Disconnect reason for a failed write, a write that timed out stalled the connection
+fn send_failure_reason(e: &std::io::Error) -> &'static str {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => DISCONNECT_REASON_STALLED,
+        _ => DISCONNECT_REASON_SEND_FAILED,
+    }
+}
+
+/// Applies the configured socket options to an accepted connection
+/// Failing options are only logged, the connection stays usable with the defaults
+pub fn apply_socket_options(stream: &TcpStream, config: &SocketConfig, nodelay: bool, address: SocketAddr) {
+    let socket = SockRef::from(stream);
+    if let Some(time) = config.keepalive_time {
+        let keepalive = TcpKeepalive::new().with_time(time);
+        #[cfg(any(target_os = "linux", target_vendor = "apple", windows))]
+        let keepalive = match config.keepalive_interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+        if let Err(e) = socket.set_tcp_keepalive(&keepalive) {
+            warn!("apply_socket_options(..): Enabling keepalive for {} failed!\nError: {}", address, e);
+        }
+    }
+    if nodelay {
+        if let Err(e) = stream.set_nodelay(true) {
+            warn!("apply_socket_options(..): Setting TCP_NODELAY for {} failed!\nError: {}", address, e);
+        }
+    }
+    if let Some(size) = config.send_buffer_size {
+        if let Err(e) = socket.set_send_buffer_size(size) {
+            warn!("apply_socket_options(..): Setting send buffer size for {} failed!\nError: {}", address, e);
+        }
+    }
+    if let Some(size) = config.recv_buffer_size {
+        if let Err(e) = socket.set_recv_buffer_size(size) {
+            warn!("apply_socket_options(..): Setting receive buffer size for {} failed!\nError: {}", address, e);
+        }
+    }
+}
+
+/// What a listener accepts connections for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ListenerRole {
+    Clients,
+    Hosts,
+    ShadowHosts,
+    Admin,
+    Replication,
+    LiveView,
+    Acme,
+}
+
+impl Display for ListenerRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ListenerRole::Clients => "client (websocket)",
+            ListenerRole::Hosts => "host (tcp)",
+            ListenerRole::ShadowHosts => "shadow host (tcp)",
+            ListenerRole::Admin => "admin (http)",
+            ListenerRole::Replication => "replication (tcp)",
+            ListenerRole::LiveView => "live view (http)",
+            ListenerRole::Acme => "ACME challenge (http)",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A listener could not be bound on any of its ports
+#[derive(Debug)]
+pub struct BindError {
+    pub role: ListenerRole,
+    /// Last address tried
+    pub address: SocketAddr,
+    pub error: std::io::Error,
+}
+
+impl Display for BindError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Binding the {} listener on {} failed: {}", self.role, self.address, self.error)
+    }
+}
+
+impl std::error::Error for BindError {}
+
+/// Binds the listener on the port or one of the alternatives configured for its role
+/// If all ports fail, the attempt is repeated after an exponentially growing delay until the
+/// configured retries are used up
+pub async fn bind_listener<F, Fut>(role: ListenerRole, ip: IpAddr, port: u16, config: &BindConfig, create: F) -> Result<Listener, BindError>
+    where F: Fn(SocketAddr) -> Fut, Fut: Future<Output = std::io::Result<Listener>> {
+    // After an in-place upgrade the listener continues on the socket of the previous process
+    if let Some(address) = inherited_address(role) {
+        return create(address).await.map_err(|error| BindError { role, address, error })
+    }
+    let ports: Vec<u16> = std::iter::once(port).chain(config.alternatives(role).iter().copied()).collect();
+    let mut delay = config.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let mut last_error = None;
+        for port in ports.iter() {
+            let address = SocketAddr::new(ip, *port);
+            match create(address).await {
+                Ok(listener) => return Ok(listener),
+                Err(error) => {
+                    warn!("bind_listener(..): Binding the {} listener on {} failed\nError: {}", role, address, error);
+                    last_error = Some(BindError { role, address, error });
+                }
+            }
+        }
+        if attempt >= config.retries {
+            return Err(last_error.expect("bind_listener(..): At least one port has been tried"))
+        }
+        attempt += 1;
+        info!("bind_listener(..): Retrying to bind the {} listener in {:?} ({}/{})", role, delay, attempt, config.retries);
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Binds a listening socket, the one inherited from the previous process if bound to the address
+pub async fn bind_tcp(address: SocketAddr) -> std::io::Result<TcpListener> {
+    match take_inherited(address) {
+        Some(listener) => listener,
+        None => TcpListener::bind(&address).await,
+    }
+}
+
+/// Starts the accept loop of a listener on the listening socket
+type AcceptLoop = Box<dyn Fn(TcpListener) -> JoinHandle<()> + Send + Sync>;
+
+/// Accept loop of a listener
+/// The listening socket outlives the loop, a loop that ended (e.g. when the process ran out of
+/// descriptors) is restarted on it by the supervision of the main handler
+/// Stopping it only stops accepting, connections accepted before stay open until they close
+pub struct Listener {
+    address: SocketAddr,
+    /// Listening socket, shared with the accept loop
+    socket: std::net::TcpListener,
+    accept: AcceptLoop,
+    task: JoinHandle<()>,
+    restarts: u32,
+}
+
+impl Listener {
+    /// Spawns the accept loop on the socket
+    pub fn start<F, Fut>(address: SocketAddr, listener: TcpListener, accept: F) -> std::io::Result<Self>
+        where F: Fn(TcpListener) -> Fut + Send + Sync + 'static, Fut: Future<Output = ()> + Send + 'static {
+        let socket = listener.into_std()?;
+        let listener = TcpListener::from_std(socket.try_clone()?)?;
+        let accept: AcceptLoop = Box::new(move |listener| tokio::spawn(accept(listener)));
+        let task = accept(listener);
+        Ok(Listener { address, socket, accept, task, restarts: 0 })
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Descriptor of the listening socket, valid while the listener exists
+    pub fn get_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    pub fn get_restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Starts the accept loop anew on the same socket
+    pub fn restart(&mut self) -> std::io::Result<()> {
+        let listener = TcpListener::from_std(self.socket.try_clone()?)?;
+        self.task.abort();
+        self.task = (self.accept)(listener);
+        self.restarts += 1;
+        Ok(())
+    }
+
+    pub fn stop(self) {
+        info!("stop(..): No longer accepting connections on {}", self.address);
+        self.task.abort();
+    }
+}
+
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Listener({}, restarts: {})", self.address, self.restarts)
+    }
+}
+
+/// Whether the accept loop has to end after the error, it is restarted by the supervision later
+/// Errors of single connections (e.g. reset before they were accepted) are skipped
+pub fn accept_exhausted(error: &std::io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM))
+}
+
+/// Read half of a host connection, a tcp connection or a pipe of a co-located process
+pub struct HostReader(Box<dyn AsyncRead + Send + Unpin>);
+
+/// Write half of a host connection, a tcp connection or a pipe of a co-located process
+pub struct HostWriter(Box<dyn AsyncWrite + Send + Unpin>);
+
+impl HostReader {
+    pub fn new(read: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        HostReader(Box::new(read))
+    }
+}
+
+impl HostWriter {
+    pub fn new(write: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        HostWriter(Box::new(write))
+    }
+}
+
+impl std::fmt::Debug for HostReader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostReader")
+    }
+}
+
+impl std::fmt::Debug for HostWriter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostWriter")
+    }
+}
+
+impl AsyncRead for HostReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for HostWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut *self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.0).poll_shutdown(cx)
+    }
+}
+
+/// Connection of a host, as accepted on a host port or opened on the pipes of a process
+#[derive(Debug)]
+pub struct HostStream {
+    pub read: HostReader,
+    pub write: HostWriter,
+}
+
+impl From<TcpStream> for HostStream {
+    fn from(stream: TcpStream) -> Self {
+        let (read, write) = stream.into_split();
+        HostStream {read: HostReader::new(read), write: HostWriter::new(write)}
+    }
+}
+
+impl From<tokio_openssl::SslStream<TcpStream>> for HostStream {
+    fn from(stream: tokio_openssl::SslStream<TcpStream>) -> Self {
+        let (read, write) = tokio::io::split(stream);
+        HostStream {read: HostReader::new(read), write: HostWriter::new(write)}
+    }
+}
+
 #[derive(Debug)]
 pub struct HostConnection {
     address: SocketAddr,
-    write: OwnedWriteHalf,
-    channel: Sender<InternalMessage>,
+    write: HostWriter,
+    channel: Bus,
+    send_timeout: Option<Duration>,
+    /// Protocol spoken by the host, None until its first frame arrived
+    protocol: Option<HostProtocol>,
+    /// Messages held back until the protocol is known
+    held: VecDeque<StampedMessage>,
+    /// Capabilities agreed by the handshake
+    agreed: Agreed,
 }
 
 impl HostConnection {
     pub fn get_address(&self) -> SocketAddr {
-        self.address.clone()
+        self.address
     }
 
     pub fn get_address_as_str(&self) -> String {
         self.address.to_string()
     }
 
-    pub async fn send_message(&mut self, msg: BackendMessage) {
-        match host_send_message(&mut self.write, msg).await {
+    pub async fn send_message(&mut self, msg: StampedMessage) {
+        if !self.agreed.admits(msg.message()) {
+            return
+        }
+        let protocol = match self.protocol {
+            None => {
+                if self.held.len() == MAX_HELD_MESSAGES {
+                    self.held.pop_front();
+                }
+                self.held.push_back(msg);
+                return
+            }
+            Some(v) => v,
+        };
+        let frame = match protocol {
+            HostProtocol::Current => msg.encode(),
+            HostProtocol::Legacy => match encode_legacy(msg.message().clone()) {
+                None => return,
+                Some(v) => v,
+            },
+        };
+        match host_send_frame(&mut self.write, &frame, protocol, self.send_timeout).await {
             Ok(_) => {}
             Err(e) => {
                 warn!("host_send_message(..): Sending message to {} failed!\nError: {}",
                     self.address, e);
-                let int_msg = InternalMessage::HostCloseConnection { address: self.address, reason: DISCONNECT_REASON_SEND_FAILED};
+                let int_msg = InternalMessage::HostCloseConnection { address: self.address, reason: send_failure_reason(&e)};
                 self.channel.send(int_msg).await.expect("host_send_message(..): Sending internal message failed");
             }
         }
     }
 
-    pub async fn close(self, reason: &str) {
-        host_close_connection(self.write, self.address, reason).await
+    /// Sends the messages held back so far in the protocol of the host
+    pub async fn set_protocol(&mut self, protocol: HostProtocol) {
+        self.protocol = Some(protocol);
+        for msg in std::mem::take(&mut self.held) {
+            self.send_message(msg).await;
+        }
+    }
+
+    /// Notifications of capabilities not agreed are dropped from now on
+    pub fn set_agreed(&mut self, agreed: Agreed) {
+        self.agreed = agreed;
     }
 
-    pub fn new(address: SocketAddr, write: OwnedWriteHalf, channel: Sender<InternalMessage>) -> Self {
-        HostConnection{ address, write, channel }
+    /// 'reconnect' is passed on with 'Disconnecting', None if the host should not reconnect
+    pub async fn close(self, reason: &str, reconnect: Option<ReconnectHint>) {
+        let protocol = self.protocol.unwrap_or(HostProtocol::Current);
+        host_close_connection(self.write, self.address, reason, reconnect, protocol, self.send_timeout).await
+    }
+
+    /// Writes taking longer than 'send_timeout' close the connection as stalled
+    pub fn new(address: SocketAddr, write: HostWriter, channel: Bus, send_timeout: Option<Duration>) -> Self {
+        HostConnection{ address, write, channel, send_timeout, protocol: None, held: VecDeque::new(), agreed: Default::default() }
     }
 }
 
@@ -64,14 +444,41 @@ impl HostConnection {
 #[derive(Debug)]
 pub struct ClientConnection {
     name: String,
+    role: Option<String>,
+    team: Option<String>,
+    /// Logged in without a token
+    guest: bool,
+    /// Server timestamp the guest access ends at, unlimited if None
+    guest_until: Option<i64>,
+    /// Access code given at login
+    access_code: Option<String>,
+    /// Rejoin token given at login, redeemed by the main handler
+    rejoin: Option<String>,
+    /// Resume token given at login, the main handler continues the departed participant of it
+    resume: Option<String>,
+    /// Join code of the room given at login
+    room: Option<String>,
+    /// Keyed hash of the resume token of the participant, assigned by the main handler
+    identity: String,
     address: SocketAddr,
-    channel: Sender<InternalMessage>,
-    write: WsWriteHalve,
+    queue: UnboundedSender<Outbound>,
+    queue_stats: Arc<QueueStats>,
+    /// Wire codec negotiated at login
+    codec: &'static dyn WireCodec,
+    recent_input_ids: InputIdWindow,
+    answered_state: Option<i32>,
+    /// Latest state delivered to the client or answered by it
+    last_state: Option<i32>,
+    muted: bool,
+    /// Bytes of all messages enqueued for the client
+    bytes_sent: u64,
+    messages_sent: u64,
+    traffic: Arc<TrafficStats>,
 }
 
 impl ClientConnection {
     pub fn get_address(&self) -> SocketAddr {
-        self.address.clone()
+        self.address
     }
 
     pub fn get_address_as_str(&self) -> String {
@@ -82,185 +489,700 @@ impl ClientConnection {
         &self.name
     }
 
-    pub async fn send_message(&mut self, msg: BackendMessage) {
-        match client_send_message(&mut self.write, msg).await {
-            Ok(_) => {}
-            Err(e) => {
-                warn!("client_send_message(..): Sending message to {} failed!\nError: {:?}",
-                    self.address, e);
-                let int_msg = InternalMessage::ClientCloseConnection { address: self.address, reason: DISCONNECT_REASON_SEND_FAILED};
-                self.channel.send(int_msg).await.expect("client_send_message(..): Sending internal message failed");
-            }
+    /// Role assigned by the AuthProvider, if any
+    pub fn get_role(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+
+    /// Team picked at login, replaced by the team assigned by the server
+    pub fn get_team(&self) -> Option<&str> {
+        self.team.as_deref()
+    }
+
+    pub fn set_team(&mut self, team: Option<String>) {
+        self.team = team;
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.guest
+    }
+
+    pub fn get_guest_until(&self) -> Option<i64> {
+        self.guest_until
+    }
+
+    pub fn set_guest_until(&mut self, guest_until: Option<i64>) {
+        self.guest_until = guest_until;
+    }
+
+    pub fn get_access_code(&self) -> Option<&str> {
+        self.access_code.as_deref()
+    }
+
+    pub fn set_access_code(&mut self, access_code: Option<String>) {
+        self.access_code = access_code;
+    }
+
+    pub fn get_rejoin(&self) -> Option<&str> {
+        self.rejoin.as_deref()
+    }
+
+    pub fn set_rejoin(&mut self, rejoin: Option<String>) {
+        self.rejoin = rejoin;
+    }
+
+    pub fn get_resume(&self) -> Option<&str> {
+        self.resume.as_deref()
+    }
+
+    pub fn set_resume(&mut self, resume: Option<String>) {
+        self.resume = resume;
+    }
+
+    pub fn get_room(&self) -> Option<&str> {
+        self.room.as_deref()
+    }
+
+    pub fn set_room(&mut self, room: Option<String>) {
+        self.room = room;
+    }
+
+    /// Stable identity of the participant, unlike name and address
+    pub fn get_identity(&self) -> &str {
+        &self.identity
+    }
+
+    pub fn set_identity(&mut self, identity: String) {
+        self.identity = identity;
+    }
+
+    /// Continues the participant that departed, its team is kept
+    pub fn resume_from(&mut self, departed: &Departed) {
+        self.team = departed.team.clone().or(self.team.take());
+        self.answered_state = departed.answered_state;
+        self.last_state = departed.last_state;
+    }
+
+    /// What has to be kept of the participant until it resumes
+    pub fn departed(&self, departed_at: i64) -> Departed {
+        Departed {
+            name: self.name.clone(),
+            address: self.address,
+            team: self.team.clone(),
+            answered_state: self.answered_state,
+            last_state: self.last_state,
+            departed_at,
+        }
+    }
+
+    /// Takes over the identity and the answered state of the redeemed rejoin token
+    pub fn restore(&mut self, grant: RejoinGrant) {
+        self.name = grant.name;
+        self.role = grant.role;
+        self.team = grant.team;
+        self.guest = grant.guest;
+        self.answered_state = grant.answered_state;
+        self.last_state = grant.last_state;
+    }
+
+    /// Identity a rejoin token has to restore, valid until 'expires_at'
+    pub fn rejoin_grant(&self, expires_at: i64) -> RejoinGrant {
+        RejoinGrant {
+            name: self.name.clone(),
+            role: self.role.clone(),
+            team: self.team.clone(),
+            guest: self.guest,
+            answered_state: self.answered_state,
+            last_state: self.last_state,
+            expires_at,
+        }
+    }
+
+    /// Returns the bookkeeping of the messages still waiting to be written to the client
+    pub fn get_queue_stats(&self) -> &QueueStats {
+        &self.queue_stats
+    }
+
+    /// Remembers the input id within the sliding window of this client
+    /// Returns false if the id is already known (the input was retransmitted)
+    pub fn register_input_id(&mut self, input_id: &str) -> bool {
+        self.recent_input_ids.register(input_id)
+    }
+
+    /// Remembers that an input of this client was forwarded for the given state
+    pub fn register_answer(&mut self, state_id: i32) {
+        self.answered_state = Some(state_id);
+        self.last_state = Some(state_id);
+    }
+
+    /// Whether an input of this client was forwarded for the given state
+    pub fn has_answered(&self, state_id: i32) -> bool {
+        self.answered_state == Some(state_id)
+    }
+
+    /// Id of the latest state the client has seen, if any
+    pub fn get_last_state(&self) -> Option<i32> {
+        self.last_state
+    }
+
+    pub fn get_bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    pub fn get_codec(&self) -> &'static dyn WireCodec {
+        self.codec
+    }
+
+    /// Counters of the received messages, shared with the reader task
+    pub fn get_traffic_stats(&self) -> &Arc<TrafficStats> {
+        &self.traffic
+    }
+
+    /// Message and byte counters of both directions
+    pub fn stats(&self) -> ClientStats {
+        ClientStats {
+            name: self.name.clone(),
+            address: self.get_address_as_str(),
+            messages_sent: self.messages_sent,
+            bytes_sent: self.bytes_sent,
+            messages_received: self.traffic.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.traffic.bytes_received.load(Ordering::Relaxed),
+            last_activity: self.traffic.last_activity.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Muted clients keep receiving updates, but their inputs are dropped
+    /// Set by the main handler, which keeps the muted identities across reconnects
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Quality of the link measured by the pings so far
+    pub fn connection_quality(&self) -> BackendMessage {
+        BackendMessage::ConnectionQuality {
+            rtt_ms: self.traffic.rtt(),
+            missed_pings: self.traffic.missed_pings.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Enqueues a websocket ping, a still unanswered previous ping counts as missed
+    /// Browsers answer pings on their own, the reader task measures the round trip time
+    pub fn ping(&mut self, now: i64) {
+        self.traffic.pinged(now);
+        if self.queue.send(Outbound::Ping(now)).is_err() {
+            warn!("client_ping(..): Writer of client {} already stopped. Dropping ping!", self.address);
+        }
+    }
+
+    /// Enqueues the message for the writer task
+    /// Sending errors are reported by the writer task via 'ClientCloseConnection'
+    pub async fn send_message(&mut self, msg: StampedMessage) {
+        self.send_encoded(&mut EncodedMessage::new(msg)).await
+    }
+
+    /// Enqueues the message in the codec of the client, reusing its encoding by other clients
+    pub async fn send_encoded(&mut self, msg: &mut EncodedMessage) {
+        if let Some(state_id) = msg.state_id() {
+            self.last_state = Some(state_id);
+        }
+        let frame = msg.frame(self.codec);
+        self.bytes_sent += frame.len() as u64;
+        self.messages_sent += 1;
+        self.queue_stats.push(frame.len());
+        if self.queue.send(Outbound::Message(frame)).is_err() {
+            self.queue_stats.pop();
+            warn!("client_send_message(..): Writer of client {} already stopped. Dropping message!", self.address);
+        }
+    }
+
+    /// Lets the writer task send the remaining messages and close the connection afterwards
+    /// 'reconnect' is passed on with 'Disconnecting', None if the client should not reconnect
+    pub async fn close(self, reason: &str, reconnect: Option<ReconnectHint>) {
+        if self.queue.send(Outbound::Close(String::from(reason), reconnect)).is_err() {
+            info!("client_close(..): Writer of client {} already stopped", self.address);
+        }
+    }
+
+    /// Creates the connection and spawns its writer task
+    /// Writes taking longer than 'send_timeout' close the connection as stalled
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(name: String, role: Option<String>, team: Option<String>, guest: bool, address: SocketAddr, channel: Bus, write: WsWriteHalve, codec: &'static dyn WireCodec, send_timeout: Option<Duration>) -> Self {
+        let (queue, queue_rcv) = mpsc::unbounded_channel();
+        let queue_stats = Arc::new(QueueStats::default());
+        tokio::spawn(client_socket_writer(channel, write, address, queue_rcv, queue_stats.clone(), codec, send_timeout));
+        ClientConnection{ name, role, team, guest, guest_until: None, access_code: None, rejoin: None, resume: None, room: None, identity: String::new(), address, queue, queue_stats, codec, recent_input_ids: Default::default(), answered_state: None, last_state: None, muted: false, bytes_sent: 0, messages_sent: 0, traffic: Arc::new(TrafficStats::new(current_timestamp())) }
+    }
+}
+
+/// Messages received from one client, updated by its reader task
+#[derive(Debug)]
+pub struct TrafficStats {
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Server timestamp of the last received message, or of the login
+    last_activity: AtomicI64,
+    /// Server timestamp of the unanswered ping, 0 if every ping was answered
+    ping_sent_at: AtomicI64,
+    /// Round trip time of the latest answered ping in milliseconds, -1 before the first one
+    rtt: AtomicI64,
+    /// Pings in a row the client did not answer before the next one
+    missed_pings: AtomicU64,
+    /// Messages dropped since the last report because they were no (json) text
+    malformed: AtomicU64,
+    /// Inputs dropped since the last report because the client was muted or the session paused
+    rejected_inputs: AtomicU64,
+    /// Json messages since the last report that are no valid or expected client message
+    violations: AtomicU64,
+}
+
+impl TrafficStats {
+    fn new(now: i64) -> Self {
+        TrafficStats {
+            messages_received: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            last_activity: AtomicI64::new(now),
+            ping_sent_at: AtomicI64::new(0),
+            rtt: AtomicI64::new(-1),
+            missed_pings: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+            rejected_inputs: AtomicU64::new(0),
+            violations: AtomicU64::new(0),
+        }
+    }
+
+    fn pinged(&self, now: i64) {
+        if self.ping_sent_at.swap(now, Ordering::Relaxed) != 0 {
+            self.missed_pings.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes the round trip time from the pong, pongs of older pings (or unsolicited ones) are ignored
+    pub fn ponged(&self, payload: &[u8], now: i64) {
+        let sent_at = match <[u8; 8]>::try_from(payload) {
+            Ok(v) => i64::from_be_bytes(v),
+            Err(_) => return,
+        };
+        if sent_at != 0 && self.ping_sent_at.compare_exchange(sent_at, 0, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.rtt.store(now - sent_at, Ordering::Relaxed);
+            self.missed_pings.store(0, Ordering::Relaxed);
         }
     }
 
-    pub async fn close(self, reason: &str) {
-        client_close_connection(self.write, self.address, reason).await
+    fn rtt(&self) -> Option<i64> {
+        Some(self.rtt.load(Ordering::Relaxed)).filter(|rtt| *rtt >= 0)
+    }
+
+    /// Counts every received message, also the ones dropped as malformed
+    pub fn received(&self, bytes: usize, now: i64) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_activity.store(now, Ordering::Relaxed);
     }
 
-    pub fn new(name: String, address: SocketAddr, channel: Sender<InternalMessage>, write: WsWriteHalve) -> Self {
-        ClientConnection{ name, address, channel, write }
+    pub fn malformed(&self) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected_input(&self) {
+        self.rejected_inputs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn violation(&self) {
+        self.violations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the issues since the last report, None if there were none
+    pub fn take_issues(&self, name: &str, address: String) -> Option<ClientIssues> {
+        let issues = ClientIssues {
+            name: String::from(name),
+            address,
+            malformed: self.malformed.swap(0, Ordering::Relaxed),
+            rejected_inputs: self.rejected_inputs.swap(0, Ordering::Relaxed),
+            violations: self.violations.swap(0, Ordering::Relaxed),
+        };
+        Some(issues).filter(|issues| issues.malformed + issues.rejected_inputs + issues.violations > 0)
+    }
+}
+
+/// Traffic of one client dropped since the last report, sent to the host in 'ClientIssues'
+#[derive(Debug, Clone)]
+pub struct ClientIssues {
+    pub name: String,
+    pub address: String,
+    pub malformed: u64,
+    pub rejected_inputs: u64,
+    pub violations: u64,
+}
+
+/// Snapshot of the counters of one client, sent to the host in 'ClientList' and 'ClientListDiff'
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientStats {
+    pub name: String,
+    pub address: String,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub last_activity: i64,
+}
+
+/// Item of an outbound queue, processed in order by the writer task
+#[derive(Debug)]
+pub enum Outbound {
+    Message(WireFrame),
+    /// Websocket ping carrying the server timestamp it was sent at
+    Ping(i64),
+    Close(String, Option<ReconnectHint>),
+}
+
+/// Input ids of the latest INPUT_ID_WINDOW inputs of a client, oldest first
+#[derive(Debug, Default)]
+pub struct InputIdWindow {
+    ids: VecDeque<String>,
+}
+
+impl InputIdWindow {
+    /// Remembers the id, evicting the oldest one if the window is full
+    /// Returns false if the id is already known
+    pub fn register(&mut self, input_id: &str) -> bool {
+        if self.ids.iter().any(|id| id == input_id) {
+            return false
+        }
+        if self.ids.len() == INPUT_ID_WINDOW {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(String::from(input_id));
+        true
+    }
+}
+
+/// Sizes (in bytes) of the messages waiting in an outbound queue, in queue order
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    sizes: Mutex<VecDeque<usize>>,
+}
+
+impl QueueStats {
+    /// Number of messages waiting to be written
+    pub fn len(&self) -> usize {
+        self.sizes.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the largest message waiting to be written, 0 if the queue is empty
+    pub fn largest(&self) -> usize {
+        self.sizes.lock().unwrap().iter().copied().max().unwrap_or(0)
+    }
+
+    /// Bytes of all messages waiting to be written
+    pub fn bytes(&self) -> usize {
+        self.sizes.lock().unwrap().iter().sum()
+    }
+
+    fn push(&self, size: usize) {
+        self.sizes.lock().unwrap().push_back(size);
+    }
+
+    fn pop(&self) {
+        self.sizes.lock().unwrap().pop_front();
     }
 }
 
 /// Useful functions to interact with clients connected via websocket
 pub mod websockets {
     use std::net::SocketAddr;
-    #[cfg(not(feature = "insecure_ws"))]
     use std::sync::Arc;
+    use std::time::Duration;
     use futures_util::stream::{SplitSink, SplitStream};
     use futures_util::{SinkExt, StreamExt};
     use log::{error, info, warn};
-    #[cfg(not(feature = "insecure_ws"))]
-    use tokio::fs::File;
-    #[cfg(not(feature = "insecure_ws"))]
-    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
     use tokio::net::{TcpListener, TcpStream};
-    use tokio::sync::mpsc::Sender;
-    #[cfg(not(feature = "insecure_ws"))]
-    use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+    use tokio::sync::mpsc::UnboundedReceiver;
     use tokio_tungstenite::tungstenite::{Error, Message};
-    use tokio_tungstenite::WebSocketStream;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
     use crate::server::InternalMessage;
-    use crate::server::messages::{BackendMessage, ClientMessage, encode_backend_msg, parse_client_msg};
-    use crate::server::networking::{ClientConnection, DISCONNECT_REASON_CLI_CLOSED_FORCEFULLY, DISCONNECT_REASON_CLI_CLOSED_GRACEFULLY, DISCONNECT_REASON_VIOLATION};
+    use crate::server::access_code;
+    use crate::server::bus::Bus;
+    use crate::server::auth::{AuthDecision, AuthProvider, Credentials};
+    use crate::server::certificates::{load_acceptor, watch_certificate};
+    use crate::server::challenge::{LoginChallenge, REJECT_REASON_CHALLENGE_FAILED};
+    use crate::server::codec::{codec_by_name, CODEC_JSON, JSON, supported_codecs, WireCodec, WireFrame};
+    use crate::server::config::{SocketConfig, TlsConfig};
+    use crate::server::descriptors::{CAPACITY_REJECT_TIMEOUT, DescriptorGuard, REJECT_REASON_AT_CAPACITY};
+    use crate::server::handshake::{HandshakeFailure, HandshakeStats, PEEK_SIZE, PLAIN_HTTP_RESPONSE};
+    use crate::server::proxy::TrustedProxies;
+    use crate::server::reconnect::ReconnectHint;
+    use crate::server::messages::{BackendMessage, ClientMessage, current_timestamp, encode_backend_json, parse_client_json, parse_client_msg};
+    use crate::server::networking::{accept_exhausted, apply_socket_options, bind_tcp, ClientConnection, Listener, DISCONNECT_REASON_CLI_CLOSED_FORCEFULLY, DISCONNECT_REASON_CLI_CLOSED_GRACEFULLY, DISCONNECT_REASON_LOGIN_REJECTED, DISCONNECT_REASON_SEND_FAILED, DISCONNECT_REASON_VIOLATION, Outbound, QueueStats, send_failure_reason, TrafficStats, write_within};
 
     type WSStream = SplitStream<WebSocketStream<TcpStream>>;
 
-    #[cfg(not(feature = "insecure_ws"))]
-    pub type TcpOrTlsStream = tokio_native_tls::TlsStream<TcpStream>;
-    #[cfg(feature = "insecure_ws")]
-    pub type TcpOrTlsStream = TcpStream;
+    /// Client connection, plain if the server runs without TLS ('insecure_ws')
+    pub type TcpOrTlsStream = MaybeTlsStream<TcpStream>;
     pub type WsReadHalve = SplitStream<WebSocketStream<TcpOrTlsStream>>;
     pub type WsWriteHalve = SplitSink<WebSocketStream<TcpOrTlsStream>, Message>;
 
 
     /// Create a listener on the websocket port waiting for client connections
-    pub async fn create_client_listener(channel: Sender<InternalMessage>, ip: &str, port: u16) {
-        // Websocket address
-        let addr = (ip.to_owned()+":"+ &*port.to_string()).to_string();
-
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_client_listener(channel: Bus, auth: Arc<dyn AuthProvider>, challenge: Option<Arc<LoginChallenge>>, socket_config: SocketConfig, proxies: Arc<TrustedProxies>, handshakes: Arc<HandshakeStats>, descriptors: Arc<DescriptorGuard>, tls: TlsConfig, addr: SocketAddr) -> std::io::Result<Listener> {
         // TCP listener
-        let listener = TcpListener::bind(&addr).await.expect("create_client_listener(..): Creating tcp listener failed");
+        let listener = bind_tcp(addr).await?;
         info!("create_client_listener(..): Listening for clients on {}", addr);
 
-        // Spawn listener
-        tokio::spawn(listen(channel, listener));
+        // Spawn listener, restarted by the supervision if it ends
+        Listener::start(addr, listener, move |listener| listen(channel.clone(), auth.clone(), challenge.clone(), socket_config.clone(), proxies.clone(), handshakes.clone(), descriptors.clone(), tls.clone(), listener))
     }
 
-    #[cfg(not(feature = "insecure_ws"))]
-    async fn create_tls_acceptor() -> Arc<tokio_native_tls::TlsAcceptor> {
-        // TODO error handling
-        let mut cert_file = File::open("res/cert/cert.pem").await.unwrap();
-        let mut cert_data = vec![];
-        let x = cert_file.read_to_end(&mut cert_data).await.unwrap();
-        info!("create_tls_acceptor(..): reading cert successful, {} bytes", x);
-
-        let mut key_file = File::open("res/cert/key.pem").await.unwrap();
-        let mut key_data = vec![];
-        let x = key_file.read_to_end(&mut key_data).await.unwrap();
-        info!("create_tls_acceptor(..): reading key successful, {} bytes", x);
-
-        let identity = Identity::from_pkcs8(&cert_data, &key_data).unwrap();
-
-        //let acceptor = TlsAcceptor::new(identity).unwrap();
-        let acceptor = tokio_native_tls::TlsAcceptor::from(TlsAcceptor::builder(identity).build().unwrap());
-
-        info!("worked!");
-
-        Arc::new(acceptor)
-    }
-
-    #[cfg(not(feature = "insecure_ws"))]
-    async fn listen(channel: Sender<InternalMessage>, listener: TcpListener) {
-        let tls_acceptor = create_tls_acceptor().await;
+    /// Waiting for incoming connections
+    /// Incoming connections are forwarded to upgrade and login the client, after the TLS handshake
+    /// unless the server runs without TLS ('insecure_ws')
+    /// Close to the descriptor limit clients are told that the server is at capacity instead
+    #[allow(clippy::too_many_arguments)]
+    async fn listen(channel: Bus, auth: Arc<dyn AuthProvider>, challenge: Option<Arc<LoginChallenge>>, socket_config: SocketConfig, proxies: Arc<TrustedProxies>, handshakes: Arc<HandshakeStats>, descriptors: Arc<DescriptorGuard>, tls: TlsConfig, listener: TcpListener) {
+        // The certificate is not read without TLS, it may be missing
+        let tls_acceptor = if tls.insecure_ws {
+            warn!("listen(..): Accepting plain websocket connections without TLS");
+            None
+        } else {
+            let acceptor = match load_acceptor(&tls).await {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("listen(..): Refusing clients until a usable certificate is found\nError: {}", e);
+                    None
+                }
+            };
+            Some(watch_certificate(tls, acceptor))
+        };
 
         // Listen forever
         loop {
             let (stream, address) = match listener.accept().await {
                 Ok(v) => v,
+                Err(e) if accept_exhausted(&e) => {
+                    error!("listen(..): Could not accept connection, stopping until restarted\nError: {}", e);
+                    return
+                }
                 Err(e) => {
                     warn!("listen(..): Could not accept connection\nError: {}", e);
                     continue
                 },
             };
-
-            let tls_acceptor = tls_acceptor.clone();
-            let x = match tls_acceptor.accept(stream).await {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!("listen(..): Could not accept TLS connection\nError: {}", e);
+            apply_socket_options(&stream, &socket_config, false, address);
+            let send_timeout = socket_config.send_timeout;
+
+            // Handshake and login in an own task, so slow clients (or a slow AuthProvider) don't
+            // block the accept loop
+            // Connections keep the certificate they were accepted with
+            let tls_acceptor = match tls_acceptor.as_ref().map(|acceptor| acceptor.borrow().clone()) {
+                Some(None) => {
+                    warn!("listen(..): Refusing client {}, there is no usable certificate", address);
                     continue
-                },
+                }
+                acceptor => acceptor.flatten(),
             };
-
-            client_connecting(channel.clone(), x, address).await;
+            let reconnect = if descriptors.admit() { None } else { Some(descriptors.get_reconnect()) };
+            let channel = channel.clone();
+            let auth = auth.clone();
+            let challenge = challenge.clone();
+            let proxies = proxies.clone();
+            let handshakes = handshakes.clone();
+            tokio::spawn(async move {
+                let stream = match tls_acceptor {
+                    None => MaybeTlsStream::Plain(stream),
+                    Some(tls_acceptor) => match tls_handshake(&tls_acceptor, stream, address, &handshakes).await {
+                        None => return,
+                        Some(v) => MaybeTlsStream::NativeTls(v),
+                    },
+                };
+                if let Some(reconnect) = reconnect {
+                    client_reject_at_capacity(stream, address, reconnect, send_timeout).await;
+                    return
+                }
+                client_connecting(channel, auth, challenge, proxies, stream, address, send_timeout).await;
+            });
         }
     }
 
-    /// Waiting for incoming connections
-    /// Incoming connections are forwarded to upgrade and login the client
-    #[cfg(feature = "insecure_ws")]
-    async fn listen(channel: Sender<InternalMessage>, listener: TcpListener) {
-        // TODO nice terminate
-
-        // Listen forever
-        loop {
-            // Get next client
-            let (stream, address) = match listener.accept().await {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!("listen(..): Could not accept connection\nError: {}", e);
-                    continue
-                },
-            };
-
-            // Forward client for socket upgrade and login
-            info!("listen(..): Client {} accepted", address);
-            client_connecting(channel.clone(), stream, address).await;
+    /// Accepts the TLS connection, failed handshakes are classified in 'handshakes'
+    /// Plain HTTP requests are answered with a hint to use TLS
+    async fn tls_handshake(tls_acceptor: &tokio_native_tls::TlsAcceptor, mut stream: TcpStream, address: SocketAddr, handshakes: &HandshakeStats) -> Option<tokio_native_tls::TlsStream<TcpStream>> {
+        let mut first = [0u8; PEEK_SIZE];
+        let peeked = stream.peek(&mut first).await.unwrap_or(0);
+        if let Some(failure) = HandshakeFailure::from_peek(&first[..peeked]) {
+            warn!("tls_handshake(..): Connection by {} is no TLS handshake ({})", address, failure.as_str());
+            handshakes.failed(address, failure, String::from_utf8_lossy(&first[..peeked]).into_owned());
+            if failure == HandshakeFailure::PlainHttp {
+                let _ = stream.write_all(PLAIN_HTTP_RESPONSE.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+            return None
         }
+        match tls_acceptor.accept(stream).await {
+            Ok(v) => {
+                handshakes.succeeded();
+                Some(v)
+            }
+            Err(e) => {
+                let failure = HandshakeFailure::from_error(&e.to_string());
+                warn!("tls_handshake(..): Could not accept TLS connection by {} ({})\nError: {}", address, failure.as_str(), e);
+                handshakes.failed(address, failure, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Upgrades the connection and closes it right away with 'Disconnecting' and the reconnect hint
+    async fn client_reject_at_capacity(stream: TcpOrTlsStream, address: SocketAddr, reconnect: ReconnectHint, send_timeout: Option<Duration>) {
+        warn!("client_reject_at_capacity(..): Rejecting client {}, the server is at capacity", address);
+        let ws_stream = match tokio::time::timeout(CAPACITY_REJECT_TIMEOUT, tokio_tungstenite::accept_async(stream)).await {
+            Ok(Ok(v)) => v,
+            Ok(Err(e)) => {
+                info!("client_reject_at_capacity(..): Websocket handshake with client {} failed
+Error: {:?}", address, e);
+                return
+            }
+            Err(_) => return,
+        };
+        let (ws_write, _) = ws_stream.split();
+        client_close_connection(ws_write, address, REJECT_REASON_AT_CAPACITY, Some(reconnect), &JSON, send_timeout).await;
     }
 
     /// Upgrade client connection and login
     /// First upgrades the connection to websocket
     /// Then waits for a 'ClientLogin' message, all messages before will be dropped (except Disconnect)
+    /// If a challenge is configured, it is sent after the upgrade and its proof checked first
+    /// The login is checked by the AuthProvider, rejected logins are answered with 'LoginRejected'
     /// Once the login is successful triggers the 'ClientConnected' event
-    async fn client_connecting(channel: Sender<InternalMessage>, stream: TcpOrTlsStream, address: SocketAddr) {
+    /// Clients connecting through a trusted proxy are identified by their forwarded address
+    /// The access code may be given by the path ('/session/<code>') instead of the login
+    async fn client_connecting(channel: Bus, auth: Arc<dyn AuthProvider>, challenge: Option<Arc<LoginChallenge>>, proxies: Arc<TrustedProxies>, stream: TcpOrTlsStream, address: SocketAddr, send_timeout: Option<Duration>) {
         info!("client_connecting(..): Client {} connected", address);
 
-        // Upgrade to websocket
-        let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+        // Upgrade to websocket, keeping the forwarding headers and the path of the handshake
+        let mut forwarded = (None, None);
+        let mut path = String::new();
+        // The error type is given by tungstenite
+        #[allow(clippy::result_large_err)]
+        let callback = |request: &Request, response: Response| {
+            let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+            forwarded = (header("forwarded"), header("x-forwarded-for"));
+            path = String::from(request.uri().path());
+            Ok(response)
+        };
+        let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
             Ok(v) => v,
             Err(e) => {
                 error!("client_connecting(..): Websocket handshake failed\nclient: {}\nmsg: {:?}", address, e);
                 return
             }
         };
-        let (ws_write, mut ws_read) = ws_stream.split();
+        let (mut ws_write, mut ws_read) = ws_stream.split();
         info!("client_connecting(..): Client {} upgraded to websocket", address);
 
+        let peer = address;
+        let address = proxies.real_address(peer, forwarded.0.as_deref(), forwarded.1.as_deref());
+        if address != peer {
+            info!("client_connecting(..): Client {} connected through proxy {}", address, peer);
+        } else if forwarded.0.is_some() || forwarded.1.is_some() {
+            warn!("client_connecting(..): Ignoring forwarding headers of client {}, it is no trusted proxy", peer);
+        }
+
+        let path_code = access_code::from_path(&path);
+        if path_code.is_none() && path.starts_with(access_code::SESSION_PATH_PREFIX) {
+            warn!("client_connecting(..): Client {} connected to invalid session path '{}'", address, path);
+        }
+
+        // Challenge to solve before the login is accepted
+        let issued = challenge.as_ref().map(|challenge| challenge.issue(address));
+        if let Some(issued) = issued.clone() {
+            if let Err(e) = client_send_message(&mut ws_write, BackendMessage::Challenge {challenge: issued}, &JSON, send_timeout).await {
+                warn!("client_connecting(..): Sending 'Challenge' to client {} failed!\nError: {:?}", address, e);
+            }
+        }
+
         // Waiting for login
         loop {
             // Get next message
-            let tmp_msg = match client_get_next_json(&mut ws_read, address).await {
+            let tmp_msg = match client_get_next_json(&mut ws_read, address, &JSON, None).await {
                 None => {
                     error!("client_connecting(..): Client {} closed connection. Closing connection.", address);
-                    client_close_connection(ws_write, address, DISCONNECT_REASON_CLI_CLOSED_FORCEFULLY).await;
+                    client_close_connection(ws_write, address, DISCONNECT_REASON_CLI_CLOSED_FORCEFULLY, None, &JSON, send_timeout).await;
                     return
                 }
                 Some(v) => v
             };
 
             match tmp_msg {
-                ClientMessage::ClientLogin {name} => {
+                ClientMessage::ClientLogin {name, token, team, proof, codec, code, rejoin, resume, room} => {
                     info!("client_connecting(..): Client {} sent 'ClientLogin'.", address);
-                    let client = ClientConnection::new(name, address, channel.clone(), ws_write);
+                    let codec = match codec_by_name(codec.as_deref().unwrap_or(CODEC_JSON)) {
+                        Some(v) => v,
+                        None => {
+                            info!("client_connecting(..): Client {} requested unsupported codec {:?}. Closing connection!", address, codec);
+                            let reason = format!("Unsupported codec '{}', supported are: {}", codec.unwrap_or_default(), supported_codecs());
+                            if let Err(e) = client_send_message(&mut ws_write, BackendMessage::LoginRejected {reason}, &JSON, send_timeout).await {
+                                warn!("client_connecting(..): Sending 'LoginRejected' to client {} failed!\nError: {:?}", address, e);
+                            }
+                            client_close_connection(ws_write, address, DISCONNECT_REASON_LOGIN_REJECTED, None, &JSON, send_timeout).await;
+                            return
+                        }
+                    };
+                    let solved = match (challenge.as_ref(), issued.as_ref(), proof.as_deref()) {
+                        (Some(_), Some(_), None) => {
+                            // Sent before the client saw the challenge, it logs in again with the proof
+                            info!("client_connecting(..): Login of client {} carries no proof, waiting for the solved challenge", address);
+                            continue
+                        }
+                        (Some(challenge), Some(issued), proof) => challenge.verify(issued, proof, address).await,
+                        _ => true,
+                    };
+                    if !solved {
+                        info!("client_connecting(..): Client {} did not solve the challenge. Closing connection!", address);
+                        if let Err(e) = client_send_message(&mut ws_write, BackendMessage::LoginRejected {reason: String::from(REJECT_REASON_CHALLENGE_FAILED)}, &JSON, send_timeout).await {
+                            warn!("client_connecting(..): Sending 'LoginRejected' to client {} failed!\nError: {:?}", address, e);
+                        }
+                        client_close_connection(ws_write, address, DISCONNECT_REASON_LOGIN_REJECTED, None, &JSON, send_timeout).await;
+                        return
+                    }
+                    let guest = token.is_none();
+                    let credentials = Credentials {name, token, address, host: false};
+                    // The rejoin token replaces the credentials, the main handler redeems it
+                    let decision = match rejoin {
+                        Some(_) => AuthDecision::Allow {name: None, role: None},
+                        None => auth.authenticate(&credentials).await,
+                    };
+                    let (name, role) = match decision {
+                        AuthDecision::Allow {name, role} => (name.unwrap_or(credentials.name), role),
+                        AuthDecision::Deny {reason} => {
+                            info!("client_connecting(..): Login of client {} rejected. Closing connection!\nReason: {}", address, reason);
+                            if let Err(e) = client_send_message(&mut ws_write, BackendMessage::LoginRejected {reason}, &JSON, send_timeout).await {
+                                warn!("client_connecting(..): Sending 'LoginRejected' to client {} failed!\nError: {:?}", address, e);
+                            }
+                            client_close_connection(ws_write, address, DISCONNECT_REASON_LOGIN_REJECTED, None, &JSON, send_timeout).await;
+                            return
+                        }
+                    };
+                    let mut client = ClientConnection::new(name, role, team, guest, address, channel.clone(), ws_write, codec, send_timeout);
+                    client.set_access_code(code.or_else(|| path_code.clone()));
+                    client.set_rejoin(rejoin);
+                    client.set_resume(resume);
+                    client.set_room(room);
                     channel.send(InternalMessage::ClientConnected{read: ws_read, client}).await.expect("client_connecting(..): Sending internal message failed!");
                     return
                 }
                 ClientMessage::Disconnect {reason} => {
                     info!("client_connecting(..): Client {} send 'Disconnecting'. Closing connection!\nReason: {}", address, reason);
-                    client_close_connection(ws_write, address, DISCONNECT_REASON_CLI_CLOSED_GRACEFULLY).await;
+                    client_close_connection(ws_write, address, DISCONNECT_REASON_CLI_CLOSED_GRACEFULLY, None, &JSON, send_timeout).await;
                     return
                 }
                 _ => {
@@ -270,9 +1192,10 @@ pub mod websockets {
         }
     }
 
-    /// Returns the next parsable json message
-    /// Will drop non-text or malformed messages
-    pub async fn client_get_next_json(reader: &mut WsReadHalve, address: SocketAddr) -> Option<ClientMessage> {
+    /// Returns the next parsable message, text is json and binary is decoded with 'codec'
+    /// Will drop malformed messages and binary messages of json connections
+    /// Every message received is counted in 'stats', if given
+    pub async fn client_get_next_json(reader: &mut WsReadHalve, address: SocketAddr, codec: &dyn WireCodec, stats: Option<&TrafficStats>) -> Option<ClientMessage> {
         // TODO find out how closed behaviour and return None
         loop {
             // Get next message
@@ -288,16 +1211,58 @@ pub mod websockets {
                 }
             };
 
+            if let Some(stats) = stats {
+                stats.received(msg.len(), current_timestamp());
+                if let Message::Pong(payload) = &msg {
+                    stats.ponged(payload, current_timestamp());
+                    continue
+                }
+            }
+
+            // Binary messages in the codec of the connection
+            if msg.is_binary() && codec.name() != CODEC_JSON {
+                let parsed = match codec.decode(&msg.into_data()) {
+                    None => {
+                        error!("client_get_next_json(..): Message by client {} is no valid {}. Dropping!", address, codec.name());
+                        if let Some(stats) = stats {
+                            stats.malformed();
+                        }
+                        continue
+                    }
+                    Some(json) => parse_client_json(&json),
+                };
+                match parsed {
+                    None => {
+                        error!("client_get_next_json(..): Message by client {} is no valid client message. Dropping!", address);
+                        if let Some(stats) = stats {
+                            stats.violation();
+                        }
+                        continue
+                    }
+                    Some(v) => return Some(v),
+                }
+            }
+
             // Check if message is text
             if !msg.is_text() {
                 error!("client_get_next_json(..): Message by client {} is not text. Dropping!\nMessage: {}", address, msg);
+                if let Some(stats) = stats {
+                    stats.malformed();
+                }
                 continue
             }
 
             // Parse message
-            let parsed = match parse_client_msg(&msg.clone().into_text().unwrap()) {
+            let text = msg.clone().into_text().unwrap();
+            let parsed = match parse_client_msg(&text) {
                 None => {
                     error!("client_get_next_json(..): Message by client {} is no valid json. Dropping!\nMessage: {}", address, msg);
+                    if let Some(stats) = stats {
+                        match serde_json::from_str::<serde_json::Value>(&text) {
+                            Ok(_) => stats.violation(),
+                            Err(_) => stats.malformed(),
+                        }
+                    }
                     continue
                 }
                 Some(v) => v
@@ -308,15 +1273,15 @@ pub mod websockets {
     }
 
     /// Closes the connection, ignoring possible errors
-    pub async fn client_close_connection(mut writer: WsWriteHalve, address: SocketAddr, reason: &str) {
+    pub async fn client_close_connection(mut writer: WsWriteHalve, address: SocketAddr, reason: &str, reconnect: Option<ReconnectHint>, codec: &dyn WireCodec, send_timeout: Option<Duration>) {
         let reason = String::from(reason);
-        match client_send_message(&mut writer, BackendMessage::Disconnect {reason}).await {
+        match client_send_message(&mut writer, BackendMessage::Disconnect {reason, reconnect}, codec, send_timeout).await {
             Ok(_) => {}
             Err(e) => {
                 warn!("client_close_connection(..): Sending 'Disconnecting' to client {} failed!\nError: {:?}", address, e);
             }
         };
-        match writer.close().await {
+        match write_within(send_timeout, writer.close()).await {
             Ok(_) => {}
             Err(e) => {
                 error!("client_close_connection(..): Closing connection to client {} failed!\nError: {:?}", address, e);
@@ -325,21 +1290,64 @@ pub mod websockets {
     }
 
     /// Send the BackendMessage to the client (connected to the given websocket)
-    /// Transforms the BackendMessage to the correct format.
-    /// Forwards any sending errors
-    pub async fn client_send_message(writer: &mut WsWriteHalve, msg_enum: BackendMessage) -> Result<(), Error> {
-        let msg_str = encode_backend_msg(msg_enum);
-        let msg = Message::from(msg_str);
-        writer.send(msg).await
+    /// Transforms the BackendMessage to the format of the codec.
+    /// Forwards any sending errors, a send exceeding the timeout fails with 'TimedOut'
+    pub async fn client_send_message(writer: &mut WsWriteHalve, msg_enum: BackendMessage, codec: &dyn WireCodec, send_timeout: Option<Duration>) -> Result<(), Error> {
+        let msg = frame_message(codec.encode(&encode_backend_json(msg_enum)));
+        write_within(send_timeout, writer.send(msg)).await
+    }
+
+    fn frame_message(frame: WireFrame) -> Message {
+        match frame {
+            WireFrame::Text(text) => Message::Text(text),
+            WireFrame::Binary(bytes) => Message::Binary(bytes),
+        }
+    }
+
+    /// Disconnect reason for a failed send, a send that timed out stalled the connection
+    fn client_send_failure_reason(e: &Error) -> &'static str {
+        match e {
+            Error::Io(e) => send_failure_reason(e),
+            _ => DISCONNECT_REASON_SEND_FAILED,
+        }
+    }
+
+    /// Writes all messages of the outbound queue to the given socket
+    /// A failed (or stalled) write triggers the 'ClientCloseConnection' event and stops the writer
+    pub async fn client_socket_writer(channel: Bus, mut writer: WsWriteHalve, address: SocketAddr, mut queue: UnboundedReceiver<Outbound>, stats: Arc<QueueStats>, codec: &'static dyn WireCodec, send_timeout: Option<Duration>) {
+        while let Some(item) = queue.recv().await {
+            match item {
+                Outbound::Message(frame) => {
+                    let result = write_within(send_timeout, writer.send(frame_message(frame))).await;
+                    stats.pop();
+                    if let Err(e) = result {
+                        warn!("client_socket_writer(..): Sending message to {} failed!\nError: {:?}", address, e);
+                        channel.send(InternalMessage::ClientCloseConnection {address, reason: client_send_failure_reason(&e)}).await.expect("client_socket_writer(..): Sending internal message failed");
+                        return
+                    }
+                }
+                Outbound::Ping(sent_at) => {
+                    if let Err(e) = write_within(send_timeout, writer.send(Message::Ping(sent_at.to_be_bytes().to_vec()))).await {
+                        warn!("client_socket_writer(..): Sending ping to {} failed!\nError: {:?}", address, e);
+                        channel.send(InternalMessage::ClientCloseConnection {address, reason: client_send_failure_reason(&e)}).await.expect("client_socket_writer(..): Sending internal message failed");
+                        return
+                    }
+                }
+                Outbound::Close(reason, reconnect) => {
+                    client_close_connection(writer, address, &reason, reconnect, codec, send_timeout).await;
+                    return
+                }
+            }
+        }
     }
 
     /// Reads all messages from the given socket
     /// Each valid message triggers the according event
-    pub async fn client_socket_reader(channel: Sender<InternalMessage>, mut reader: WsReadHalve, address: SocketAddr) {
+    pub async fn client_socket_reader(channel: Bus, mut reader: WsReadHalve, address: SocketAddr, codec: &'static dyn WireCodec, stats: Arc<TrafficStats>) {
         // Read forever (until closed by client)
         loop {
             // Get next message
-            let msg = match client_get_next_json(&mut reader, address).await {
+            let msg = match client_get_next_json(&mut reader, address, codec, Some(&stats)).await {
                 None => {
                     warn!("client_socket_reader(..): Client {} closed the connection. Closing connection.", address);
                     channel.send(InternalMessage::ClientCloseConnection {address, reason: DISCONNECT_REASON_CLI_CLOSED_FORCEFULLY}).await.expect("websocket_listen(..): Sending internal message failed!");
@@ -351,6 +1359,7 @@ pub mod websockets {
             match msg {
                 ClientMessage::ClientLogin { .. } => {
                     error!("client_socket_reader(..): Received unexpected 'ClientLogin' from {}. Closing connection!", address);
+                    stats.violation();
                     channel.send(InternalMessage::ClientCloseConnection {address, reason: DISCONNECT_REASON_VIOLATION }).await.expect("client_socket_reader(..): Sending internal message failed!");
                     return;
                 }
@@ -359,8 +1368,16 @@ pub mod websockets {
                     channel.send(InternalMessage::ClientCloseConnection {address, reason: DISCONNECT_REASON_CLI_CLOSED_GRACEFULLY}).await.expect("websocket_listen(..): Sending internal message failed!");
                     return;
                 }
-                ClientMessage::Input {state_id, content} => {
-                    channel.send(InternalMessage::ClientInput {state_id, address, content}).await.expect("client_socket_reader(..): Sending internal message failed");
+                ClientMessage::Input {state_id, content, client_ts, input_id} => {
+                    let server_ts = current_timestamp();
+                    channel.send(InternalMessage::ClientInput {state_id, address, content, client_ts, input_id, server_ts}).await.expect("client_socket_reader(..): Sending internal message failed");
+                }
+                ClientMessage::RequestResync => {
+                    info!("client_socket_reader(..): Client {} requested a resync", address);
+                    channel.send(InternalMessage::ClientRequestResync {address}).await.expect("client_socket_reader(..): Sending internal message failed");
+                }
+                ClientMessage::StateAck {state_id} => {
+                    channel.send(InternalMessage::ClientStateAck {address, state_id}).await.expect("client_socket_reader(..): Sending internal message failed");
                 }
             }
         }
@@ -371,33 +1388,47 @@ pub mod websockets {
 /// Useful functions to interact with hosts connected via tcp socket
 pub mod tcp_sockets {
     use std::io::Error;
-    use std::io::ErrorKind::ConnectionReset;
+    use std::io::ErrorKind::{InvalidData, UnexpectedEof};
     use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
     use log::{error, info, warn};
+    use openssl::ssl::{Ssl, SslAcceptor};
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
-    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-    use tokio::net::TcpListener;
-    use tokio::sync::mpsc::Sender;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::timeout;
+    use tokio_openssl::SslStream;
     use crate::server::InternalMessage;
+    use crate::server::auth::{AuthDecision, AuthProvider, Credentials};
+    use crate::server::bus::Bus;
+    use crate::server::compat::{encode_legacy, HostProtocol};
+    use crate::server::config::SocketConfig;
+    use crate::server::link::LINK_SAMPLE_MIN_BYTES;
     use crate::server::messages::{BackendMessage, encode_backend_msg, HostMessage, parse_host_msg};
-    use crate::server::networking::{DISCONNECT_REASON_HOST_CLOSED_FORCEFULLY, DISCONNECT_REASON_HOST_CLOSED_GRACEFULLY};
+    use crate::server::networking::{accept_exhausted, apply_socket_options, bind_tcp, HostReader, HostStream, HostWriter, Listener, DISCONNECT_REASON_HOST_CLOSED_FORCEFULLY, DISCONNECT_REASON_HOST_CLOSED_GRACEFULLY, DISCONNECT_REASON_VIOLATION, HOST_CHUNK_FIRST, HOST_CHUNK_LAST, HOST_CHUNK_MAGIC, HOST_FRAME_MAGIC, HOST_FRAME_TIMEOUT, HOST_MAX_FRAME_SIZE, write_within};
+    use crate::server::reconnect::ReconnectHint;
 
     /// Create a listener on the tcp port waiting for host(s) connection(s)
-    pub async fn create_host_listener(channel: Sender<InternalMessage>, ip: &str, port: u16) {
-        // TCP address
-        let addr = (ip.to_owned()+":"+ &*port.to_string()).to_string();
-
+    /// Connections on a shadow listener become shadow hosts
+    /// With an acceptor hosts connect over TLS and have to present a trusted certificate
+    pub async fn create_host_listener(channel: Bus, socket_config: SocketConfig, tls: Option<Arc<SslAcceptor>>, addr: SocketAddr, shadow: bool) -> std::io::Result<Listener> {
         // TCP listener
-        let listener = TcpListener::bind(&addr).await.expect("create_host_listener(..): Creating tcp listener failed");
-        info!("create_host_listener(..): Listening for host(s) on {}", addr);
+        let listener = bind_tcp(addr).await?;
+        if shadow {
+            info!("create_host_listener(..): Listening for shadow host(s) on {}", addr);
+        } else {
+            info!("create_host_listener(..): Listening for host(s) on {}", addr);
+        }
 
-        // Spawn listener
-        tokio::spawn(listen(channel, listener));
+        // Spawn listener, restarted by the supervision if it ends
+        Listener::start(addr, listener, move |listener| listen(channel.clone(), socket_config.clone(), tls.clone(), listener, shadow))
     }
 
     /// Waiting for incoming connections
-    /// Incoming connections trigger the 'HostConnected' event
-    async fn listen(channel: Sender<InternalMessage>, listener: TcpListener) {
+    /// Incoming connections trigger the 'HostConnected' event, after the TLS handshake if the host
+    /// ports speak TLS
+    async fn listen(channel: Bus, socket_config: SocketConfig, tls: Option<Arc<SslAcceptor>>, listener: TcpListener, shadow: bool) {
         // TODO nice terminate
 
         // Listen forever
@@ -405,46 +1436,157 @@ pub mod tcp_sockets {
             // Get next host
             let (stream, address) = match listener.accept().await {
                 Ok(v) => v,
+                Err(e) if accept_exhausted(&e) => {
+                    error!("listen(..): Could not accept connection, stopping until restarted\nError: {}", e);
+                    return
+                }
                 Err(e) => {
                     warn!("listen(..): Could not accept connection\nError: {}", e);
                     continue
                 },
             };
+            apply_socket_options(&stream, &socket_config, socket_config.host_nodelay, address);
 
-            // Trigger HostConnected Event
-            channel.send(InternalMessage::HostConnected{stream, address}).await.expect("listen(..): Sending internal message failed!");
+            let acceptor = match tls.as_ref() {
+                None => {
+                    // Trigger HostConnected Event
+                    channel.send(InternalMessage::HostConnected{stream: HostStream::from(stream), address, shadow}).await.expect("listen(..): Sending internal message failed!");
+                    continue
+                }
+                Some(v) => v.clone(),
+            };
+            // In an own task, so a stalling handshake doesn't block the accept loop
+            let channel = channel.clone();
+            tokio::spawn(async move {
+                if let Some(stream) = host_tls_handshake(&acceptor, stream, address).await {
+                    channel.send(InternalMessage::HostConnected{stream: HostStream::from(stream), address, shadow}).await.expect("listen(..): Sending internal message failed!");
+                }
+            });
         }
     }
 
+    /// Accepts the TLS connection of a host, which has to present a certificate issued by one of
+    /// the trusted CAs if there are any
+    /// Fails after HOST_FRAME_TIMEOUT
+    async fn host_tls_handshake(acceptor: &SslAcceptor, stream: TcpStream, address: SocketAddr) -> Option<SslStream<TcpStream>> {
+        let mut stream = match Ssl::new(acceptor.context()).and_then(|ssl| SslStream::new(ssl, stream)) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("host_tls_handshake(..): Creating the TLS session for {} failed
+Error: {}", address, e);
+                return None
+            }
+        };
+        match timeout(HOST_FRAME_TIMEOUT, Pin::new(&mut stream).accept()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!("host_tls_handshake(..): Refusing host {}, the TLS handshake failed
+Error: {}", address, e);
+                return None
+            }
+            Err(_) => {
+                warn!("host_tls_handshake(..): Refusing host {}, the TLS handshake timed out", address);
+                return None
+            }
+        }
+        match stream.ssl().peer_certificate() {
+            Some(certificate) => {
+                let subject = certificate.subject_name().entries().filter_map(|entry| entry.data().to_string().ok()).collect::<Vec<_>>().join(", ");
+                info!("host_tls_handshake(..): Host {} authenticated as '{}'", address, subject);
+            }
+            None => info!("host_tls_handshake(..): Host {} connected over TLS", address),
+        }
+        Some(stream)
+    }
+
+    /// Per connection state of the host framing
+    #[derive(Debug, Default)]
+    pub struct HostFraming {
+        /// Number of recoveries from a desynchronized stream
+        pub resyncs: u32,
+        /// Whether frames end with a CRC32 of their payload, negotiated by 'HostLogin'
+        pub checksum: bool,
+        /// Number of frames received so far, the index of the next frame
+        pub frames: u64,
+        /// Size of the latest frame and the time from its first to its last byte
+        pub last_frame: Option<(usize, Duration)>,
+        /// Chunks of the bulk message received so far
+        pub bulk: Option<Vec<u8>>,
+        /// Protocol of the host, detected from its first frame
+        pub protocol: Option<HostProtocol>,
+    }
+
+    /// Frame as read from the stream, its checksum is not verified yet
+    struct RawFrame {
+        payload: Vec<u8>,
+        checksum: Option<u32>,
+        /// Started with HOST_CHUNK_MAGIC
+        chunk: bool,
+    }
+
     /// Returns the next parsable json message
     /// Will drop malformed messages
-    pub async fn host_get_next_json(reader: &mut OwnedReadHalf, address: SocketAddr) -> Option<HostMessage> {
+    /// Returns None if a frame failed its checksum, it is dropped and its index is stored in
+    /// 'corrupted' (the caller may request a retransmission)
+    /// Chunks are collected until the last one completes the bulk message, a corrupted chunk
+    /// drops the whole bulk message (the host retransmits it from its first chunk)
+    /// Every recovery from a desynchronized stream increments the 'resyncs' of the framing
+    /// Fails with the disconnect reason if the connection is closed or a frame stalls for longer
+    /// than HOST_FRAME_TIMEOUT (the partial frame is abandoned)
+    pub async fn host_get_next_json(reader: &mut HostReader, address: SocketAddr, framing: &mut HostFraming, corrupted: &mut Option<u64>) -> Result<Option<HostMessage>, &'static str> {
         loop {
-            // Read length
-            let length = match reader.read_u32().await {
+            // Wait for the next frame, the host may stay silent for any time
+            let first = match reader.read_u8().await {
                 Ok(v) => v,
+                // The stream ended between two frames
+                Err(e) if e.kind() == UnexpectedEof => {
+                    info!("host_get_next_json(..): host {} closed connection", address);
+                    return Err(DISCONNECT_REASON_HOST_CLOSED_GRACEFULLY)
+                }
                 Err(e) => {
-                    if e.kind() == ConnectionReset {
-                        info!("host_get_next_json(..): host {} closed connection", address);
-                        return None
-                    }
-                    error!("host_get_next_json(..): read_u32 returned Err.\nHost: {}\nError: {}", address, e);
-                    continue;
+                    warn!("host_get_next_json(..): Reading from host {} failed, closing connection\nError: {}", address, e);
+                    return Err(DISCONNECT_REASON_HOST_CLOSED_FORCEFULLY)
                 }
             };
 
-            // Read json
-            let mut buf = vec![0; length as usize];
-            match reader.read_exact(&mut buf).await {
-                Ok(v) => assert_eq!(v, length as usize),
-                Err(e) => {
-                    if e.kind() == ConnectionReset {
-                        info!("host_get_next_json(..): host {} closed connection", address);
-                        return None
-                    }
-                    error!("host_get_next_json(..): read_exact returned Err.\nHost: {}\nError: {}", address, e);
+            // Once a frame started, the rest of it has to follow within HOST_FRAME_TIMEOUT
+            let started = Instant::now();
+            let frame_buf = match timeout(HOST_FRAME_TIMEOUT, host_read_frame(reader, address, first, framing)).await {
+                Ok(Ok(v)) => v,
+                // Malformed frame, the framing resynchronizes on the following bytes
+                Ok(Err(e)) if e.kind() == InvalidData => {
+                    error!("host_get_next_json(..): Reading frame returned Err.\nHost: {}\nError: {}", address, e);
                     continue;
                 }
+                // The stream ended or broke within a frame
+                Ok(Err(e)) => {
+                    warn!("host_get_next_json(..): Reading a frame of host {} failed, closing connection\nError: {}", address, e);
+                    return Err(DISCONNECT_REASON_HOST_CLOSED_FORCEFULLY)
+                }
+                Err(_) => {
+                    warn!("host_get_next_json(..): Host {} stalled within a frame for {:?}. Abandoning frame!", address, HOST_FRAME_TIMEOUT);
+                    return Err(DISCONNECT_REASON_VIOLATION)
+                }
+            };
+            let frame = framing.frames;
+            framing.frames += 1;
+            framing.last_frame = Some((frame_buf.payload.len(), started.elapsed()));
+
+            // Verify checksum
+            if frame_buf.checksum.is_some_and(|checksum| crc32(&frame_buf.payload) != checksum) {
+                warn!("host_get_next_json(..): Frame {} of host {} failed its checksum. Dropping!", frame, address);
+                if frame_buf.chunk {
+                    framing.bulk = None;
+                }
+                *corrupted = Some(frame);
+                return Ok(None)
+            }
+            let buf = match frame_buf.chunk {
+                false => frame_buf.payload,
+                true => match host_collect_chunk(frame_buf.payload, address, framing) {
+                    None => continue,
+                    Some(v) => v,
+                },
             };
 
             // Decoding bytes to utf-8 string
@@ -465,21 +1607,145 @@ pub mod tcp_sockets {
                 Some(v) => v
             };
 
-            return Some(host_message)
+            return Ok(Some(host_message))
+        }
+    }
+
+    /// Adds the chunk to the bulk message, returns the bulk message once its last chunk arrived
+    /// Chunks without a preceding first chunk (of a dropped bulk message) are skipped
+    fn host_collect_chunk(payload: Vec<u8>, address: SocketAddr, framing: &mut HostFraming) -> Option<Vec<u8>> {
+        let (flags, data) = match payload.split_first() {
+            None => {
+                warn!("host_collect_chunk(..): Host {} sent an empty chunk. Dropping!", address);
+                return None
+            }
+            Some(v) => v,
+        };
+        if flags & HOST_CHUNK_FIRST != 0 {
+            if framing.bulk.is_some() {
+                warn!("host_collect_chunk(..): Host {} started a bulk message before completing the previous one. Dropping the previous one!", address);
+            }
+            framing.bulk = Some(Vec::new());
+        }
+        let bulk = match framing.bulk.as_mut() {
+            None => {
+                info!("host_collect_chunk(..): Skipping chunk of a dropped bulk message of host {}", address);
+                return None
+            }
+            Some(v) => v,
+        };
+        bulk.extend_from_slice(data);
+        if bulk.len() > HOST_MAX_FRAME_SIZE as usize {
+            warn!("host_collect_chunk(..): Bulk message of host {} exceeds {} bytes. Dropping!", address, HOST_MAX_FRAME_SIZE);
+            framing.bulk = None;
+            return None
+        }
+        match flags & HOST_CHUNK_LAST != 0 {
+            true => framing.bulk.take(),
+            false => None,
+        }
+    }
+
+    /// Reads the remainder of a frame whose first byte was already read
+    /// If the frame does not start with HOST_FRAME_MAGIC (or HOST_CHUNK_MAGIC) or announces an
+    /// impossible length, the stream is scanned for the next magic
+    /// Returns the payload and its checksum, if checksums were negotiated
+    async fn host_read_frame(reader: &mut HostReader, address: SocketAddr, first: u8, framing: &mut HostFraming) -> Result<RawFrame, Error> {
+        // Read magic
+        let mut window = [first, 0, 0, 0];
+        reader.read_exact(&mut window[1..]).await?;
+        if *framing.protocol.get_or_insert_with(|| HostProtocol::detect(window)) == HostProtocol::Legacy {
+            return host_read_legacy_frame(reader, window).await
         }
+        let mut skipped = 0;
+        let length = loop {
+            while window != HOST_FRAME_MAGIC && window != HOST_CHUNK_MAGIC {
+                window.rotate_left(1);
+                window[3] = reader.read_u8().await?;
+                skipped += 1;
+            }
+
+            // Read length
+            let length = reader.read_u32().await?;
+            if length <= HOST_MAX_FRAME_SIZE {
+                break length
+            }
+
+            // The magic was part of the garbage, continue scanning behind it
+            window = length.to_be_bytes();
+            skipped += HOST_FRAME_MAGIC.len();
+        };
+
+        if skipped > 0 {
+            framing.resyncs += 1;
+            warn!("host_read_frame(..): Stream of host {} was out of sync, skipped {} bytes (resync #{})", address, skipped, framing.resyncs);
+        }
+
+        // Read json
+        let mut buf = vec![0; length as usize];
+        reader.read_exact(&mut buf).await?;
+
+        // Read checksum
+        let checksum = match framing.checksum {
+            true => Some(reader.read_u32().await?),
+            false => None,
+        };
+        Ok(RawFrame {payload: buf, checksum, chunk: window == HOST_CHUNK_MAGIC})
+    }
+
+    /// Reads the payload of a legacy frame, its length prefix was already read
+    /// Legacy frames have no magic, so a desynchronized stream can not be recovered
+    async fn host_read_legacy_frame(reader: &mut HostReader, length: [u8; 4]) -> Result<RawFrame, Error> {
+        let length = u32::from_be_bytes(length);
+        if length > HOST_MAX_FRAME_SIZE {
+            return Err(Error::new(InvalidData, format!("Legacy frame of {} bytes exceeds the maximum frame size", length)))
+        }
+        let mut buf = vec![0; length as usize];
+        reader.read_exact(&mut buf).await?;
+        Ok(RawFrame {payload: buf, checksum: None, chunk: false})
+    }
+
+    /// CRC-32 (IEEE 802.3), the variant of java.util.zip.CRC32 used by the HostApp
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
     }
 
     /// Send the BackendMessage to the host (connected to the given tcp socket)
     /// Transforms the BackendMessage to the correct format.
-    /// Forwards any sending errors
-    pub async fn host_send_message(write: &mut OwnedWriteHalf, msg: BackendMessage) -> Result<(), Error> {
-        // Encode BackendMessage to string
-        let str_msg = encode_backend_msg(msg);
+    /// Forwards any sending errors, a frame exceeding the timeout fails with 'TimedOut'
+    pub async fn host_send_message(write: &mut HostWriter, msg: BackendMessage, protocol: HostProtocol, send_timeout: Option<Duration>) -> Result<(), Error> {
+        let str_msg = match protocol {
+            HostProtocol::Current => encode_backend_msg(msg),
+            HostProtocol::Legacy => match encode_legacy(msg) {
+                None => return Ok(()),
+                Some(v) => v,
+            },
+        };
+        host_send_frame(write, &str_msg, protocol, send_timeout).await
+    }
 
+    /// Sends the encoded message as one frame, legacy frames have no magic
+    pub async fn host_send_frame(write: &mut HostWriter, str_msg: &str, protocol: HostProtocol, send_timeout: Option<Duration>) -> Result<(), Error> {
+        write_within(send_timeout, host_write_frame(write, str_msg, protocol)).await
+    }
+
+    async fn host_write_frame(write: &mut HostWriter, str_msg: &str, protocol: HostProtocol) -> Result<(), Error> {
         // Encode string message to utf-8 encoded bytes
         let bytes = str_msg.as_bytes();
         let length = bytes.len() as u32;
 
+        // Send magic
+        if protocol == HostProtocol::Current {
+            write.write_all(&HOST_FRAME_MAGIC).await?;
+        }
+
         // Send length
         match write.write_u32(length).await {
             Ok(_) => {}
@@ -491,25 +1757,75 @@ pub mod tcp_sockets {
             Ok(_) => {}
             Err(e) => return Err(e)
         };
-        Ok(())
+        // Pipes may buffer the frame
+        write.flush().await
     }
 
     /// Reads all messages from the given socket
-    /// Each valid message triggers the according event
-    pub async fn host_socket_reader(channel: Sender<InternalMessage>, mut reader: OwnedReadHalf, address: SocketAddr) {
+    /// Each valid message triggers the according event, the login is decided by 'auth' before any
+    /// later frame is read
+    pub async fn host_socket_reader(channel: Bus, mut reader: HostReader, address: SocketAddr, auth: Arc<dyn AuthProvider>) {
+        let mut framing = HostFraming::default();
         // Read forever (until closed by host)
         loop {
-            let msg = match host_get_next_json(&mut reader, address).await {
-                None => {
-                    warn!("host_socket_reader(..): Host {} closed the connection. Closing connection", address);
-                    channel.send(InternalMessage::HostCloseConnection {address, reason: DISCONNECT_REASON_HOST_CLOSED_FORCEFULLY}).await.expect("host_socket_reader(..): Sending internal message failed");
+            let reported_resyncs = framing.resyncs;
+            let detected = framing.protocol.is_some();
+            let mut corrupted = None;
+            let msg = host_get_next_json(&mut reader, address, &mut framing, &mut corrupted).await;
+
+            // The first frame tells the protocol, legacy hosts are logged in without an api key
+            if let Some(protocol) = framing.protocol.filter(|_| !detected) {
+                channel.send(InternalMessage::HostProtocolDetected {address, protocol}).await.expect("host_socket_reader(..): Sending internal message failed");
+                if protocol == HostProtocol::Legacy {
+                    info!("host_socket_reader(..): Host {} speaks the legacy protocol", address);
+                    let decision = authenticate_host(auth.as_ref(), address, None).await;
+                    channel.send(InternalMessage::HostLogin {address, api_key: None, decision, version: None, capabilities: None, room: None}).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+            }
+
+            // Let the host know that some of its frames were lost
+            if framing.resyncs != reported_resyncs {
+                channel.send(InternalMessage::HostResync {address, count: framing.resyncs}).await.expect("host_socket_reader(..): Sending internal message failed");
+            }
+            if let Some(frame) = corrupted {
+                channel.send(InternalMessage::HostRetransmit {address, frame}).await.expect("host_socket_reader(..): Sending internal message failed");
+            }
+            // Large frames tell how fast the uplink of the host is
+            if let Some((bytes, elapsed)) = framing.last_frame.take().filter(|(bytes, _)| *bytes >= LINK_SAMPLE_MIN_BYTES) {
+                channel.send(InternalMessage::HostLinkSample {address, bytes, elapsed}).await.expect("host_socket_reader(..): Sending internal message failed");
+            }
+
+            let msg = match msg {
+                Err(reason) => {
+                    warn!("host_socket_reader(..): Connection to host {} failed. Closing connection\nReason: {}", address, reason);
+                    channel.send(InternalMessage::HostCloseConnection {address, reason}).await.expect("host_socket_reader(..): Sending internal message failed");
                     break;
                 }
-                Some(v) => v
+                Ok(None) => continue,
+                Ok(Some(v)) => v
             };
 
+            // Without a 'HostLogin' first the host presented no credentials, a host waiting for its
+            // login is rejected then
+            let login = matches!(msg, HostMessage::HostLogin { .. });
+            if framing.frames == 1 && !login && framing.protocol != Some(HostProtocol::Legacy) {
+                let decision = authenticate_host(auth.as_ref(), address, None).await;
+                channel.send(InternalMessage::HostLogin { address, api_key: None, decision, version: None, capabilities: None, room: None }).await.expect("host_socket_reader(..): Sending internal message failed");
+            }
+
             // Handle HostMessage (send according event)
             match msg {
+                HostMessage::HostLogin { checksum, api_key, token, version, capabilities, room } if framing.frames == 1 => {
+                    info!("host_socket_reader(..): Host {} logged in, checksums: {}, version: {:?}", address, checksum, version);
+                    framing.checksum = checksum;
+                    let decision = authenticate_host(auth.as_ref(), address, token).await;
+                    channel.send(InternalMessage::HostLogin { address, api_key, decision, version, capabilities, room }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::HostLogin { .. } => {
+                    error!("host_socket_reader(..): Received unexpected 'HostLogin' from {}. Closing connection!", address);
+                    channel.send(InternalMessage::HostCloseConnection {address, reason: DISCONNECT_REASON_VIOLATION}).await.expect("host_socket_reader(..): Sending internal message failed");
+                    break;
+                }
                 HostMessage::Disconnect { reason } => {
                     info!("host_socket_reader(..): Host {} closed the connection. Closing connection\nReason: {}", address, reason);
                     channel.send(InternalMessage::HostCloseConnection {address, reason: DISCONNECT_REASON_HOST_CLOSED_GRACEFULLY}).await.expect("host_socket_reader(..): Sending internal message failed");
@@ -519,28 +1835,182 @@ pub mod tcp_sockets {
                     info!("host_socket_reader(..): Host {} send Update {}", address, content);
                     channel.send(InternalMessage::HostUpdate { state_id, address, content }).await.expect("host_socket_reader(..): Sending internal message failed");
                 }
-                HostMessage::ChangeState { state_id, content } => {
+                HostMessage::ChangeState { state_id, content, variants } => {
                     info!("host_socket_reader(..): Host {} send ChangeState {}", address, content);
-                    channel.send(InternalMessage::HostChangeState { state_id, address, content }).await.expect("host_socket_reader(..): Sending internal message failed");
+                    channel.send(InternalMessage::HostChangeState { state_id, address, content, variants }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ResendState { client_id } => {
+                    info!("host_socket_reader(..): Host {} requested resending the state to client {}", address, client_id);
+                    channel.send(InternalMessage::HostResendState { address, client_id }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::MuteClient { id, muted } => {
+                    info!("host_socket_reader(..): Host {} send MuteClient {} (muted: {})", address, id, muted);
+                    channel.send(InternalMessage::HostMuteClient { address, client_id: id, muted }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::SetRules { rules } => {
+                    info!("host_socket_reader(..): Host {} send {} rule(s)", address, rules.len());
+                    channel.send(InternalMessage::HostSetRules { address, rules }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::SetScoring { state_id, rule } => {
+                    info!("host_socket_reader(..): Host {} send scoring rule for state {}", address, state_id);
+                    channel.send(InternalMessage::HostSetScoring { address, state_id, rule }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ShowLeaderboard { count } => {
+                    info!("host_socket_reader(..): Host {} requested the top {} of the leaderboard", address, count);
+                    channel.send(InternalMessage::HostShowLeaderboard { address, count }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::StartTimer { id, duration } => {
+                    info!("host_socket_reader(..): Host {} started timer {} ({} ms)", address, id, duration);
+                    channel.send(InternalMessage::HostStartTimer { address, id, duration }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ScheduleMessage { id, at, interval, message } => {
+                    info!("host_socket_reader(..): Host {} scheduled message {} (at: {:?}, interval: {:?})", address, id, at, interval);
+                    channel.send(InternalMessage::HostScheduleMessage { address, id, at, interval, message }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::CancelScheduledMessage { id } => {
+                    info!("host_socket_reader(..): Host {} cancelled scheduled message {}", address, id);
+                    channel.send(InternalMessage::HostCancelScheduledMessage { address, id }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ListScheduledMessages => {
+                    channel.send(InternalMessage::HostListScheduledMessages { address }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::CancelTimer { id } => {
+                    info!("host_socket_reader(..): Host {} cancelled timer {}", address, id);
+                    channel.send(InternalMessage::HostCancelTimer { address, id }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::StartFromTemplate { name } => {
+                    info!("host_socket_reader(..): Host {} requested template {}", address, name);
+                    channel.send(InternalMessage::HostStartFromTemplate { address, name }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::SetPublic { public } => {
+                    info!("host_socket_reader(..): Host {} set the live view public: {}", address, public);
+                    channel.send(InternalMessage::HostSetPublic { address, public }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::PauseSession { message } => {
+                    info!("host_socket_reader(..): Host {} paused the session", address);
+                    channel.send(InternalMessage::HostPauseSession { address, message }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ResumeSession => {
+                    info!("host_socket_reader(..): Host {} resumed the session", address);
+                    channel.send(InternalMessage::HostResumeSession { address }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::StageState { index, content, assets } => {
+                    info!("host_socket_reader(..): Host {} staged state {} ({} bytes, {} asset(s))", address, index, content.len(), assets.len());
+                    channel.send(InternalMessage::HostStageState { address, index, content, assets }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ShowStaged { index } => {
+                    info!("host_socket_reader(..): Host {} requested staged state {}", address, index);
+                    channel.send(InternalMessage::HostShowStaged { address, index }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::PickRandomClients { count, filter } => {
+                    info!("host_socket_reader(..): Host {} requested {} random client(s)", address, count);
+                    channel.send(InternalMessage::HostPickRandomClients { address, count, filter }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::GetTeamSummary => {
+                    info!("host_socket_reader(..): Host {} requested the team summary", address);
+                    channel.send(InternalMessage::HostGetTeamSummary { address }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::ClientCommand { action, min_version } => {
+                    info!("host_socket_reader(..): Host {} send ClientCommand {} (min version: {:?})", address, action.as_str(), min_version);
+                    channel.send(InternalMessage::HostClientCommand { address, action, min_version }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::SyncClientList { version } => {
+                    channel.send(InternalMessage::HostSyncClientList { address, version }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::Subscribe { events } => {
+                    info!("host_socket_reader(..): Host {} subscribed to {:?}", address, events);
+                    channel.send(InternalMessage::HostSubscribe { address, events }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::GetClientList { page } => {
+                    info!("host_socket_reader(..): Host {} requested the client list (page: {:?})", address, page);
+                    channel.send(InternalMessage::HostGetClientList { address, page }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::LinkProbeAck { probe } => {
+                    channel.send(InternalMessage::HostLinkProbeAck { address, probe }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::RequestSnapshotExport => {
+                    info!("host_socket_reader(..): Host {} requested a snapshot export", address);
+                    channel.send(InternalMessage::HostRequestSnapshotExport { address }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::RotateAccessCode { code, announce } => {
+                    info!("host_socket_reader(..): Host {} rotates the access code", address);
+                    channel.send(InternalMessage::HostRotateAccessCode { address, code, announce }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::LoadPlugin { module } => {
+                    info!("host_socket_reader(..): Host {} loads a plugin ({} bytes)", address, module.len());
+                    channel.send(InternalMessage::HostLoadPlugin { address, module }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::UnloadPlugin => {
+                    info!("host_socket_reader(..): Host {} unloads the plugin", address);
+                    channel.send(InternalMessage::HostUnloadPlugin { address }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::GetIntegrity => {
+                    info!("host_socket_reader(..): Host {} requested the integrity report", address);
+                    channel.send(InternalMessage::HostGetIntegrity { address }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::GetAttendance { page } => {
+                    info!("host_socket_reader(..): Host {} requested the attendance (page: {:?})", address, page);
+                    channel.send(InternalMessage::HostGetAttendance { address, page }).await.expect("host_socket_reader(..): Sending internal message failed");
+                }
+                HostMessage::GetClientInputs { client_id, state_id, page } => {
+                    info!("host_socket_reader(..): Host {} requested inputs of client {} for state {} (page: {:?})", address, client_id, state_id, page);
+                    channel.send(InternalMessage::HostGetClientInputs { address, client_id, state_id, page }).await.expect("host_socket_reader(..): Sending internal message failed");
                 }
             }
         }
     }
 
+    /// Asks the provider whether the host may log in with the token
+    async fn authenticate_host(auth: &dyn AuthProvider, address: SocketAddr, token: Option<String>) -> AuthDecision {
+        let decision = auth.authenticate(&Credentials {name: String::new(), token, address, host: true}).await;
+        if let AuthDecision::Deny {reason} = &decision {
+            info!("authenticate_host(..): Login of host {} denied\nReason: {}", address, reason);
+        }
+        decision
+    }
+
     /// Closes the connection, ignoring possible errors
-    pub async fn host_close_connection(mut write: OwnedWriteHalf, address: SocketAddr, reason: &str) {
+    pub async fn host_close_connection(mut write: HostWriter, address: SocketAddr, reason: &str, reconnect: Option<ReconnectHint>, protocol: HostProtocol, send_timeout: Option<Duration>) {
         let reason = String::from(reason);
-        match host_send_message(&mut write, BackendMessage::Disconnect {reason}).await {
+        match host_send_message(&mut write, BackendMessage::Disconnect {reason, reconnect}, protocol, send_timeout).await {
             Ok(_) => {}
             Err(e) => {
                 warn!("host_close_connection(..): Sending 'Disconnecting' to host {} failed!\nError: {}", address, e);
             }
         };
-        match write.shutdown().await {
+        match write_within(send_timeout, write.shutdown()).await {
             Ok(_) => {}
             Err(e) => {
                 error!("host_close_connection(..): Closing connection to host {} failed!\nError: {}", address, e);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retransmitted_input_ids_are_dropped() {
+        let mut window = InputIdWindow::default();
+        assert!(window.register("a-1"));
+        assert!(window.register("a-2"));
+        assert!(!window.register("a-1"));
+        assert!(!window.register("a-2"));
+    }
+
+    #[test]
+    fn oldest_input_id_is_evicted_from_a_full_window() {
+        let mut window = InputIdWindow::default();
+        for id in 0..INPUT_ID_WINDOW {
+            assert!(window.register(&id.to_string()));
+        }
+        assert!(!window.register("0"));
+        assert!(window.register("new"));
+        // '0' was evicted by 'new', the newer ids are still known
+        assert!(window.register("0"));
+        assert!(!window.register(&(INPUT_ID_WINDOW - 1).to_string()));
+        assert_eq!(window.ids.len(), INPUT_ID_WINDOW);
+    }
+}
@@ -0,0 +1,39 @@
+//!
+//! What clients see while no host is connected (TT_BACKEND_NO_HOST).
+//! 'keep' (default) leaves the last state visible. 'waiting:<content>' shows a static state with
+//! the content instead, the state of the host comes back once a host is connected again.
+//! 'disconnect:<seconds>' disconnects every client once no host was connected for that long,
+//! clients logging in afterwards are rejected until a host is connected.
+//! The time without host starts with the server and whenever the host leaves.
+//!
+
+use std::time::Duration;
+
+/// State id of the waiting state, hosts use ids from 0
+pub const WAITING_STATE_ID: i32 = -1;
+pub const REJECT_REASON_NO_HOST: &str = "No host connected, please try again later";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NoHostBehavior {
+    #[default]
+    Keep,
+    /// Content of the waiting state
+    Waiting(String),
+    /// Time without host after which the clients are disconnected
+    Disconnect(Duration),
+}
+
+impl NoHostBehavior {
+    /// Parses 'keep', 'waiting:<content>' or 'disconnect:<seconds>'
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once(':') {
+            None if spec == "keep" => Ok(NoHostBehavior::Keep),
+            Some(("waiting", content)) => Ok(NoHostBehavior::Waiting(String::from(content))),
+            Some(("disconnect", seconds)) => match seconds.parse() {
+                Ok(v) => Ok(NoHostBehavior::Disconnect(Duration::from_secs(v))),
+                Err(_) => Err(format!("Invalid timeout '{}' of the no-host behavior", seconds)),
+            },
+            _ => Err(format!("Invalid no-host behavior '{}', expected 'keep', 'waiting:<content>' or 'disconnect:<seconds>'", spec)),
+        }
+    }
+}
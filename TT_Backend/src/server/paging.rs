@@ -0,0 +1,105 @@
+//!
+//! Pages of the lists the host queries ('GetClientList', 'GetAttendance', 'GetClientInputs').
+//! With thousands of clients a whole list exceeds the frame size of the host and blocks its
+//! connection while it is serialized. A query with 'offset' and/or 'limit' is answered with one
+//! page and a 'page' object ('offset', 'total' and 'next', the offset of the following page if
+//! any). Queries without either still get the whole list.
+//!
+
+/// Entries of a page if the host gives no (or a larger) limit
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Part of a list requested by the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Page {
+    /// Page of the given offset and limit, the limit is capped at the maximum page size
+    pub fn new(offset: Option<usize>, limit: Option<usize>) -> Self {
+        Page {
+            offset: offset.unwrap_or(0),
+            limit: limit.unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+        }
+    }
+
+    /// Keeps only the entries of the page
+    pub fn apply<T>(&self, entries: Vec<T>) -> (Vec<T>, PageInfo) {
+        let total = entries.len();
+        let entries: Vec<T> = entries.into_iter().skip(self.offset).take(self.limit).collect();
+        let end = self.offset.saturating_add(entries.len());
+        let next = Some(end).filter(|end| *end < total);
+        (entries, PageInfo {offset: self.offset, total, next})
+    }
+}
+
+/// Position of an answered page in the whole list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageInfo {
+    pub offset: usize,
+    pub total: usize,
+    /// Offset of the following page, None on the last page
+    pub next: Option<usize>,
+}
+
+/// Answers the whole list if no page was requested
+pub fn paginate<T>(entries: Vec<T>, page: Option<Page>) -> (Vec<T>, Option<PageInfo>) {
+    match page {
+        None => (entries, None),
+        Some(page) => {
+            let (entries, info) = page.apply(entries);
+            (entries, Some(info))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_follow_each_other() {
+        let (entries, info) = Page::new(Some(0), Some(4)).apply((0..10).collect());
+        assert_eq!(entries, vec![0, 1, 2, 3]);
+        assert_eq!(info, PageInfo {offset: 0, total: 10, next: Some(4)});
+        let (entries, info) = Page::new(Some(8), Some(4)).apply((0..10).collect());
+        assert_eq!(entries, vec![8, 9]);
+        assert_eq!(info.next, None);
+    }
+
+    #[test]
+    fn last_full_page_has_no_next() {
+        let (entries, info) = Page::new(Some(5), Some(5)).apply((0..10).collect());
+        assert_eq!(entries, vec![5, 6, 7, 8, 9]);
+        assert_eq!(info.next, None);
+    }
+
+    #[test]
+    fn offset_past_the_end_gives_an_empty_page() {
+        let (entries, info) = Page::new(Some(20), None).apply((0..10).collect::<Vec<i32>>());
+        assert!(entries.is_empty());
+        assert_eq!(info, PageInfo {offset: 20, total: 10, next: None});
+        let (_, info) = Page::new(Some(usize::MAX), Some(1)).apply((0..10).collect::<Vec<i32>>());
+        assert_eq!(info.next, None);
+    }
+
+    #[test]
+    fn limit_is_clamped() {
+        assert_eq!(Page::new(None, Some(0)), Page {offset: 0, limit: 1});
+        assert_eq!(Page::new(None, Some(MAX_PAGE_SIZE + 1)).limit, MAX_PAGE_SIZE);
+        assert_eq!(Page::new(Some(3), None), Page {offset: 3, limit: MAX_PAGE_SIZE});
+    }
+
+    #[test]
+    fn whole_list_without_a_page() {
+        let entries: Vec<usize> = (0..MAX_PAGE_SIZE * 2).collect();
+        let (all, info) = paginate(entries.clone(), None);
+        assert_eq!(all, entries);
+        assert_eq!(info, None);
+        let (page, info) = paginate(entries, Some(Page::new(None, None)));
+        assert_eq!(page.len(), MAX_PAGE_SIZE);
+        assert_eq!(info.and_then(|info| info.next), Some(MAX_PAGE_SIZE));
+    }
+}
@@ -0,0 +1,242 @@
+//!
+//! Host-uploaded WASM plugins processing the inputs (feature 'wasm_plugins').
+//! With TT_BACKEND_PLUGINS (e.g. 'fuel=1000000,memory=1024') the host may send a module with
+//! 'LoadPlugin' (base64). Every input of the session is then handed to the module before it
+//! reaches the host, which keeps logic like leaderboard scoring next to the clients. The module
+//! may emit JSON results, each one is sent to the host(s) and all clients as 'PluginResult'.
+//! Every call gets 'fuel' instructions and the linear memory is capped at 'memory' KiB, a module
+//! exceeding them (or trapping otherwise) is unloaded and the host is told why by 'PluginStatus'.
+//! The plugin lives in the session only, it is neither replicated to a standby nor handed over
+//! on upgrade.
+//!
+//! The module exports
+//!   'memory'                      its linear memory
+//!   'alloc(len: i32) -> i32'      pointer to 'len' free bytes, the input is written there
+//!   'on_input(ptr: i32, len: i32)' called with the input as JSON
+//!     ({"name", "state_id", "input", "server_ts"})
+//! and may import
+//!   'env.emit(ptr: i32, len: i32)' sends the JSON at 'ptr' as result
+//!
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::Value;
+use wasmi::core::Trap;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+/// Instructions a call may execute by default
+pub const DEFAULT_PLUGIN_FUEL: u64 = 1_000_000;
+/// Linear memory of the module by default, in KiB
+pub const DEFAULT_PLUGIN_MEMORY: usize = 1024;
+/// Results a module may emit for one input
+const MAX_RESULTS: usize = 8;
+/// Bytes of one result
+const MAX_RESULT_SIZE: usize = 64 * 1024;
+
+/// Resources a plugin gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginLimits {
+    /// Fuel of every call, roughly the instructions executed
+    pub fuel: u64,
+    /// Linear memory in KiB
+    pub memory: usize,
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        PluginLimits {fuel: DEFAULT_PLUGIN_FUEL, memory: DEFAULT_PLUGIN_MEMORY}
+    }
+}
+
+impl PluginLimits {
+    /// Parses 'fuel=<instructions>,memory=<KiB>', missing limits keep their default
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut limits = PluginLimits::default();
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("'{}' is no key=value pair", part))?;
+            let invalid = || format!("'{}' is no valid value of {}", value, key);
+            match key.trim() {
+                "fuel" => limits.fuel = value.trim().parse().map_err(|_| invalid())?,
+                "memory" => limits.memory = value.trim().parse().map_err(|_| invalid())?,
+                other => return Err(format!("Unknown plugin limit '{}', expecting fuel or memory", other)),
+            }
+        }
+        if limits.fuel == 0 || limits.memory == 0 {
+            return Err(String::from("Plugin limits have to be positive"))
+        }
+        Ok(limits)
+    }
+}
+
+#[derive(Debug)]
+struct PluginState {
+    limits: StoreLimits,
+    /// Results emitted by the running call
+    results: Vec<Value>,
+}
+
+/// Instantiated module of the host
+#[derive(Debug)]
+pub struct Plugin {
+    store: Store<PluginState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    on_input: TypedFunc<(i32, i32), ()>,
+    fuel: u64,
+}
+
+impl Plugin {
+    /// Decodes and instantiates the module, its start function runs with the fuel of one call
+    pub fn load(module: &str, limits: PluginLimits) -> Result<Self, String> {
+        let bytes = STANDARD.decode(module.trim()).map_err(|e| format!("Module is no valid base64: {}", e))?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes[..]).map_err(|e| format!("Module is invalid: {}", e))?;
+
+        let state = PluginState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory * 1024)
+                .memories(1)
+                .tables(1)
+                .instances(1)
+                .build(),
+            results: vec![],
+        };
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.add_fuel(limits.fuel).map_err(|e| e.to_string())?;
+
+        let mut linker = <Linker<PluginState>>::new(&engine);
+        linker.func_wrap("env", "emit", emit).map_err(|e| e.to_string())?;
+        let instance = linker.instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| format!("Instantiating the module failed: {}", e))?;
+        let memory = instance.get_memory(&store, "memory").ok_or_else(|| String::from("Module exports no 'memory'"))?;
+        let alloc = instance.get_typed_func(&store, "alloc").map_err(|e| format!("Module exports no 'alloc(i32) -> i32': {}", e))?;
+        let on_input = instance.get_typed_func(&store, "on_input").map_err(|e| format!("Module exports no 'on_input(i32, i32)': {}", e))?;
+        Ok(Plugin {store, memory, alloc, on_input, fuel: limits.fuel})
+    }
+
+    /// Hands the input to the module and returns the results it emitted
+    /// Fails if the module runs out of fuel or memory, traps or emits something else than JSON
+    pub fn on_input(&mut self, input: &Value) -> Result<Vec<Value>, String> {
+        self.refuel()?;
+        let bytes = input.to_string().into_bytes();
+        let len = i32::try_from(bytes.len()).map_err(|_| String::from("Input too large for the module"))?;
+        let ptr = self.alloc.call(&mut self.store, len).map_err(|e| format!("'alloc' failed: {}", e))?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, &bytes).map_err(|e| format!("'alloc' returned an invalid pointer: {}", e))?;
+        let result = self.on_input.call(&mut self.store, (ptr, len)).map_err(|e| format!("'on_input' failed: {}", e));
+        let results = std::mem::take(&mut self.store.data_mut().results);
+        result.map(|_| results)
+    }
+
+    /// Resets the fuel to the budget of one call, unused fuel does not carry over
+    fn refuel(&mut self) -> Result<(), String> {
+        let remaining = self.store.consume_fuel(0).map_err(|e| e.to_string())?;
+        self.store.consume_fuel(remaining).map_err(|e| e.to_string())?;
+        self.store.add_fuel(self.fuel).map_err(|e| e.to_string())
+    }
+}
+
+/// 'env.emit(ptr, len)', collects the JSON at 'ptr' as result of the running call
+fn emit(mut caller: Caller<'_, PluginState>, ptr: i32, len: i32) -> Result<(), Trap> {
+    if caller.data().results.len() >= MAX_RESULTS {
+        return Err(Trap::new(format!("More than {} results for one input", MAX_RESULTS)))
+    }
+    let len = usize::try_from(len).ok()
+        .filter(|len| *len <= MAX_RESULT_SIZE)
+        .ok_or_else(|| Trap::new(format!("Result of {} bytes, at most {} are allowed", len, MAX_RESULT_SIZE)))?;
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("Module exports no 'memory'"))?;
+    let mut bytes = vec![0; len];
+    memory.read(&caller, ptr as u32 as usize, &mut bytes).map_err(|e| Trap::new(e.to_string()))?;
+    let result = serde_json::from_slice(&bytes).map_err(|e| Trap::new(format!("Result is no JSON: {}", e)))?;
+    caller.data_mut().results.push(result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Module importing 'env.emit', exporting one page per 'pages', 'alloc' returning 1024 and
+    /// 'on_input' with the given body (without locals)
+    fn module(pages: u8, on_input: &[u8]) -> String {
+        fn section(id: u8, contents: &[u8]) -> Vec<u8> {
+            [&[id, contents.len() as u8][..], contents].concat()
+        }
+        fn name(name: &str) -> Vec<u8> {
+            [&[name.len() as u8][..], name.as_bytes()].concat()
+        }
+        let alloc = [0x00, 0x41, 0x80, 0x08, 0x0b];
+        let on_input = [&[0x00][..], on_input, &[0x0b]].concat();
+        let wasm = [
+            vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00],
+            section(1, &[2, 0x60, 2, 0x7f, 0x7f, 0, 0x60, 1, 0x7f, 1, 0x7f]),
+            section(2, &[&[1][..], &name("env"), &name("emit"), &[0x00, 0]].concat()),
+            section(3, &[2, 1, 0]),
+            section(5, &[1, 0x00, pages]),
+            section(7, &[&[3][..], &name("memory"), &[0x02, 0], &name("alloc"), &[0x00, 1], &name("on_input"), &[0x00, 2]].concat()),
+            section(10, &[&[2, alloc.len() as u8][..], &alloc, &[on_input.len() as u8], &on_input].concat()),
+        ].concat();
+        STANDARD.encode(wasm)
+    }
+
+    /// 'on_input' emitting the input unchanged
+    const ECHO: &[u8] = &[0x20, 0x00, 0x20, 0x01, 0x10, 0x00];
+    /// 'on_input' looping forever
+    const LOOP: &[u8] = &[0x03, 0x40, 0x0c, 0x00, 0x0b];
+    /// 'on_input' emitting 3 zero bytes
+    const GARBAGE: &[u8] = &[0x41, 0x00, 0x41, 0x03, 0x10, 0x00];
+
+    fn input(input: &str) -> Value {
+        json!({"name": "ada", "state_id": 3, "input": input, "server_ts": 1_700_000_000_000i64})
+    }
+
+    #[test]
+    fn limits_are_parsed() {
+        assert_eq!(PluginLimits::parse(""), Ok(PluginLimits::default()));
+        assert_eq!(PluginLimits::parse("fuel=5000, memory=64"), Ok(PluginLimits {fuel: 5000, memory: 64}));
+        assert!(PluginLimits::parse("fuel=0").is_err());
+        assert!(PluginLimits::parse("memory=lots").unwrap_err().contains("lots"));
+        assert!(PluginLimits::parse("time=5").unwrap_err().contains("time"));
+    }
+
+    #[test]
+    fn inputs_are_processed_into_results() {
+        let mut plugin = Plugin::load(&module(1, ECHO), PluginLimits::default()).unwrap();
+        assert_eq!(plugin.on_input(&input("A")), Ok(vec![input("A")]));
+        assert_eq!(plugin.on_input(&input("B")), Ok(vec![input("B")]));
+        let mut silent = Plugin::load(&module(1, &[]), PluginLimits::default()).unwrap();
+        assert_eq!(silent.on_input(&input("A")), Ok(vec![]));
+    }
+
+    #[test]
+    fn fuel_is_limited_per_call() {
+        let mut plugin = Plugin::load(&module(1, LOOP), PluginLimits {fuel: 10_000, memory: 64}).unwrap();
+        assert!(plugin.on_input(&input("A")).is_err());
+        // Unused fuel does not add up over many cheap calls
+        let mut plugin = Plugin::load(&module(1, ECHO), PluginLimits {fuel: 10_000, memory: 64}).unwrap();
+        for _ in 0..100 {
+            assert!(plugin.on_input(&input("A")).is_ok());
+        }
+        assert!(plugin.store.consume_fuel(0).unwrap() < 10_000);
+    }
+
+    #[test]
+    fn memory_is_limited() {
+        // One page has 64 KiB
+        assert!(Plugin::load(&module(2, ECHO), PluginLimits {fuel: 10_000, memory: 64}).unwrap_err().contains("memory"));
+        assert!(Plugin::load(&module(1, ECHO), PluginLimits {fuel: 10_000, memory: 64}).is_ok());
+    }
+
+    #[test]
+    fn invalid_modules_and_results_are_reported() {
+        assert!(Plugin::load("not base64!", PluginLimits::default()).unwrap_err().contains("base64"));
+        assert!(Plugin::load(&STANDARD.encode(b"\0asm"), PluginLimits::default()).unwrap_err().contains("invalid"));
+        let mut plugin = Plugin::load(&module(1, GARBAGE), PluginLimits::default()).unwrap();
+        assert!(plugin.on_input(&input("A")).unwrap_err().contains("JSON"));
+    }
+}
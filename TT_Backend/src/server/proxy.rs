@@ -0,0 +1,143 @@
+//!
+//! Real client addresses behind reverse proxies.
+//! If TLS terminates at a proxy, every client seems to connect from the proxy. Connections from
+//! the configured trusted proxies (TT_BACKEND_TRUSTED_PROXIES, comma separated IPs or CIDR ranges)
+//! are attributed to the address in their 'Forwarded' or 'X-Forwarded-For' header instead.
+//! Headers of all other connections are ignored, so clients can not spoof their address.
+//!
+
+use std::net::{IpAddr, SocketAddr};
+use ipnet::IpNet;
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Parses a comma separated list of IPs and CIDR ranges
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let ranges = spec.split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| range.parse::<IpNet>()
+                .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("Invalid trusted proxy '{}', expected an IP or a CIDR range", range)))
+            .collect::<Result<Vec<IpNet>, String>>()?;
+        Ok(TrustedProxies { ranges })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(&ip))
+    }
+
+    /// Returns the address the client really connects from
+    /// The chain of forwarding proxies is followed from the right (the closest proxy) as long as
+    /// they are trusted. The port of the peer is kept, so connections stay distinguishable.
+    pub fn real_address(&self, peer: SocketAddr, forwarded: Option<&str>, forwarded_for: Option<&str>) -> SocketAddr {
+        if !self.is_trusted(peer.ip()) {
+            return peer
+        }
+        let chain: Vec<IpAddr> = match (forwarded, forwarded_for) {
+            (Some(forwarded), _) => parse_forwarded(forwarded),
+            (None, Some(forwarded_for)) => forwarded_for.split(',').filter_map(parse_node).collect(),
+            (None, None) => return peer,
+        };
+        let client = chain.iter().rev()
+            .find(|ip| !self.is_trusted(**ip))
+            .or_else(|| chain.first());
+        match client {
+            Some(ip) => SocketAddr::new(*ip, peer.port()),
+            None => peer,
+        }
+    }
+}
+
+/// Returns the 'for' addresses of a 'Forwarded' header (RFC 7239), obfuscated ones are skipped
+fn parse_forwarded(header: &str) -> Vec<IpAddr> {
+    header.split(',')
+        .flat_map(|element| element.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .filter(|(key, _)| key.eq_ignore_ascii_case("for"))
+        .filter_map(|(_, value)| parse_node(value))
+        .collect()
+}
+
+/// Parses '1.2.3.4', '1.2.3.4:80', '2001:db8::1' or '"[2001:db8::1]:80"'
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip)
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip())
+    }
+    node.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(address: &str) -> SocketAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn headers_of_untrusted_peers_are_ignored() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        let client = peer("203.0.113.7:4000");
+        assert_eq!(proxies.real_address(client, Some("for=198.51.100.1"), Some("198.51.100.2")), client);
+        assert_eq!(TrustedProxies::default().real_address(client, None, Some("198.51.100.2")), client);
+    }
+
+    #[test]
+    fn trusted_chain_is_walked_from_the_right() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.1.1").unwrap();
+        // The client may put anything in front, only the entry added by the first trusted proxy counts
+        let address = proxies.real_address(peer("10.0.0.1:4000"), None, Some("1.1.1.1, 203.0.113.7, 192.168.1.1, 10.0.0.2"));
+        assert_eq!(address, peer("203.0.113.7:4000"));
+        assert_eq!(proxies.real_address(peer("10.0.0.1:4000"), None, None), peer("10.0.0.1:4000"));
+    }
+
+    #[test]
+    fn fully_trusted_chain_gives_its_first_entry() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        let address = proxies.real_address(peer("10.0.0.1:4000"), None, Some("10.1.1.1, 10.2.2.2"));
+        assert_eq!(address, peer("10.1.1.1:4000"));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence() {
+        let proxies = TrustedProxies::parse("10.0.0.1").unwrap();
+        let address = proxies.real_address(peer("10.0.0.1:4000"), Some("for=203.0.113.7;proto=https"), Some("198.51.100.2"));
+        assert_eq!(address, peer("203.0.113.7:4000"));
+        let address = proxies.real_address(peer("10.0.0.1:4000"), Some(r#"for="[2001:db8::1]:80", For=10.0.0.1"#), None);
+        assert_eq!(address, peer("[2001:db8::1]:4000"));
+    }
+
+    #[test]
+    fn nodes_are_parsed_in_every_form() {
+        assert_eq!(parse_node(r#""[2001:db8::1]:80""#), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_node("[2001:db8::1]"), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_node(" 1.2.3.4:80 "), Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(parse_node("1.2.3.4"), Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_forwarded("for=_hidden, for=1.2.3.4"), vec!["1.2.3.4".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn invalid_ranges_are_reported() {
+        assert!(TrustedProxies::parse("10.0.0.0/33").unwrap_err().contains("10.0.0.0/33"));
+        assert!(TrustedProxies::parse("10.0.0.1, proxy.local").unwrap_err().contains("proxy.local"));
+        let proxies = TrustedProxies::parse(" 10.0.0.0/8 ,, ::1 ").unwrap();
+        assert!(proxies.is_trusted("10.255.0.1".parse().unwrap()));
+        assert!(proxies.is_trusted("::1".parse().unwrap()));
+        assert!(!proxies.is_trusted("11.0.0.1".parse().unwrap()));
+        assert!(TrustedProxies::parse("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//!
+//! Acknowledgment quorum of the current state.
+//! Clients send 'StateAck' once a state is on screen. With TT_BACKEND_STATE_QUORUM set to a
+//! percentage, the host is told by 'QuorumReached' as soon as that share of the connected clients
+//! acknowledged the latest 'ChangeState', e.g. to start a timer only once the audience sees the
+//! question. Clients leaving count as well, the quorum is reported once per state.
+//!
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+#[derive(Debug)]
+pub struct StateQuorum {
+    /// Share of the clients that has to acknowledge, in percent
+    percent: u8,
+    state_id: Option<i32>,
+    acknowledged: HashSet<SocketAddr>,
+    reported: bool,
+}
+
+impl StateQuorum {
+    pub fn new(percent: u8) -> Self {
+        StateQuorum {percent, state_id: None, acknowledged: HashSet::new(), reported: false}
+    }
+
+    /// Acknowledgments of the previous state no longer count
+    pub fn state_changed(&mut self, state_id: i32) {
+        self.state_id = Some(state_id);
+        self.acknowledged.clear();
+        self.reported = false;
+    }
+
+    /// Counts the acknowledgment if it is for the current state, returns whether it was counted
+    pub fn acknowledge(&mut self, address: SocketAddr, state_id: i32) -> bool {
+        if self.state_id != Some(state_id) {
+            return false
+        }
+        self.acknowledged.insert(address)
+    }
+
+    pub fn client_left(&mut self, address: SocketAddr) {
+        self.acknowledged.remove(&address);
+    }
+
+    /// State and acknowledged percentage once the quorum of the 'connected' clients is reached,
+    /// None before and after
+    pub fn check(&mut self, connected: usize) -> Option<(i32, u8)> {
+        let state_id = self.state_id?;
+        if self.reported || connected == 0 {
+            return None
+        }
+        let percent = (self.acknowledged.len() * 100 / connected).min(100) as u8;
+        if percent < self.percent {
+            return None
+        }
+        self.reported = true;
+        Some((state_id, percent))
+    }
+}
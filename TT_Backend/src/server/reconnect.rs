@@ -0,0 +1,83 @@
+//!
+//! Reconnect guidance for clients, sent with 'Disconnecting' and 'Migrate'.
+//! After a restart every client reconnecting at once overloads the server again. The hint tells
+//! clients how to spread their attempts: the n-th attempt (from 0) waits
+//! 'min(max_backoff, max(min_backoff * 2^n, min_backoff + (max_backoff - min_backoff) * load))',
+//! shortened by a random share of up to 'jitter'. The load hint (0 to 1) is the share of
+//! RECONNECT_FULL_LOAD_CLIENTS leaving together, so large crowds start with wider windows.
+//! The backoff is configured with TT_BACKEND_RECONNECT_BACKOFF as '<min_ms>:<max_ms>:<jitter>'.
+//!
+
+use std::time::Duration;
+use serde_json::{json, Value};
+
+/// Clients leaving together at which the full backoff window is used from the first attempt
+pub const RECONNECT_FULL_LOAD_CLIENTS: usize = 500;
+const DEFAULT_MIN_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DEFAULT_JITTER: f64 = 0.5;
+
+/// Configured backoff, the load is added per disconnect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectBackoff {
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Share (0 to 1) of the delay that is randomized
+    pub jitter: f64,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        ReconnectBackoff {min_backoff: DEFAULT_MIN_BACKOFF, max_backoff: DEFAULT_MAX_BACKOFF, jitter: DEFAULT_JITTER}
+    }
+}
+
+impl ReconnectBackoff {
+    /// Parses '<min_ms>:<max_ms>:<jitter>'
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (min, max, jitter) = match parts.as_slice() {
+            [min, max, jitter] => (min.parse::<u64>(), max.parse::<u64>(), jitter.parse::<f64>()),
+            _ => return Err(format!("Invalid reconnect backoff '{}', expected '<min_ms>:<max_ms>:<jitter>'", spec)),
+        };
+        match (min, max, jitter) {
+            (Ok(min), Ok(max), Ok(jitter)) if min <= max && (0.0..=1.0).contains(&jitter) => Ok(ReconnectBackoff {
+                min_backoff: Duration::from_millis(min),
+                max_backoff: Duration::from_millis(max),
+                jitter,
+            }),
+            _ => Err(format!("Invalid reconnect backoff '{}', minimum must not exceed maximum and jitter must be within 0 and 1", spec)),
+        }
+    }
+
+    /// Hint for the given number of clients leaving together
+    pub fn hint(&self, leaving: usize) -> ReconnectHint {
+        let load = (leaving as f64 / RECONNECT_FULL_LOAD_CLIENTS as f64).min(1.0);
+        ReconnectHint {
+            min_backoff: self.min_backoff,
+            max_backoff: self.max_backoff,
+            jitter: self.jitter,
+            load: (load * 100.0).round() / 100.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectHint {
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+    /// Share (0 to 1) of the backoff window already used by the first attempt
+    pub load: f64,
+}
+
+impl ReconnectHint {
+    pub fn to_json(self) -> Value {
+        json!({
+            "min_backoff_ms": self.min_backoff.as_millis() as u64,
+            "max_backoff_ms": self.max_backoff.as_millis() as u64,
+            "jitter": self.jitter,
+            "load": self.load,
+        })
+    }
+}
@@ -0,0 +1,161 @@
+//!
+//! Record of the inputs forwarded to the host.
+//! The host can query the inputs of a single client to resolve disputes during a session.
+//! Records are kept in memory only, they are dropped once the grace period of a disconnected
+//! client is over.
+//!
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+/// Number of most recent inputs recorded per client
+pub const INPUT_RECORD_LIMIT: usize = 1024;
+
+/// One input as it was forwarded to the host
+#[derive(Debug, Clone)]
+pub struct RecordedInput {
+    pub state_id: i32,
+    pub input: String,
+    pub client_ts: Option<i64>,
+    pub input_id: Option<String>,
+    pub server_ts: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct InputRecorder {
+    inputs: HashMap<SocketAddr, VecDeque<RecordedInput>>,
+}
+
+impl InputRecorder {
+    /// Records the input, the oldest input of the client is dropped once INPUT_RECORD_LIMIT is reached
+    pub fn record(&mut self, address: SocketAddr, input: RecordedInput) {
+        let inputs = self.inputs.entry(address).or_default();
+        if inputs.len() == INPUT_RECORD_LIMIT {
+            inputs.pop_front();
+        }
+        inputs.push_back(input);
+    }
+
+    /// Returns the recorded inputs of the client for the given state, oldest first
+    pub fn get(&self, address: SocketAddr, state_id: i32) -> Vec<RecordedInput> {
+        self.inputs.get(&address)
+            .map(|inputs| inputs.iter().filter(|input| input.state_id == state_id).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the recorded inputs of every client, oldest first per client
+    pub fn all(&self) -> impl Iterator<Item = (SocketAddr, &RecordedInput)> {
+        self.inputs.iter().flat_map(|(address, inputs)| inputs.iter().map(move |input| (*address, input)))
+    }
+
+    /// Moves the inputs recorded under 'from' to the client now at 'to', before its own
+    pub fn transfer(&mut self, from: SocketAddr, to: SocketAddr) {
+        let mut inputs = match self.inputs.remove(&from) {
+            None => return,
+            Some(v) => v,
+        };
+        if let Some(newer) = self.inputs.remove(&to) {
+            inputs.extend(newer);
+            let excess = inputs.len().saturating_sub(INPUT_RECORD_LIMIT);
+            inputs.drain(..excess);
+        }
+        self.inputs.insert(to, inputs);
+    }
+
+    /// Drops all inputs of the client
+    pub fn forget(&mut self, address: SocketAddr) {
+        self.inputs.remove(&address);
+    }
+
+    /// Approximate bytes of the recorded inputs
+    pub fn bytes(&self) -> usize {
+        self.all().map(|(_, input)| input_bytes(input)).sum()
+    }
+
+    /// Drops the older half of every client's inputs until 'bytes' are freed (or none are left),
+    /// returns the freed bytes
+    pub fn trim(&mut self, bytes: usize) -> usize {
+        let mut freed = 0;
+        while freed < bytes && !self.inputs.is_empty() {
+            for inputs in self.inputs.values_mut() {
+                let dropped = inputs.len().div_ceil(2);
+                freed += inputs.drain(..dropped).map(|input| input_bytes(&input)).sum::<usize>();
+            }
+            self.inputs.retain(|_, inputs| !inputs.is_empty());
+        }
+        freed
+    }
+}
+
+fn input_bytes(input: &RecordedInput) -> usize {
+    input.input.len() + input.input_id.as_ref().map(String::len).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(state_id: i32, input: &str) -> RecordedInput {
+        RecordedInput {state_id, input: String::from(input), client_ts: None, input_id: None, server_ts: 0}
+    }
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn oldest_input_is_dropped_at_the_limit() {
+        let mut recorder = InputRecorder::default();
+        for i in 0..INPUT_RECORD_LIMIT + 2 {
+            recorder.record(address(1), input(1, &i.to_string()));
+        }
+        recorder.record(address(1), input(2, "other state"));
+
+        let inputs = recorder.get(address(1), 1);
+        assert_eq!(inputs.len(), INPUT_RECORD_LIMIT - 1);
+        assert_eq!(inputs[0].input, "3");
+        assert_eq!(recorder.get(address(1), 2).len(), 1);
+        assert_eq!(recorder.all().count(), INPUT_RECORD_LIMIT);
+    }
+
+    #[test]
+    fn trim_drops_the_older_half_until_enough_is_freed() {
+        let mut recorder = InputRecorder::default();
+        for i in 0..4 {
+            recorder.record(address(1), input(1, &format!("a{}", i)));
+        }
+        recorder.record(address(2), input(1, "b0"));
+        assert_eq!(recorder.bytes(), 10);
+
+        // One round halves alice and drops bob's only input
+        assert_eq!(recorder.trim(1), 6);
+        let inputs: Vec<String> = recorder.get(address(1), 1).into_iter().map(|input| input.input).collect();
+        assert_eq!(inputs, vec!["a2", "a3"]);
+        assert!(recorder.get(address(2), 1).is_empty());
+
+        assert_eq!(recorder.trim(usize::MAX), 4);
+        assert_eq!(recorder.bytes(), 0);
+        assert_eq!(recorder.trim(1), 0);
+    }
+
+    #[test]
+    fn forgotten_client_has_no_inputs() {
+        let mut recorder = InputRecorder::default();
+        recorder.record(address(1), input(1, "a"));
+        recorder.forget(address(1));
+        assert!(recorder.get(address(1), 1).is_empty());
+        assert_eq!(recorder.bytes(), 0);
+    }
+
+    #[test]
+    fn transferred_inputs_precede_the_new_ones() {
+        let mut recorder = InputRecorder::default();
+        recorder.record(address(1), input(1, "old"));
+        recorder.record(address(2), input(1, "new"));
+        recorder.transfer(address(1), address(2));
+
+        let inputs: Vec<String> = recorder.get(address(2), 1).into_iter().map(|input| input.input).collect();
+        assert_eq!(inputs, vec!["old", "new"]);
+        assert!(recorder.get(address(1), 1).is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+//!
+//! One-time rejoin links.
+//! Before a planned restart the operator hands every connected client a link ('POST /rejoin-links'
+//! on the admin interface): the client receives 'RejoinLink' with a random token in the url
+//! ('?rejoin=<token>', on the advertised url if there is one, relative to the web app otherwise). Logging in with
+//! the token as 'rejoin' of its 'ClientLogin' restores name, role, team and whether the current
+//! state was answered without entering name, login token or access code again. A token is used up
+//! by its first login and expires after 'ttl' (TT_BACKEND_REJOIN_TTL), the tokens survive an upgrade
+//! or failover with the session snapshot.
+//! Only keyed hashes of the tokens are kept, neither memory nor snapshots reveal usable tokens.
+//!
+
+use std::collections::HashMap;
+use std::time::Duration;
+use rand::RngCore;
+use serde_json::{json, Value};
+use crate::server::secrets::SecretKey;
+
+/// Time a rejoin link can be used, if not configured
+pub const DEFAULT_REJOIN_TTL: Duration = Duration::from_secs(30 * 60);
+pub const REJECT_REASON_REJOIN: &str = "Invalid or expired rejoin link";
+const TOKEN_BYTES: usize = 16;
+
+/// Identity a rejoin token restores
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejoinGrant {
+    pub name: String,
+    pub role: Option<String>,
+    pub team: Option<String>,
+    pub guest: bool,
+    /// State the client answered, if any
+    pub answered_state: Option<i32>,
+    pub last_state: Option<i32>,
+    /// Server timestamp the token expires at
+    pub expires_at: i64,
+}
+
+/// Unused rejoin tokens
+#[derive(Debug, Clone, Default)]
+pub struct RejoinTokens {
+    /// Grants by the keyed hash of their token
+    grants: HashMap<String, RejoinGrant>,
+}
+
+impl RejoinTokens {
+    /// Stores the grant, returns its token
+    /// An unused token of the same name is replaced, only the latest link works
+    pub fn issue(&mut self, grant: RejoinGrant, now: i64, key: &SecretKey) -> String {
+        self.prune(now);
+        self.grants.retain(|_, other| other.name != grant.name);
+        let mut bytes = [0u8; TOKEN_BYTES];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.grants.insert(key.hash(&token), grant);
+        token
+    }
+
+    /// Grant of the token, which is used up by it, None if it is unknown or expired
+    pub fn redeem(&mut self, token: &str, now: i64, key: &SecretKey) -> Option<RejoinGrant> {
+        self.prune(now);
+        self.grants.remove(&key.hash(token))
+    }
+
+    /// Tokens not used yet, expired ones included until the next prune
+    pub fn pending(&self) -> usize {
+        self.grants.len()
+    }
+
+    fn prune(&mut self, now: i64) {
+        self.grants.retain(|_, grant| grant.expires_at > now);
+    }
+
+    pub fn to_json(&self) -> Value {
+        let grants: Vec<Value> = self.grants.iter()
+            .map(|(hash, grant)| json!({
+                "token_hash": hash,
+                "name": grant.name,
+                "role": grant.role,
+                "team": grant.team,
+                "guest": grant.guest,
+                "answered_state": grant.answered_state,
+                "last_state": grant.last_state,
+                "expires_at": grant.expires_at,
+            }))
+            .collect();
+        Value::from(grants)
+    }
+
+    /// Parses the output of 'to_json', None if it is malformed
+    pub fn from_json(json: &Value) -> Option<Self> {
+        let state = |value: &Value| value.as_i64().and_then(|state_id| i32::try_from(state_id).ok());
+        let grants = json.as_array()?.iter()
+            .map(|grant| Some((String::from(grant["token_hash"].as_str()?), RejoinGrant {
+                name: String::from(grant["name"].as_str()?),
+                role: grant["role"].as_str().map(String::from),
+                team: grant["team"].as_str().map(String::from),
+                guest: grant["guest"].as_bool()?,
+                answered_state: state(&grant["answered_state"]),
+                last_state: state(&grant["last_state"]),
+                expires_at: grant["expires_at"].as_i64()?,
+            })))
+            .collect::<Option<HashMap<String, RejoinGrant>>>()?;
+        Some(RejoinTokens {grants})
+    }
+}
+
+/// Url of the rejoin link, relative to the web app if no public url is known
+pub fn rejoin_url(public_url: Option<&str>, token: &str) -> String {
+    let base = public_url.unwrap_or("");
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{}{}rejoin={}", base, separator, token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(name: &str, expires_at: i64) -> RejoinGrant {
+        RejoinGrant {name: String::from(name), role: None, team: None, guest: true, answered_state: Some(3), last_state: Some(3), expires_at}
+    }
+
+    #[test]
+    fn snapshot_holds_no_usable_token() {
+        let key = SecretKey::new("key material");
+        let mut tokens = RejoinTokens::default();
+        let token = tokens.issue(grant("alice", 1000), 0, &key);
+        let json = tokens.to_json();
+        assert!(!json.to_string().contains(&token));
+
+        let mut restored = RejoinTokens::from_json(&json).expect("Snapshot is malformed");
+        assert_eq!(restored.clone().redeem(&token, 10, &SecretKey::new("other material")), None);
+        assert_eq!(restored.redeem(&token, 10, &key), Some(grant("alice", 1000)));
+        assert_eq!(restored.redeem(&token, 10, &key), None);
+    }
+
+    #[test]
+    fn expired_and_replaced_tokens_are_rejected() {
+        let key = SecretKey::new("key material");
+        let mut tokens = RejoinTokens::default();
+        let expired = tokens.issue(grant("alice", 100), 0, &key);
+        assert_eq!(tokens.redeem(&expired, 100, &key), None);
+
+        let first = tokens.issue(grant("bob", 1000), 0, &key);
+        let second = tokens.issue(grant("bob", 1000), 0, &key);
+        assert_eq!(tokens.redeem(&first, 10, &key), None);
+        assert!(tokens.redeem(&second, 10, &key).is_some());
+    }
+}
@@ -0,0 +1,399 @@
+//!
+//! Warm standby.
+//! A primary with a replication port streams a snapshot of its session (state, presence of the
+//! clients, announcement, access code, rejoin links) every replication interval to every standby connected to
+//! it, one json object per line. A standby follows the primary given by 'TT_BACKEND_STANDBY_OF', applies the
+//! snapshots and rejects hosts and clients until it takes over: either the primary requests it
+//! (planned failover via the admin interface, the clients are migrated to the standby) or nothing
+//! was received for the failover timeout (the primary died, participants reconnect via the
+//! advertised host).
+//! Primary and standbys share TT_BACKEND_SECRET_KEY: the primary challenges every standby with a
+//! random nonce which the standby answers with a keyed hash of the nonce and its url, standbys
+//! without the key are dropped before they receive anything. Every line of the replication stream
+//! is encrypted with the key, which also proves the primary to the standby. The replication
+//! listener binds to TT_BACKEND_REPLICATION_IP (the loopback address by default), it is meant for
+//! the internal network of the servers.
+//!
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
+use rand::RngCore;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use crate::server::InternalMessage;
+use crate::server::auth::constant_time_eq;
+use crate::server::bus::Bus;
+use crate::server::networking::{accept_exhausted, bind_tcp, Listener};
+use crate::server::rejoin::RejoinTokens;
+use crate::server::secrets::{SECRET_KEY_ENV, SecretKey};
+use crate::server::session::Session;
+
+/// Interval between two snapshots sent to the standbys
+pub const REPLICATION_INTERVAL: Duration = Duration::from_millis(500);
+/// Silence of the primary after which a standby takes over, if not configured
+pub const DEFAULT_FAILOVER_TIMEOUT: Duration = Duration::from_secs(10);
+pub const REJECT_REASON_STANDBY: &str = "Server is on standby";
+/// Time a standby may take to introduce itself
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+const STANDBY_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const CHALLENGE_BYTES: usize = 16;
+
+/// Client connected to the primary
+#[derive(Debug, Clone)]
+pub struct ReplicatedClient {
+    pub name: String,
+    pub address: String,
+    pub team: Option<String>,
+}
+
+/// Everything a standby needs to continue the session of the primary
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub session: Option<Session>,
+    /// Id and content of the current state
+    pub state: Option<(i32, String)>,
+    pub clients: Vec<ReplicatedClient>,
+    /// Operator announcement and the server timestamp it expires at
+    pub announcement: Option<(String, Option<i64>)>,
+    /// Access code new clients have to give, rotations by the host survive the takeover
+    pub access_code: Option<String>,
+    /// Unused rejoin links, clients holding one rejoin after the takeover
+    pub rejoin: RejoinTokens,
+}
+
+impl Snapshot {
+    /// The access code is encrypted with the key
+    pub fn to_json(&self, key: &SecretKey) -> Value {
+        let session = self.session.as_ref().map(|session| json!({
+            "started": session.started,
+            "ends_at": session.ends_at,
+            "generation": session.generation,
+            "tenant": session.tenant,
+            "template": session.template,
+        }));
+        let state = self.state.as_ref().map(|(state_id, content)| json!({"state_id": state_id, "content": content}));
+        let clients: Vec<Value> = self.clients.iter()
+            .map(|client| json!({"name": client.name, "address": client.address, "team": client.team}))
+            .collect();
+        let announcement = self.announcement.as_ref().map(|(message, expires_at)| json!({"message": message, "expires_at": expires_at}));
+        json!({
+            "session": session,
+            "state": state,
+            "clients": clients,
+            "announcement": announcement,
+            "access_code": self.access_code.as_ref().map(|code| key.encrypt(code)),
+            "rejoin": self.rejoin.to_json(),
+        })
+    }
+
+    /// Parses the output of 'to_json', None if it is malformed or its access code can not be
+    /// decrypted with the key (a session must not lose its access code)
+    pub fn from_json(json: &Value, key: &SecretKey) -> Option<Self> {
+        let session = match &json["session"] {
+            Value::Null => None,
+            session => Some(Session {
+                started: session["started"].as_i64()?,
+                ends_at: session["ends_at"].as_i64(),
+                generation: session["generation"].as_u64()?,
+                tenant: session["tenant"].as_str().map(String::from),
+                template: session["template"].as_str().map(String::from),
+            }),
+        };
+        let state = match &json["state"] {
+            Value::Null => None,
+            state => Some((i32::try_from(state["state_id"].as_i64()?).ok()?, String::from(state["content"].as_str()?))),
+        };
+        let clients = json["clients"].as_array()?.iter()
+            .map(|client| Some(ReplicatedClient {
+                name: String::from(client["name"].as_str()?),
+                address: String::from(client["address"].as_str()?),
+                team: client["team"].as_str().map(String::from),
+            }))
+            .collect::<Option<Vec<ReplicatedClient>>>()?;
+        let announcement = match &json["announcement"] {
+            Value::Null => None,
+            announcement => Some((String::from(announcement["message"].as_str()?), announcement["expires_at"].as_i64())),
+        };
+        let access_code = match json["access_code"].as_str() {
+            None => None,
+            Some(encrypted) => Some(key.decrypt(encrypted)?),
+        };
+        let rejoin = match &json["rejoin"] {
+            Value::Null => Default::default(),
+            rejoin => RejoinTokens::from_json(rejoin)?,
+        };
+        Some(Snapshot { session, state, clients, announcement, access_code, rejoin })
+    }
+}
+
+/// Messages of the primary to its standbys
+#[derive(Debug, Clone)]
+pub enum ReplicationMessage {
+    Snapshot(Box<Snapshot>),
+    /// The standby becomes the active server
+    Takeover,
+}
+
+impl ReplicationMessage {
+    /// Single line, the json of the message encrypted with the key
+    pub fn encode(&self, key: &SecretKey) -> String {
+        let json = match self {
+            ReplicationMessage::Snapshot(snapshot) => json!({"type": "Snapshot", "snapshot": snapshot.to_json(key)}),
+            ReplicationMessage::Takeover => json!({"type": "Takeover"}),
+        };
+        key.encrypt(&json.to_string())
+    }
+
+    fn parse(line: &str, key: &SecretKey) -> Option<Self> {
+        let json: Value = serde_json::from_str(&key.decrypt(line)?).ok()?;
+        match json["type"].as_str()? {
+            "Snapshot" => Some(ReplicationMessage::Snapshot(Box::new(Snapshot::from_json(&json["snapshot"], key)?))),
+            "Takeover" => Some(ReplicationMessage::Takeover),
+            _ => None,
+        }
+    }
+}
+
+/// Create a listener on the replication port waiting for standbys
+pub async fn create_replication_listener(channel: Bus, key: SecretKey, addr: SocketAddr) -> std::io::Result<Listener> {
+    // TCP listener
+    let listener = bind_tcp(addr).await?;
+    info!("create_replication_listener(..): Listening for standbys on {}", addr);
+
+    // Spawn listener, restarted by the supervision if it ends
+    Listener::start(addr, listener, move |listener| listen(channel.clone(), key.clone(), listener))
+}
+
+/// Waiting for incoming connections
+/// Every standby is served in its own task
+async fn listen(channel: Bus, key: SecretKey, listener: TcpListener) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) if accept_exhausted(&e) => {
+                error!("listen(..): Could not accept connection, stopping until restarted\nError: {}", e);
+                return
+            }
+            Err(e) => {
+                warn!("listen(..): Could not accept connection\nError: {}", e);
+                continue
+            },
+        };
+
+        tokio::spawn(standby_connection(channel.clone(), key.clone(), stream, address));
+    }
+}
+
+/// Challenges the standby, registers it with the main handler by the 'StandbyConnected' event
+/// once its hello proves the key and writes the replication stream to it
+/// Triggers the 'StandbyDisconnected' event once the connection is gone
+async fn standby_connection(channel: Bus, key: SecretKey, stream: TcpStream, address: SocketAddr) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let nonce = new_nonce();
+    if let Err(e) = write.write_all(format!("{}\n", challenge(&nonce)).as_bytes()).await {
+        warn!("standby_connection(..): Challenging standby {} failed. Dropping!\nError: {}", address, e);
+        return
+    }
+    let url = match timeout(HELLO_TIMEOUT, lines.next_line()).await {
+        Ok(Ok(Some(line))) => match parse_hello(&line, &nonce, &key) {
+            Some(v) => v,
+            None => {
+                warn!("standby_connection(..): Hello of standby {} is malformed or not proven with {}. Dropping!", address, SECRET_KEY_ENV);
+                return
+            }
+        },
+        _ => {
+            warn!("standby_connection(..): Standby {} did not introduce itself. Dropping!", address);
+            return
+        }
+    };
+    info!("standby_connection(..): Standby {} connected, url: {:?}", address, url);
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    channel.send(InternalMessage::StandbyConnected {address, url, sender}).await.expect("standby_connection(..): Sending internal message failed");
+
+    // The standby sends nothing after the hello, the end of its stream means it is gone
+    loop {
+        tokio::select! {
+            line = receiver.recv() => match line {
+                None => break,
+                Some(line) => if let Err(e) = write.write_all(format!("{}\n", line).as_bytes()).await {
+                    warn!("standby_connection(..): Sending to standby {} failed!\nError: {}", address, e);
+                    break
+                },
+            },
+            read = lines.next_line() => if !matches!(read, Ok(Some(_))) {
+                break
+            },
+        }
+    }
+    info!("standby_connection(..): Standby {} disconnected", address);
+    channel.send(InternalMessage::StandbyDisconnected {address}).await.expect("standby_connection(..): Sending internal message failed");
+}
+
+fn new_nonce() -> String {
+    let mut bytes = [0u8; CHALLENGE_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The primary asks the standby to prove the key on the nonce
+fn challenge(nonce: &str) -> String {
+    json!({"type": "StandbyChallenge", "nonce": nonce}).to_string()
+}
+
+fn parse_challenge(line: &str) -> Option<String> {
+    let json: Value = serde_json::from_str(line).ok()?;
+    if json["type"] != "StandbyChallenge" {
+        return None
+    }
+    json["nonce"].as_str().map(String::from)
+}
+
+/// Keyed hash binding the url to the nonce, a recorded hello is useless for another connection
+fn proof(nonce: &str, url: Option<&str>, key: &SecretKey) -> String {
+    key.hash(&format!("standby:{}:{}", nonce, url.unwrap_or_default()))
+}
+
+/// The standby introduces itself with the url clients reach it at, if any
+fn hello(nonce: &str, url: Option<&str>, key: &SecretKey) -> String {
+    json!({"type": "StandbyHello", "url": url, "proof": proof(nonce, url, key)}).to_string()
+}
+
+/// Url of the standby, None if the hello is malformed or its proof does not match
+fn parse_hello(line: &str, nonce: &str, key: &SecretKey) -> Option<Option<String>> {
+    let json: Value = serde_json::from_str(line).ok()?;
+    if json["type"] != "StandbyHello" {
+        return None
+    }
+    let url = json["url"].as_str().map(String::from);
+    if !constant_time_eq(json["proof"].as_str()?, &proof(nonce, url.as_deref(), key)) {
+        return None
+    }
+    Some(url)
+}
+
+/// Spawns a task following the primary at 'primary' (address of its replication listener)
+/// Every snapshot triggers the 'Replicated' event. The 'Takeover' event is triggered once the
+/// primary requests it or sent nothing for 'failover_timeout', the task ends afterwards.
+pub fn start_standby(channel: Bus, primary: String, url: Option<String>, failover_timeout: Duration, key: SecretKey) {
+    tokio::spawn(async move {
+        let mut last_seen = Instant::now();
+        let reason = loop {
+            match follow(&channel, &primary, url.as_deref(), failover_timeout, &mut last_seen, &key).await {
+                Ok(_) => break format!("Primary {} requested the takeover", primary),
+                Err(e) => warn!("start_standby(..): Following primary {} failed!\nError: {}", primary, e),
+            }
+            if last_seen.elapsed() >= failover_timeout {
+                break format!("Primary {} was unreachable for {:?}", primary, failover_timeout)
+            }
+            tokio::time::sleep(STANDBY_RECONNECT_DELAY).await;
+        };
+        channel.send(InternalMessage::Takeover {reason}).await.expect("start_standby(..): Sending internal message failed");
+    });
+}
+
+/// Forwards the replication stream of the primary to the main handler
+/// Returns once the primary requested the takeover, fails if the connection broke or stayed
+/// silent for 'failover_timeout'
+async fn follow(channel: &Bus, primary: &str, url: Option<&str>, failover_timeout: Duration, last_seen: &mut Instant, key: &SecretKey) -> Result<(), String> {
+    let stream = timeout(failover_timeout, TcpStream::connect(primary)).await
+        .map_err(|_| String::from("Connecting timed out"))?
+        .map_err(|e| e.to_string())?;
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let nonce = match timeout(failover_timeout, lines.next_line()).await {
+        Ok(Ok(Some(line))) => parse_challenge(&line).ok_or_else(|| String::from("Challenge of the primary is malformed"))?,
+        _ => return Err(String::from("Primary sent no challenge")),
+    };
+    write.write_all(format!("{}\n", hello(&nonce, url, key)).as_bytes()).await.map_err(|e| e.to_string())?;
+    info!("follow(..): Following primary {}", primary);
+
+    loop {
+        let line = match timeout(failover_timeout, lines.next_line()).await {
+            Err(_) => return Err(format!("Nothing received for {:?}", failover_timeout)),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Ok(Ok(None)) => return Err(String::from("Primary closed the connection")),
+            Ok(Ok(Some(line))) => line,
+        };
+        *last_seen = Instant::now();
+        match ReplicationMessage::parse(&line, key) {
+            Some(ReplicationMessage::Snapshot(snapshot)) =>
+                channel.send(InternalMessage::Replicated {snapshot: *snapshot}).await.expect("follow(..): Sending internal message failed"),
+            Some(ReplicationMessage::Takeover) => return Ok(()),
+            None => warn!("follow(..): Replication message is malformed or not encrypted with {}. Dropping!", SECRET_KEY_ENV),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::rejoin::RejoinGrant;
+
+    fn snapshot(key: &SecretKey) -> (Snapshot, String) {
+        let mut rejoin = RejoinTokens::default();
+        let grant = RejoinGrant {name: String::from("alice"), role: None, team: Some(String::from("red")), guest: false, answered_state: Some(2), last_state: Some(2), expires_at: 5000};
+        let token = rejoin.issue(grant, 1000, key);
+        let snapshot = Snapshot {
+            session: Some(Session {started: 1000, ends_at: Some(9000), generation: 3, tenant: Some(String::from("acme")), template: None}),
+            state: Some((2, String::from("Question"))),
+            clients: vec![ReplicatedClient {name: String::from("alice"), address: String::from("10.0.0.1:5000"), team: Some(String::from("red"))}],
+            announcement: Some((String::from("Maintenance"), Some(8000))),
+            access_code: Some(String::from("secret-code")),
+            rejoin,
+        };
+        (snapshot, token)
+    }
+
+    #[test]
+    fn snapshot_survives_the_round_trip() {
+        let key = SecretKey::new("shared key");
+        let (snapshot, token) = snapshot(&key);
+        let json = snapshot.to_json(&key);
+        assert!(!json.to_string().contains("secret-code"));
+
+        let mut restored = Snapshot::from_json(&json, &key).unwrap();
+        let session = restored.session.as_ref().unwrap();
+        assert_eq!((session.started, session.ends_at, session.generation), (1000, Some(9000), 3));
+        assert_eq!((session.tenant.as_deref(), session.template.as_deref()), (Some("acme"), None));
+        assert_eq!(restored.state, snapshot.state);
+        assert_eq!(restored.clients.len(), 1);
+        assert_eq!((restored.clients[0].name.as_str(), restored.clients[0].address.as_str(), restored.clients[0].team.as_deref()),
+            ("alice", "10.0.0.1:5000", Some("red")));
+        assert_eq!(restored.announcement, snapshot.announcement);
+        assert_eq!(restored.access_code.as_deref(), Some("secret-code"));
+        assert_eq!(restored.rejoin.redeem(&token, 2000, &key).map(|grant| grant.name), Some(String::from("alice")));
+    }
+
+    #[test]
+    fn snapshot_of_another_key_is_rejected() {
+        let key = SecretKey::new("shared key");
+        let json = snapshot(&key).0.to_json(&key);
+        assert!(Snapshot::from_json(&json, &SecretKey::new("other key")).is_none());
+        let line = ReplicationMessage::Takeover.encode(&key);
+        assert!(matches!(ReplicationMessage::parse(&line, &key), Some(ReplicationMessage::Takeover)));
+        assert!(ReplicationMessage::parse(&line, &SecretKey::new("other key")).is_none());
+        assert!(ReplicationMessage::parse(r#"{"type": "Takeover"}"#, &key).is_none());
+    }
+
+    #[test]
+    fn hello_has_to_prove_the_key_on_the_nonce() {
+        let key = SecretKey::new("shared key");
+        let nonce = new_nonce();
+        assert_eq!(parse_challenge(&challenge(&nonce)), Some(nonce.clone()));
+        let line = hello(&nonce, Some("wss://standby.example"), &key);
+        assert_eq!(parse_hello(&line, &nonce, &key), Some(Some(String::from("wss://standby.example"))));
+        assert_eq!(parse_hello(&line, &new_nonce(), &key), None);
+        assert_eq!(parse_hello(&hello(&nonce, None, &SecretKey::new("other key")), &nonce, &key), None);
+        assert_eq!(parse_hello(r#"{"type": "StandbyHello", "url": "wss://attacker.example"}"#, &nonce, &key), None);
+        // The proof covers the url, it can not be moved to another one
+        let forged = line.replace("standby.example", "attacker.example");
+        assert_eq!(parse_hello(&forged, &nonce, &key), None);
+    }
+}
@@ -0,0 +1,103 @@
+//!
+//! Resuming after a dropped connection.
+//! Every client receives a random resume token with 'Resume' after its login. Reconnecting within
+//! the disconnect grace period (TT_BACKEND_DISCONNECT_GRACE) it presents the token as 'resume' of
+//! its 'ClientLogin' and continues as the same participant: its recorded inputs and whether it
+//! answered the current state carry over, no matter which name it logs in with. Only keyed hashes
+//! of the tokens are kept, the hash is the identity of the participant.
+//!
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use rand::RngCore;
+use crate::server::secrets::SecretKey;
+
+const TOKEN_BYTES: usize = 16;
+
+/// Participant whose connection dropped, kept until its grace period is over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Departed {
+    pub name: String,
+    /// Address its inputs are recorded under
+    pub address: SocketAddr,
+    pub team: Option<String>,
+    pub answered_state: Option<i32>,
+    pub last_state: Option<i32>,
+    /// Server timestamp of the disconnect, tells the grace periods of repeated disconnects apart
+    pub departed_at: i64,
+}
+
+/// Departed participants by identity
+#[derive(Debug, Default)]
+pub struct DepartedClients {
+    departed: HashMap<String, Departed>,
+}
+
+impl DepartedClients {
+    pub fn depart(&mut self, identity: &str, departed: Departed) {
+        self.departed.insert(String::from(identity), departed);
+    }
+
+    /// Removes the participant returning with the identity
+    pub fn resume(&mut self, identity: &str) -> Option<Departed> {
+        self.departed.remove(identity)
+    }
+
+    /// Removes the participant once the grace period started at 'departed_at' is over, None if it
+    /// returned in the meantime (and maybe departed again)
+    pub fn expire(&mut self, identity: &str, departed_at: i64) -> Option<Departed> {
+        match self.departed.get(identity) {
+            Some(departed) if departed.departed_at == departed_at => self.departed.remove(identity),
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.departed.clear();
+    }
+}
+
+/// New random resume token and the identity it stands for
+pub fn issue_resume_token(key: &SecretKey) -> (String, String) {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let identity = key.hash(&token);
+    (token, identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn departed(name: &str, departed_at: i64) -> Departed {
+        Departed {name: String::from(name), address: "127.0.0.1:5000".parse().unwrap(), team: None, answered_state: Some(2), last_state: Some(2), departed_at}
+    }
+
+    #[test]
+    fn token_stands_for_its_identity() {
+        let key = SecretKey::new("key material");
+        let (token, identity) = issue_resume_token(&key);
+        assert_eq!(key.hash(&token), identity);
+        assert_ne!(issue_resume_token(&key).1, identity);
+    }
+
+    #[test]
+    fn returning_participant_is_found_by_identity_only() {
+        let mut clients = DepartedClients::default();
+        clients.depart("alice-id", departed("alice", 100));
+        assert_eq!(clients.resume("other-id"), None);
+        assert_eq!(clients.resume("alice-id"), Some(departed("alice", 100)));
+        assert_eq!(clients.expire("alice-id", 100), None);
+    }
+
+    #[test]
+    fn outdated_grace_period_does_not_expire_a_later_departure() {
+        let mut clients = DepartedClients::default();
+        clients.depart("alice-id", departed("alice", 100));
+        clients.resume("alice-id");
+        clients.depart("alice-id", departed("alice", 200));
+        assert_eq!(clients.expire("alice-id", 100), None);
+        assert_eq!(clients.expire("alice-id", 200), Some(departed("alice", 200)));
+    }
+}
@@ -0,0 +1,60 @@
+//!
+//! Recovery of clients that lost track of the current state.
+//! A client sending 'RequestResync' gets the current state once more, followed by the updates the
+//! host sent within that state and a 'Resynced' with the latest sequence number. Updates apply on
+//! top of their state, so they are buffered from one state change to the next. A state with more
+//! updates than the buffer holds can only be recovered partially, 'Resynced' tells the client.
+//!
+
+use std::collections::VecDeque;
+use crate::server::factory::StampedMessage;
+use crate::server::memory::message_bytes;
+
+/// Updates kept per state, older ones are dropped
+pub const UPDATE_BUFFER_SIZE: usize = 256;
+
+#[derive(Debug, Default)]
+pub struct UpdateBuffer {
+    updates: VecDeque<StampedMessage>,
+    /// Whether updates of the current state were dropped
+    overflowed: bool,
+}
+
+impl UpdateBuffer {
+    /// Updates of the previous state no longer apply
+    pub fn state_changed(&mut self) {
+        self.updates.clear();
+        self.overflowed = false;
+    }
+
+    pub fn push(&mut self, update: StampedMessage) {
+        if self.updates.len() == UPDATE_BUFFER_SIZE {
+            self.updates.pop_front();
+            self.overflowed = true;
+        }
+        self.updates.push_back(update);
+    }
+
+    /// The buffered updates in order, and whether they are all updates of the current state
+    pub fn replay(&self) -> (Vec<StampedMessage>, bool) {
+        (self.updates.iter().cloned().collect(), !self.overflowed)
+    }
+
+    /// Approximate bytes of the buffered updates
+    pub fn bytes(&self) -> usize {
+        self.updates.iter().map(|update| message_bytes(update.message())).sum()
+    }
+
+    /// Drops the oldest updates until 'bytes' are freed (or none are left), returns the freed bytes
+    pub fn trim(&mut self, bytes: usize) -> usize {
+        let mut freed = 0;
+        while freed < bytes {
+            match self.updates.pop_front() {
+                None => break,
+                Some(update) => freed += message_bytes(update.message()),
+            }
+            self.overflowed = true;
+        }
+        freed
+    }
+}
@@ -0,0 +1,135 @@
+//!
+//! Retention of the data the server stores on disk.
+//! Every retention interval, the usage reports exported to a file are pruned once they are older
+//! than the retention age of their tenant ('retention_days' in the tenant file) or the default age
+//! (TT_BACKEND_RETENTION_DAYS). Data without an age is kept forever. The pruned reports are logged
+//! and returned to the admin interface, so deletions can be documented (e.g. for schools).
+//! Usage exports are the only data written to disk so far: input recordings are kept in memory
+//! and dropped with their client, and there is no audit log. Once either is stored, it has to be
+//! pruned here as well.
+//!
+
+use std::collections::HashMap;
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::server::usage::UsageExport;
+
+/// Interval between two prunes, if not configured
+pub const DEFAULT_RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Age of data without a tenant or of tenants without an own age
+    pub max_age: Option<Duration>,
+    /// Ages of the tenants, by name
+    pub tenants: HashMap<String, Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || !self.tenants.is_empty()
+    }
+
+    pub fn max_age(&self, tenant: Option<&str>) -> Option<Duration> {
+        tenant.and_then(|tenant| self.tenants.get(tenant)).copied().or(self.max_age)
+    }
+
+    /// Whether data of the tenant from 'timestamp' is too old at 'now'
+    pub fn is_expired(&self, tenant: Option<&str>, timestamp: i64, now: i64) -> bool {
+        self.max_age(tenant).is_some_and(|age| timestamp < now - age.as_millis() as i64)
+    }
+}
+
+/// Prunes everything the policy covers
+/// Returns what was deleted, per file and tenant
+pub async fn enforce(policy: &RetentionPolicy, usage_export: Option<&UsageExport>, now: i64) -> Result<Value, String> {
+    let mut files = vec![];
+    if let Some(export) = usage_export {
+        if let Some((path, removed)) = export.prune(|tenant, reported_at| policy.is_expired(tenant, reported_at, now)).await? {
+            files.push(json!({"file": path.display().to_string(), "kind": "usage", "removed": removed}));
+        }
+    }
+    Ok(json!({"pruned_at": now, "files": files}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::server::timefmt::{Locale, TimeFormat};
+    use crate::server::usage::UsageReport;
+
+    const DAY: i64 = 24 * 3600 * 1000;
+    const NOW: i64 = 1_790_000_000_000;
+
+    fn policy() -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: Some(Duration::from_secs(30 * 24 * 3600)),
+            tenants: HashMap::from([(String::from("school"), Duration::from_secs(7 * 24 * 3600))]),
+        }
+    }
+
+    fn report(tenant: Option<&str>, reported_at: i64) -> UsageReport {
+        UsageReport {
+            tenant: tenant.map(String::from),
+            session: 1,
+            started: reported_at - 3_600_000,
+            reported_at,
+            connection_minutes: 12.5,
+            peak_clients: 3,
+            bytes_relayed: 4096,
+            final_report: true,
+        }
+    }
+
+    fn export_file(extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tt_online_retention_{}_{}.{}", std::process::id(), extension, extension))
+    }
+
+    #[test]
+    fn tenant_age_overrides_the_default() {
+        let policy = policy();
+        assert!(policy.is_enabled());
+        assert!(policy.is_expired(Some("school"), NOW - 8 * DAY, NOW));
+        assert!(!policy.is_expired(Some("school"), NOW - 6 * DAY, NOW));
+        // Tenants without an own age and data without a tenant get the default age
+        assert!(!policy.is_expired(Some("quiz_club"), NOW - 8 * DAY, NOW));
+        assert!(policy.is_expired(Some("quiz_club"), NOW - 31 * DAY, NOW));
+        assert!(policy.is_expired(None, NOW - 31 * DAY, NOW));
+
+        let tenants_only = RetentionPolicy {max_age: None, ..policy};
+        assert!(!tenants_only.is_expired(None, 0, NOW));
+        assert!(tenants_only.is_expired(Some("school"), NOW - 8 * DAY, NOW));
+        assert!(!RetentionPolicy::default().is_enabled());
+    }
+
+    #[tokio::test]
+    async fn expired_usage_reports_are_pruned() {
+        let format = TimeFormat {timezone: TimeFormat::parse_timezone("Europe/Berlin").unwrap(), locale: Locale::DayMonthDot};
+        for (path, export) in [(export_file("json"), UsageExport::Json(export_file("json"))), (export_file("csv"), UsageExport::Csv(export_file("csv")))] {
+            let _ = std::fs::remove_file(&path);
+            for report in [report(Some("school"), NOW - 8 * DAY), report(Some("school"), NOW - DAY), report(None, NOW - 31 * DAY), report(Some("quiz_club"), NOW - 8 * DAY)] {
+                export.export(&report, &format).await.unwrap();
+            }
+
+            let pruned = enforce(&policy(), Some(&export), NOW).await.unwrap();
+            let content = std::fs::read_to_string(&path).unwrap();
+            let again = enforce(&policy(), Some(&export), NOW).await.unwrap();
+            let _ = std::fs::remove_file(&path);
+            assert_eq!(pruned["files"][0]["removed"], json!({"school": 1, "": 1}), "{}", path.display());
+            // Kept are the header and the reports still within their age
+            assert_eq!(content.lines().filter(|line| line.contains("school") || line.contains("quiz_club")).count(), 2, "{}", content);
+            assert_eq!(again["files"][0]["removed"], json!({}));
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_and_pushed_exports_are_nothing_to_prune() {
+        let path = std::env::temp_dir().join(format!("tt_online_retention_{}_missing.json", std::process::id()));
+        let pruned = enforce(&policy(), Some(&UsageExport::Json(path)), NOW).await.unwrap();
+        assert_eq!(pruned["files"][0]["removed"], json!({}));
+        let pushed = UsageExport::parse("https://usage.example.org/report").unwrap();
+        assert_eq!(enforce(&policy(), Some(&pushed), NOW).await.unwrap()["files"], json!([]));
+        assert_eq!(enforce(&policy(), None, NOW).await.unwrap(), json!({"pruned_at": NOW, "files": []}));
+    }
+}
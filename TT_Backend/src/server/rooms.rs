@@ -0,0 +1,297 @@
+//!
+//! Rooms, the sessions of further hosts, identified by a session id and a short join code.
+//! Besides the session of the server, every host may open a room of its own by logging in with
+//! '"room": true'. It is answered with 'RoomOpened', the join code and the session id. Clients join
+//! the room by giving the code as 'room' of their 'ClientLogin'. A host returns to its room by
+//! logging in with the session id as 'room', the id is only known to the host. Hosts and clients
+//! without a room use the session of the server as before.
+//! Every room runs its own main handler with its own host, state, clients, rules and timers, so
+//! state changes and broadcasts stay within the room. The server routes the events of the
+//! connections in a room to it and shares its periodic checks with the rooms. A room closes once
+//! neither its host nor any client is connected.
+//! A room belongs to the tenant of the host that opened it and counts against its session quota.
+//! The operator lists the open rooms with 'GET /rooms' on the admin interface.
+//! Rooms are not replicated to standbys, not handed over by upgrades and not shown by the live
+//! view. An upgrade ends them, closing their clients and hosts with the upgrade as reason, a
+//! failover closes them as ended sessions.
+//!
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use async_trait::async_trait;
+use log::debug;
+use rand::{Rng, RngCore};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use crate::server::InternalMessage;
+use crate::server::bus::{Bus, BusClosed, EventBus, EventSource};
+use crate::server::config::ServerConfig;
+
+pub const REJECT_REASON_UNKNOWN_ROOM: &str = "Unknown room";
+pub const REJECT_REASON_ROOM_LIMIT: &str = "No further rooms can be opened";
+/// Rooms open at once
+pub const MAX_ROOMS: usize = 64;
+const ROOM_CODE_LENGTH: usize = 6;
+/// Upper case letters and digits without the ones easily confused (0/O, 1/I)
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+/// Random bytes of a session id
+const SESSION_ID_BYTES: usize = 16;
+
+/// Room a host asks for at its login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomRequest {
+    Open,
+    /// Return to the room of the session id
+    Return(String),
+}
+
+impl RoomRequest {
+    /// 'true' opens a room, a string returns to the room of the session id
+    pub fn parse(json: &Value) -> Option<Self> {
+        match json {
+            Value::Bool(true) => Some(RoomRequest::Open),
+            Value::String(session) => Some(RoomRequest::Return(String::from(session.trim()))),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn to_json(&self) -> Value {
+        match self {
+            RoomRequest::Open => Value::Bool(true),
+            RoomRequest::Return(code) => Value::String(code.clone()),
+        }
+    }
+}
+
+/// Join codes are typed by people, case and surrounding spaces do not matter
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+#[derive(Debug)]
+struct Room {
+    code: String,
+    /// Bus of the main handler of the room
+    bus: Bus,
+    tenant: Option<String>,
+}
+
+/// Open rooms and the connections routed to them
+#[derive(Debug, Default)]
+pub struct Rooms {
+    /// Rooms by session id
+    rooms: HashMap<String, Room>,
+    /// Session id of every room by join code
+    codes: HashMap<String, String>,
+    /// Session id of the room every connection belongs to
+    routes: HashMap<SocketAddr, String>,
+}
+
+impl Rooms {
+    /// Registers a room of the tenant handling the events of the bus, returns its new session id
+    /// and join code
+    /// None if MAX_ROOMS are open already
+    pub fn open(&mut self, bus: Bus, tenant: Option<String>) -> Option<(String, String)> {
+        if self.rooms.len() >= MAX_ROOMS {
+            return None
+        }
+        let code = loop {
+            let code = new_code();
+            if !self.codes.contains_key(&code) {
+                break code
+            }
+        };
+        let session = new_session_id();
+        self.codes.insert(code.clone(), session.clone());
+        self.rooms.insert(session.clone(), Room {code: code.clone(), bus, tenant});
+        Some((session, code))
+    }
+
+    /// Removes the room and the routes of its connections
+    pub fn close(&mut self, session: &str) {
+        if let Some(room) = self.rooms.remove(session) {
+            self.codes.remove(&room.code);
+        }
+        self.routes.retain(|_, room| room != session);
+    }
+
+    pub fn get(&self, session: &str) -> Option<&Bus> {
+        self.rooms.get(session).map(|room| &room.bus)
+    }
+
+    /// Session id of the room the join code belongs to
+    pub fn session_of(&self, code: &str) -> Option<&str> {
+        self.codes.get(&normalize_code(code)).map(String::as_str)
+    }
+
+    /// Events of the connection are handled by the room of the session id from now on
+    pub fn route(&mut self, address: SocketAddr, session: &str) {
+        self.routes.insert(address, String::from(session));
+    }
+
+    /// Forgets the room of a former connection, e.g. once its address is used by a new one
+    pub fn forget(&mut self, address: SocketAddr) {
+        self.routes.remove(&address);
+    }
+
+    /// Bus of the room the connection belongs to, None for connections of the server's session
+    pub fn bus_of(&self, address: SocketAddr) -> Option<&Bus> {
+        self.routes.get(&address).and_then(|session| self.get(session))
+    }
+
+    pub fn buses(&self) -> impl Iterator<Item = &Bus> {
+        self.rooms.values().map(|room| &room.bus)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    /// Rooms of the tenant open at the moment
+    pub fn of_tenant(&self, tenant: &str) -> usize {
+        self.rooms.values().filter(|room| room.tenant.as_deref() == Some(tenant)).count()
+    }
+
+    /// Open rooms by join code with their tenant and connections, without the session ids
+    pub fn to_json(&self) -> Value {
+        let mut rooms: Vec<Value> = self.rooms.iter()
+            .map(|(session, room)| json!({
+                "code": room.code,
+                "tenant": room.tenant,
+                "connections": self.routes.values().filter(|other| *other == session).count(),
+            }))
+            .collect();
+        rooms.sort_by(|a, b| a["code"].as_str().cmp(&b["code"].as_str()));
+        json!({"rooms": rooms})
+    }
+}
+
+fn new_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..ROOM_CODE_LENGTH)
+        .map(|_| ROOM_CODE_ALPHABET[rng.gen_range(0..ROOM_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn new_session_id() -> String {
+    let mut bytes = [0u8; SESSION_ID_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Configuration of the main handler of a room, without everything only the server's session
+/// has (listeners, standby, stdio host) and with its secret key, so the rooms share it
+pub fn room_config(config: &ServerConfig, key_material: &str) -> ServerConfig {
+    let mut config = config.clone();
+    config.replication_port = None;
+    config.live_view_port = None;
+    config.standby_of = None;
+    config.host_stdio = None;
+    config.acme = None;
+    // The join code admits the clients of a room
+    config.access_code = None;
+    config.secret_key = Some(String::from(key_material));
+    config
+}
+
+/// Creates the bus of a room holding up to 'capacity' events
+/// Events published once the room is closed (e.g. by a timer it started) are dropped
+pub fn room_bus(capacity: usize) -> (Bus, Box<dyn EventSource>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    (Bus::new(Arc::new(RoomBus { sender, capacity })), Box::new(RoomEvents { receiver }))
+}
+
+#[derive(Debug)]
+struct RoomBus {
+    sender: mpsc::Sender<InternalMessage>,
+    capacity: usize,
+}
+
+#[async_trait]
+impl EventBus for RoomBus {
+    async fn send(&self, message: InternalMessage) -> Result<(), BusClosed> {
+        if let Err(e) = self.sender.send(message).await {
+            debug!("send(..): Dropping event of a closed room: {:?}", e.0);
+        }
+        Ok(())
+    }
+
+    fn queued(&self) -> Option<usize> {
+        Some(self.capacity - self.sender.capacity())
+    }
+}
+
+#[derive(Debug)]
+struct RoomEvents {
+    receiver: mpsc::Receiver<InternalMessage>,
+}
+
+#[async_trait]
+impl EventSource for RoomEvents {
+    async fn recv(&mut self) -> Option<InternalMessage> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_short_and_unambiguous() {
+        let code = new_code();
+        assert_eq!(code.len(), ROOM_CODE_LENGTH);
+        assert!(code.bytes().all(|byte| ROOM_CODE_ALPHABET.contains(&byte)));
+        assert_eq!(RoomRequest::parse(&Value::from(" 3f2a ")), Some(RoomRequest::Return(String::from("3f2a"))));
+        assert_eq!(RoomRequest::parse(&Value::Bool(true)), Some(RoomRequest::Open));
+        assert_eq!(RoomRequest::parse(&Value::Bool(false)), None);
+    }
+
+    #[tokio::test]
+    async fn connections_are_routed_to_their_room() {
+        let mut rooms = Rooms::default();
+        let (session, code) = rooms.open(room_bus(4).0, None).unwrap();
+        assert_eq!(rooms.session_of(&code.to_lowercase()), Some(session.as_str()));
+        // The join code does not stand in for the session id
+        assert!(rooms.get(&code).is_none());
+        let client: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let other: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        rooms.route(client, &session);
+        assert!(rooms.bus_of(client).is_some());
+        assert!(rooms.bus_of(other).is_none());
+        assert_eq!(rooms.to_json()["rooms"][0]["connections"], 1);
+
+        rooms.forget(client);
+        assert!(rooms.bus_of(client).is_none());
+        rooms.route(client, &session);
+        rooms.close(&session);
+        assert!(rooms.bus_of(client).is_none());
+        assert!(rooms.get(&session).is_none());
+        assert_eq!(rooms.session_of(&code), None);
+    }
+
+    #[tokio::test]
+    async fn rooms_are_limited() {
+        let mut rooms = Rooms::default();
+        for _ in 0..MAX_ROOMS {
+            assert!(rooms.open(room_bus(1).0, Some(String::from("quiz_club"))).is_some());
+        }
+        assert_eq!(rooms.open(room_bus(1).0, None), None);
+        assert_eq!(rooms.len(), MAX_ROOMS);
+        assert_eq!(rooms.of_tenant("quiz_club"), MAX_ROOMS);
+        assert_eq!(rooms.of_tenant("other"), 0);
+    }
+
+    #[tokio::test]
+    async fn events_of_a_closed_room_are_dropped() {
+        let (bus, events) = room_bus(1);
+        drop(events);
+        assert_eq!(bus.send(InternalMessage::ClientPingDue).await, Ok(()));
+    }
+}
@@ -0,0 +1,234 @@
+//!
+//! Small rules engine evaluated by the main handler.
+//! The host sends its rules with 'SetRules', every rule fires at most once per state. Actions are
+//! expressed as existing messages: a notification to the host or an 'Update'/'ChangeState' executed
+//! as if the host had sent it. A rule stays fired in the state its own 'ChangeState' set, so it can
+//! not fire again and again.
+//!
+//! Example rules:
+//! {"name": "almost_all", "when": {"inputs": 0.9}, "then": {"notify": "90% answered"}}
+//! {"name": "timeout", "when": {"after": 60}, "then": {"send": {"type": "ChangeState", ...}}}
+//!
+
+use std::time::Duration;
+use log::warn;
+use serde_json::Value;
+use crate::server::messages::{HostMessage, parse_host_msg};
+
+/// Condition of a rule, always evaluated for the current state
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// Share (0.0 - 1.0) of the connected clients has sent an input, optionally only in one state
+    InputShare { share: f64, state_id: Option<i32> },
+    /// Time since the state was set
+    StateAge { after: Duration },
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Sends a 'RuleTriggered' message with the text to the host
+    NotifyHost { message: String },
+    /// Executes the 'Update' or 'ChangeState' as if the host had sent it
+    Execute(HostMessage),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub trigger: Trigger,
+    pub action: Action,
+}
+
+impl Rule {
+    /// Parses a rule as described in the module documentation
+    pub fn parse(json: &Value) -> Option<Self> {
+        let name = json["name"].as_str().unwrap_or_default().to_string();
+
+        let when = &json["when"];
+        let trigger = if let Some(share) = when["inputs"].as_f64() {
+            let state_id = when["state_id"].as_i64().map(|v| v as i32);
+            Trigger::InputShare { share, state_id }
+        } else if let Some(after) = when["after"].as_u64() {
+            if after == 0 {
+                warn!("parse(..): Rule '{}' would fire immediately, 'after' has to be at least 1 second\nrule: {}", name, json);
+                return None
+            }
+            Trigger::StateAge { after: Duration::from_secs(after) }
+        } else {
+            warn!("parse(..): Rule '{}' has no valid trigger\nrule: {}", name, json);
+            return None
+        };
+
+        let then = &json["then"];
+        let action = if let Some(message) = then["notify"].as_str() {
+            Action::NotifyHost { message: String::from(message) }
+        } else {
+            match parse_host_msg(&then["send"].to_string()) {
+                Some(msg @ HostMessage::Update { .. }) | Some(msg @ HostMessage::ChangeState { .. }) => Action::Execute(msg),
+                _ => {
+                    warn!("parse(..): Rule '{}' has no valid action, expecting 'notify' or 'send' with an 'Update' or 'ChangeState'\nrule: {}", name, json);
+                    return None
+                }
+            }
+        };
+
+        Some(Rule { name, trigger, action })
+    }
+}
+
+/// The rules of the session and which of them already fired in the current state
+#[derive(Debug, Default)]
+pub struct RulesEngine {
+    rules: Vec<Rule>,
+    fired: Vec<bool>,
+    /// Incremented with every state change, so timers of old states can be recognized
+    generation: u64,
+}
+
+impl RulesEngine {
+    /// Replaces all rules, restart(..) has to be called afterwards
+    pub fn set_rules(&mut self, rules: Vec<Rule>) {
+        self.fired = vec![false; rules.len()];
+        self.rules = rules;
+    }
+
+    /// Resets the fired rules, called whenever the state changes
+    /// The rule whose action changed the state ('cause') stays fired in the new state
+    /// Returns the generation of the new state and the timers to start (rule index and delay)
+    pub fn restart(&mut self, cause: Option<usize>) -> (u64, Vec<(usize, Duration)>) {
+        self.generation += 1;
+        for (index, fired) in self.fired.iter_mut().enumerate() {
+            *fired = Some(index) == cause;
+        }
+        let timers = self.rules.iter().enumerate()
+            .filter(|(index, _)| Some(*index) != cause)
+            .filter_map(|(index, rule)| match rule.trigger {
+                Trigger::StateAge { after } => Some((index, after)),
+                _ => None,
+            })
+            .collect();
+        (self.generation, timers)
+    }
+
+    /// Returns the index, name and action of the timer rule if it has to fire now
+    pub fn timer_expired(&mut self, generation: u64, index: usize) -> Option<(usize, String, Action)> {
+        if generation != self.generation {
+            return None
+        }
+        self.fire(index)
+    }
+
+    /// Returns the index, name and action of all input rules that have to fire now
+    pub fn inputs_changed(&mut self, state_id: i32, answered: usize, participants: usize) -> Vec<(usize, String, Action)> {
+        if participants == 0 {
+            return vec![]
+        }
+        let share = answered as f64 / participants as f64;
+        let due: Vec<usize> = self.rules.iter().enumerate()
+            .filter(|(_, rule)| match rule.trigger {
+                Trigger::InputShare { share: threshold, state_id: rule_state } =>
+                    share >= threshold && rule_state.map(|v| v == state_id).unwrap_or(true),
+                _ => false,
+            })
+            .map(|(index, _)| index)
+            .collect();
+        due.into_iter().filter_map(|index| self.fire(index)).collect()
+    }
+
+    fn fire(&mut self, index: usize) -> Option<(usize, String, Action)> {
+        match self.fired.get_mut(index) {
+            Some(fired) if !*fired => {
+                *fired = true;
+                let rule = &self.rules[index];
+                Some((index, rule.name.clone(), rule.action.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn rule(json: Value) -> Rule {
+        Rule::parse(&json).expect("Rule was rejected")
+    }
+
+    fn names(fired: Vec<(usize, String, Action)>) -> Vec<String> {
+        fired.into_iter().map(|(_, name, _)| name).collect()
+    }
+
+    fn engine(rules: Vec<Rule>) -> RulesEngine {
+        let mut engine = RulesEngine::default();
+        engine.set_rules(rules);
+        engine
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let notify = rule(json!({"name": "most", "when": {"inputs": 0.8, "state_id": 3}, "then": {"notify": "80% answered"}}));
+        assert!(matches!(notify.trigger, Trigger::InputShare {state_id: Some(3), ..}));
+        assert!(matches!(notify.action, Action::NotifyHost {..}));
+
+        let next = rule(json!({"name": "next", "when": {"after": 30}, "then": {"send": {"type": "ChangeState", "state_id": 4, "content": "q4"}}}));
+        assert!(matches!(next.trigger, Trigger::StateAge {after} if after == Duration::from_secs(30)));
+        assert!(matches!(next.action, Action::Execute(HostMessage::ChangeState {state_id: 4, ..})));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let notify = json!({"notify": "Time is up"});
+        assert!(Rule::parse(&json!({"name": "now", "when": {"after": 0}, "then": notify})).is_none());
+        assert!(Rule::parse(&json!({"name": "never", "when": {}, "then": notify})).is_none());
+        assert!(Rule::parse(&json!({"name": "kick", "when": {"after": 5}, "then": {"send": {"type": "MuteClient", "id": "x", "muted": true}}})).is_none());
+    }
+
+    #[test]
+    fn input_rule_fires_once_per_state() {
+        let mut engine = engine(vec![
+            rule(json!({"name": "half", "when": {"inputs": 0.5}, "then": {"notify": "half"}})),
+            rule(json!({"name": "q2", "when": {"inputs": 0.5, "state_id": 2}, "then": {"notify": "q2"}})),
+        ]);
+        engine.restart(None);
+        assert!(engine.inputs_changed(1, 1, 0).is_empty());
+        assert!(engine.inputs_changed(1, 1, 3).is_empty());
+        assert_eq!(names(engine.inputs_changed(1, 2, 3)), vec!["half"]);
+        assert!(engine.inputs_changed(1, 3, 3).is_empty());
+
+        engine.restart(None);
+        assert_eq!(names(engine.inputs_changed(2, 2, 3)), vec!["half", "q2"]);
+    }
+
+    #[test]
+    fn timer_of_an_old_state_does_not_fire() {
+        let mut engine = engine(vec![rule(json!({"name": "timeout", "when": {"after": 60}, "then": {"notify": "Time is up"}}))]);
+        let (old, timers) = engine.restart(None);
+        assert_eq!(timers, vec![(0, Duration::from_secs(60))]);
+        let (current, _) = engine.restart(None);
+
+        assert!(engine.timer_expired(old, 0).is_none());
+        assert_eq!(engine.timer_expired(current, 0).map(|(_, name, _)| name), Some(String::from("timeout")));
+        assert!(engine.timer_expired(current, 0).is_none());
+    }
+
+    #[test]
+    fn rule_changing_the_state_is_not_armed_again() {
+        let mut engine = engine(vec![
+            rule(json!({"name": "next", "when": {"after": 10}, "then": {"send": {"type": "ChangeState", "state_id": 2, "content": "q2"}}})),
+            rule(json!({"name": "reminder", "when": {"after": 5}, "then": {"notify": "Hurry up"}})),
+        ]);
+        let (generation, _) = engine.restart(None);
+        let (index, _, _) = engine.timer_expired(generation, 0).expect("Rule did not fire");
+
+        // The state change caused by the rule re-arms only the other rules
+        let (generation, timers) = engine.restart(Some(index));
+        assert_eq!(timers, vec![(1, Duration::from_secs(5))]);
+        assert!(engine.timer_expired(generation, 0).is_none());
+
+        // A state change by the host arms it again
+        let (_, timers) = engine.restart(None);
+        assert_eq!(timers.len(), 2);
+    }
+}
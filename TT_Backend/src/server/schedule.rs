@@ -0,0 +1,129 @@
+//!
+//! Messages the server sends on behalf of the host at a given time.
+//! With 'ScheduleMessage' the host hands over an 'Update', 'ChangeState' or 'ShowLeaderboard' to
+//! be executed 'at' a server timestamp, every 'interval' milliseconds, or both (first at 'at', then
+//! every 'interval'). A scheduled message fires from the server clock even if the HostApp is busy
+//! or disconnected at the time, the host is told with 'ScheduledMessageSent' when it is connected.
+//! Scheduling with the id of a pending message replaces it. 'ListScheduledMessages' and
+//! 'CancelScheduledMessage' are answered with the pending messages, as is every 'ScheduleMessage'.
+//! Like timers they end with the session.
+//!
+
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::server::messages::HostMessage;
+
+/// Pending messages per session, further ones are rejected
+pub const MAX_SCHEDULED_MESSAGES: usize = 64;
+/// Shortest interval of a recurring message
+pub const MIN_SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ScheduledMessage {
+    pub message: HostMessage,
+    /// Server timestamp (milliseconds) of the next send
+    pub next_at: i64,
+    /// Time between the sends of a recurring message
+    pub interval: Option<Duration>,
+    /// Distinguishes replaced messages with the same id, wake-ups of older ones are ignored
+    pub generation: u64,
+}
+
+/// Pending message as listed to the host
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleEntry {
+    pub id: String,
+    /// Type of the scheduled message
+    pub message_type: String,
+    pub next_at: i64,
+    /// Milliseconds, None if sent once
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Schedules {
+    messages: HashMap<String, ScheduledMessage>,
+    generation: u64,
+}
+
+impl Schedules {
+    /// Schedules the message (replacing one with the same id), returns it
+    /// Errors if the message can not be scheduled or too many are pending
+    pub fn schedule(&mut self, id: &str, message: HostMessage, at: Option<i64>, interval: Option<Duration>, now: i64) -> Result<ScheduledMessage, String> {
+        if !matches!(message, HostMessage::Update {..} | HostMessage::ChangeState {..} | HostMessage::ShowLeaderboard {..}) {
+            return Err(String::from("Only 'Update', 'ChangeState' and 'ShowLeaderboard' can be scheduled"))
+        }
+        if interval.is_some_and(|interval| interval < MIN_SCHEDULE_INTERVAL) {
+            return Err(format!("The interval has to be at least {} ms", MIN_SCHEDULE_INTERVAL.as_millis()))
+        }
+        let next_at = match (at, interval) {
+            (Some(at), _) => at.max(now),
+            (None, Some(interval)) => now + interval.as_millis() as i64,
+            (None, None) => return Err(String::from("Neither 'at' nor 'interval' given")),
+        };
+        if !self.messages.contains_key(id) && self.messages.len() >= MAX_SCHEDULED_MESSAGES {
+            return Err(format!("At most {} messages can be scheduled", MAX_SCHEDULED_MESSAGES))
+        }
+        self.generation += 1;
+        let scheduled = ScheduledMessage {message, next_at, interval, generation: self.generation};
+        self.messages.insert(String::from(id), scheduled.clone());
+        Ok(scheduled)
+    }
+
+    /// Returns false if no such message is pending
+    pub fn cancel(&mut self, id: &str) -> bool {
+        self.messages.remove(id).is_some()
+    }
+
+    /// Returns the message if the wake-up belongs to it, messages sent once are removed
+    pub fn due(&mut self, id: &str, generation: u64, now: i64) -> Option<HostMessage> {
+        let scheduled = self.messages.get_mut(id).filter(|scheduled| scheduled.generation == generation)?;
+        let message = scheduled.message.clone();
+        match scheduled.interval {
+            None => {
+                self.messages.remove(id);
+            }
+            Some(interval) => {
+                // Sends missed while the server was busy are not repeated
+                let interval = interval.as_millis() as i64;
+                while scheduled.next_at <= now {
+                    scheduled.next_at += interval;
+                }
+            }
+        }
+        Some(message)
+    }
+
+    /// Next send of the message, None if it is not pending (anymore)
+    pub fn next_at(&self, id: &str) -> Option<i64> {
+        self.messages.get(id).map(|scheduled| scheduled.next_at)
+    }
+
+    /// Drops all messages, wake-ups of them are ignored afterwards
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    /// The pending messages, the next one first
+    pub fn entries(&self) -> Vec<ScheduleEntry> {
+        let mut entries: Vec<ScheduleEntry> = self.messages.iter()
+            .map(|(id, scheduled)| ScheduleEntry {
+                id: id.clone(),
+                message_type: String::from(message_type(&scheduled.message)),
+                next_at: scheduled.next_at,
+                interval: scheduled.interval.map(|interval| interval.as_millis() as u64),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.next_at.cmp(&b.next_at).then_with(|| a.id.cmp(&b.id)));
+        entries
+    }
+}
+
+fn message_type(message: &HostMessage) -> &'static str {
+    match message {
+        HostMessage::Update {..} => "Update",
+        HostMessage::ChangeState {..} => "ChangeState",
+        HostMessage::ShowLeaderboard {..} => "ShowLeaderboard",
+        _ => "Unknown",
+    }
+}
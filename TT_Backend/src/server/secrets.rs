@@ -0,0 +1,176 @@
+//!
+//! Protection of secrets (rejoin tokens, access codes) before they leave the process.
+//! Secrets that only have to be verified are kept as keyed hash (rejoin tokens), secrets that have
+//! to be restored are encrypted (AES-256-GCM) wherever the session snapshot is written: the
+//! replication stream to standbys and the state file of an upgrade. The key material is taken from
+//! TT_BACKEND_SECRET_KEY, primary and standbys need the same. Without it a key is generated per
+//! process and handed to the new process of an upgrade, replication needs a configured key.
+//!
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, OsRng};
+use hmac::{Hmac, Mac};
+use log::warn;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+pub const SECRET_KEY_ENV: &str = "TT_BACKEND_SECRET_KEY";
+
+const NONCE_LENGTH: usize = 12;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes of generated key material
+const GENERATED_KEY_BYTES: usize = 32;
+
+/// Keys derived from the configured key material
+/// Hashing and encryption use different keys, so a stored hash never reveals anything about the
+/// encryption key and vice versa
+#[derive(Clone)]
+pub struct SecretKey {
+    /// Kept to hand the key to the process of an upgrade
+    material: String,
+    hash_key: [u8; 32],
+    encryption_key: [u8; 32],
+}
+
+impl SecretKey {
+    /// Derives the keys from arbitrary key material
+    pub fn new(key_material: &str) -> Self {
+        let master = Sha256::digest(key_material.as_bytes());
+        SecretKey {
+            material: String::from(key_material),
+            hash_key: derive_key(&master, b"hash"),
+            encryption_key: derive_key(&master, b"encryption"),
+        }
+    }
+
+    /// Key of random material, for a server without configured key
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; GENERATED_KEY_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        SecretKey::new(&to_hex(&bytes))
+    }
+
+    /// Key material the key was derived from
+    pub fn material(&self) -> &str {
+        &self.material
+    }
+
+    /// Keyed hash (hex encoded) of the secret, suitable for storing tokens that only need to be
+    /// verified later
+    pub fn hash(&self, secret: &str) -> String {
+        to_hex(&self.hash_mac(secret).finalize().into_bytes())
+    }
+
+    /// Encrypts the secret, the result (hex encoded nonce and ciphertext) can be restored with
+    /// decrypt(..)
+    pub fn encrypt(&self, secret: &str) -> String {
+        let cipher = Aes256Gcm::new(&self.encryption_key.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, secret.as_bytes())
+            .expect("encrypt(..): Encrypting secret failed");
+
+        let mut bytes = nonce.to_vec();
+        bytes.extend_from_slice(&ciphertext);
+        to_hex(&bytes)
+    }
+
+    /// Restores a secret produced by encrypt(..)
+    /// Returns None if the data is malformed, was tampered with or was encrypted with another key
+    pub fn decrypt(&self, encrypted: &str) -> Option<String> {
+        let bytes = from_hex(encrypted)?;
+        if bytes.len() < NONCE_LENGTH {
+            warn!("decrypt(..): Encrypted secret is too short");
+            return None
+        }
+
+        let cipher = Aes256Gcm::new(&self.encryption_key.into());
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let plaintext = match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+            Ok(v) => v,
+            Err(_) => {
+                warn!("decrypt(..): Decrypting secret failed, wrong key or corrupted data");
+                return None
+            }
+        };
+        String::from_utf8(plaintext).ok()
+    }
+
+    fn hash_mac(&self, secret: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.hash_key).expect("hash_mac(..): HMAC accepts keys of any size");
+        mac.update(secret.as_bytes());
+        mac
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SecretKey(..)")
+    }
+}
+
+fn derive_key(master: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(master).expect("derive_key(..): HMAC accepts keys of any size");
+    mac.update(purpose);
+    mac.finalize().into_bytes().into()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_depends_on_secret_and_key() {
+        let key = SecretKey::new("key material");
+        let hash = key.hash("token");
+        assert_eq!(hash, key.hash("token"));
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("token"));
+        assert_ne!(hash, key.hash("other token"));
+        assert_ne!(hash, SecretKey::new("other material").hash("token"));
+    }
+
+    #[test]
+    fn encrypted_secret_is_restored() {
+        let key = SecretKey::new("key material");
+        let encrypted = key.encrypt("K7QX4M");
+        assert!(!encrypted.contains("K7QX4M"));
+        // Every encryption has its own nonce
+        assert_ne!(encrypted, key.encrypt("K7QX4M"));
+        assert_eq!(key.decrypt(&encrypted).as_deref(), Some("K7QX4M"));
+    }
+
+    #[test]
+    fn tampered_or_foreign_secret_is_rejected() {
+        let key = SecretKey::new("key material");
+        let encrypted = key.encrypt("K7QX4M");
+        let last = encrypted.len() - 1;
+        let flipped = if &encrypted[last..] == "0" { "1" } else { "0" };
+        let tampered = format!("{}{}", &encrypted[..last], flipped);
+        assert_eq!(key.decrypt(&tampered), None);
+        assert_eq!(key.decrypt(&encrypted[..20]), None);
+        assert_eq!(key.decrypt("zz"), None);
+        assert_eq!(SecretKey::new("other material").decrypt(&encrypted), None);
+    }
+
+    #[test]
+    fn generated_keys_differ() {
+        let (first, second) = (SecretKey::generate(), SecretKey::generate());
+        assert_ne!(first.material(), second.material());
+        assert_eq!(SecretKey::new(first.material()).hash("token"), first.hash("token"));
+    }
+}
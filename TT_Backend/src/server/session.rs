@@ -0,0 +1,59 @@
+//!
+//! Scheduled end of a session.
+//! A session starts when a host connects while none is running. It ends after the configured
+//! maximum duration or at the configured absolute end time, whichever comes first. The host and
+//! the clients are warned shortly before, at the end the results are exported to the host and
+//! everybody is disconnected, so shared deployments do not keep abandoned sessions alive.
+//!
+
+use std::time::Duration;
+
+/// Time before the end at which host and clients are warned, if not configured
+pub const DEFAULT_SESSION_WARNING: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct SessionLimits {
+    pub max_duration: Option<Duration>,
+    /// Server timestamp (milliseconds) at which every session ends
+    pub end_at: Option<i64>,
+    pub warning: Duration,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        SessionLimits { max_duration: None, end_at: None, warning: DEFAULT_SESSION_WARNING }
+    }
+}
+
+impl SessionLimits {
+    /// Server timestamp at which a session started at 'started' ends, None if it runs indefinitely
+    pub fn deadline(&self, started: i64) -> Option<i64> {
+        let by_duration = self.max_duration.map(|duration| started + duration.as_millis() as i64);
+        match (by_duration, self.end_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub started: i64,
+    pub ends_at: Option<i64>,
+    /// Distinguishes sessions, events of ended sessions are ignored
+    pub generation: u64,
+    /// Tenant the session belongs to, None without configured tenants
+    pub tenant: Option<String>,
+    /// Template the host started the session from, if any
+    pub template: Option<String>,
+}
+
+/// Break of the session, started by the host with 'PauseSession'
+/// Inputs are rejected (not buffered) and the host is not told about clients joining or leaving
+#[derive(Debug, Clone)]
+pub struct Pause {
+    /// Server timestamp the break started at
+    pub since: i64,
+    /// Banner shown to the clients
+    pub message: Option<String>,
+}
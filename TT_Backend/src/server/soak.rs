@@ -0,0 +1,222 @@
+//!
+//! Soak mode, synthetic clients for long running stability tests (feature 'soak').
+//! With TT_BACKEND_SOAK (e.g. 'clients=200,churn=5,inputs=20') the server connects synthetic
+//! clients to its own client port: 'clients' are kept connected, 'churn' of them per second leave
+//! and are replaced by new ones and 'inputs' per second are sent for the current state by random
+//! clients. They go through the same websocket, login and input paths as real clients, so the
+//! handler, its memory and the cleanup after leaving clients are exercised without an external
+//! load generator. A host is still needed for states, without one the clients only join and leave.
+//! The clients log in as 'soak-<n>' without code or token, sessions requiring them, a challenge or
+//! a connection limit below 'clients' reject them. Counters and the resident memory of the process
+//! are logged every SOAK_REPORT_INTERVAL.
+//!
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use rand::Rng;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async_tls_with_config, Connector};
+
+/// Interval the rates are applied in
+const SOAK_TICK: Duration = Duration::from_millis(100);
+/// Interval between two reports of the counters
+pub const SOAK_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// Commands queued per synthetic client, further ones are dropped
+const COMMAND_QUEUE: usize = 8;
+/// Clients connected per tick at most, ramps up the load and slows down reconnecting rejected ones
+const MAX_JOINS_PER_TICK: usize = 50;
+
+/// Rates of the synthetic load
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakRates {
+    /// Synthetic clients kept connected
+    pub clients: usize,
+    /// Clients replaced per second
+    pub churn: f64,
+    /// Inputs per second, over all clients
+    pub inputs: f64,
+}
+
+impl SoakRates {
+    /// Parses 'clients=<n>,churn=<per second>,inputs=<per second>', missing rates are 0
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rates = SoakRates {clients: 0, churn: 0.0, inputs: 0.0};
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("'{}' is no key=value pair", part))?;
+            let invalid = || format!("'{}' is no valid value of {}", value, key);
+            match key.trim() {
+                "clients" => rates.clients = value.trim().parse().map_err(|_| invalid())?,
+                "churn" => rates.churn = value.trim().parse().map_err(|_| invalid())?,
+                "inputs" => rates.inputs = value.trim().parse().map_err(|_| invalid())?,
+                other => return Err(format!("Unknown soak rate '{}', expecting clients, churn or inputs", other)),
+            }
+        }
+        if rates.churn < 0.0 || rates.inputs < 0.0 || !rates.churn.is_finite() || !rates.inputs.is_finite() {
+            return Err(String::from("Soak rates have to be positive"))
+        }
+        if rates.clients == 0 {
+            return Err(String::from("The soak mode needs at least one client"))
+        }
+        Ok(rates)
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Input,
+    Leave,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    joined: u64,
+    left: u64,
+    inputs: u64,
+    failed: u64,
+}
+
+/// Starts the synthetic clients against the client listener, in an own task
+pub fn start_soak(rates: SoakRates, listener: SocketAddr, tls: bool) {
+    let ip = match listener.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let url = format!("{}://{}", if tls { "wss" } else { "ws" }, SocketAddr::new(ip, listener.port()));
+    warn!("start_soak(..): Soak mode, keeping {} synthetic clients connected to {} ({} leave and {} inputs per second)", rates.clients, url, rates.churn, rates.inputs);
+
+    tokio::spawn(async move {
+        let (failures, mut failed) = mpsc::channel::<()>(rates.clients.max(1));
+        let mut clients: Vec<mpsc::Sender<Command>> = vec![];
+        let mut counters = Counters::default();
+        let mut next_name = 0u64;
+        let (mut churn_due, mut inputs_due) = (0.0, 0.0);
+        let mut report = tokio::time::interval(SOAK_REPORT_INTERVAL);
+        let mut tick = tokio::time::interval(SOAK_TICK);
+        loop {
+            tokio::select! {
+                _ = report.tick() => log_counters(&counters, clients.len()),
+                Some(()) = failed.recv() => counters.failed += 1,
+                _ = tick.tick() => {
+                    clients.retain(|client| !client.is_closed());
+                    let seconds = SOAK_TICK.as_secs_f64();
+                    churn_due += rates.churn * seconds;
+                    while churn_due >= 1.0 && !clients.is_empty() {
+                        churn_due -= 1.0;
+                        let client = clients.swap_remove(rand::thread_rng().gen_range(0..clients.len()));
+                        if client.try_send(Command::Leave).is_ok() {
+                            counters.left += 1;
+                        }
+                    }
+                    inputs_due += rates.inputs * seconds;
+                    while inputs_due >= 1.0 && !clients.is_empty() {
+                        inputs_due -= 1.0;
+                        let client = &clients[rand::thread_rng().gen_range(0..clients.len())];
+                        if client.try_send(Command::Input).is_ok() {
+                            counters.inputs += 1;
+                        }
+                    }
+                    let joins = rates.clients.saturating_sub(clients.len()).min(MAX_JOINS_PER_TICK);
+                    for _ in 0..joins {
+                        next_name += 1;
+                        let (commands, receiver) = mpsc::channel(COMMAND_QUEUE);
+                        tokio::spawn(synthetic_client(url.clone(), format!("soak-{}", next_name), receiver, failures.clone()));
+                        clients.push(commands);
+                        counters.joined += 1;
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn log_counters(counters: &Counters, connected: usize) {
+    info!("log_counters(..): Soak: {} synthetic clients connected, {} joined, {} left, {} inputs, {} failed, resident memory: {}",
+        connected, counters.joined, counters.left, counters.inputs, counters.failed,
+        resident_memory().map(|bytes| format!("{} KiB", bytes / 1024)).unwrap_or_else(|| String::from("unknown")));
+}
+
+/// Resident set size of the process in bytes
+fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+/// One synthetic client: logs in, tracks the current state and answers it on command
+/// Ends once told to leave or its connection fails, failures are reported
+async fn synthetic_client(url: String, name: String, mut commands: mpsc::Receiver<Command>, failures: mpsc::Sender<()>) {
+    // The server connects to itself, its certificate is not issued for the loopback address
+    let connector = match native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true).build() {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("synthetic_client(..): Creating the TLS connector failed\nError: {}", e);
+            let _ = failures.send(()).await;
+            return
+        }
+    };
+    let mut socket = match connect_async_tls_with_config(url.as_str(), None, Some(Connector::NativeTls(connector))).await {
+        Ok((socket, _)) => socket,
+        Err(e) => {
+            warn!("synthetic_client(..): Connecting {} failed\nError: {}", name, e);
+            let _ = failures.send(()).await;
+            return
+        }
+    };
+    let login = json!({"type": "ClientLogin", "name": name});
+    if socket.send(Message::Text(login.to_string())).await.is_err() {
+        let _ = failures.send(()).await;
+        return
+    }
+
+    let mut state_id = None;
+    let mut sent = 0u64;
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Input) => {
+                    let state_id = match state_id {
+                        None => continue,
+                        Some(v) => v,
+                    };
+                    sent += 1;
+                    let input = json!({"type": "Input", "state_id": state_id, "content": format!("soak {}", sent), "input_id": format!("{}-{}", name, sent)});
+                    if socket.send(Message::Text(input.to_string())).await.is_err() {
+                        let _ = failures.send(()).await;
+                        return
+                    }
+                }
+                Some(Command::Leave) | None => {
+                    let leave = json!({"type": "Disconnecting", "reason": "soak churn"});
+                    let _ = socket.send(Message::Text(leave.to_string())).await;
+                    let _ = socket.close(None).await;
+                    return
+                }
+            },
+            frame = socket.next() => match frame {
+                Some(Ok(Message::Text(text))) => {
+                    let msg: Value = serde_json::from_str(&text).unwrap_or_default();
+                    match msg["type"].as_str() {
+                        Some("ChangeState") => state_id = msg["state_id"].as_i64().map(|v| v as i32),
+                        Some("LoginRejected") | Some("Disconnecting") => {
+                            warn!("synthetic_client(..): {} was disconnected: {}", name, msg["reason"]);
+                            let _ = failures.send(()).await;
+                            return
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => {
+                    let _ = failures.send(()).await;
+                    return
+                }
+            },
+        }
+    }
+}
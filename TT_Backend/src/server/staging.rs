@@ -0,0 +1,71 @@
+//!
+//! States staged ahead of the presentation.
+//! During the preparation the host uploads the upcoming states with 'StageState' (each one is
+//! acknowledged with 'Staged'), during the presentation a small 'ShowStaged' shows one of them as if
+//! the host had sent its 'ChangeState', with the index as state id. Staged states can be shown any
+//! number of times and are kept until the session ends. A 'ShowStaged' sent before the 'Staged' of
+//! its index arrived may find nothing staged, it is answered with 'StageMissing'.
+//! A staged state may list the assets (images, videos) its content loads. Whenever a staged state
+//! is shown, the clients get a 'Prefetch' hint for every asset of the following staged state, so
+//! they fetch them over HTTP before the switch. Clients joining later get the hints on login.
+//!
+
+use std::collections::BTreeMap;
+
+/// Bytes of content all staged states may hold together
+pub const MAX_STAGED_BYTES: usize = 256 * 1024 * 1024;
+
+/// Asset loaded by the content of a staged state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefetchAsset {
+    pub url: String,
+    /// Content hash, lets clients skip assets they already have
+    pub hash: String,
+    /// Size in bytes, if known
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct StagedStates {
+    states: BTreeMap<i32, (String, Vec<PrefetchAsset>)>,
+    bytes: usize,
+    /// Index of the staged state shown last
+    shown: Option<i32>,
+}
+
+impl StagedStates {
+    /// Stages the content, replacing a state of the same index
+    /// Returns false if the content does not fit into the staging limit
+    pub fn stage(&mut self, index: i32, content: String, assets: Vec<PrefetchAsset>) -> bool {
+        let replaced = self.states.get(&index).map(|(content, _)| content.len()).unwrap_or(0);
+        if self.bytes - replaced + content.len() > MAX_STAGED_BYTES {
+            return false
+        }
+        self.bytes = self.bytes - replaced + content.len();
+        self.states.insert(index, (content, assets));
+        true
+    }
+
+    /// Returns the content of the staged state and remembers it as shown
+    pub fn show(&mut self, index: i32) -> Option<String> {
+        let (content, _) = self.states.get(&index)?;
+        self.shown = Some(index);
+        Some(content.clone())
+    }
+
+    /// Index and assets of the staged state following the one shown last
+    pub fn upcoming(&self) -> Option<(i32, &[PrefetchAsset])> {
+        let shown = self.shown?;
+        self.states.range(shown.checked_add(1)?..).next().map(|(index, (_, assets))| (*index, assets.as_slice()))
+    }
+
+    /// Number of staged states
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Bytes of content of all staged states
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
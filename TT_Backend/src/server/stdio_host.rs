@@ -0,0 +1,59 @@
+//!
+//! Host connection over standard streams, for a HostApp running next to the backend.
+//! With 'parent' the backend talks to the process that launched it over its own stdin and stdout
+//! (the log goes to stderr), with 'exec:<command>' the backend launches the host and talks over
+//! the pipes of the child (the command is split at whitespace). Both speak the framed protocol of
+//! the host port and become the active host like a host connecting over tcp, which still replaces
+//! it. Shadow hosts keep connecting over tcp.
+//! Configured with TT_BACKEND_HOST_STDIO, 'host.stdio' in the configuration file or '--host-stdio'.
+//!
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+use crate::server::networking::{HostReader, HostStream, HostWriter};
+
+/// Address the stdio host is known by, no tcp peer has port 0
+pub const STDIO_HOST_ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostStdio {
+    /// Stdin and stdout of the backend, connected to the process that launched it
+    Parent,
+    /// Program and arguments of the host launched by the backend
+    Child(Vec<String>),
+}
+
+impl HostStdio {
+    /// Parses 'parent' or 'exec:<command>'
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once(':') {
+            None if spec == "parent" => Ok(HostStdio::Parent),
+            Some(("exec", command)) if !command.trim().is_empty() =>
+                Ok(HostStdio::Child(command.split_whitespace().map(String::from).collect())),
+            _ => Err(format!("Invalid stdio host '{}', expected 'parent' or 'exec:<command>'", spec)),
+        }
+    }
+
+    /// Opens the connection, launching the host first for 'exec'
+    /// The child is killed once its handle is dropped
+    pub fn open(&self) -> std::io::Result<(HostStream, Option<Child>)> {
+        match self {
+            HostStdio::Parent => {
+                let stream = HostStream {read: HostReader::new(tokio::io::stdin()), write: HostWriter::new(tokio::io::stdout())};
+                Ok((stream, None))
+            }
+            HostStdio::Child(command) => {
+                let mut child = Command::new(&command[0])
+                    .args(&command[1..])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()?;
+                let read = child.stdout.take().expect("open(..): Stdout of the child should be piped");
+                let write = child.stdin.take().expect("open(..): Stdin of the child should be piped");
+                Ok((HostStream {read: HostReader::new(read), write: HostWriter::new(write)}, Some(child)))
+            }
+        }
+    }
+}
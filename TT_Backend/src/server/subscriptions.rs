@@ -0,0 +1,100 @@
+//!
+//! Event classes the host receives one by one.
+//! In very large sessions every join and leave reaches the host as its own event. With
+//! 'Subscribe' the host lists the classes it still wants one by one, the others are held back and
+//! summarized every SUMMARY_INTERVAL: presence ('ClientConnected', 'ClientDisconnected',
+//! 'ClientExpired') as counts in 'PresenceSummary', variants as one 'VariantsAssigned' per state.
+//! Hosts that never subscribe receive every event, a new host starts subscribed to everything.
+//!
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use crate::server::messages::BackendMessage;
+use crate::server::variants::VariantAssignment;
+
+/// Interval between the summaries of the held back events
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventClass {
+    /// Clients joining, leaving and expiring
+    Presence,
+    /// Variants assigned to single clients
+    Variants,
+}
+
+impl EventClass {
+    pub const ALL: [EventClass; 2] = [EventClass::Presence, EventClass::Variants];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        EventClass::ALL.into_iter().find(|class| class.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventClass::Presence => "presence",
+            EventClass::Variants => "variants",
+        }
+    }
+
+    /// Class of the message, None if it is always sent
+    fn of(msg: &BackendMessage) -> Option<Self> {
+        match msg {
+            BackendMessage::ClientConnected {..} | BackendMessage::ClientDisconnected {..} | BackendMessage::ClientExpired {..} => Some(EventClass::Presence),
+            BackendMessage::VariantsAssigned {..} => Some(EventClass::Variants),
+            _ => None,
+        }
+    }
+}
+
+/// Classes the host opted out of and what was held back since the latest summary
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    muted: HashSet<EventClass>,
+    joined: u64,
+    left: u64,
+    expired: u64,
+    /// Held back assignments by state
+    assignments: BTreeMap<i32, Vec<VariantAssignment>>,
+}
+
+impl Subscriptions {
+    /// Subscribes the given classes only, returns the subscribed ones
+    pub fn subscribe(&mut self, classes: &[EventClass]) -> Vec<EventClass> {
+        self.muted = EventClass::ALL.into_iter().filter(|class| !classes.contains(class)).collect();
+        EventClass::ALL.into_iter().filter(|class| !self.muted.contains(class)).collect()
+    }
+
+    /// Holds the message back if its class is muted, returns it if it has to be sent now
+    pub fn filter(&mut self, msg: BackendMessage) -> Option<BackendMessage> {
+        match EventClass::of(&msg) {
+            Some(class) if self.muted.contains(&class) => {}
+            _ => return Some(msg),
+        }
+        match msg {
+            BackendMessage::ClientConnected {..} => self.joined += 1,
+            BackendMessage::ClientDisconnected {..} => self.left += 1,
+            BackendMessage::ClientExpired {..} => self.expired += 1,
+            BackendMessage::VariantsAssigned {state_id, assignments} => self.assignments.entry(state_id).or_default().extend(assignments),
+            _ => {}
+        }
+        None
+    }
+
+    /// Summaries of the events held back since the latest call, nothing if there were none
+    pub fn take_summary(&mut self, clients: usize) -> Vec<BackendMessage> {
+        let mut summary = vec![];
+        if self.joined + self.left + self.expired > 0 {
+            summary.push(BackendMessage::PresenceSummary {joined: self.joined, left: self.left, expired: self.expired, clients});
+            (self.joined, self.left, self.expired) = (0, 0, 0);
+        }
+        let assignments = std::mem::take(&mut self.assignments);
+        summary.extend(assignments.into_iter().map(|(state_id, assignments)| BackendMessage::VariantsAssigned {state_id, assignments}));
+        summary
+    }
+
+    /// Subscribes everything again, e.g. for a new host
+    pub fn reset(&mut self) {
+        *self = Default::default();
+    }
+}
@@ -0,0 +1,78 @@
+//!
+//! Grouping of clients into teams.
+//! Clients may pick a team at login. If teams are configured (TT_BACKEND_TEAMS), only those are
+//! available and clients without a valid pick join the smallest one. Without configured teams every
+//! pick is accepted and clients without a pick stay without team.
+//!
+
+use std::collections::HashMap;
+
+/// Aggregate of one team, as reported to the host
+#[derive(Debug, Clone)]
+pub struct TeamSummary {
+    pub team: String,
+    /// Number of connected members
+    pub members: usize,
+    /// Number of connected members that sent an input in the current state
+    pub answered: usize,
+    /// Cumulative score of all members, including those that left
+    pub score: i64,
+}
+
+/// Returns the team the client joins
+/// 'members' are the teams of the already connected clients
+pub fn assign_team<'a>(configured: &[String], picked: Option<String>, members: impl Iterator<Item = &'a str>) -> Option<String> {
+    if configured.is_empty() {
+        return picked
+    }
+    if let Some(picked) = picked.filter(|picked| configured.contains(picked)) {
+        return Some(picked)
+    }
+
+    let mut sizes: HashMap<&str, usize> = configured.iter().map(|team| (team.as_str(), 0)).collect();
+    for team in members {
+        if let Some(size) = sizes.get_mut(team) {
+            *size += 1;
+        }
+    }
+    // Ties go to the team configured first
+    configured.iter()
+        .min_by_key(|team| sizes[team.as_str()])
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn teams(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| String::from(*name)).collect()
+    }
+
+    #[test]
+    fn any_pick_is_accepted_without_configured_teams() {
+        assert_eq!(assign_team(&[], Some(String::from("pirates")), ["red"].into_iter()), Some(String::from("pirates")));
+        assert_eq!(assign_team(&[], None, ["red"].into_iter()), None);
+    }
+
+    #[test]
+    fn configured_pick_is_kept() {
+        let configured = teams(&["red", "blue"]);
+        assert_eq!(assign_team(&configured, Some(String::from("blue")), ["blue", "blue"].into_iter()), Some(String::from("blue")));
+    }
+
+    #[test]
+    fn clients_without_valid_pick_join_the_smallest_team() {
+        let configured = teams(&["red", "blue", "green"]);
+        let members = ["red", "blue", "red", "pirates", "pirates"];
+        assert_eq!(assign_team(&configured, Some(String::from("pirates")), members.into_iter()), Some(String::from("green")));
+        assert_eq!(assign_team(&configured, None, ["red", "green"].into_iter()), Some(String::from("blue")));
+    }
+
+    #[test]
+    fn ties_go_to_the_team_configured_first() {
+        let configured = teams(&["red", "blue"]);
+        assert_eq!(assign_team(&configured, None, std::iter::empty()), Some(String::from("red")));
+        assert_eq!(assign_team(&configured, None, ["blue", "red"].into_iter()), Some(String::from("red")));
+    }
+}
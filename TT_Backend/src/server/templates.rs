@@ -0,0 +1,148 @@
+//!
+//! Session templates for recurring formats.
+//! Templates are JSON files in a directory (TT_BACKEND_TEMPLATES), the file name without '.json'
+//! is the name the host requests with 'StartFromTemplate'. A template sets the initial state, the
+//! rules, the polls (scoring rules per state), starts its timers and restricts the access to the
+//! session: a limit of clients (below the one of the tenant) and whether guests may join.
+//! Every part is optional.
+//!
+//! Example file 'weekly_quiz.json':
+//! {"state": {"state_id": 1, "content": "Welcome"},
+//!  "rules": [{"name": "timeout", "when": {"after": 60}, "then": {"notify": "Time is up"}}],
+//!  "polls": [{"state_id": 2, "correct": "b", "points": 10, "speed_bonus": 5, "bonus_window": 20000}],
+//!  "timers": [{"id": "class", "duration": 5400000}],
+//!  "access": {"max_clients": 30, "guests": false}}
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+use serde_json::Value;
+use crate::server::leaderboard::ScoringRule;
+use crate::server::rules::Rule;
+
+pub const REJECT_REASON_NO_GUESTS: &str = "Guests are not admitted to this session";
+
+/// Restrictions of a session started from a template
+#[derive(Debug, Clone)]
+pub struct AccessPolicy {
+    pub max_clients: Option<usize>,
+    /// Whether clients without a token may join
+    pub guests: bool,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        AccessPolicy { max_clients: None, guests: true }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionTemplate {
+    /// Id and content of the initial state
+    pub state: Option<(i32, String)>,
+    pub rules: Vec<Rule>,
+    /// Scoring rule per state
+    pub polls: Vec<(i32, ScoringRule)>,
+    /// Id and duration (in milliseconds) of the timers started with the template
+    pub timers: Vec<(String, i64)>,
+    pub access: AccessPolicy,
+}
+
+impl SessionTemplate {
+    fn parse(json: &Value) -> Result<Self, String> {
+        let state = match &json["state"] {
+            Value::Null => None,
+            state => {
+                let state_id = state["state_id"].as_i64().and_then(|v| i32::try_from(v).ok());
+                match (state_id, state["content"].as_str()) {
+                    (Some(state_id), Some(content)) => Some((state_id, String::from(content))),
+                    _ => return Err(String::from("'state' needs a 'state_id' and a 'content'")),
+                }
+            }
+        };
+        let rules = entries(json, "rules")?.iter()
+            .map(|rule| Rule::parse(rule).ok_or_else(|| format!("Invalid rule {}", rule)))
+            .collect::<Result<Vec<Rule>, String>>()?;
+        let polls = entries(json, "polls")?.iter()
+            .map(|poll| {
+                let state_id = poll["state_id"].as_i64().and_then(|v| i32::try_from(v).ok());
+                match (state_id, poll["correct"].as_str(), poll["points"].as_i64()) {
+                    (Some(state_id), Some(correct), Some(points)) => {
+                        let speed_bonus = poll["speed_bonus"].as_i64().unwrap_or(0);
+                        let bonus_window = poll["bonus_window"].as_i64().unwrap_or(0);
+                        let rule = ScoringRule::new(String::from(correct), points, speed_bonus, bonus_window)
+                            .map_err(|e| format!("{}: {}", e, poll))?;
+                        Ok((state_id, rule))
+                    }
+                    _ => Err(format!("Poll without 'state_id', 'correct' or 'points': {}", poll)),
+                }
+            })
+            .collect::<Result<Vec<(i32, ScoringRule)>, String>>()?;
+        let timers = entries(json, "timers")?.iter()
+            .map(|timer| match (timer["id"].as_str(), timer["duration"].as_i64()) {
+                (Some(id), Some(duration)) => Ok((String::from(id), duration)),
+                _ => Err(format!("Timer without 'id' or 'duration': {}", timer)),
+            })
+            .collect::<Result<Vec<(String, i64)>, String>>()?;
+        let access = &json["access"];
+        let access = AccessPolicy {
+            max_clients: access["max_clients"].as_u64().map(|v| v as usize),
+            guests: access["guests"].as_bool().unwrap_or(true),
+        };
+        Ok(SessionTemplate { state, rules, polls, timers, access })
+    }
+}
+
+/// Array of the field, a missing field has no entries
+fn entries<'a>(json: &'a Value, field: &str) -> Result<&'a [Value], String> {
+    match &json[field] {
+        Value::Null => Ok(&[]),
+        Value::Array(entries) => Ok(entries),
+        _ => Err(format!("'{}' has to be an array", field)),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TemplateRegistry {
+    templates: HashMap<String, SessionTemplate>,
+}
+
+impl TemplateRegistry {
+    /// Reads every '.json' file of the directory, a single invalid template fails the start
+    pub fn load(directory: &Path) -> Result<Self, String> {
+        let files = std::fs::read_dir(directory)
+            .map_err(|e| format!("Reading template directory {} failed: {}", directory.display(), e))?;
+        let mut templates = HashMap::new();
+        for file in files {
+            let path = file.map_err(|e| format!("Reading template directory {} failed: {}", directory.display(), e))?.path();
+            let name = match (path.extension().and_then(|v| v.to_str()), path.file_stem().and_then(|v| v.to_str())) {
+                (Some("json"), Some(name)) => String::from(name),
+                _ => continue,
+            };
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Reading template {} failed: {}", path.display(), e))?;
+            let json: Value = serde_json::from_str(&content)
+                .map_err(|e| format!("Parsing template {} failed: {}", path.display(), e))?;
+            let template = SessionTemplate::parse(&json)
+                .map_err(|e| format!("Invalid template {}: {}", path.display(), e))?;
+            templates.insert(name, template);
+        }
+        Ok(TemplateRegistry { templates })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SessionTemplate> {
+        self.templates.get(name)
+    }
+
+    /// Names of all templates, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.templates.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Access policy of a session started from the template, the default one for other sessions
+    pub fn access(&self, template: Option<&str>) -> AccessPolicy {
+        template.and_then(|name| self.get(name)).map(|template| template.access.clone()).unwrap_or_default()
+    }
+}
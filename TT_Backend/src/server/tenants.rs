@@ -0,0 +1,120 @@
+//!
+//! Tenants sharing one backend.
+//! Tenants are read from a JSON file (TT_BACKEND_TENANTS), every tenant has an API key the host
+//! presents with 'HostLogin' and optional quotas. Without the file there are no tenants and every
+//! host is accepted, as before.
+//! The session of the server belongs to the tenant of the host that started it, its quotas apply
+//! until the session ends. Hosts of other tenants are rejected in the meantime, they may open rooms
+//! of their own. 'max_sessions' counts the session of the server and the open rooms of the tenant.
+//!
+//! Example file:
+//! [{"name": "quiz_club", "api_key": "secret", "max_sessions": 1, "max_clients": 50, "max_bandwidth": 1000000, "retention_days": 30}]
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use serde_json::Value;
+use crate::server::auth::constant_time_eq;
+
+pub const REJECT_REASON_MISSING_API_KEY: &str = "Missing API key";
+pub const REJECT_REASON_INVALID_API_KEY: &str = "Invalid API key";
+pub const REJECT_REASON_SESSION_QUOTA: &str = "Session quota of the tenant exhausted";
+pub const REJECT_REASON_OTHER_TENANT: &str = "Server is in use by another tenant";
+pub const REJECT_REASON_CLIENT_QUOTA: &str = "Client limit of the session reached";
+
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub name: String,
+    api_key: String,
+    /// Concurrent sessions, 0 disables hosting for the tenant
+    pub max_sessions: Option<usize>,
+    /// Clients connected at the same time
+    pub max_clients: Option<usize>,
+    /// Bytes per second relayed from the host to the clients
+    pub max_bandwidth: Option<u64>,
+    /// Age after which stored data of the tenant is deleted
+    pub retention: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TenantRegistry {
+    tenants: Vec<Tenant>,
+}
+
+impl TenantRegistry {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Reading tenant file {} failed: {}", path.display(), e))?;
+        let json: Value = serde_json::from_str(&content)
+            .map_err(|e| format!("Parsing tenant file {} failed: {}", path.display(), e))?;
+
+        let tenants = json.as_array()
+            .ok_or_else(|| format!("Tenant file {} has to contain an array", path.display()))?
+            .iter()
+            .map(|tenant| {
+                let name = tenant["name"].as_str().map(String::from);
+                let api_key = tenant["api_key"].as_str().map(String::from);
+                match (name, api_key) {
+                    (Some(name), Some(api_key)) => Ok(Tenant {
+                        name,
+                        api_key,
+                        max_sessions: tenant["max_sessions"].as_u64().map(|v| v as usize),
+                        max_clients: tenant["max_clients"].as_u64().map(|v| v as usize),
+                        max_bandwidth: tenant["max_bandwidth"].as_u64(),
+                        retention: tenant["retention_days"].as_u64().map(|days| Duration::from_secs(days * 24 * 3600)),
+                    }),
+                    _ => Err(format!("Tenant without 'name' or 'api_key' in {}: {}", path.display(), tenant)),
+                }
+            })
+            .collect::<Result<Vec<Tenant>, String>>()?;
+        Ok(TenantRegistry { tenants })
+    }
+
+    /// Whether hosts have to log in with the API key of a tenant
+    pub fn is_enabled(&self) -> bool {
+        !self.tenants.is_empty()
+    }
+
+    /// Returns the tenant the API key belongs to
+    pub fn authenticate(&self, api_key: Option<&str>) -> Result<&Tenant, &'static str> {
+        let api_key = api_key.ok_or(REJECT_REASON_MISSING_API_KEY)?;
+        self.tenants.iter()
+            .find(|tenant| constant_time_eq(&tenant.api_key, api_key))
+            .ok_or(REJECT_REASON_INVALID_API_KEY)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Tenant> {
+        self.tenants.iter().find(|tenant| tenant.name == name)
+    }
+
+    /// Retention ages of the tenants that have one, by name
+    pub fn retention(&self) -> HashMap<String, Duration> {
+        self.tenants.iter()
+            .filter_map(|tenant| tenant.retention.map(|age| (tenant.name.clone(), age)))
+            .collect()
+    }
+}
+
+/// Bytes relayed within the current second
+#[derive(Debug, Default)]
+pub struct BandwidthMeter {
+    second: i64,
+    bytes: u64,
+}
+
+impl BandwidthMeter {
+    /// Accounts the bytes if they fit into the limit of the current second
+    /// 'now' is a server timestamp in milliseconds
+    pub fn try_consume(&mut self, bytes: u64, limit: u64, now: i64) -> bool {
+        if now / 1000 != self.second {
+            self.second = now / 1000;
+            self.bytes = 0;
+        }
+        if self.bytes + bytes > limit {
+            return false
+        }
+        self.bytes += bytes;
+        true
+    }
+}
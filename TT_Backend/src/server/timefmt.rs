@@ -0,0 +1,171 @@
+//!
+//! Formatting of server timestamps (milliseconds since the unix epoch) for exports.
+//! Machine formats (json lines, webhooks, http pushes) use RFC 3339 in UTC. Human-facing formats
+//! (csv reports, digest mails, the status page) use the configured timezone (TT_BACKEND_TIMEZONE,
+//! an IANA name like 'Europe/Berlin') and the date order of the configured locale
+//! (TT_BACKEND_LOCALE, a language tag like 'de-DE'). Human timestamps carry their UTC offset, so
+//! they can be read back regardless of the configuration they were written with.
+//!
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Date and time order of a locale, the time is always followed by the UTC offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    /// 2026-10-17 14:03:05 +02:00
+    Iso,
+    /// 10/17/2026 02:03:05 PM +02:00
+    UnitedStates,
+    /// 17/10/2026 14:03:05 +02:00
+    DayMonthSlash,
+    /// 17.10.2026 14:03:05 +02:00
+    DayMonthDot,
+    /// 2026/10/17 14:03:05 +02:00
+    YearMonthSlash,
+}
+
+const LOCALES: [Locale; 5] = [Locale::Iso, Locale::UnitedStates, Locale::DayMonthSlash, Locale::DayMonthDot, Locale::YearMonthSlash];
+
+impl Locale {
+    /// Parses a language tag, only language and region are considered
+    pub fn parse(tag: &str) -> Result<Self, String> {
+        let tag = tag.trim().replace('_', "-").to_lowercase();
+        let language = tag.split('-').next().unwrap_or_default();
+        match (language, tag.as_str()) {
+            ("iso" | "c" | "", _) => Ok(Locale::Iso),
+            (_, "en-us") => Ok(Locale::UnitedStates),
+            ("en" | "fr" | "es" | "it" | "pt" | "nl" | "el", _) => Ok(Locale::DayMonthSlash),
+            ("de" | "ru" | "pl" | "cs" | "sk" | "fi" | "nb" | "no" | "da" | "tr" | "uk", _) => Ok(Locale::DayMonthDot),
+            ("ja" | "zh" | "ko", _) => Ok(Locale::YearMonthSlash),
+            ("sv" | "lt", _) => Ok(Locale::Iso),
+            _ => Err(format!("Unsupported locale '{}'", tag)),
+        }
+    }
+
+    fn pattern(&self) -> &'static str {
+        match self {
+            Locale::Iso => "%Y-%m-%d %H:%M:%S %:z",
+            Locale::UnitedStates => "%m/%d/%Y %I:%M:%S %p %:z",
+            Locale::DayMonthSlash => "%d/%m/%Y %H:%M:%S %:z",
+            Locale::DayMonthDot => "%d.%m.%Y %H:%M:%S %:z",
+            Locale::YearMonthSlash => "%Y/%m/%d %H:%M:%S %:z",
+        }
+    }
+}
+
+/// Timezone and locale of human-facing timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeFormat {
+    pub timezone: Tz,
+    pub locale: Locale,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat { timezone: Tz::UTC, locale: Locale::Iso }
+    }
+}
+
+impl TimeFormat {
+    pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+        name.trim().parse().map_err(|_| format!("Unknown timezone '{}', expected an IANA name like 'Europe/Berlin'", name))
+    }
+
+    /// Human-facing representation of the timestamp
+    pub fn human(&self, timestamp: i64) -> String {
+        match self.timezone.timestamp_millis_opt(timestamp).single() {
+            Some(time) => time.format(self.locale.pattern()).to_string(),
+            None => timestamp.to_string(),
+        }
+    }
+}
+
+/// Machine representation of the timestamp, in UTC with milliseconds
+pub fn rfc3339(timestamp: i64) -> String {
+    match Utc.timestamp_millis_opt(timestamp).single() {
+        Some(time) => time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Reads back a timestamp written as epoch milliseconds, in RFC 3339 or in any human format
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Some(timestamp)
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.timestamp_millis())
+    }
+    LOCALES.iter()
+        .find_map(|locale| DateTime::parse_from_str(value, locale.pattern()).ok())
+        .map(|time| time.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 5).unwrap().timestamp_millis()
+    }
+
+    #[test]
+    fn human_timestamps_are_read_back() {
+        let timestamps = [
+            utc(2026, 10, 17, 12, 3),
+            // Afternoon and midnight, the 12 hour clock of the United States
+            utc(2026, 1, 5, 23, 59),
+            utc(2026, 1, 5, 0, 0),
+            // Around the daylight saving changes of Europe/Berlin, the clock skips 02:00 to 03:00
+            // and repeats 02:00 to 03:00 in October
+            utc(2026, 3, 29, 0, 59),
+            utc(2026, 3, 29, 1, 0),
+            utc(2026, 10, 25, 0, 30),
+            utc(2026, 10, 25, 1, 30),
+        ];
+        for timezone in [Tz::UTC, Tz::Europe__Berlin, Tz::America__New_York, Tz::Asia__Kolkata] {
+            for locale in LOCALES {
+                let format = TimeFormat {timezone, locale};
+                for timestamp in timestamps {
+                    let human = format.human(timestamp);
+                    assert_eq!(parse_timestamp(&human), Some(timestamp), "{:?} in {} read back from '{}'", locale, timezone, human);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn locales_are_told_apart() {
+        let timestamp = utc(2026, 3, 4, 14, 3);
+        let format = |locale| TimeFormat {timezone: Tz::UTC, locale}.human(timestamp);
+        assert_eq!(format(Locale::UnitedStates), "03/04/2026 02:03:05 PM +00:00");
+        assert_eq!(format(Locale::DayMonthSlash), "04/03/2026 14:03:05 +00:00");
+        assert_eq!(parse_timestamp("04/03/2026 14:03:05 +00:00"), Some(timestamp));
+        assert_eq!(parse_timestamp("03/04/2026 02:03:05 PM +00:00"), Some(timestamp));
+    }
+
+    #[test]
+    fn machine_timestamps_keep_milliseconds() {
+        let timestamp = utc(2026, 10, 17, 12, 3) + 42;
+        assert_eq!(rfc3339(timestamp), "2026-10-17T12:03:05.042Z");
+        assert_eq!(parse_timestamp(&rfc3339(timestamp)), Some(timestamp));
+        assert_eq!(parse_timestamp(&timestamp.to_string()), Some(timestamp));
+        assert_eq!(parse_timestamp("yesterday"), None);
+        assert_eq!(rfc3339(i64::MAX), i64::MAX.to_string());
+        // Timestamps no calendar can show are written and read back as the number
+        assert_eq!(TimeFormat::default().human(i64::MAX), i64::MAX.to_string());
+        assert_eq!(parse_timestamp(&TimeFormat::default().human(i64::MAX)), Some(i64::MAX));
+    }
+
+    #[test]
+    fn locales_are_parsed_from_language_tags() {
+        assert_eq!(Locale::parse("en-US"), Ok(Locale::UnitedStates));
+        assert_eq!(Locale::parse("en_GB"), Ok(Locale::DayMonthSlash));
+        assert_eq!(Locale::parse("de"), Ok(Locale::DayMonthDot));
+        assert_eq!(Locale::parse("ja-JP"), Ok(Locale::YearMonthSlash));
+        assert_eq!(Locale::parse(""), Ok(Locale::Iso));
+        assert_eq!(Locale::parse("tlh"), Err(String::from("Unsupported locale 'tlh'")));
+    }
+}
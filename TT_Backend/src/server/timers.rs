@@ -0,0 +1,60 @@
+//!
+//! Countdown timers managed by the server.
+//! The host starts and cancels timers, the server broadcasts their remaining time every
+//! TIMER_TICK and their expiry to clients and host. All times are derived from the server clock,
+//! so clients joining late see the same remaining time as everybody else.
+//!
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Interval between two broadcasts of the remaining time
+pub const TIMER_TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct RunningTimer {
+    /// Server timestamp (milliseconds) at which the timer expires
+    pub ends_at: i64,
+    /// Distinguishes restarted timers with the same id, ticks of older ones are ignored
+    pub generation: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Timers {
+    timers: HashMap<String, RunningTimer>,
+    generation: u64,
+}
+
+impl Timers {
+    /// Starts (or restarts) the timer and returns it
+    pub fn start(&mut self, id: &str, ends_at: i64) -> RunningTimer {
+        self.generation += 1;
+        let timer = RunningTimer { ends_at, generation: self.generation };
+        self.timers.insert(String::from(id), timer.clone());
+        timer
+    }
+
+    /// Returns false if no such timer is running
+    pub fn cancel(&mut self, id: &str) -> bool {
+        self.timers.remove(id).is_some()
+    }
+
+    /// Returns the timer if the tick belongs to it, expired timers are removed
+    pub fn tick(&mut self, id: &str, generation: u64, now: i64) -> Option<RunningTimer> {
+        let timer = self.timers.get(id).filter(|timer| timer.generation == generation)?.clone();
+        if timer.ends_at <= now {
+            self.timers.remove(id);
+        }
+        Some(timer)
+    }
+
+    /// Stops all timers, ticks of them are ignored afterwards
+    pub fn clear(&mut self) {
+        self.timers.clear();
+    }
+
+    /// All running timers with their ids
+    pub fn running(&self) -> impl Iterator<Item = (&str, &RunningTimer)> {
+        self.timers.iter().map(|(id, timer)| (id.as_str(), timer))
+    }
+}
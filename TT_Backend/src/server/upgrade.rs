@@ -0,0 +1,209 @@
+//!
+//! In-place upgrade of the server binary without closing the listening sockets.
+//! 'POST /upgrade' on the admin interface spawns the running binary (replaced on disk beforehand)
+//! with duplicates of all listening sockets and a state file holding the snapshot of the session
+//! (as replicated to standbys). The state file has a random name and is readable by the owner only. The
+//! new process continues the session, accepts on the inherited sockets and removes the state file
+//! once its listeners run. Until then both processes accept. The old process stops accepting
+//! afterwards, moves its clients over ('Migrate' to the advertised url, 'Reload' without one),
+//! disconnects the hosts with a reconnect hint, ends the rooms and exits. Connections waiting in
+//! the accept queue are never refused, but every client and host reconnects once. Clients
+//! reaching the new process before the host see the session without a host until it is back.
+//! If the new process exits or does not get ready within UPGRADE_READY_TIMEOUT, it is killed and
+//! the old process keeps serving.
+//!
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use log::{info, warn};
+use rand::RngCore;
+use serde_json::Value;
+use socket2::SockRef;
+use tokio::net::TcpListener;
+use tokio::process::{Child, Command};
+use tokio::time::Instant;
+use crate::server::networking::ListenerRole;
+use crate::server::replication::Snapshot;
+use crate::server::secrets::{SECRET_KEY_ENV, SecretKey};
+
+/// Inherited listening sockets as 'role=fd' pairs, separated by ','
+pub const UPGRADE_LISTENERS_ENV: &str = "TT_BACKEND_UPGRADE_LISTENERS";
+/// Path of the state file, removed by the new process once it is ready
+pub const UPGRADE_STATE_ENV: &str = "TT_BACKEND_UPGRADE_STATE";
+pub const UPGRADE_READY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time the old process keeps running after the handoff, so its last messages get out
+pub const UPGRADE_EXIT_DELAY: Duration = Duration::from_secs(2);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const STATE_NAME_BYTES: usize = 16;
+
+const ROLES: [(ListenerRole, &str); 7] = [
+    (ListenerRole::Clients, "clients"),
+    (ListenerRole::Hosts, "hosts"),
+    (ListenerRole::ShadowHosts, "shadow"),
+    (ListenerRole::Admin, "admin"),
+    (ListenerRole::Replication, "replication"),
+    (ListenerRole::LiveView, "live_view"),
+    (ListenerRole::Acme, "acme"),
+];
+
+/// Listening sockets passed by the previous process, taken once they are bound again
+fn inherited() -> &'static Mutex<Vec<(ListenerRole, std::net::TcpListener)>> {
+    static INHERITED: OnceLock<Mutex<Vec<(ListenerRole, std::net::TcpListener)>>> = OnceLock::new();
+    INHERITED.get_or_init(|| Mutex::new(std::env::var(UPGRADE_LISTENERS_ENV).map(|v| parse_listeners(&v)).unwrap_or_default()))
+}
+
+fn parse_listeners(spec: &str) -> Vec<(ListenerRole, std::net::TcpListener)> {
+    spec.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(key, fd)| {
+            let role = ROLES.iter().find(|(_, k)| *k == key).map(|(role, _)| *role);
+            match (role, fd.parse::<RawFd>()) {
+                // SAFETY: the previous process passed the descriptor for this process only, it is
+                // owned by the listener from here on
+                (Some(role), Ok(fd)) => Some((role, unsafe { std::net::TcpListener::from_raw_fd(fd) })),
+                _ => {
+                    warn!("parse_listeners(..): Ignoring invalid inherited listener '{}={}'", key, fd);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Address of the listening socket inherited for the role, if any
+pub fn inherited_address(role: ListenerRole) -> Option<SocketAddr> {
+    let inherited = inherited().lock().expect("inherited_address(..): Lock is poisoned");
+    inherited.iter().find(|(r, _)| *r == role).and_then(|(_, listener)| listener.local_addr().ok())
+}
+
+/// Takes the inherited listening socket bound to the address, if any
+pub fn take_inherited(address: SocketAddr) -> Option<std::io::Result<TcpListener>> {
+    let mut inherited = inherited().lock().expect("take_inherited(..): Lock is poisoned");
+    let index = inherited.iter().position(|(_, listener)| listener.local_addr().ok() == Some(address))?;
+    let (role, listener) = inherited.remove(index);
+    info!("take_inherited(..): Continuing the {} listener on {} of the previous process", role, address);
+    Some(listener.set_nonblocking(true).and_then(|_| TcpListener::from_std(listener)))
+}
+
+/// Snapshot of the session passed by the previous process, if this process is an upgrade
+pub fn inherited_state(key: &SecretKey) -> Option<Snapshot> {
+    let path = std::env::var(UPGRADE_STATE_ENV).ok()?;
+    let snapshot = std::fs::read_to_string(&path).ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+        .and_then(|json| Snapshot::from_json(&json, key));
+    if snapshot.is_none() {
+        warn!("inherited_state(..): State file {} of the previous process is unreadable, starting without session", path);
+    }
+    snapshot
+}
+
+/// Tells the previous process that this one is ready by removing the state file
+pub fn confirm_upgrade() {
+    if let Ok(path) = std::env::var(UPGRADE_STATE_ENV) {
+        match std::fs::remove_file(&path) {
+            Ok(_) => info!("confirm_upgrade(..): Ready, the previous process hands over"),
+            Err(e) => warn!("confirm_upgrade(..): Removing state file {} failed!\nError: {}", path, e),
+        }
+    }
+}
+
+/// Spawns the binary with duplicates of the listening sockets and the snapshot in a state file
+/// The secrets of the snapshot are encrypted, the new process gets the key in its environment
+/// Returns the new process and the path of the state file
+pub fn spawn_upgrade(binary: &Path, listeners: &[(ListenerRole, RawFd)], snapshot: &Snapshot, key: &SecretKey) -> Result<(Child, PathBuf), String> {
+    let state = write_state(snapshot, key)?;
+
+    // Duplicates without close-on-exec are inherited, the originals keep accepting here
+    let mut duplicates = Vec::new();
+    for (role, fd) in listeners {
+        // SAFETY: the descriptor belongs to a running listener and outlives this function
+        let fd = unsafe { BorrowedFd::borrow_raw(*fd) };
+        let duplicate = SockRef::from(&fd).try_clone()
+            .and_then(|socket| socket.set_cloexec(false).map(|_| socket))
+            .map_err(|e| format!("Duplicating the {} listener failed: {}", role, e))?;
+        let key = ROLES.iter().find(|(r, _)| r == role).map(|(_, key)| *key).unwrap_or_default();
+        duplicates.push((key, duplicate));
+    }
+    let spec: Vec<String> = duplicates.iter().map(|(key, socket)| format!("{}={}", key, socket.as_raw_fd())).collect();
+
+    let child = Command::new(binary)
+        .args(std::env::args_os().skip(1))
+        .env(UPGRADE_LISTENERS_ENV, spec.join(","))
+        .env(UPGRADE_STATE_ENV, &state)
+        .env(SECRET_KEY_ENV, key.material())
+        .spawn();
+    drop(duplicates);
+    match child {
+        Ok(child) => Ok((child, state)),
+        Err(e) => {
+            let _ = std::fs::remove_file(&state);
+            Err(format!("Starting {} failed: {}", binary.display(), e))
+        }
+    }
+}
+
+/// Writes the snapshot to a new file with a random name, readable by the owner only
+/// An existing file (e.g. a planted link) is never followed or overwritten
+fn write_state(snapshot: &Snapshot, key: &SecretKey) -> Result<PathBuf, String> {
+    let mut bytes = [0u8; STATE_NAME_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let name: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let state = std::env::temp_dir().join(format!("tt_online_upgrade_{}.json", name));
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&state)
+        .map_err(|e| format!("Creating state file {} failed: {}", state.display(), e))?;
+    if let Err(e) = file.write_all(snapshot.to_json(key).to_string().as_bytes()) {
+        let _ = std::fs::remove_file(&state);
+        return Err(format!("Writing state file {} failed: {}", state.display(), e))
+    }
+    Ok(state)
+}
+
+/// Waits until the new process removed the state file, kills it if it does not
+pub async fn wait_until_ready(mut child: Child, state: PathBuf) -> Result<(), String> {
+    let deadline = Instant::now() + UPGRADE_READY_TIMEOUT;
+    let result = loop {
+        if !state.exists() {
+            break Ok(())
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            break Err(format!("New process exited early with {}", status))
+        }
+        if Instant::now() >= deadline {
+            break Err(format!("New process was not ready within {:?}", UPGRADE_READY_TIMEOUT))
+        }
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    };
+    if result.is_err() {
+        let _ = child.kill().await;
+        let _ = std::fs::remove_file(&state);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn state_file_is_private_and_unpredictable() {
+        let key = SecretKey::new("upgrade key");
+        let first = write_state(&Snapshot::default(), &key).unwrap();
+        let second = write_state(&Snapshot::default(), &key).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(std::fs::metadata(&first).unwrap().permissions().mode() & 0o777, 0o600);
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(&first).unwrap()).unwrap();
+        assert!(Snapshot::from_json(&json, &key).is_some());
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+    }
+}
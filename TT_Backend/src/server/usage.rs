@@ -0,0 +1,231 @@
+//!
+//! Usage accounting per session.
+//! The server records connection time of the clients, the peak number of connected clients and
+//! the bytes relayed between host and clients. Reports are exported periodically and once the
+//! session ends, either appended to a file (json lines or csv) or pushed to a http endpoint, so
+//! operators of a shared instance can do chargeback or enforce fair use.
+//!
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use crate::server::http_client::{post_json, Url};
+use crate::server::timefmt::{parse_timestamp, rfc3339, TimeFormat};
+
+/// Interval between two reports, if not configured
+pub const DEFAULT_USAGE_INTERVAL: Duration = Duration::from_secs(300);
+/// Maximum time a http push may take
+const USAGE_PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+const USAGE_CSV_HEADER: &str = "tenant,session,started,reported_at,connection_minutes,peak_clients,bytes_relayed,final";
+
+/// Serializes appending and pruning of the export files, so no report is lost to a prune
+static EXPORT_FILES: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Where usage reports are exported to
+#[derive(Debug, Clone)]
+pub enum UsageExport {
+    Json(PathBuf),
+    Csv(PathBuf),
+    Http(Url),
+}
+
+impl UsageExport {
+    /// Parses 'json:<path>', 'csv:<path>' or a 'http(s)://' url
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            return Ok(UsageExport::Http(Url::parse(spec)?))
+        }
+        match spec.split_once(':') {
+            Some(("json", path)) if !path.is_empty() => Ok(UsageExport::Json(PathBuf::from(path))),
+            Some(("csv", path)) if !path.is_empty() => Ok(UsageExport::Csv(PathBuf::from(path))),
+            _ => Err(format!("Invalid usage export '{}', expected 'json:<path>', 'csv:<path>' or a http(s) url", spec)),
+        }
+    }
+
+    /// Appends the report to the file or pushes it to the url
+    /// Timestamps of csv reports are written in the given format, of json reports in RFC 3339
+    pub async fn export(&self, report: &UsageReport, format: &TimeFormat) -> Result<(), String> {
+        match self {
+            UsageExport::Json(path) => {
+                let _lock = EXPORT_FILES.lock().await;
+                append(path, &format!("{}\n", report.to_json()), None).await
+            }
+            UsageExport::Csv(path) => {
+                let _lock = EXPORT_FILES.lock().await;
+                append(path, &format!("{}\n", report.to_csv(format)), Some(USAGE_CSV_HEADER)).await
+            }
+            UsageExport::Http(url) => match post_json(url, &report.to_json(), USAGE_PUSH_TIMEOUT).await? {
+                (status, _) if (200..300).contains(&status) => Ok(()),
+                (status, body) => Err(format!("Usage endpoint answered {}: {}", status, body)),
+            },
+        }
+    }
+
+    /// Removes the reports 'expired' returns true for (given tenant and 'reported_at')
+    /// Returns the file and the number of removed reports per tenant ("" without tenant), None if
+    /// the reports are not stored in a file. Lines that can not be parsed are kept.
+    pub async fn prune(&self, expired: impl Fn(Option<&str>, i64) -> bool) -> Result<Option<(PathBuf, HashMap<String, usize>)>, String> {
+        let (path, csv) = match self {
+            UsageExport::Json(path) => (path, false),
+            UsageExport::Csv(path) => (path, true),
+            UsageExport::Http(_) => return Ok(None),
+        };
+        let _lock = EXPORT_FILES.lock().await;
+        let content = match tokio::fs::read_to_string(path).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Some((path.clone(), HashMap::new()))),
+            Err(e) => return Err(format!("Reading {} failed: {}", path.display(), e)),
+        };
+
+        let mut removed: HashMap<String, usize> = HashMap::new();
+        let mut kept = String::new();
+        for line in content.lines() {
+            let report = match csv {
+                true => parse_csv_report(line),
+                false => parse_json_report(line),
+            };
+            match report {
+                Some((tenant, reported_at)) if expired(tenant.as_deref(), reported_at) =>
+                    *removed.entry(tenant.unwrap_or_default()).or_default() += 1,
+                _ => {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+        }
+        if removed.is_empty() {
+            return Ok(Some((path.clone(), removed)))
+        }
+
+        // Replaced at once, a crash never leaves a half written file
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        tokio::fs::write(&temporary, kept).await
+            .map_err(|e| format!("Writing {} failed: {}", path.display(), e))?;
+        tokio::fs::rename(&temporary, path).await
+            .map_err(|e| format!("Replacing {} failed: {}", path.display(), e))?;
+        Ok(Some((path.clone(), removed)))
+    }
+}
+
+/// Tenant and 'reported_at' of a json line
+fn parse_json_report(line: &str) -> Option<(Option<String>, i64)> {
+    let json: Value = serde_json::from_str(line).ok()?;
+    let reported_at = match &json["reported_at"] {
+        Value::String(reported_at) => parse_timestamp(reported_at)?,
+        reported_at => reported_at.as_i64()?,
+    };
+    Some((json["tenant"].as_str().map(String::from), reported_at))
+}
+
+/// Tenant and 'reported_at' of a csv line, the header has none
+fn parse_csv_report(line: &str) -> Option<(Option<String>, i64)> {
+    let mut fields = line.split(',');
+    let tenant = fields.next()?;
+    let reported_at = parse_timestamp(fields.nth(2)?)?;
+    Some((Some(String::from(tenant)).filter(|tenant| !tenant.is_empty()), reported_at))
+}
+
+/// Appends the line to the file, a new file starts with the header
+async fn append(path: &PathBuf, line: &str, header: Option<&str>) -> Result<(), String> {
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await
+        .map_err(|e| format!("Opening {} failed: {}", path.display(), e))?;
+    let empty = file.metadata().await.map(|metadata| metadata.len() == 0).unwrap_or(false);
+    let content = match header {
+        Some(header) if empty => format!("{}\n{}", header, line),
+        _ => String::from(line),
+    };
+    file.write_all(content.as_bytes()).await
+        .map_err(|e| format!("Writing {} failed: {}", path.display(), e))
+}
+
+/// Usage of one session up to 'reported_at'
+#[derive(Debug, Clone)]
+pub struct UsageReport {
+    pub tenant: Option<String>,
+    pub session: u64,
+    pub started: i64,
+    pub reported_at: i64,
+    pub connection_minutes: f64,
+    pub peak_clients: usize,
+    pub bytes_relayed: u64,
+    /// Whether this is the last report of the session
+    pub final_report: bool,
+}
+
+impl UsageReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "tenant": self.tenant,
+            "session": self.session,
+            "started": rfc3339(self.started),
+            "reported_at": rfc3339(self.reported_at),
+            "connection_minutes": self.connection_minutes,
+            "peak_clients": self.peak_clients,
+            "bytes_relayed": self.bytes_relayed,
+            "final": self.final_report,
+        })
+    }
+
+    pub fn to_csv(&self, format: &TimeFormat) -> String {
+        format!("{},{},{},{},{:.2},{},{},{}",
+            self.tenant.as_deref().unwrap_or_default().replace(',', " "), self.session, format.human(self.started),
+            format.human(self.reported_at), self.connection_minutes, self.peak_clients, self.bytes_relayed, self.final_report)
+    }
+}
+
+/// Counters of the running session
+#[derive(Debug, Default)]
+pub struct UsageMeter {
+    /// Connected clients and the server timestamp they connected (or the session started) at
+    connected: HashMap<SocketAddr, i64>,
+    /// Connection time of clients that already left
+    connection_ms: i64,
+    peak_clients: usize,
+    bytes_relayed: u64,
+}
+
+impl UsageMeter {
+    /// Starts counting anew, the given clients count as connected from now on
+    pub fn reset(&mut self, connected: impl Iterator<Item = SocketAddr>, now: i64) {
+        *self = UsageMeter::default();
+        for address in connected {
+            self.client_connected(address, now);
+        }
+    }
+
+    pub fn client_connected(&mut self, address: SocketAddr, now: i64) {
+        self.connected.insert(address, now);
+        self.peak_clients = self.peak_clients.max(self.connected.len());
+    }
+
+    /// 'bytes' were sent to the client while it was connected
+    pub fn client_disconnected(&mut self, address: SocketAddr, bytes: u64, now: i64) {
+        if let Some(since) = self.connected.remove(&address) {
+            self.connection_ms += now - since;
+        }
+        self.bytes_relayed += bytes;
+    }
+
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.bytes_relayed += bytes;
+    }
+
+    /// 'pending_bytes' were sent to the still connected clients
+    pub fn report(&self, tenant: Option<String>, session: u64, started: i64, pending_bytes: u64, now: i64, final_report: bool) -> UsageReport {
+        let open_ms: i64 = self.connected.values().map(|since| now - since).sum();
+        UsageReport {
+            tenant,
+            session,
+            started,
+            reported_at: now,
+            connection_minutes: (self.connection_ms + open_ms) as f64 / 60_000.0,
+            peak_clients: self.peak_clients,
+            bytes_relayed: self.bytes_relayed + pending_bytes,
+            final_report,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//!
+//! Per-client variants of a state.
+//! The host may send a set of variants with 'ChangeState', every client then receives only the
+//! variant assigned to it (e.g. for A/B questions or to make copying answers useless). The host is
+//! told which client got which variant.
+//!
+
+use std::collections::HashMap;
+use rand::Rng;
+
+/// How variants are assigned to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    Random,
+    /// In order of assignment, the first client gets the first variant
+    RoundRobin,
+    /// Every client keeps the variant index it got the first time, across states
+    Sticky,
+}
+
+impl Distribution {
+    /// Parses 'random', 'round_robin' or 'sticky'
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "random" => Some(Distribution::Random),
+            "round_robin" => Some(Distribution::RoundRobin),
+            "sticky" => Some(Distribution::Sticky),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Variants {
+    pub contents: Vec<String>,
+    pub distribution: Distribution,
+}
+
+/// Variant delivered to a client, as reported to the host
+#[derive(Debug, Clone)]
+pub struct VariantAssignment {
+    pub name: String,
+    pub address: String,
+    pub variant: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct VariantAssigner {
+    current: Option<Variants>,
+    next: usize,
+    sticky: HashMap<String, usize>,
+}
+
+impl VariantAssigner {
+    /// Replaces the variants of the previous state, None if the new state has no variants
+    pub fn state_changed(&mut self, variants: Option<Variants>) {
+        self.current = variants.filter(|variants| !variants.contents.is_empty());
+        self.next = 0;
+    }
+
+    /// Returns the index and content of the variant for the client
+    /// None if the current state has no variants
+    pub fn assign(&mut self, name: &str) -> Option<(usize, String)> {
+        let variants = self.current.as_ref()?;
+        let count = variants.contents.len();
+        let index = match variants.distribution {
+            Distribution::Random => rand::thread_rng().gen_range(0..count),
+            Distribution::RoundRobin => {
+                self.next += 1;
+                (self.next - 1) % count
+            }
+            Distribution::Sticky => *self.sticky.entry(String::from(name))
+                .or_insert_with(|| rand::thread_rng().gen_range(0..count)) % count,
+        };
+        Some((index, variants.contents[index].clone()))
+    }
+}
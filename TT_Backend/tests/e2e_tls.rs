@@ -0,0 +1,1391 @@
+//!
+//! End-to-end tests of the server binary.
+//! Every test generates an ephemeral self-signed certificate, starts the server on free ports and
+//! talks to it like the WebApp (websocket over TLS) and the HostApp (framed tcp) do.
+//!
+#![cfg(not(feature = "insecure_ws"))]
+
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::{ChildStdin, ChildStdout};
+use tokio::time::timeout;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::Message;
+
+const HOST_FRAME_MAGIC: [u8; 4] = *b"TTHF";
+const HOST_CHUNK_MAGIC: [u8; 4] = *b"TTHC";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+const ADMIN_SECRET: &str = "e2e-admin-secret";
+
+type ClientSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Server process running in its own directory, killed on drop
+struct TestServer {
+    process: Child,
+    directory: PathBuf,
+    cert_pem: String,
+    ws_port: u16,
+    tcp_port: u16,
+    admin_port: u16,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        TestServer::start_with(&[]).await
+    }
+
+    /// Starts the server with additional environment variables
+    async fn start_with(env: &[(&str, &str)]) -> Self {
+        TestServer::start_piped(env, false).await
+    }
+
+    /// Starts the server as the stdio host of the test, returning its stdin and stdout
+    async fn start_with_stdio_host() -> (Self, ChildStdin, ChildStdout) {
+        let mut server = TestServer::start_piped(&[("TT_BACKEND_HOST_STDIO", "parent")], true).await;
+        let stdin = ChildStdin::from_std(server.process.stdin.take().unwrap()).expect("Piping stdin failed");
+        let stdout = ChildStdout::from_std(server.process.stdout.take().unwrap()).expect("Piping stdout failed");
+        (server, stdin, stdout)
+    }
+
+    async fn start_piped(env: &[(&str, &str)], piped: bool) -> Self {
+        let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).expect("Generating certificate failed");
+        let cert_pem = certificate.serialize_pem().expect("Serializing certificate failed");
+        let key_pem = certificate.serialize_private_key_pem();
+
+        // The server reads its certificate from 'res/cert' relative to its working directory
+        let directory = std::env::temp_dir().join(format!("tt_online_e2e_{}_{}", std::process::id(), free_port()));
+        std::fs::create_dir_all(directory.join("res/cert")).expect("Creating test directory failed");
+        std::fs::write(directory.join("res/cert/cert.pem"), &cert_pem).expect("Writing certificate failed");
+        std::fs::write(directory.join("res/cert/key.pem"), &key_pem).expect("Writing key failed");
+
+        let [ws_port, tcp_port, shadow_port, admin_port] = [free_port(), free_port(), free_port(), free_port()];
+        let process = Command::new(env!("CARGO_BIN_EXE_tt_online"))
+            .current_dir(&directory)
+            .env("TT_BACKEND_WS_PORT", ws_port.to_string())
+            .env("TT_BACKEND_TCP_PORT", tcp_port.to_string())
+            .env("TT_BACKEND_SHADOW_PORT", shadow_port.to_string())
+            .env("TT_BACKEND_ADMIN_PORT", admin_port.to_string())
+            .env("TT_BACKEND_ADMIN_SECRET", ADMIN_SECRET)
+            .envs(env.iter().copied())
+            .stdin(if piped { Stdio::piped() } else { Stdio::null() })
+            .stdout(if piped { Stdio::piped() } else { Stdio::null() })
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Starting server failed");
+
+        let server = TestServer { process, directory, cert_pem, ws_port, tcp_port, admin_port };
+        server.wait_until_ready().await;
+        server
+    }
+
+    /// Waits until the admin interface reports all listeners as bound
+    async fn wait_until_ready(&self) {
+        timeout(STARTUP_TIMEOUT, async {
+            loop {
+                if let Some(health) = self.health().await {
+                    if health["status"] == "ok" {
+                        return
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }).await.expect("Server did not start in time");
+    }
+
+    async fn health(&self) -> Option<Value> {
+        self.admin_get("/health").await
+    }
+
+    async fn admin_get(&self, path: &str) -> Option<Value> {
+        self.admin_request("GET", path).await
+    }
+
+    async fn admin_request(&self, method: &str, path: &str) -> Option<Value> {
+        self.admin_request_as(method, path, Some(ADMIN_SECRET)).await
+    }
+
+    /// Sends the admin request with the given bearer secret, or none
+    async fn admin_request_as(&self, method: &str, path: &str, secret: Option<&str>) -> Option<Value> {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.admin_port)).await.ok()?;
+        let authorization = secret.map(|secret| format!("Authorization: Bearer {}\r\n", secret)).unwrap_or_default();
+        stream.write_all(format!("{} {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n", method, path, authorization).as_bytes()).await.ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok()?;
+        let (_, body) = response.split_once("\r\n\r\n")?;
+        serde_json::from_str(body).ok()
+    }
+
+    /// Connects a client via websocket over TLS, trusting only the generated certificate
+    async fn connect_client(&self) -> ClientSocket {
+        self.try_connect_client().await.expect("Connecting client failed")
+    }
+
+    /// Connects a client to the websocket path, e.g. '/session/<code>'
+    async fn connect_client_at(&self, path: &str) -> ClientSocket {
+        self.try_connect_client_at(path).await.expect("Connecting client failed")
+    }
+
+    async fn try_connect_client(&self) -> Option<ClientSocket> {
+        self.try_connect_client_at("").await
+    }
+
+    async fn try_connect_client_at(&self, path: &str) -> Option<ClientSocket> {
+        let certificate = native_tls::Certificate::from_pem(self.cert_pem.as_bytes()).expect("Parsing certificate failed");
+        let connector = native_tls::TlsConnector::builder()
+            .add_root_certificate(certificate)
+            .build()
+            .expect("Creating tls connector failed");
+        let url = format!("wss://localhost:{}{}", self.ws_port, path);
+        let (socket, _) = tokio_tungstenite::connect_async_tls_with_config(url, None, Some(Connector::NativeTls(connector))).await.ok()?;
+        Some(socket)
+    }
+
+    /// Replaces certificate and key by a newly generated pair, trusted from now on
+    fn renew_certificate(&mut self) {
+        let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).expect("Generating certificate failed");
+        self.cert_pem = certificate.serialize_pem().expect("Serializing certificate failed");
+        std::fs::write(self.directory.join("res/cert/key.pem"), certificate.serialize_private_key_pem()).expect("Writing key failed");
+        std::fs::write(self.directory.join("res/cert/cert.pem"), &self.cert_pem).expect("Writing certificate failed");
+    }
+
+    fn signal(&self, signal: &str) {
+        let status = Command::new("kill").arg(format!("-{}", signal)).arg(self.process.id().to_string()).status().expect("Running kill failed");
+        assert!(status.success(), "Sending {} failed", signal);
+    }
+
+    async fn connect_host(&self) -> TcpStream {
+        let stream = TcpStream::connect(("127.0.0.1", self.tcp_port)).await.expect("Connecting host failed");
+        // Frames are written in pieces, which Nagle holds back once the server delays its acks
+        // (it does as soon as it wrote the hello)
+        stream.set_nodelay(true).expect("Disabling Nagle failed");
+        stream
+    }
+
+    /// Connects and logs in a host, returns once it is the active host
+    /// Hosts only receive client events from then on, joins racing the login would be missed
+    async fn login_host(&self) -> TcpStream {
+        let mut host = self.connect_host().await;
+        host_send(&mut host, json!({"type": "HostLogin"})).await;
+        self.wait_for_host().await;
+        host
+    }
+
+    async fn wait_for_host(&self) {
+        timeout(RECEIVE_TIMEOUT, async {
+            while self.health().await.map(|health| health["host_connected"] != true).unwrap_or(true) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("Host did not become the active host");
+    }
+
+    /// Connects a host over TLS, presenting the identity (certificate and key pem) if given
+    async fn try_connect_host_tls(&self, identity: Option<(&str, &str)>) -> Option<tokio_native_tls::TlsStream<TcpStream>> {
+        let certificate = native_tls::Certificate::from_pem(self.cert_pem.as_bytes()).expect("Parsing certificate failed");
+        let mut builder = native_tls::TlsConnector::builder();
+        builder.add_root_certificate(certificate);
+        if let Some((cert, key)) = identity {
+            builder.identity(native_tls::Identity::from_pkcs8(cert.as_bytes(), key.as_bytes()).expect("Parsing host identity failed"));
+        }
+        let connector = tokio_native_tls::TlsConnector::from(builder.build().expect("Creating tls connector failed"));
+        let stream = self.connect_host().await;
+        connector.connect("localhost", stream).await.ok()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").expect("Binding free port failed").local_addr().unwrap().port()
+}
+
+async fn client_send(socket: &mut ClientSocket, msg: Value) {
+    socket.send(Message::Text(msg.to_string())).await.expect("Sending client message failed");
+}
+
+/// Returns the next message of the given type, other messages are skipped
+async fn client_receive(socket: &mut ClientSocket, msg_type: &str) -> Value {
+    timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            match socket.next().await.expect("Client connection closed").expect("Receiving failed") {
+                Message::Text(text) => {
+                    let msg: Value = serde_json::from_str(&text).expect("Client received malformed json");
+                    if msg["type"] == msg_type {
+                        return msg
+                    }
+                }
+                _ => continue,
+            }
+        }
+    }).await.unwrap_or_else(|_| panic!("Client did not receive '{}'", msg_type))
+}
+
+/// Returns the next MessagePack message of the given type, other messages are skipped
+async fn client_receive_msgpack(socket: &mut ClientSocket, msg_type: &str) -> Value {
+    timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            match socket.next().await.expect("Client connection closed").expect("Receiving failed") {
+                Message::Binary(bytes) => {
+                    let msg: Value = rmp_serde::from_slice(&bytes).expect("Client received malformed MessagePack");
+                    if msg["type"] == msg_type {
+                        return msg
+                    }
+                }
+                Message::Text(text) => panic!("Client received text instead of MessagePack: {}", text),
+                _ => continue,
+            }
+        }
+    }).await.unwrap_or_else(|_| panic!("Client did not receive '{}'", msg_type))
+}
+
+async fn host_send(stream: &mut (impl AsyncWrite + Unpin), msg: Value) {
+    let payload = msg.to_string();
+    stream.write_all(&HOST_FRAME_MAGIC).await.unwrap();
+    stream.write_u32(payload.len() as u32).await.unwrap();
+    stream.write_all(payload.as_bytes()).await.expect("Sending host message failed");
+}
+
+/// Sends a chunk of a bulk message, 'flags' marks the first and/or last chunk
+async fn host_send_chunk(stream: &mut TcpStream, flags: u8, data: &[u8]) {
+    stream.write_all(&HOST_CHUNK_MAGIC).await.unwrap();
+    stream.write_u32(data.len() as u32 + 1).await.unwrap();
+    stream.write_u8(flags).await.unwrap();
+    stream.write_all(data).await.expect("Sending host chunk failed");
+}
+
+/// Returns the next message of the given type, other messages are skipped
+async fn host_receive(stream: &mut (impl AsyncRead + Unpin), msg_type: &str) -> Value {
+    timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            let mut magic = [0; 4];
+            stream.read_exact(&mut magic).await.expect("Host connection closed");
+            assert_eq!(magic, HOST_FRAME_MAGIC, "Host frame does not start with the magic");
+            let length = stream.read_u32().await.unwrap();
+            let mut payload = vec![0; length as usize];
+            stream.read_exact(&mut payload).await.unwrap();
+            let msg: Value = serde_json::from_slice(&payload).expect("Host received malformed json");
+            if msg["type"] == msg_type {
+                return msg
+            }
+        }
+    }).await.unwrap_or_else(|_| panic!("Host did not receive '{}'", msg_type))
+}
+
+#[tokio::test]
+async fn health_reports_bound_ports() {
+    let server = TestServer::start().await;
+    let health = server.health().await.expect("No health response");
+
+    assert_eq!(health["listeners"]["clients"], format!("127.0.0.1:{}", server.ws_port));
+    assert_eq!(health["listeners"]["hosts"], format!("127.0.0.1:{}", server.tcp_port));
+    assert_eq!(health["clients"], 0);
+}
+
+#[tokio::test]
+async fn listeners_report_their_accept_loops() {
+    let server = TestServer::start().await;
+    let report = server.admin_get("/debug/listeners").await.expect("No diagnostics response");
+    let listeners = report["listeners"].as_array().expect("No listeners reported");
+
+    assert_eq!(listeners.len(), 4);
+    assert!(listeners.iter().all(|listener| listener["running"] == true && listener["restarts"] == 0));
+    assert!(listeners.iter().any(|listener| listener["address"] == format!("127.0.0.1:{}", server.ws_port)));
+}
+
+#[tokio::test]
+async fn clients_are_rejected_at_descriptor_capacity() {
+    // A reserve above any limit leaves no descriptor for clients
+    let server = TestServer::start_with(&[("TT_BACKEND_FD_RESERVE", "100000000")]).await;
+    let mut client = server.connect_client().await;
+    let rejected = client_receive(&mut client, "Disconnecting").await;
+    assert_eq!(rejected["reason"], "Server is at capacity, please try again later");
+    assert!(rejected["reconnect"].is_object());
+
+    let descriptors = &server.health().await.expect("No health response")["file_descriptors"];
+    assert_eq!(descriptors["rejected"], 1);
+    assert!(descriptors["limit"].as_u64().is_some());
+}
+
+#[tokio::test]
+async fn connections_per_ip_are_limited() {
+    let server = TestServer::start_with(&[("TT_BACKEND_MAX_CLIENTS_PER_IP", "1")]).await;
+    let mut host = server.login_host().await;
+    let mut first = server.connect_client().await;
+    client_send(&mut first, json!({"type": "ClientLogin", "name": "kate"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    let mut second = server.connect_client().await;
+    client_send(&mut second, json!({"type": "ClientLogin", "name": "kate_tab"})).await;
+    assert_eq!(client_receive(&mut second, "LoginRejected").await["reason"], "Too many connections from this address");
+}
+
+#[tokio::test]
+async fn host_is_notified_about_client_login() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+
+    let connected = host_receive(&mut host, "ClientConnected").await;
+    assert_eq!(connected["name"], "alice");
+}
+
+#[tokio::test]
+async fn host_has_to_present_the_secret() {
+    let server = TestServer::start_with(&[("TT_BACKEND_HOST_SECRET", "s3cret")]).await;
+    let mut host = server.connect_host().await;
+    host_send(&mut host, json!({"type": "HostLogin", "token": "s3cret"})).await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    let mut guesser = server.connect_host().await;
+    host_send(&mut guesser, json!({"type": "HostLogin", "token": "guess"})).await;
+    assert_eq!(host_receive(&mut guesser, "LoginRejected").await["reason"], "Invalid host token");
+
+    // Without a 'HostLogin' first the connection never becomes the host
+    let mut intruder = server.connect_host().await;
+    host_send(&mut intruder, json!({"type": "ChangeState", "state_id": 1, "content": "spam"})).await;
+    assert_eq!(host_receive(&mut intruder, "LoginRejected").await["reason"], "Invalid host token");
+
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 2, "content": "question"})).await;
+    assert_eq!(client_receive(&mut client, "ChangeState").await["content"], "question");
+}
+
+#[tokio::test]
+async fn host_is_authenticated_by_the_provider() {
+    let server = TestServer::start_with(&[("TT_BACKEND_AUTH", "static:letmein")]).await;
+    let mut guesser = server.connect_host().await;
+    host_send(&mut guesser, json!({"type": "HostLogin", "token": "guess"})).await;
+    assert_eq!(host_receive(&mut guesser, "LoginRejected").await["reason"], "Invalid token");
+
+    let mut host = server.connect_host().await;
+    host_send(&mut host, json!({"type": "HostLogin", "token": "letmein"})).await;
+    server.wait_for_host().await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice", "token": "letmein"})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "alice");
+}
+
+#[tokio::test]
+async fn resumed_client_keeps_its_inputs() {
+    let server = TestServer::start_with(&[("TT_BACKEND_DISCONNECT_GRACE", "30")]).await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 1, "content": "question"})).await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    let token = client_receive(&mut client, "Resume").await["token"].as_str().expect("No token").to_string();
+    client_send(&mut client, json!({"type": "Input", "state_id": 1, "content": "42"})).await;
+    host_receive(&mut host, "Input").await;
+    drop(client);
+    let departed = host_receive(&mut host, "ClientDisconnected").await;
+    assert_eq!(departed["answered"], true);
+
+    let mut returning = server.connect_client().await;
+    client_send(&mut returning, json!({"type": "ClientLogin", "name": "alice (phone)", "resume": token})).await;
+    let address = host_receive(&mut host, "ClientConnected").await["address"].as_str().expect("No address").to_string();
+    assert_ne!(Some(address.as_str()), departed["address"].as_str());
+    host_send(&mut host, json!({"type": "GetClientInputs", "client_id": address, "state_id": 1})).await;
+    let inputs = host_receive(&mut host, "ClientInputs").await;
+    assert_eq!(inputs["inputs"][0]["input"], "42");
+}
+
+#[tokio::test]
+async fn muted_client_stays_muted_after_reconnecting() {
+    let server = TestServer::start_with(&[("TT_BACKEND_DISCONNECT_GRACE", "30")]).await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 1, "content": "question"})).await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "mallory"})).await;
+    let token = client_receive(&mut client, "Resume").await["token"].clone();
+    let address = host_receive(&mut host, "ClientConnected").await["address"].clone();
+    host_send(&mut host, json!({"type": "MuteClient", "id": address, "muted": true})).await;
+    client_send(&mut client, json!({"type": "Input", "state_id": 1, "content": "spam"})).await;
+    client_receive(&mut client, "Muted").await;
+    drop(client);
+    host_receive(&mut host, "ClientDisconnected").await;
+
+    let mut returning = server.connect_client().await;
+    client_send(&mut returning, json!({"type": "ClientLogin", "name": "mallory", "resume": token})).await;
+    client_receive(&mut returning, "ChangeState").await;
+    client_send(&mut returning, json!({"type": "Input", "state_id": 1, "content": "spam"})).await;
+    client_receive(&mut returning, "Muted").await;
+}
+
+#[tokio::test]
+async fn same_name_does_not_cancel_the_grace_period() {
+    let server = TestServer::start_with(&[("TT_BACKEND_DISCONNECT_GRACE", "1")]).await;
+    let mut host = server.login_host().await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    drop(client);
+    let address = host_receive(&mut host, "ClientDisconnected").await["address"].clone();
+
+    let mut impostor = server.connect_client().await;
+    client_send(&mut impostor, json!({"type": "ClientLogin", "name": "alice"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    let expired = host_receive(&mut host, "ClientExpired").await;
+    assert_eq!(expired["name"], "alice");
+    assert_eq!(expired["address"], address);
+}
+
+#[tokio::test]
+async fn rotated_access_code_applies_to_new_joins() {
+    let server = TestServer::start_with(&[("TT_BACKEND_ACCESS_CODE", "leaked")]).await;
+    let mut host = server.login_host().await;
+
+    let mut intruder = server.connect_client().await;
+    client_send(&mut intruder, json!({"type": "ClientLogin", "name": "mallory", "code": "guess"})).await;
+    assert_eq!(client_receive(&mut intruder, "LoginRejected").await["reason"], "Invalid access code");
+
+    let mut early = server.connect_client().await;
+    client_send(&mut early, json!({"type": "ClientLogin", "name": "alice", "code": " Leaked"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    host_send(&mut host, json!({"type": "RotateAccessCode"})).await;
+    let code = host_receive(&mut host, "AccessCode").await["code"].as_str().expect("No code").to_string();
+    assert_eq!(code.len(), 6);
+
+    let mut late = server.connect_client().await;
+    client_send(&mut late, json!({"type": "ClientLogin", "name": "mallory", "code": "leaked"})).await;
+    assert_eq!(client_receive(&mut late, "LoginRejected").await["reason"], "Invalid access code");
+
+    let mut invited = server.connect_client().await;
+    client_send(&mut invited, json!({"type": "ClientLogin", "name": "bob", "code": code})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "bob");
+
+    // Clients joined with the old code stay
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 3, "content": "question"})).await;
+    assert_eq!(client_receive(&mut early, "ChangeState").await["state_id"], 3);
+    assert_eq!(server.health().await.expect("No health response")["access_code_required"], true);
+}
+
+#[tokio::test]
+async fn session_path_gives_the_access_code() {
+    let server = TestServer::start_with(&[("TT_BACKEND_ACCESS_CODE", "quiz")]).await;
+    let mut host = server.login_host().await;
+
+    let mut lost = server.connect_client_at("/session/other").await;
+    client_send(&mut lost, json!({"type": "ClientLogin", "name": "mallory"})).await;
+    assert_eq!(client_receive(&mut lost, "LoginRejected").await["reason"], "Invalid access code");
+
+    let mut linked = server.connect_client_at("/session/QUIZ").await;
+    client_send(&mut linked, json!({"type": "ClientLogin", "name": "alice"})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "alice");
+
+    // The code of the login wins over the path
+    let mut typed = server.connect_client_at("/session/other").await;
+    client_send(&mut typed, json!({"type": "ClientLogin", "name": "bob", "code": "quiz"})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "bob");
+}
+
+#[tokio::test]
+async fn session_memory_is_listed_by_holder() {
+    let server = TestServer::start_with(&[("TT_BACKEND_SESSION_MEMORY_LIMIT", "64")]).await;
+    let mut host = server.login_host().await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 1, "content": "q".repeat(1000)})).await;
+    client_receive(&mut client, "ChangeState").await;
+    host_send(&mut host, json!({"type": "Update", "state_id": 1, "content": "u".repeat(500)})).await;
+    client_receive(&mut client, "Update").await;
+    client_send(&mut client, json!({"type": "Input", "state_id": 1, "content": "answer"})).await;
+    host_receive(&mut host, "Input").await;
+
+    let memory = server.admin_get("/debug/memory").await.expect("No admin response");
+    assert_eq!(memory["limit"], 64 * 1024 * 1024);
+    assert_eq!(memory["state"], 1000);
+    assert_eq!(memory["updates"], 500);
+    assert_eq!(memory["recorded_inputs"], 6);
+    assert!(memory["total"].as_u64().expect("No total") >= 1506);
+    assert_eq!(server.health().await.expect("No health response")["session_memory"], memory["total"]);
+}
+
+#[tokio::test]
+async fn rejoin_link_restores_the_client() {
+    let server = TestServer::start_with(&[("TT_BACKEND_ACCESS_CODE", "quiz")]).await;
+    let mut host = server.login_host().await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice", "code": "quiz"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 5, "content": "question"})).await;
+    client_receive(&mut client, "ChangeState").await;
+    client_send(&mut client, json!({"type": "Input", "state_id": 5, "content": "answer"})).await;
+    host_receive(&mut host, "Input").await;
+
+    let issued = server.admin_request("POST", "/rejoin-links").await.expect("No admin response");
+    assert_eq!(issued["clients"], 1);
+    let link = client_receive(&mut client, "RejoinLink").await;
+    let token = link["url"].as_str().and_then(|url| url.strip_prefix("?rejoin=")).expect("No rejoin token").to_string();
+    drop(client);
+    host_receive(&mut host, "ClientDisconnected").await;
+
+    // Neither name nor access code, the token restores them and the answer
+    let mut rejoined = server.connect_client().await;
+    client_send(&mut rejoined, json!({"type": "ClientLogin", "rejoin": token})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "alice");
+    client_send(&mut rejoined, json!({"type": "Disconnecting", "reason": "done"})).await;
+    assert_eq!(host_receive(&mut host, "ClientDisconnected").await["answered"], true);
+
+    let mut reused = server.connect_client().await;
+    client_send(&mut reused, json!({"type": "ClientLogin", "rejoin": token})).await;
+    assert_eq!(client_receive(&mut reused, "LoginRejected").await["reason"], "Invalid or expired rejoin link");
+}
+
+#[tokio::test]
+async fn host_syncs_the_client_list_incrementally() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let mut alice = server.connect_client().await;
+    client_send(&mut alice, json!({"type": "ClientLogin", "name": "alice"})).await;
+    let alice_address = host_receive(&mut host, "ClientConnected").await["address"].clone();
+
+    host_send(&mut host, json!({"type": "SyncClientList"})).await;
+    let full = host_receive(&mut host, "ClientListDiff").await;
+    assert_eq!(full["full"], true);
+    assert_eq!(full["added"][0]["name"], "alice");
+
+    let mut bob = server.connect_client().await;
+    client_send(&mut bob, json!({"type": "ClientLogin", "name": "bob"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    client_send(&mut alice, json!({"type": "Disconnecting", "reason": "done"})).await;
+    host_receive(&mut host, "ClientDisconnected").await;
+
+    host_send(&mut host, json!({"type": "SyncClientList", "version": full["version"]})).await;
+    let diff = host_receive(&mut host, "ClientListDiff").await;
+    assert_eq!(diff["full"], false);
+    assert_eq!(diff["added"].as_array().map(Vec::len), Some(1));
+    assert_eq!(diff["added"][0]["name"], "bob");
+    assert_eq!(diff["removed"], json!([alice_address]));
+
+    // A version the server did not send last gets the whole list again
+    host_send(&mut host, json!({"type": "SyncClientList", "version": full["version"]})).await;
+    let resync = host_receive(&mut host, "ClientListDiff").await;
+    assert_eq!(resync["full"], true);
+    assert_eq!(resync["added"][0]["name"], "bob");
+}
+
+#[tokio::test]
+async fn muted_presence_events_are_summarized() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 1, "content": "lobby"})).await;
+
+    host_send(&mut host, json!({"type": "Subscribe", "events": ["variants", "reactions"]})).await;
+    assert_eq!(host_receive(&mut host, "Subscribed").await["events"], json!(["variants"]));
+
+    let mut alice = server.connect_client().await;
+    client_send(&mut alice, json!({"type": "ClientLogin", "name": "alice"})).await;
+    client_receive(&mut alice, "ChangeState").await;
+
+    // Subscribing again summarizes what was held back, alice never reached the host one by one
+    host_send(&mut host, json!({"type": "Subscribe", "events": ["presence"]})).await;
+    let summary = host_receive(&mut host, "PresenceSummary").await;
+    assert_eq!(summary["joined"], 1);
+    assert_eq!(summary["clients"], 1);
+    host_receive(&mut host, "Subscribed").await;
+
+    let mut bob = server.connect_client().await;
+    client_send(&mut bob, json!({"type": "ClientLogin", "name": "bob"})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "bob");
+}
+
+#[tokio::test]
+async fn hosts_take_over_after_the_hello() {
+    let server = TestServer::start().await;
+    let mut host = server.connect_host().await;
+    host_send(&mut host, json!({"type": "HostLogin", "version": 2, "capabilities": ["chunks", "teleport"]})).await;
+    let hello = host_receive(&mut host, "ServerHello").await;
+    assert_eq!(hello["version"], 2);
+    assert!(hello["capabilities"].as_array().is_some_and(|capabilities| capabilities.contains(&json!("chunks"))));
+    assert_eq!(hello["limits"]["max_frame_size"], 16 * 1024 * 1024);
+    server.wait_for_host().await;
+
+    // A host that did not answer the hello yet does not replace the active one
+    let mut next = server.connect_host().await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "alice");
+
+    host_send(&mut next, json!({"type": "HostLogin"})).await;
+    host_receive(&mut next, "ServerHello").await;
+    assert_eq!(host_receive(&mut host, "Disconnecting").await["reason"], "Another host connected");
+}
+
+#[tokio::test]
+async fn scheduled_messages_are_sent_by_the_server() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    host_send(&mut host, json!({"type": "ScheduleMessage", "id": "late", "message": {"type": "Update", "state_id": 1, "content": "x"}})).await;
+    assert_eq!(host_receive(&mut host, "ScheduledMessages").await["error"], "Neither 'at' nor 'interval' given");
+
+    host_send(&mut host, json!({"type": "ScheduleMessage", "id": "reminder", "interval": 600_000, "message": {"type": "ShowLeaderboard", "count": 3}})).await;
+    let pending = host_receive(&mut host, "ScheduledMessages").await;
+    assert_eq!(pending["messages"][0]["id"], "reminder");
+    assert_eq!(pending["messages"][0]["interval"], 600_000);
+    host_send(&mut host, json!({"type": "CancelScheduledMessage", "id": "reminder"})).await;
+    assert_eq!(host_receive(&mut host, "ScheduledMessages").await["messages"], json!([]));
+
+    // A timestamp in the past is sent right away
+    host_send(&mut host, json!({"type": "ScheduleMessage", "id": "reveal", "at": 0, "message": {"type": "ChangeState", "state_id": 9, "content": "answer"}})).await;
+    assert_eq!(client_receive(&mut client, "ChangeState").await["state_id"], 9);
+    let sent = host_receive(&mut host, "ScheduledMessageSent").await;
+    assert_eq!(sent["id"], "reveal");
+    assert_eq!(sent["next_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn state_and_inputs_are_relayed() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "bob"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 7, "content": "question"})).await;
+    let state = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(state["state_id"], 7);
+    assert_eq!(state["content"], "question");
+
+    client_send(&mut client, json!({"type": "Input", "state_id": 7, "content": "answer", "input_id": "i1"})).await;
+    let input = host_receive(&mut host, "Input").await;
+    assert_eq!(input["name"], "bob");
+    assert_eq!(input["input"], "answer");
+
+    let ack = client_receive(&mut client, "InputAck").await;
+    assert_eq!(ack["input_id"], "i1");
+
+    // Every message is stamped with the session and state it was sent in
+    assert!(ack["meta"]["seq"].as_u64() > state["meta"]["seq"].as_u64());
+    assert_eq!(ack["meta"]["state_id"], 7);
+    assert_eq!(ack["meta"]["session"], input["meta"]["session"]);
+}
+
+/// Module emitting every input unchanged, see src/server/plugins.rs for the interface
+#[cfg(feature = "wasm_plugins")]
+const ECHO_PLUGIN: &str = "AGFzbQEAAAABCwJgAn9/AGABfwF/AgwBA2VudgRlbWl0AAADAwIBAAUDAQABBx0DBm1lbW9yeQIABWFsbG9jAAEIb25faW5wdXQAAgoQAgUAQYAICwgAIAAgARAACw==";
+
+#[cfg(feature = "wasm_plugins")]
+#[tokio::test]
+async fn plugin_results_reach_host_and_clients() {
+    let server = TestServer::start_with(&[("TT_BACKEND_PLUGINS", "fuel=100000,memory=64")]).await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "LoadPlugin", "module": ECHO_PLUGIN})).await;
+    assert_eq!(host_receive(&mut host, "PluginStatus").await["loaded"], true);
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "bob"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    client_send(&mut client, json!({"type": "Input", "state_id": 7, "content": "answer"})).await;
+    let result = host_receive(&mut host, "PluginResult").await;
+    assert_eq!(result["content"]["name"], "bob");
+    assert_eq!(result["content"]["input"], "answer");
+    assert_eq!(client_receive(&mut client, "PluginResult").await["content"], result["content"]);
+
+    host_send(&mut host, json!({"type": "LoadPlugin", "module": "AGFzbQ=="})).await;
+    let status = host_receive(&mut host, "PluginStatus").await;
+    assert_eq!(status["loaded"], true, "A broken module keeps the loaded one");
+    assert!(status["error"].is_string());
+}
+
+#[cfg(not(feature = "wasm_plugins"))]
+#[tokio::test]
+async fn plugins_need_the_runtime() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "LoadPlugin", "module": "AGFzbQ=="})).await;
+    let status = host_receive(&mut host, "PluginStatus").await;
+    assert_eq!(status["loaded"], false);
+    assert!(status["error"].as_str().unwrap().contains("wasm_plugins"));
+}
+
+#[tokio::test]
+async fn host_downloads_session_snapshot() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "bob"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 3, "content": "question"})).await;
+    client_receive(&mut client, "ChangeState").await;
+    client_send(&mut client, json!({"type": "Input", "state_id": 3, "content": "answer", "input_id": "i1"})).await;
+    host_receive(&mut host, "Input").await;
+
+    host_send(&mut host, json!({"type": "RequestSnapshotExport"})).await;
+    let archive = host_receive(&mut host, "SnapshotExport").await["archive"].clone();
+    assert_eq!(archive["state"]["state_id"], 3);
+    assert_eq!(archive["clients"][0]["name"], "bob");
+    assert_eq!(archive["inputs"][0]["input"], "answer");
+
+    // The admin interface serves no archive, unauthenticated requests are refused anyway
+    let download = server.admin_request_as("GET", "/snapshot-export?token=unknown", None).await.expect("No admin response");
+    assert!(download.get("error").is_some());
+}
+
+#[tokio::test]
+async fn host_is_told_once_the_quorum_acknowledged() {
+    let server = TestServer::start_with(&[("TT_BACKEND_STATE_QUORUM", "50")]).await;
+    let mut host = server.login_host().await;
+
+    let mut clients = vec![];
+    for name in ["alice", "bob"] {
+        let mut client = server.connect_client().await;
+        client_send(&mut client, json!({"type": "ClientLogin", "name": name})).await;
+        host_receive(&mut host, "ClientConnected").await;
+        clients.push(client);
+    }
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 5, "content": "question"})).await;
+    for client in clients.iter_mut() {
+        client_receive(client, "ChangeState").await;
+    }
+
+    // Acknowledgments of other states do not count
+    client_send(&mut clients[0], json!({"type": "StateAck", "state_id": 4})).await;
+    client_send(&mut clients[0], json!({"type": "StateAck", "state_id": 5})).await;
+    let quorum = host_receive(&mut host, "QuorumReached").await;
+    assert_eq!(quorum["state_id"], 5);
+    assert_eq!(quorum["percent"], 50);
+}
+
+#[tokio::test]
+async fn renewed_certificate_is_reloaded() {
+    let mut server = TestServer::start().await;
+    let mut host = server.login_host().await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "bob"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    server.renew_certificate();
+    server.signal("HUP");
+    let renewed = timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            if let Some(socket) = server.try_connect_client().await {
+                return socket
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.expect("Renewed certificate was not picked up");
+    drop(renewed);
+
+    // Established connections keep their certificate
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 2, "content": "question"})).await;
+    let state = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(state["state_id"], 2);
+}
+
+#[tokio::test]
+async fn acme_challenges_are_answered_on_their_port() {
+    let acme_port = free_port().to_string();
+    // The generated certificate covers 'localhost' for long, nothing is ordered from the directory
+    let directory = format!("http://127.0.0.1:{}/directory", free_port());
+    let server = TestServer::start_with(&[("TT_BACKEND_ACME_DOMAIN", "localhost"), ("TT_BACKEND_ACME_HTTP_PORT", &acme_port), ("TT_BACKEND_ACME_DIRECTORY", &directory)]).await;
+    let health = server.health().await.expect("No health response");
+    assert_eq!(health["listeners"]["acme"], format!("127.0.0.1:{}", acme_port));
+
+    let (status, _) = live_view_get(acme_port.parse().unwrap(), "/.well-known/acme-challenge/unknown").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    server.connect_client().await;
+}
+
+#[tokio::test]
+async fn clients_negotiate_their_codec() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let mut unsupported = server.connect_client().await;
+    client_send(&mut unsupported, json!({"type": "ClientLogin", "name": "zoe", "codec": "xml"})).await;
+    let rejected = client_receive(&mut unsupported, "LoginRejected").await;
+    assert!(rejected["reason"].as_str().unwrap().contains("msgpack"));
+
+    let mut packed = server.connect_client().await;
+    client_send(&mut packed, json!({"type": "ClientLogin", "name": "frank", "codec": "msgpack"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+    let mut plain = server.connect_client().await;
+    client_send(&mut plain, json!({"type": "ClientLogin", "name": "grace"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    // The same broadcast in the codec of each client
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 3, "content": "question"})).await;
+    let state = client_receive_msgpack(&mut packed, "ChangeState").await;
+    assert_eq!(state, client_receive(&mut plain, "ChangeState").await);
+    assert_eq!(state["content"], "question");
+
+    let input = json!({"type": "Input", "state_id": 3, "content": "answer", "input_id": "p1"});
+    packed.send(Message::Binary(rmp_serde::to_vec_named(&input).unwrap())).await.expect("Sending client message failed");
+    assert_eq!(host_receive(&mut host, "Input").await["input"], "answer");
+    assert_eq!(client_receive_msgpack(&mut packed, "InputAck").await["input_id"], "p1");
+}
+
+#[tokio::test]
+async fn host_connects_over_standard_streams() {
+    let (server, mut stdin, mut stdout) = TestServer::start_with_stdio_host().await;
+    host_send(&mut stdin, json!({"type": "HostLogin"})).await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "heidi"})).await;
+    assert_eq!(host_receive(&mut stdout, "ClientConnected").await["name"], "heidi");
+
+    host_send(&mut stdin, json!({"type": "ChangeState", "state_id": 5, "content": "question"})).await;
+    assert_eq!(client_receive(&mut client, "ChangeState").await["content"], "question");
+    client_send(&mut client, json!({"type": "Input", "state_id": 5, "content": "answer"})).await;
+    assert_eq!(host_receive(&mut stdout, "Input").await["input"], "answer");
+}
+
+#[tokio::test]
+async fn closed_standard_input_disconnects_the_host() {
+    let (server, mut stdin, _stdout) = TestServer::start_with_stdio_host().await;
+    host_send(&mut stdin, json!({"type": "HostLogin"})).await;
+    server.wait_for_host().await;
+
+    drop(stdin);
+    timeout(RECEIVE_TIMEOUT, async {
+        while server.health().await.map(|health| health["host_connected"] != false).unwrap_or(true) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("Host was not disconnected at the end of its input");
+}
+
+#[tokio::test]
+async fn plain_websocket_without_tls() {
+    let server = TestServer::start_with(&[("TT_BACKEND_INSECURE_WS", "true")]).await;
+    let mut host = server.login_host().await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://localhost:{}", server.ws_port)).await
+        .expect("Connecting plain client failed");
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "ivan"})).await;
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "ivan");
+}
+
+#[tokio::test]
+async fn late_client_receives_current_state() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 3, "content": "lobby"})).await;
+
+    // Wait until the state change was processed, the health request goes through the main handler
+    server.health().await.expect("No health response");
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "carol"})).await;
+    let state = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(state["state_id"], 3);
+}
+
+#[tokio::test]
+async fn rooms_keep_their_state_apart() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+    let mut room_host = server.connect_host().await;
+    host_send(&mut room_host, json!({"type": "HostLogin", "room": true})).await;
+    let code = host_receive(&mut room_host, "RoomOpened").await["code"].as_str().expect("No join code").to_string();
+    assert_eq!(server.health().await.expect("No health response")["rooms"], 1);
+
+    let mut guest = server.connect_client().await;
+    client_send(&mut guest, json!({"type": "ClientLogin", "name": "frank", "room": code.to_lowercase()})).await;
+    assert_eq!(host_receive(&mut room_host, "ClientConnected").await["name"], "frank");
+    let mut player = server.connect_client().await;
+    client_send(&mut player, json!({"type": "ClientLogin", "name": "grace"})).await;
+    // The host of the server's session is not told about the guest of the room
+    assert_eq!(host_receive(&mut host, "ClientConnected").await["name"], "grace");
+
+    host_send(&mut room_host, json!({"type": "ChangeState", "state_id": 2, "content": "room"})).await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 1, "content": "main"})).await;
+    assert_eq!(client_receive(&mut guest, "ChangeState").await["content"], "room");
+    assert_eq!(client_receive(&mut player, "ChangeState").await["content"], "main");
+
+    client_send(&mut guest, json!({"type": "Input", "state_id": 2, "content": "answer", "input_id": "i1"})).await;
+    assert_eq!(host_receive(&mut room_host, "Input").await["name"], "frank");
+    client_receive(&mut guest, "InputAck").await;
+
+    let mut stranger = server.connect_client().await;
+    client_send(&mut stranger, json!({"type": "ClientLogin", "name": "heidi", "room": "NOROOM"})).await;
+    assert_eq!(client_receive(&mut stranger, "LoginRejected").await["reason"], "Unknown room");
+}
+
+#[tokio::test]
+async fn hosts_of_several_tenants_own_their_rooms() {
+    let path = std::env::temp_dir().join(format!("tt_online_e2e_tenants_{}_{}.json", std::process::id(), free_port()));
+    std::fs::write(&path, r#"[{"name": "quiz_club", "api_key": "quiz", "max_sessions": 1}, {"name": "school", "api_key": "school"}]"#)
+        .expect("Writing tenant file failed");
+    let server = TestServer::start_with(&[("TT_BACKEND_TENANTS", path.to_str().unwrap())]).await;
+    let mut host = server.connect_host().await;
+    host_send(&mut host, json!({"type": "HostLogin", "api_key": "quiz"})).await;
+    server.wait_for_host().await;
+
+    // The session of the server is in use, another tenant opens a room instead
+    let mut teacher = server.connect_host().await;
+    host_send(&mut teacher, json!({"type": "HostLogin", "api_key": "school"})).await;
+    assert_eq!(host_receive(&mut teacher, "LoginRejected").await["reason"], "Server is in use by another tenant");
+    let mut teacher = server.connect_host().await;
+    host_send(&mut teacher, json!({"type": "HostLogin", "api_key": "school", "room": true})).await;
+    let opened = host_receive(&mut teacher, "RoomOpened").await;
+    let code = opened["code"].as_str().expect("No join code").to_string();
+    let session = opened["session"].as_str().expect("No session id").to_string();
+
+    // The session of the server uses up the quota of its tenant
+    let mut quiz_room = server.connect_host().await;
+    host_send(&mut quiz_room, json!({"type": "HostLogin", "api_key": "quiz", "room": true})).await;
+    assert_eq!(host_receive(&mut quiz_room, "LoginRejected").await["reason"], "Session quota of the tenant exhausted");
+
+    let mut pupil = server.connect_client().await;
+    client_send(&mut pupil, json!({"type": "ClientLogin", "name": "ivan", "room": code})).await;
+    host_receive(&mut teacher, "ClientConnected").await;
+    let rooms = server.admin_get("/rooms").await.expect("No rooms response");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(rooms["rooms"][0]["code"], code.as_str());
+    assert_eq!(rooms["rooms"][0]["tenant"], "school");
+    assert_eq!(rooms["rooms"][0]["connections"], 2);
+
+    // The room outlives its host while clients are in it, only the session id gets the host back
+    host_send(&mut teacher, json!({"type": "Disconnecting", "reason": "break"})).await;
+    let mut intruder = server.connect_host().await;
+    host_send(&mut intruder, json!({"type": "HostLogin", "api_key": "school", "room": code})).await;
+    assert_eq!(host_receive(&mut intruder, "LoginRejected").await["reason"], "Unknown room");
+    let mut teacher = server.connect_host().await;
+    host_send(&mut teacher, json!({"type": "HostLogin", "api_key": "school", "room": session})).await;
+    host_send(&mut teacher, json!({"type": "ChangeState", "state_id": 4, "content": "after the break"})).await;
+    assert_eq!(client_receive(&mut pupil, "ChangeState").await["content"], "after the break");
+}
+
+#[tokio::test]
+async fn room_closes_once_everyone_left() {
+    let server = TestServer::start().await;
+    let mut room_host = server.connect_host().await;
+    host_send(&mut room_host, json!({"type": "HostLogin", "room": true})).await;
+    host_receive(&mut room_host, "RoomOpened").await;
+    assert_eq!(server.health().await.expect("No health response")["rooms"], 1);
+
+    host_send(&mut room_host, json!({"type": "Disconnecting", "reason": "done"})).await;
+    timeout(RECEIVE_TIMEOUT, async {
+        while server.health().await.map(|health| health["rooms"] != 0).unwrap_or(true) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("Room was not closed");
+}
+
+#[tokio::test]
+async fn hosts_reconnect_after_an_upgrade() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let upgrade = server.admin_request("POST", "/upgrade").await.expect("No upgrade response");
+    let pid = upgrade["pid"].as_u64().unwrap_or_else(|| panic!("Upgrade did not start: {}", upgrade));
+    let disconnected = host_receive(&mut host, "Disconnecting").await;
+    // The new process accepts on the same port
+    let mut host = server.connect_host().await;
+    host_send(&mut host, json!({"type": "HostLogin"})).await;
+    let hello = host_receive(&mut host, "ServerHello").await;
+    let _ = Command::new("kill").arg(pid.to_string()).status();
+    assert_eq!(disconnected["reason"], "Server is upgraded, please reconnect");
+    assert!(disconnected["reconnect"]["max_backoff_ms"].is_u64());
+    assert!(hello["version"].is_u64());
+}
+
+#[tokio::test]
+async fn upgrade_ends_the_rooms() {
+    let server = TestServer::start().await;
+    let mut room_host = server.connect_host().await;
+    host_send(&mut room_host, json!({"type": "HostLogin", "room": true})).await;
+    let code = host_receive(&mut room_host, "RoomOpened").await["code"].as_str().expect("No join code").to_string();
+    let mut guest = server.connect_client().await;
+    client_send(&mut guest, json!({"type": "ClientLogin", "name": "judy", "room": code})).await;
+    host_receive(&mut room_host, "ClientConnected").await;
+
+    let upgrade = server.admin_request("POST", "/upgrade").await.expect("No upgrade response");
+    let pid = upgrade["pid"].as_u64().unwrap_or_else(|| panic!("Upgrade did not start: {}", upgrade));
+    let disconnected = client_receive(&mut guest, "Disconnecting").await;
+    let host_disconnected = host_receive(&mut room_host, "Disconnecting").await;
+    // The new process took over the listeners, it is not stopped with the test server
+    let _ = Command::new("kill").arg(pid.to_string()).status();
+    assert_eq!(disconnected["reason"], "Server is upgraded, please reconnect");
+    assert!(disconnected["reconnect"].is_object());
+    assert_eq!(host_disconnected["reason"], "Server is upgraded, please reconnect");
+    assert!(host_disconnected["reconnect"].is_object());
+}
+
+#[tokio::test]
+async fn login_requires_solved_challenge() {
+    let server = TestServer::start_with(&[("TT_BACKEND_CHALLENGE", "pow:8")]).await;
+    let mut host = server.login_host().await;
+
+    let mut cheater = server.connect_client().await;
+    let challenge = client_receive(&mut cheater, "Challenge").await;
+    assert_eq!(challenge["kind"], "pow");
+    let wrong = (0..).find(|n| Sha256::digest(format!("{}:{}", challenge["nonce"].as_str().unwrap(), n))[0] != 0).unwrap();
+    client_send(&mut cheater, json!({"type": "ClientLogin", "name": "mallory", "proof": wrong.to_string()})).await;
+    let rejected = client_receive(&mut cheater, "LoginRejected").await;
+    assert_eq!(rejected["reason"], "Challenge not solved");
+
+    let mut client = server.connect_client().await;
+    let challenge = client_receive(&mut client, "Challenge").await;
+    let nonce = challenge["nonce"].as_str().unwrap();
+    // The login before solving is ignored, not rejected
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    let proof = (0..).find(|n| Sha256::digest(format!("{}:{}", nonce, n))[0] == 0).unwrap();
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice", "proof": proof.to_string()})).await;
+
+    let connected = host_receive(&mut host, "ClientConnected").await;
+    assert_eq!(connected["name"], "alice");
+}
+
+#[tokio::test]
+async fn control_message_overtakes_bulk_message() {
+    let server = TestServer::start().await;
+    let mut host = server.login_host().await;
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "dave"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    let state = json!({"type": "ChangeState", "state_id": 9, "content": "x".repeat(100_000)}).to_string();
+    let (head, tail) = state.as_bytes().split_at(state.len() / 2);
+    host_send_chunk(&mut host, 0b01, head).await;
+
+    // Answered while the state is still incomplete
+    host_send(&mut host, json!({"type": "GetClientList"})).await;
+    let list = host_receive(&mut host, "ClientList").await;
+    assert_eq!(list["clients"][0]["name"], "dave");
+
+    host_send_chunk(&mut host, 0b10, tail).await;
+    let state = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(state["state_id"], 9);
+    assert_eq!(state["content"].as_str().map(str::len), Some(100_000));
+}
+
+/// Sends one request to the live view and returns status line and body
+async fn live_view_get(port: u16, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.expect("Connecting to live view failed");
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.expect("Sending request failed");
+    let mut response = String::new();
+    timeout(RECEIVE_TIMEOUT, stream.read_to_string(&mut response)).await.expect("Live view did not answer").expect("Reading response failed");
+    let (head, body) = response.split_once("\r\n\r\n").expect("Response is malformed");
+    (String::from(head.lines().next().unwrap_or_default()), String::from(body))
+}
+
+#[tokio::test]
+async fn live_view_mirrors_public_state() {
+    let port = free_port();
+    let server = TestServer::start_with(&[("TT_BACKEND_LIVE_VIEW_PORT", &port.to_string())]).await;
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 4, "content": "slide"})).await;
+    server.health().await.expect("No health response");
+
+    let (status, _) = live_view_get(port, "/state").await;
+    assert!(status.contains("404"), "{}", status);
+
+    host_send(&mut host, json!({"type": "SetPublic", "public": true})).await;
+    server.health().await.expect("No health response");
+    let (status, body) = live_view_get(port, "/state").await;
+    assert!(status.contains("200"), "{}", status);
+    let snapshot: Value = serde_json::from_str(&body).expect("Snapshot is no json");
+    assert_eq!(snapshot[0]["type"], "ChangeState");
+    assert_eq!(snapshot[0]["content"], "slide");
+
+    // The event stream starts with the snapshot and ends once the presentation is withdrawn
+    let mut events = TcpStream::connect(("127.0.0.1", port)).await.expect("Connecting to live view failed");
+    events.write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n").await.expect("Sending request failed");
+    let mut stream = Vec::new();
+    timeout(RECEIVE_TIMEOUT, async {
+        let mut chunk = [0; 1024];
+        while !String::from_utf8_lossy(&stream).contains("data: ") {
+            let n = events.read(&mut chunk).await.expect("Reading events failed");
+            assert!(n > 0, "Event stream ended early");
+            stream.extend_from_slice(&chunk[..n]);
+        }
+    }).await.expect("No snapshot streamed");
+    host_send(&mut host, json!({"type": "Update", "state_id": 4, "content": "step"})).await;
+    host_send(&mut host, json!({"type": "SetPublic", "public": false})).await;
+    timeout(RECEIVE_TIMEOUT, events.read_to_end(&mut stream)).await.expect("Event stream did not end").expect("Reading events failed");
+    let events: Vec<Value> = String::from_utf8_lossy(&stream).lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).expect("Event is no json"))
+        .collect();
+    let types: Vec<&str> = events.iter().map(|event| event["type"].as_str().unwrap_or_default()).collect();
+    assert_eq!(types, ["ChangeState", "Update", "Hidden"]);
+
+    // Viewers are no participants
+    let health = server.health().await.expect("No health response");
+    assert_eq!(health["clients"], 0);
+}
+
+/// Sends a frame as HostApps before the frame magic did, with the bare length prefix
+async fn legacy_host_send(stream: &mut TcpStream, msg: Value) {
+    let payload = msg.to_string();
+    stream.write_u32(payload.len() as u32).await.unwrap();
+    stream.write_all(payload.as_bytes()).await.expect("Sending legacy host message failed");
+}
+
+#[tokio::test]
+async fn legacy_host_is_translated() {
+    let server = TestServer::start().await;
+    let mut host = server.connect_host().await;
+    legacy_host_send(&mut host, json!({"type": "ChangeState", "state_id": 2, "content": "legacy"})).await;
+    server.health().await.expect("No health response");
+
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "dave"})).await;
+    let state = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(state["content"], "legacy");
+    client_send(&mut client, json!({"type": "Input", "state_id": 2, "content": "old"})).await;
+
+    // Legacy frames have no magic, messages unknown to legacy hosts and the metadata are left out
+    let mut types = vec![];
+    let input = timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            let length = host.read_u32().await.expect("Host connection closed");
+            let mut payload = vec![0; length as usize];
+            host.read_exact(&mut payload).await.unwrap();
+            let msg: Value = serde_json::from_slice(&payload).expect("Legacy host received malformed json");
+            assert!(msg.get("meta").is_none(), "{}", msg);
+            types.push(String::from(msg["type"].as_str().unwrap_or_default()));
+            if msg["type"] == "Input" {
+                return msg
+            }
+        }
+    }).await.expect("Legacy host did not receive 'Input'");
+    assert_eq!(types, ["ClientConnected", "Input"]);
+    assert_eq!(input["content"], "old");
+}
+
+#[tokio::test]
+async fn waiting_state_is_shown_without_host() {
+    let server = TestServer::start_with(&[("TT_BACKEND_NO_HOST", "waiting:Starting soon")]).await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "erin"})).await;
+    let waiting = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(waiting["state_id"], -1);
+    assert_eq!(waiting["content"], "Starting soon");
+
+    let mut host = server.login_host().await;
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 1, "content": "quiz"})).await;
+    assert_eq!(client_receive(&mut client, "ChangeState").await["content"], "quiz");
+
+    host_send(&mut host, json!({"type": "Disconnecting", "reason": "done"})).await;
+    let waiting = client_receive(&mut client, "ChangeState").await;
+    assert_eq!(waiting["state_id"], -1);
+}
+
+#[tokio::test]
+async fn tls_handshake_failures_are_classified() {
+    let server = TestServer::start().await;
+
+    // Plain HTTP on the TLS port is answered with a hint instead of a failing handshake
+    let mut probe = TcpStream::connect(("127.0.0.1", server.ws_port)).await.expect("Connecting probe failed");
+    probe.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.expect("Sending probe failed");
+    let mut response = String::new();
+    timeout(RECEIVE_TIMEOUT, probe.read_to_string(&mut response)).await.expect("No response in time").expect("Reading response failed");
+    assert!(response.starts_with("HTTP/1.1 400"));
+    assert!(response.contains("wss://"));
+
+    // A client not trusting the certificate rejects the chain
+    let connector = native_tls::TlsConnector::new().expect("Creating tls connector failed");
+    let url = format!("wss://localhost:{}", server.ws_port);
+    let rejected = tokio_tungstenite::connect_async_tls_with_config(url, None, Some(Connector::NativeTls(connector))).await;
+    assert!(rejected.is_err());
+
+    let report = timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            let report = server.admin_get("/debug/tls").await.expect("No diagnostics response");
+            if report["failed"]["bad_certificate"] == 1 {
+                return report
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.expect("Rejected certificate was not reported in time");
+    assert_eq!(report["failed"]["plain_http"], 1);
+    assert_eq!(report["recent"][0]["cause"], "bad_certificate");
+    assert_eq!(server.health().await.expect("No health response")["tls_failures"]["plain_http"], 1);
+}
+
+#[tokio::test]
+async fn configuration_file_is_applied() {
+    let path = std::env::temp_dir().join(format!("tt_online_e2e_config_{}_{}.toml", std::process::id(), free_port()));
+    std::fs::write(&path, "# Deployment settings\n[server]\nchannel_size = 64 # events\nlog_level = \"warn\"\n\n[tls]\ncert = \"res/cert/cert.pem\"\n")
+        .expect("Writing configuration file failed");
+    let server = TestServer::start_with(&[("TT_BACKEND_CONFIG", path.to_str().unwrap())]).await;
+
+    let queues = server.admin_get("/debug/queues").await.expect("No diagnostics response");
+    assert_eq!(queues["internal_channel"]["size"], 64);
+    let log_level = server.admin_get("/log-level").await.expect("No log level response");
+    let _ = std::fs::remove_file(&path);
+    // RUST_LOG (inherited by the server) takes precedence over the file
+    if std::env::var("RUST_LOG").is_err() {
+        assert_eq!(log_level["filter"], "warn");
+    }
+}
+
+#[tokio::test]
+async fn environment_overrides_configuration_file() {
+    let path = std::env::temp_dir().join(format!("tt_online_e2e_config_{}_{}.toml", std::process::id(), free_port()));
+    std::fs::write(&path, "[server]\nchannel_size = 64\n").expect("Writing configuration file failed");
+    let server = TestServer::start_with(&[
+        ("TT_BACKEND_CONFIG", path.to_str().unwrap()),
+        ("TT_BACKEND_CHANNEL_SIZE", "32"),
+        ("TT_BACKEND_IP", "127.0.0.1"),
+        ("TT_BACKEND_CERT_PATH", "res/cert/cert.pem"),
+    ]).await;
+
+    let queues = server.admin_get("/debug/queues").await.expect("No diagnostics response");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(queues["internal_channel"]["size"], 32);
+}
+
+/// Starts the server in 'directory' expecting it to refuse the configuration, returns its stderr
+async fn failed_startup(directory: &PathBuf, env: &[(&str, &str)]) -> String {
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_tt_online"))
+        .current_dir(directory)
+        .env("TT_BACKEND_WS_PORT", free_port().to_string())
+        .env("TT_BACKEND_TCP_PORT", free_port().to_string())
+        .env("TT_BACKEND_SHADOW_PORT", free_port().to_string())
+        .env("TT_BACKEND_ADMIN_PORT", free_port().to_string())
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .output();
+    let output = timeout(STARTUP_TIMEOUT, output).await.expect("Server did not stop").expect("Starting server failed");
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[tokio::test]
+async fn mismatching_certificate_key_stops_startup() {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).expect("Generating certificate failed");
+    let other = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).expect("Generating certificate failed");
+    let directory = std::env::temp_dir().join(format!("tt_online_e2e_{}_{}", std::process::id(), free_port()));
+    std::fs::create_dir_all(directory.join("res/cert")).expect("Creating test directory failed");
+    std::fs::write(directory.join("res/cert/cert.pem"), certificate.serialize_pem().unwrap()).expect("Writing certificate failed");
+    std::fs::write(directory.join("res/cert/key.pem"), other.serialize_private_key_pem()).expect("Writing key failed");
+
+    let stderr = failed_startup(&directory, &[]).await;
+    let _ = std::fs::remove_dir_all(&directory);
+    assert!(stderr.contains("does not belong to the certificate"), "unexpected output: {}", stderr);
+}
+
+#[tokio::test]
+async fn admin_requests_need_the_secret() {
+    let server = TestServer::start().await;
+    let refused = server.admin_request_as("POST", "/log-level?filter=trace", None).await.expect("No admin response");
+    assert_eq!(refused["error"], "Missing or invalid admin secret");
+    let refused = server.admin_request_as("GET", "/debug/queues", Some("guess")).await.expect("No admin response");
+    assert_eq!(refused["error"], "Missing or invalid admin secret");
+    assert_eq!(server.admin_request_as("GET", "/health", None).await.expect("No health response")["status"], "ok");
+    assert!(server.admin_get("/debug/queues").await.expect("No diagnostics response")["internal_channel"].is_object());
+
+    let unprotected = TestServer::start_with(&[("TT_BACKEND_ADMIN_SECRET", "")]).await;
+    assert_eq!(unprotected.admin_get("/debug/queues").await.expect("No admin response")["error"], "No admin secret configured");
+}
+
+#[tokio::test]
+async fn public_admin_interface_needs_a_secret() {
+    let directory = std::env::temp_dir().join(format!("tt_online_e2e_{}_{}", std::process::id(), free_port()));
+    std::fs::create_dir_all(&directory).expect("Creating test directory failed");
+    let stderr = failed_startup(&directory, &[("TT_BACKEND_ADMIN_IP", "0.0.0.0")]).await;
+    let _ = std::fs::remove_dir_all(&directory);
+    assert!(stderr.contains("TT_BACKEND_ADMIN_IP is the public address 0.0.0.0, set TT_BACKEND_ADMIN_SECRET"), "unexpected output: {}", stderr);
+}
+
+#[tokio::test]
+async fn configuration_problems_are_reported_together() {
+    let directory = std::env::temp_dir().join(format!("tt_online_e2e_{}_{}", std::process::id(), free_port()));
+    std::fs::create_dir_all(&directory).expect("Creating test directory failed");
+    let port = free_port().to_string();
+
+    let stderr = failed_startup(&directory, &[
+        ("TT_BACKEND_TCP_PORT", &port),
+        ("TT_BACKEND_ADMIN_PORT", &port),
+        ("TT_BACKEND_HOST_CA", "missing/hosts.pem"),
+    ]).await;
+    let _ = std::fs::remove_dir_all(&directory);
+    assert!(stderr.contains(&format!("TT_BACKEND_TCP_PORT and TT_BACKEND_ADMIN_PORT are both {}", port)), "unexpected output: {}", stderr);
+    assert!(stderr.contains("Reading missing/hosts.pem failed"), "unexpected output: {}", stderr);
+    assert!(stderr.contains("res/cert/cert.pem failed"), "unexpected output: {}", stderr);
+    assert!(stderr.contains("res/cert/key.pem failed"), "unexpected output: {}", stderr);
+}
+
+#[tokio::test]
+async fn hosts_authenticate_by_certificate() {
+    // Issuer and subject differ, the host certificate would count as self-signed otherwise
+    let mut params = rcgen::CertificateParams::new(vec![]);
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    params.distinguished_name.push(rcgen::DnType::CommonName, "tt_online test CA");
+    let authority = rcgen::Certificate::from_params(params).expect("Generating CA failed");
+    let mut params = rcgen::CertificateParams::new(vec![String::from("host")]);
+    params.distinguished_name.push(rcgen::DnType::CommonName, "quiz host");
+    let host = rcgen::Certificate::from_params(params).expect("Generating certificate failed");
+    let host_cert = host.serialize_pem_with_signer(&authority).expect("Signing host certificate failed");
+    let host_key = host.serialize_private_key_pem();
+
+    let bundle = std::env::temp_dir().join(format!("tt_online_e2e_ca_{}_{}.pem", std::process::id(), free_port()));
+    std::fs::write(&bundle, authority.serialize_pem().unwrap()).expect("Writing CA bundle failed");
+    let server = TestServer::start_with(&[("TT_BACKEND_HOST_CA", bundle.to_str().unwrap())]).await;
+    let _ = std::fs::remove_file(&bundle);
+    assert_eq!(server.health().await.expect("No health response")["host_tls"], true);
+
+    // Without a certificate the handshake fails, with TLS 1.3 only once the host reads
+    if let Some(mut anonymous) = server.try_connect_host_tls(None).await {
+        let mut byte = [0u8; 1];
+        let read = timeout(RECEIVE_TIMEOUT, anonymous.read(&mut byte)).await.expect("Anonymous host was not refused");
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+    // An untrusted certificate does not do either
+    let stranger = rcgen::generate_simple_self_signed(vec![String::from("host")]).expect("Generating certificate failed");
+    let stranger_cert = stranger.serialize_pem().unwrap();
+    let stranger_key = stranger.serialize_private_key_pem();
+    if let Some(mut untrusted) = server.try_connect_host_tls(Some((&stranger_cert, &stranger_key))).await {
+        let mut byte = [0u8; 1];
+        let read = timeout(RECEIVE_TIMEOUT, untrusted.read(&mut byte)).await.expect("Untrusted host was not refused");
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+
+    let mut trusted = server.try_connect_host_tls(Some((&host_cert, &host_key))).await.expect("Trusted host was refused");
+    host_send(&mut trusted, json!({"type": "HostLogin"})).await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    let connected = host_receive(&mut trusted, "ClientConnected").await;
+    assert_eq!(connected["name"], "alice");
+}
+
+#[tokio::test]
+async fn host_channel_is_encrypted() {
+    let server = TestServer::start_with(&[("TT_BACKEND_HOST_TLS", "true")]).await;
+    let health = server.health().await.expect("No health response");
+    assert_eq!(health["host_tls"], true);
+    assert_eq!(health["host_certificates"], false);
+
+    // No certificate needed, the host only has to trust the one of the server
+    let mut host = server.try_connect_host_tls(None).await.expect("Host was refused");
+    host_send(&mut host, json!({"type": "HostLogin"})).await;
+    let mut client = server.connect_client().await;
+    client_send(&mut client, json!({"type": "ClientLogin", "name": "alice"})).await;
+    host_receive(&mut host, "ClientConnected").await;
+
+    host_send(&mut host, json!({"type": "ChangeState", "state_id": 2, "content": "secret question"})).await;
+    assert_eq!(client_receive(&mut client, "ChangeState").await["content"], "secret question");
+}
@@ -39,6 +39,11 @@ public class MainController implements MainFrame.GuiControllerCallbacks, Message
         gui.userDisconnected(name);
     }
 
+    @Override
+    public void handleClientExpired(String name, String address) {
+        // TODO remove the inputs of the client from the results of the activity
+    }
+
     @Override
     public void handleBackendDisconnect(String reason) {
         // TODO notify user
@@ -1,22 +1,95 @@
 package transport;
 
+import javax.net.ssl.SSLSocket;
+import javax.net.ssl.SSLSocketFactory;
 import java.io.*;
 import java.net.Socket;
 import java.nio.charset.StandardCharsets;
+import java.util.Arrays;
+import java.util.LinkedHashMap;
+import java.util.Map;
+import java.util.zip.CRC32;
 
 public class ConnectionLayer {
 
+    /**
+     * Every frame starts with these bytes, so the backend can recover from a desynchronized stream
+     */
+    private static final byte[] FRAME_MAGIC = "TTHF".getBytes(StandardCharsets.US_ASCII);
+    /**
+     * Frames carrying a chunk of a bulk message start with these bytes instead
+     */
+    private static final byte[] CHUNK_MAGIC = "TTHC".getBytes(StandardCharsets.US_ASCII);
+    private static final byte CHUNK_FIRST = 0b01;
+    private static final byte CHUNK_LAST = 0b10;
+    /**
+     * Larger bulk messages are sent in chunks, control messages are sent in between
+     */
+    private static final int CHUNK_SIZE = 64 * 1024;
+    /**
+     * System property enabling TLS on the host port
+     */
+    private static final String TLS_PROPERTY = "tt.host.tls";
+    /**
+     * Number of most recently sent messages kept for retransmission
+     */
+    private static final int RETRANSMIT_BUFFER = 64;
+
     private final DataOutputStream out;
     private final DataInputStream in;
 
+    private boolean checksum = false;
+    private long frames = 0;
+    /**
+     * Held while a bulk message is sent, so bulk messages keep their order and chunks do not mix
+     */
+    private final Object bulkLock = new Object();
+    private final Map<Long, SentFrame> sentFrames = new LinkedHashMap<>() {
+        @Override
+        protected boolean removeEldestEntry(Map.Entry<Long, SentFrame> eldest) {
+            return size() > RETRANSMIT_BUFFER;
+        }
+    };
+
+    private record SentFrame(String message, boolean bulk) {}
+
+    /**
+     * Connects to the host port of the backend
+     * If the backend encrypts the host port (TT_BACKEND_HOST_TLS), start the HostApp with
+     * '-Dtt.host.tls=true', a self-signed backend certificate has to be in the trust store
+     * ('-Djavax.net.ssl.trustStore=..'). If it also requires host certificates
+     * (TT_BACKEND_HOST_CA), the certificate goes into the standard key store
+     * ('-Djavax.net.ssl.keyStore=..', '-Djavax.net.ssl.keyStorePassword=..')
+     * @param ip address of the backend
+     * @param port host port of the backend
+     * @throws IOException thrown if connecting or the TLS handshake fails
+     */
     public ConnectionLayer(String ip, int port) throws IOException {
-        Socket socket = new Socket(ip, port);
+        Socket socket;
+        if (Boolean.getBoolean(TLS_PROPERTY)) {
+            SSLSocket tlsSocket = (SSLSocket) SSLSocketFactory.getDefault().createSocket(ip, port);
+            tlsSocket.startHandshake();
+            socket = tlsSocket;
+        } else {
+            socket = new Socket(ip, port);
+        }
 
         System.out.println("Connected to Host: " + socket.getLocalAddress() + ":" + socket.getLocalPort());
         out = new DataOutputStream(socket.getOutputStream());
         in = new DataInputStream(socket.getInputStream());
     }
 
+    /**
+     * Talks to a backend launched by the HostApp over its standard streams
+     * The backend has to be started with '--host-stdio parent'
+     * @param backend process of the backend, its stderr carries the log
+     */
+    public ConnectionLayer(Process backend) {
+        System.out.println("Connected to Host: launched backend (pid " + backend.pid() + ")");
+        out = new DataOutputStream(new BufferedOutputStream(backend.getOutputStream()));
+        in = new DataInputStream(backend.getInputStream());
+    }
+
     /**
      * Write the given String representation of a message into the socket
      * The message is UTF-8 encoded for compatibility with RUST
@@ -26,12 +99,83 @@ public class ConnectionLayer {
     public synchronized void sendMessage(String message) throws IOException {
         System.out.println("Sending message " + message);
         byte[] utf8 = message.getBytes(StandardCharsets.UTF_8);
-        int length = utf8.length;
-        System.out.println("length " + length);
+        System.out.println("length " + utf8.length);
+
+        writeFrame(FRAME_MAGIC, utf8, new SentFrame(message, false));
+    }
+
+    /**
+     * Write the given String representation of a large message (state or update) into the socket
+     * Messages above the chunk size are split into chunks, so control messages sent meanwhile via
+     * sendMessage are not stuck behind them. Bulk messages keep their order among each other
+     * @param message string representation of a message
+     * @throws IOException thrown if socket fails (connection should get closed)
+     */
+    public void sendBulkMessage(String message) throws IOException {
+        byte[] utf8 = message.getBytes(StandardCharsets.UTF_8);
+        SentFrame sent = new SentFrame(message, true);
+        synchronized (bulkLock) {
+            if (utf8.length <= CHUNK_SIZE) {
+                synchronized (this) {
+                    writeFrame(FRAME_MAGIC, utf8, sent);
+                }
+                return;
+            }
+            System.out.println("Sending bulk message of " + utf8.length + " bytes in chunks");
+            for (int offset = 0; offset < utf8.length; offset += CHUNK_SIZE) {
+                int end = Math.min(offset + CHUNK_SIZE, utf8.length);
+                byte[] payload = new byte[end - offset + 1];
+                payload[0] = (byte) ((offset == 0 ? CHUNK_FIRST : 0) | (end == utf8.length ? CHUNK_LAST : 0));
+                System.arraycopy(utf8, offset, payload, 1, end - offset);
+                synchronized (this) {
+                    writeFrame(CHUNK_MAGIC, payload, sent);
+                }
+            }
+        }
+    }
 
-        out.writeInt(length);
-        out.write(utf8);
+    private void writeFrame(byte[] magic, byte[] payload, SentFrame sent) throws IOException {
+        out.write(magic);
+        out.writeInt(payload.length);
+        out.write(payload);
+        if (checksum) {
+            CRC32 crc = new CRC32();
+            crc.update(payload);
+            out.writeInt((int) crc.getValue());
+        }
         out.flush();
+
+        sentFrames.put(frames, sent);
+        frames++;
+    }
+
+    /**
+     * All following frames end with a CRC32 of their payload
+     * Has to be called right after sending a 'HostLogin' message requesting checksums
+     */
+    public synchronized void enableChecksum() {
+        checksum = true;
+    }
+
+    /**
+     * Sends the message of an earlier frame again (as a new frame)
+     * A chunk is retransmitted as its whole bulk message, the backend dropped the other chunks
+     * Frames that are no longer buffered are skipped
+     * @param frame index of the frame on this connection
+     * @throws IOException thrown if socket fails (connection should get closed)
+     */
+    public void retransmit(long frame) throws IOException {
+        SentFrame sent;
+        synchronized (this) {
+            sent = sentFrames.get(frame);
+        }
+        if (sent == null) {
+            System.out.println("Frame " + frame + " is no longer buffered, can not retransmit");
+        } else if (sent.bulk()) {
+            sendBulkMessage(sent.message());
+        } else {
+            sendMessage(sent.message());
+        }
     }
 
     /**
@@ -40,6 +184,10 @@ public class ConnectionLayer {
      * @throws IOException thrown if socket fails or connection was closed (connection should get closed)
      */
     public String receiveMessage() throws IOException {
+        byte[] magic = in.readNBytes(FRAME_MAGIC.length);
+        if (!Arrays.equals(magic, FRAME_MAGIC)) {
+            throw new IOException("Frame does not start with the frame magic");
+        }
         int length = in.readInt();
 
         byte[] utf8 = in.readNBytes(length);
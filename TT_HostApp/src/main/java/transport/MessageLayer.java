@@ -1,9 +1,12 @@
 package transport;
 
+import org.json.JSONArray;
 import org.json.JSONException;
 import org.json.JSONObject;
 
 import java.io.IOException;
+import java.nio.file.Files;
+import java.nio.file.Path;
 
 public class MessageLayer  {
 
@@ -14,6 +17,7 @@ public class MessageLayer  {
         // new callbacks
         void handleClientConnected(String name, String address);
         void handleClientDisconnected(String name, String address, String reason);
+        void handleClientExpired(String name, String address);
         void handleBackendDisconnect(String reason);
         void handleClientInput(int stateId, String name, String address, String input);
         void handleOwnDisconnect(String reason);
@@ -25,11 +29,66 @@ public class MessageLayer  {
     public MessageLayer(MessageLayerToControllerCallbacks callbacks, String ip, int port) throws IOException {
         this.callbacks = callbacks;
         this.connectionLayer = new ConnectionLayer(ip, port);
+        login();
 
         Thread messageReceiver = new Thread(new MessageReceiver());
         messageReceiver.start();
     }
 
+    /**
+     * Connects to a backend launched by the HostApp (with '--host-stdio parent') over its standard streams
+     * @param backend process of the backend
+     * @throws IOException thrown if sending the login fails
+     */
+    public MessageLayer(MessageLayerToControllerCallbacks callbacks, Process backend) throws IOException {
+        this.callbacks = callbacks;
+        this.connectionLayer = new ConnectionLayer(backend);
+        login();
+
+        Thread messageReceiver = new Thread(new MessageReceiver());
+        messageReceiver.start();
+    }
+
+    /**
+     * Version of the host protocol spoken
+     */
+    private static final int PROTOCOL_VERSION = 2;
+    /**
+     * Capabilities of the backend this host makes use of, notifications of others are not sent
+     */
+    private static final String[] CAPABILITIES = {"checksums", "chunks", "subscriptions", "client_list_sync", "client_issues", "quorum"};
+
+    /**
+     * Sends the login message to the backend, answering its hello with the protocol version and
+     * capabilities and requesting checksums on all following frames
+     * The API key of the tenant is taken from the environment variable TT_HOST_API_KEY, the host
+     * secret of the backend from TT_HOST_SECRET, if set
+     * TT_HOST_ROOM=new opens a room of this host, any other value returns to the room of that
+     * session id, without it the host takes the session of the backend
+     * @throws IOException thrown if sending fails
+     */
+    private void login() throws IOException {
+        JSONObject json = new JSONObject();
+        json.put("type", "HostLogin");
+        json.put("checksum", true);
+        json.put("version", PROTOCOL_VERSION);
+        json.put("capabilities", new JSONArray(CAPABILITIES));
+        String apiKey = System.getenv("TT_HOST_API_KEY");
+        if (apiKey != null) {
+            json.put("api_key", apiKey);
+        }
+        String secret = System.getenv("TT_HOST_SECRET");
+        if (secret != null) {
+            json.put("token", secret);
+        }
+        String room = System.getenv("TT_HOST_ROOM");
+        if (room != null) {
+            json.put("room", room.equals("new") ? (Object) true : room);
+        }
+        connectionLayer.sendMessage(json.toString());
+        connectionLayer.enableChecksum();
+    }
+
     /**
      * Sends an update message to the backend
      * @param update content of the update message
@@ -43,7 +102,7 @@ public class MessageLayer  {
         String message = json.toString();
 
         try {
-            connectionLayer.sendMessage(message);
+            connectionLayer.sendBulkMessage(message);
         } catch (IOException e) {
             forceClose();
             throw new SendingFailedException();
@@ -62,6 +121,114 @@ public class MessageLayer  {
         json.put("content", state);
         String message = json.toString();
 
+        try {
+            connectionLayer.sendBulkMessage(message);
+        } catch (IOException e) {
+            forceClose();
+            throw new SendingFailedException();
+        }
+    }
+
+    /**
+     * Uploads an upcoming state to the backend ahead of the presentation, acknowledged by 'Staged'
+     * @param index index of the staged state, also the state id it is shown with
+     * @param state content of the state
+     * @throws SendingFailedException thrown if sending fails and socket was closed
+     */
+    public void sendStageState(int index, String state) throws SendingFailedException {
+        sendStageState(index, state, new JSONArray());
+    }
+
+    /**
+     * Uploads an upcoming state with the assets its content loads, clients prefetch them once the
+     * staged state before it is shown
+     * @param index index of the staged state, also the state id it is shown with
+     * @param state content of the state
+     * @param assets objects with 'url', 'hash' and optionally 'size' (in bytes)
+     * @throws SendingFailedException thrown if sending fails and socket was closed
+     */
+    public void sendStageState(int index, String state, JSONArray assets) throws SendingFailedException {
+        JSONObject json = new JSONObject();
+        json.put("type", "StageState");
+        json.put("index", index);
+        json.put("content", state);
+        json.put("assets", assets);
+        String message = json.toString();
+
+        try {
+            connectionLayer.sendBulkMessage(message);
+        } catch (IOException e) {
+            forceClose();
+            throw new SendingFailedException();
+        }
+    }
+
+    /**
+     * Shows a staged state to the clients, sent as control message so no upload delays it
+     * @param index index of a state acknowledged by 'Staged'
+     * @throws SendingFailedException thrown if sending fails and socket was closed
+     */
+    public void sendShowStaged(int index) throws SendingFailedException {
+        JSONObject json = new JSONObject();
+        json.put("type", "ShowStaged");
+        json.put("index", index);
+        String message = json.toString();
+
+        try {
+            connectionLayer.sendMessage(message);
+        } catch (IOException e) {
+            forceClose();
+            throw new SendingFailedException();
+        }
+    }
+
+    /**
+     * Shows the presentation on the public live view of the backend or withdraws it
+     * @param isPublic whether viewers without a client slot may watch
+     * @throws SendingFailedException thrown if sending fails and socket was closed
+     */
+    public void sendSetPublic(boolean isPublic) throws SendingFailedException {
+        JSONObject json = new JSONObject();
+        json.put("type", "SetPublic");
+        json.put("public", isPublic);
+        String message = json.toString();
+
+        try {
+            connectionLayer.sendMessage(message);
+        } catch (IOException e) {
+            forceClose();
+            throw new SendingFailedException();
+        }
+    }
+
+    /**
+     * Pauses the session, inputs of the clients are rejected until the session is resumed
+     * @param message banner shown to the clients, may be null
+     * @throws SendingFailedException thrown if sending fails and socket was closed
+     */
+    public void sendPauseSession(String message) throws SendingFailedException {
+        JSONObject json = new JSONObject();
+        json.put("type", "PauseSession");
+        if (message != null) json.put("message", message);
+        String str = json.toString();
+
+        try {
+            connectionLayer.sendMessage(str);
+        } catch (IOException e) {
+            forceClose();
+            throw new SendingFailedException();
+        }
+    }
+
+    /**
+     * Resumes a paused session
+     * @throws SendingFailedException thrown if sending fails and socket was closed
+     */
+    public void sendResumeSession() throws SendingFailedException {
+        JSONObject json = new JSONObject();
+        json.put("type", "ResumeSession");
+        String message = json.toString();
+
         try {
             connectionLayer.sendMessage(message);
         } catch (IOException e) {
@@ -158,11 +325,39 @@ public class MessageLayer  {
                 switch (type) {
                     case "ClientConnected" -> parseClientConnected(json);
                     case "ClientDisconnected" -> parseClientDisconnected(json);
+                    case "ClientExpired" -> parseClientExpired(json);
                     case "Disconnect" -> {
                         parseDisconnect(json);
                         return false;
                     }
                     case "Input" -> parseInput(json);
+                    case "Resync" -> parseResync(json);
+                    case "Retransmit" -> parseRetransmit(json);
+                    case "Announcement" -> parseAnnouncement(json);
+                    case "AnnouncementCleared" -> System.out.println("Server operator cleared the announcement");
+                    case "LinkProbe" -> parseLinkProbe(json);
+                    case "LinkQuality" -> parseLinkQuality(json);
+                    case "Staged" -> System.out.println("Backend staged state " + json.optInt("index") + " (" + json.optInt("count") + " staged)");
+                    case "SessionPaused" -> System.out.println("Session paused" + (json.has("message") ? ": " + json.getString("message") : ""));
+                    case "SessionResumed" -> System.out.println("Session resumed after " + json.optLong("paused_for") / 1000 + " s");
+                    case "ClientIssues" -> parseClientIssues(json);
+                    case "SnapshotExport" -> parseSnapshotExport(json);
+                    case "QuorumReached" -> System.out.println(json.optInt("percent") + "% of the clients show state " + json.optInt("state_id"));
+                    case "StageMissing" -> System.out.println("Backend has no staged state " + json.optInt("index") + ", stage it first");
+                    case "AccessCode" -> System.out.println("New access code: " + json.optString("code"));
+                    case "RoomOpened" -> System.out.println("Room opened, clients join with code " + json.optString("code")
+                            + ", return to it with TT_HOST_ROOM=" + json.optString("session"));
+                    case "ClientListDiff" -> System.out.println("Client list version " + json.optLong("version") + ": "
+                            + json.optJSONArray("added").length() + " added, " + json.optJSONArray("updated").length() + " updated, "
+                            + json.optJSONArray("removed").length() + " removed" + (json.optBoolean("full") ? " (full list)" : ""));
+                    case "ScheduledMessages" -> System.out.println("Scheduled messages: " + json.optJSONArray("messages")
+                            + (json.has("error") ? " (rejected: " + json.optString("error") + ")" : ""));
+                    case "ScheduledMessageSent" -> System.out.println("Scheduled message " + json.optString("id") + " sent");
+                    case "ServerHello" -> System.out.println("Backend speaks protocol version " + json.optInt("version")
+                            + ", capabilities: " + json.optJSONArray("capabilities"));
+                    case "Subscribed" -> System.out.println("Subscribed to " + json.optJSONArray("events"));
+                    case "PresenceSummary" -> System.out.println("Clients: " + json.optInt("clients") + " (" + json.optLong("joined")
+                            + " joined, " + json.optLong("left") + " left, " + json.optLong("expired") + " expired)");
                     default -> throw new JSONParseException("Type is not supported: " + type);
                 }
 
@@ -172,6 +367,34 @@ public class MessageLayer  {
             return true;
         }
 
+        private void parseClientIssues(JSONObject json) throws JSONParseException {
+            try {
+                JSONArray clients = json.getJSONArray("clients");
+                for (int i = 0; i < clients.length(); i++) {
+                    JSONObject client = clients.getJSONObject(i);
+                    System.out.println("Client " + client.getString("name") + " (" + client.getString("address") + ") misbehaves: "
+                            + client.getLong("malformed") + " malformed message(s), "
+                            + client.getLong("rejected_inputs") + " rejected input(s), "
+                            + client.getLong("violations") + " protocol violation(s)");
+                }
+            } catch (JSONException e) {
+                throw new JSONParseException("ClientIssues message is malformed: " + json);
+            }
+        }
+
+        private void parseSnapshotExport(JSONObject json) throws JSONParseException {
+            try {
+                JSONObject archive = json.getJSONObject("archive");
+                Path file = Path.of("snapshot_" + archive.getLong("exported_at") + ".json");
+                Files.writeString(file, archive.toString());
+                System.out.println("Session snapshot saved to " + file.toAbsolutePath());
+            } catch (JSONException e) {
+                throw new JSONParseException("SnapshotExport message is malformed: " + json);
+            } catch (IOException e) {
+                System.err.println("Saving the session snapshot failed: " + e.getMessage());
+            }
+        }
+
         private void parseClientConnected(JSONObject json) throws JSONParseException {
             try {
                 String name = json.getString("name");
@@ -193,6 +416,16 @@ public class MessageLayer  {
             }
         }
 
+        private void parseClientExpired(JSONObject json) throws JSONParseException {
+            try {
+                String name = json.getString("name");
+                String address = json.getString("address");
+                callbacks.handleClientExpired(name, address);
+            } catch (JSONException e) {
+                throw new JSONParseException("ClientExpired message is malformed: " + json);
+            }
+        }
+
         private void parseDisconnect(JSONObject json) throws JSONParseException {
             try {
                 String reason = json.getString("reason");
@@ -202,6 +435,65 @@ public class MessageLayer  {
             }
         }
 
+        private void parseResync(JSONObject json) throws JSONParseException {
+            try {
+                int count = json.getInt("count");
+                System.out.println("Backend lost synchronization with our stream " + count + " time(s)");
+            } catch (JSONException e) {
+                throw new JSONParseException("Resync message is malformed: " + json);
+            }
+        }
+
+        private void parseAnnouncement(JSONObject json) throws JSONParseException {
+            try {
+                String message = json.getString("message");
+                System.out.println("Server operator announced: " + message);
+            } catch (JSONException e) {
+                throw new JSONParseException("Announcement message is malformed: " + json);
+            }
+        }
+
+        private void parseRetransmit(JSONObject json) throws JSONParseException {
+            long frame;
+            try {
+                frame = json.getLong("frame");
+            } catch (JSONException e) {
+                throw new JSONParseException("Retransmit message is malformed: " + json);
+            }
+            try {
+                connectionLayer.retransmit(frame);
+            } catch (IOException e) {
+                forceClose();
+            }
+        }
+
+        private void parseLinkProbe(JSONObject json) throws JSONParseException {
+            long probe;
+            try {
+                probe = json.getLong("probe");
+            } catch (JSONException e) {
+                throw new JSONParseException("LinkProbe message is malformed: " + json);
+            }
+            JSONObject ack = new JSONObject();
+            ack.put("type", "LinkProbeAck");
+            ack.put("probe", probe);
+            try {
+                connectionLayer.sendMessage(ack.toString());
+            } catch (IOException e) {
+                forceClose();
+            }
+        }
+
+        private void parseLinkQuality(JSONObject json) throws JSONParseException {
+            try {
+                if (json.has("warning")) {
+                    System.out.println("Backend warns about our link: " + json.getString("warning"));
+                }
+            } catch (JSONException e) {
+                throw new JSONParseException("LinkQuality message is malformed: " + json);
+            }
+        }
+
         private void parseInput(JSONObject json) throws JSONParseException {
             try {
                 int stateId = json.getInt("state_id");
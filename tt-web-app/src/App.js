@@ -2,25 +2,111 @@
 import React, { Component } from 'react';
 import { w3cwebsocket as W3CWebSocket } from "websocket";
 
+// Session storage keys of the backend url set by 'Migrate' and of the reconnect attempts in a row
+const BACKEND_URL_KEY = "backendUrl";
+const RECONNECT_ATTEMPT_KEY = "reconnectAttempt";
+// Session storage key of the one-time rejoin token handed out by 'RejoinLink'
+const REJOIN_KEY = "rejoin";
+// Session storage key of the token handed out by 'Resume', continuing as the same participant
+const RESUME_KEY = "resume";
 //const client = new W3CWebSocket('wss://coding-capricorn.de:8080');
-const client = new W3CWebSocket('ws://localhost:8080');
+const client = new W3CWebSocket(sessionStorage.getItem(BACKEND_URL_KEY) || 'ws://localhost:8080');
+// Keep in sync with package.json, compared against 'min_version' of 'ClientCommand'
+const APP_VERSION = "0.1.0";
+// Disconnect reason of the backend once the guest access expired
+const GUEST_EXPIRED_REASON = "Guest access expired, please log in again";
+// Unanswered pings in a row ('missed_pings' of 'ConnectionQuality') before reconnecting is suggested
+const MISSED_PINGS_RECONNECT = 3;
+// State id of the waiting state the backend shows while no host is connected
+const WAITING_STATE_ID = -1;
+
+// Bars of the signal indicator (0-4) for the round trip time measured by the backend
+function signalBars(quality) {
+  if (quality.missed_pings >= MISSED_PINGS_RECONNECT || quality.rtt_ms === undefined) {
+    return 0;
+  }
+  const bars = [100, 250, 500, 1000].filter((limit) => quality.rtt_ms < limit).length;
+  return Math.max(bars - Math.min(quality.missed_pings, bars - 1), 1);
+}
+
+// Delay (ms) before the reconnect attempt (from 0) following the 'reconnect' hint of the backend
+// Exponential from the minimum backoff, starting further into the window under load, capped by the
+// maximum backoff and shortened by a random share of up to 'jitter'
+function reconnectDelay(hint, attempt) {
+  const exponential = hint.min_backoff_ms * Math.pow(2, attempt);
+  const loaded = hint.min_backoff_ms + (hint.max_backoff_ms - hint.min_backoff_ms) * hint.load;
+  const base = Math.min(hint.max_backoff_ms, Math.max(exponential, loaded));
+  return base * (1 - hint.jitter * Math.random());
+}
+
+// Reloads the app after the delay suggested by the backend, counting the attempts in a row
+function scheduleReconnect(hint) {
+  const attempt = Number(sessionStorage.getItem(RECONNECT_ATTEMPT_KEY) || 0);
+  const delay = reconnectDelay(hint, attempt);
+  console.log("reconnecting in " + Math.round(delay) + "ms (attempt " + attempt + ")");
+  sessionStorage.setItem(RECONNECT_ATTEMPT_KEY, String(attempt + 1));
+  setTimeout(() => window.location.reload(), delay);
+}
+
+// Returns true if version a is older than version b (both 'major.minor.patch')
+function isOlderVersion(a, b) {
+  const partsA = a.split(".").map(Number);
+  const partsB = b.split(".").map(Number);
+  for (let i = 0; i < Math.max(partsA.length, partsB.length); i++) {
+    const diff = (partsA[i] || 0) - (partsB[i] || 0);
+    if (diff !== 0) {
+      return diff < 0;
+    }
+  }
+  return false;
+}
+
+function leadingZeroBits(bytes) {
+  let bits = 0;
+  for (const byte of bytes) {
+    if (byte !== 0) {
+      return bits + Math.clz32(byte) - 24;
+    }
+    bits += 8;
+  }
+  return bits;
+}
 
 class App extends Component {
 
   constructor(props) {
     super(props);
-    this.state = {fastReadToken: ""};
+    this.state = {fastReadToken: "", announcement: "", signal: undefined, suggestReconnect: false, paused: undefined, waiting: undefined};
   }
 
   currentStateId = 0;
   currentState = "None";
+  // Set while a 'RequestResync' is unanswered, to request it only once
+  resyncPending = false;
+  // Hashes of the assets already prefetched
+  prefetched = new Set();
+  // Set once 'Migrate' scheduled the reconnect, the following 'Disconnecting' is expected
+  migrating = false;
+  // Rejoin token of this login, taken from the url or the session storage once
+  rejoin = undefined;
 
-  sendLogin() {
+  sendLogin(proof) {
     if (client.readyState === client.OPEN) {
       // TODO get real data
       const name = "mock_name"
       const type = "ClientLogin"
-      const message_obj = {type:type, name: name}
+      // Access code of the invitation link, e.g. ?code=K7QX4M
+      const params = new URLSearchParams(window.location.search)
+      const code = params.get("code")
+      // Join code of a room opened by a host, e.g. ?room=K7QX2M, the session of the server if absent
+      const room = params.get("room")
+      // A rejoin token restores name, team and score, it works only once
+      if (this.rejoin === undefined) {
+        this.rejoin = params.get("rejoin") || sessionStorage.getItem(REJOIN_KEY)
+        sessionStorage.removeItem(REJOIN_KEY)
+      }
+      const resume = sessionStorage.getItem(RESUME_KEY)
+      const message_obj = {type:type, name: name, proof: proof, code: code, rejoin: this.rejoin, resume: resume, room: room}
       const message_str = JSON.stringify(message_obj)
 
       console.log("sendLogin(..): " + message_str)
@@ -31,6 +117,7 @@ class App extends Component {
   componentDidMount() {
     client.onopen = () => {
       console.log('componentDidMount(..): WebSocket Client Connected');
+      sessionStorage.removeItem(RECONNECT_ATTEMPT_KEY);
       this.sendLogin()
     };
     client.onmessage = (message) => {
@@ -44,14 +131,57 @@ class App extends Component {
 
     switch (json.type) {
       case "Disconnect":
+      case "Disconnecting":
         this.handleDisconnect(json);
         break;
+      case "Migrate":
+        this.handleMigrate(json);
+        break;
       case "Update":
         this.handleUpdate(json);
         break;
       case "ChangeState":
         this.handleStateChange(json);
         break;
+      case "ClientCommand":
+        this.handleClientCommand(json);
+        break;
+      case "Announcement":
+        this.handleAnnouncement(json);
+        break;
+      case "Challenge":
+        this.handleChallenge(json);
+        break;
+      case "Resynced":
+        this.resyncPending = false;
+        if (!json.complete) {
+          console.warn("resynced up to " + json.seq + ", but earlier updates of the state were lost");
+        } else {
+          console.log("resynced up to " + json.seq);
+        }
+        break;
+      case "Prefetch":
+        this.handlePrefetch(json);
+        break;
+      case "ConnectionQuality":
+        this.setState({signal: signalBars(json), suggestReconnect: json.missed_pings >= MISSED_PINGS_RECONNECT});
+        break;
+      case "SessionPaused":
+        this.setState({paused: json.message || "The session is paused"});
+        break;
+      case "SessionResumed":
+        this.setState({paused: undefined});
+        break;
+      case "RejoinLink":
+        sessionStorage.setItem(REJOIN_KEY, new URL(json.url, window.location.href).searchParams.get("rejoin"));
+        break;
+      case "Resume":
+        sessionStorage.setItem(RESUME_KEY, json.token);
+        break;
+      case "AnnouncementCleared":
+        clearTimeout(this.timerAnnouncement);
+        this.setState({announcement: ""});
+        break;
       default:
         console.warn("received bad message: type " + json.type + " is not supported");
         break;
@@ -61,17 +191,41 @@ class App extends Component {
   handleDisconnect(json) {
     let reason = json.reason;
     console.warn("backend closed connection: " + reason);
+    if (reason === GUEST_EXPIRED_REASON) {
+      // Logging in again renews the guest access
+      window.location.reload();
+      return;
+    }
+    if (this.migrating) {
+      return;
+    }
+    if (json.reconnect) {
+      scheduleReconnect(json.reconnect);
+      return;
+    }
     // TODO send disconnect to backend
     // TODO close websocket
     // TODO ask user if should try reconnect
   }
 
+  handleMigrate(json) {
+    console.warn("backend moves clients to " + json.url);
+    sessionStorage.setItem(BACKEND_URL_KEY, json.url);
+    this.migrating = true;
+    if (json.reconnect) {
+      scheduleReconnect(json.reconnect);
+    } else {
+      window.location.reload();
+    }
+  }
+
   handleUpdate(json) {
     let stateId = json.state_id;
     let update = JSON.parse(json.content);
 
     if (this.currentStateId !== stateId) {
       console.warn("received outdated update: got " + stateId + ", expected " + this.currentStateId);
+      this.requestResync();
     } else if (this.currentState !== "ActivityFastRead") {
       console.warn("received update for unsupported state " + this.currentState);
     } else {
@@ -91,14 +245,94 @@ class App extends Component {
     }
   }
 
+  // Asks the backend for the current state and its updates after losing track of them
+  requestResync() {
+    if (this.resyncPending || client.readyState !== client.OPEN) {
+      return;
+    }
+    this.resyncPending = true;
+    console.log("requesting resync");
+    client.send(JSON.stringify({type: "RequestResync"}));
+  }
+
+  // Lets the browser fetch an asset of the upcoming state while it is idle
+  handlePrefetch(json) {
+    if (this.prefetched.has(json.hash)) {
+      return;
+    }
+    this.prefetched.add(json.hash);
+    console.log("prefetching " + json.url + (json.size !== undefined ? " (" + json.size + " bytes)" : ""));
+    const link = document.createElement("link");
+    link.rel = "prefetch";
+    link.href = json.url;
+    document.head.appendChild(link);
+  }
+
+  handleClientCommand(json) {
+    let action = json.action;
+    let minVersion = json.min_version;
+
+    if (action === "reload") {
+      console.log("backend requested reload");
+      window.location.reload();
+    } else if (action === "update_required") {
+      if (minVersion === undefined || isOlderVersion(APP_VERSION, minVersion)) {
+        console.log("backend requires version " + minVersion + ", running " + APP_VERSION + ", reloading");
+        window.location.reload();
+      }
+    } else {
+      console.warn("received unsupported client command: " + action);
+    }
+  }
+
+  // Proof-of-work: find a proof whose SHA-256 with the nonce starts with 'difficulty' zero bits
+  async handleChallenge(json) {
+    if (json.kind !== "pow") {
+      console.warn("received unsupported challenge: " + json.kind);
+      return;
+    }
+    const encoder = new TextEncoder();
+    for (let proof = 0; ; proof++) {
+      const hash = new Uint8Array(await crypto.subtle.digest("SHA-256", encoder.encode(json.nonce + ":" + proof)));
+      if (leadingZeroBits(hash) >= json.difficulty) {
+        console.log("solved challenge with proof " + proof);
+        this.sendLogin(String(proof));
+        return;
+      }
+    }
+  }
+
+  handleAnnouncement(json) {
+    let message = json.message;
+    let expiresAt = json.expires_at;
+
+    clearTimeout(this.timerAnnouncement);
+    this.setState({announcement: message});
+    if (expiresAt !== undefined) {
+      this.timerAnnouncement = setTimeout(
+          () => {
+            this.setState({announcement: ""});
+          },
+          Math.max(expiresAt - Date.now(), 0)
+      );
+    }
+  }
+
   handleStateChange(json) {
     let stateId = json.state_id;
     let state = json.content;
 
+    if (stateId === WAITING_STATE_ID) {
+      this.setState({waiting: state});
+      return;
+    }
+    this.setState({waiting: undefined});
     if (state === "None" || state === "ActivityFastRead") {
       this.currentStateId = stateId;
       this.currentState = state;
       console.log("changed state to " + state);
+      // Counts towards the quorum the host may wait for
+      client.send(JSON.stringify({type: "StateAck", state_id: stateId}));
     } else {
       console.warn("received unsupported state change: " + state);
     }
@@ -107,6 +341,36 @@ class App extends Component {
   render() {
     return (
         <div>
+          {
+            this.state.announcement &&
+            <div style={{background: "#ffd54f", padding: "0.5em", textAlign: "center"}}>
+              {this.state.announcement}
+            </div>
+          }
+          {
+            this.state.waiting &&
+            <div style={{padding: "0.5em", textAlign: "center"}}>
+              {this.state.waiting}
+            </div>
+          }
+          {
+            this.state.paused &&
+            <div style={{background: "#90caf9", padding: "0.5em", textAlign: "center"}}>
+              {this.state.paused}
+            </div>
+          }
+          {
+            this.state.suggestReconnect &&
+            <div style={{background: "#ef9a9a", padding: "0.5em", textAlign: "center"}}>
+              Connection lost, <button onClick={() => window.location.reload()}>reconnect</button>
+            </div>
+          }
+          {
+            this.state.signal !== undefined &&
+            <div style={{textAlign: "right"}} title="Connection quality">
+              {"\u2582\u2584\u2586\u2588".slice(0, this.state.signal).padEnd(4, "\u2581")}
+            </div>
+          }
           <h1>
             <center>
               {